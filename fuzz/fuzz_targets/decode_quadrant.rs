@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/mesh_math.rs"]
+mod mesh_math;
+
+fuzz_target!(|data: &[u8]| {
+    // Treat each ASCII digit of the input as a split-mesh quadrant digit.
+    for &b in data {
+        if b.is_ascii_digit() {
+            let q = b - b'0';
+            assert_eq!(mesh_math::decode_quadrant(q).is_ok(), (1..=4).contains(&q));
+        }
+    }
+});
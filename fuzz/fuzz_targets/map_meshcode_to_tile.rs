@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/mesh_math.rs"]
+mod mesh_math;
+
+fuzz_target!(|data: &[u8]| {
+    // Treat the input as a (possibly malformed) mesh code of any level.
+    let Some(code) = std::str::from_utf8(data).ok().and_then(|s| s.parse::<u64>().ok()) else {
+        return;
+    };
+    for data_level in 1..=6 {
+        for tile_level in 1..=data_level {
+            if let Ok(rows) = mesh_math::subdivisions_per_axis(tile_level, data_level) {
+                let _ = mesh_math::map_meshcode_to_tile(code, data_level, tile_level, rows);
+            }
+        }
+    }
+});
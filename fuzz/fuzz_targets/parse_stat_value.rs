@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/mesh_values.rs"]
+mod mesh_values;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = mesh_values::parse_stat_value(s);
+        let _ = mesh_values::parse_stat_value_u16(s);
+    }
+});
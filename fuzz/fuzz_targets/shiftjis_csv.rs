@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/shiftjis_csv.rs"]
+mod shiftjis_csv;
+
+fuzz_target!(|data: &[u8]| {
+    let mut rdr = shiftjis_csv::shiftjis_csv_reader(std::io::Cursor::new(data.to_vec()));
+    let mut records = rdr.records();
+    let (Some(Ok(header1)), Some(Ok(header2))) = (records.next(), records.next()) else {
+        return;
+    };
+    let _ = shiftjis_csv::normalize_headers(&header1, &header2);
+    for record in records {
+        if record.is_err() {
+            break;
+        }
+    }
+});
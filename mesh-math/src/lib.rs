@@ -0,0 +1,11 @@
+//! Mesh-code to `mesh-tile` grid mapping from `jp-estat-util`, without `std` (the default
+//! `no_std` feature).
+//!
+//! The source is shared with the main crate (`src/mesh_math.rs`), so both always agree.
+
+#![cfg_attr(feature = "no_std", no_std)]
+
+#[path = "../../src/mesh_math.rs"]
+mod mesh_math;
+
+pub use mesh_math::*;
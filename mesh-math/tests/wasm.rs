@@ -0,0 +1,32 @@
+//! Run with `wasm-pack test --node mesh-math`.
+
+#![cfg(target_arch = "wasm32")]
+
+use jp_estat_mesh_math::{MeshMathError, map_meshcode_to_tile, subdivisions_per_axis};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn maps_lv3_to_lv1() {
+    let rows = subdivisions_per_axis(1, 3).unwrap();
+    assert_eq!(rows, 80);
+    assert_eq!(
+        map_meshcode_to_tile(53393599, 3, 1, rows),
+        Ok((5339, 40, 59))
+    );
+}
+
+#[wasm_bindgen_test]
+fn maps_lv6_to_lv3() {
+    assert_eq!(
+        map_meshcode_to_tile(53370000242, 6, 3, 8),
+        Ok((53370000, 5, 7))
+    );
+}
+
+#[wasm_bindgen_test]
+fn rejects_invalid_quadrant() {
+    assert_eq!(
+        map_meshcode_to_tile(533946115, 4, 3, 2),
+        Err(MeshMathError::InvalidQuadrant(5))
+    );
+}
@@ -0,0 +1,769 @@
+//! Library entry points mirroring the `mesh`, `areamap`, `mesh-tile` and `mesh-csv` subcommands.
+//!
+//! Each config is built with `new` for the required values and `with_*` setters for the
+//! options; the defaults match the CLI defaults.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use jp_estat_util::{MeshImportConfig, import_mesh};
+//!
+//! let config = MeshImportConfig::new("host=127.0.0.1 user=postgres", 3, 2020, "人口及び世帯")
+//!     .with_table_prefix("estat_");
+//! let report = import_mesh(config).await?;
+//! println!("{} rows in {}", report.table_rows, report.table_name);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    areamap::{self, AreamapImportReport, MaterializedView, ShapeValidation},
+    download,
+    geo_filter::GeoFilter,
+    mesh::{self, MeshImportReport},
+    mesh_csv,
+    mesh_tile::{self, AggFn, TileCompression, TileDType, TileEncodeReport},
+    s3_upload::{self, S3Upload},
+    watch,
+};
+use anyhow::Result;
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+};
+
+const DEFAULT_TMP_DIR: &str = "./tmp";
+const DEFAULT_TABLE_PREFIX: &str = "jp_estat_";
+
+/// Builds the HTTP client and makes sure the download cache directory exists.
+async fn prepare(
+    tmp_dir: &Path,
+    proxy: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<reqwest::Client> {
+    tokio::fs::create_dir_all(tmp_dir).await?;
+    download::build_http_client(proxy, user_agent)
+}
+
+/// Options for [`import_mesh`] (the `mesh` subcommand).
+#[derive(Debug, Clone)]
+pub struct MeshImportConfig {
+    pub(crate) postgres_url: String,
+    pub(crate) level: u8,
+    pub(crate) year: u16,
+    pub(crate) survey: String,
+    pub(crate) tmp_dir: PathBuf,
+    pub(crate) proxy: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) table_prefix: String,
+    pub(crate) row_mismatch_threshold: u64,
+    pub(crate) allow_row_mismatch: bool,
+    pub(crate) before_import_sql: Option<PathBuf>,
+    pub(crate) after_import_sql: Option<PathBuf>,
+    pub(crate) geo_filter: Option<GeoFilter>,
+    pub(crate) column_types: Option<PathBuf>,
+    pub(crate) add_centroid_column: bool,
+    pub(crate) srid_override: Option<u32>,
+    pub(crate) comment_table: bool,
+    pub(crate) insert_batch_size: usize,
+    pub(crate) temp_postgres_url: Option<String>,
+    pub(crate) drop_old_table: bool,
+    pub(crate) lockfile: Option<PathBuf>,
+    pub(crate) cleanup: bool,
+}
+
+impl MeshImportConfig {
+    pub fn new(
+        postgres_url: impl Into<String>,
+        level: u8,
+        year: u16,
+        survey: impl Into<String>,
+    ) -> Self {
+        Self {
+            postgres_url: postgres_url.into(),
+            level,
+            year,
+            survey: survey.into(),
+            tmp_dir: PathBuf::from(DEFAULT_TMP_DIR),
+            proxy: None,
+            user_agent: None,
+            table_prefix: DEFAULT_TABLE_PREFIX.to_string(),
+            row_mismatch_threshold: 0,
+            allow_row_mismatch: false,
+            before_import_sql: None,
+            after_import_sql: None,
+            geo_filter: None,
+            column_types: None,
+            add_centroid_column: false,
+            srid_override: None,
+            comment_table: false,
+            insert_batch_size: mesh::DEFAULT_INSERT_BATCH_SIZE,
+            temp_postgres_url: None,
+            drop_old_table: false,
+            lockfile: None,
+            cleanup: false,
+        }
+    }
+
+    pub fn with_tmp_dir(mut self, tmp_dir: impl Into<PathBuf>) -> Self {
+        self.tmp_dir = tmp_dir.into();
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// `User-Agent` of the HTTP requests; [`download::DEFAULT_USER_AGENT`] by default.
+    pub fn with_user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    pub fn with_table_prefix(mut self, table_prefix: impl Into<String>) -> Self {
+        self.table_prefix = table_prefix.into();
+        self
+    }
+
+    pub fn with_row_mismatch_threshold(mut self, threshold: u64) -> Self {
+        self.row_mismatch_threshold = threshold;
+        self
+    }
+
+    pub fn with_allow_row_mismatch(mut self, allow: bool) -> Self {
+        self.allow_row_mismatch = allow;
+        self
+    }
+
+    pub fn with_before_import_sql(mut self, path: Option<PathBuf>) -> Self {
+        self.before_import_sql = path;
+        self
+    }
+
+    pub fn with_after_import_sql(mut self, path: Option<PathBuf>) -> Self {
+        self.after_import_sql = path;
+        self
+    }
+
+    pub fn with_geo_filter(mut self, geo_filter: Option<GeoFilter>) -> Self {
+        self.geo_filter = geo_filter;
+        self
+    }
+
+    /// JSON file of `{"column": "postgres type"}` overriding the inferred column types.
+    pub fn with_column_types(mut self, path: Option<PathBuf>) -> Self {
+        self.column_types = path;
+        self
+    }
+
+    /// Adds a PostGIS `centroid` point column computed from `KEY_CODE`.
+    pub fn with_centroid_column(mut self, add: bool) -> Self {
+        self.add_centroid_column = add;
+        self
+    }
+
+    /// SRID of the centroid column; the centroids are reprojected from the survey datum.
+    pub fn with_srid_override(mut self, srid: Option<u32>) -> Self {
+        self.srid_override = srid;
+        self
+    }
+
+    /// Adds `COMMENT ON TABLE` / `COMMENT ON COLUMN` descriptions to the created table.
+    pub fn with_comment_table(mut self, comment_table: bool) -> Self {
+        self.comment_table = comment_table;
+        self
+    }
+
+    /// Rows sent per multi-row `INSERT` statement.
+    pub fn with_insert_batch_size(mut self, insert_batch_size: usize) -> Self {
+        self.insert_batch_size = insert_batch_size;
+        self
+    }
+
+    /// Imports into `{table}_tmp` over this connection, then renames it over the live table
+    /// (see [`crate::postgres::swap_in_table`]). Must be the same database as `postgres_url`.
+    pub fn with_temp_postgres_url(mut self, temp_postgres_url: Option<String>) -> Self {
+        self.temp_postgres_url = temp_postgres_url;
+        self
+    }
+
+    /// Drops the replaced table instead of keeping it as `{table}_old`.
+    pub fn with_drop_old_table(mut self, drop_old_table: bool) -> Self {
+        self.drop_old_table = drop_old_table;
+        self
+    }
+
+    /// Records the downloaded archives and their SHA-256 in this lockfile after a successful
+    /// import (see [`crate::lockfile`]). Not written by default.
+    pub fn with_lockfile(mut self, lockfile: Option<PathBuf>) -> Self {
+        self.lockfile = lockfile;
+        self
+    }
+
+    /// Removes the extracted CSV files once they have been imported.
+    pub fn with_cleanup(mut self, cleanup: bool) -> Self {
+        self.cleanup = cleanup;
+        self
+    }
+}
+
+/// Downloads a mesh survey and imports it into PostgreSQL.
+pub async fn import_mesh(config: MeshImportConfig) -> Result<MeshImportReport> {
+    let http_client = prepare(
+        &config.tmp_dir,
+        config.proxy.as_deref(),
+        config.user_agent.as_deref(),
+    )
+    .await?;
+    mesh::process_mesh(&http_client, &config, None).await
+}
+
+/// Runs [`import_mesh`], then imports again whenever e-Stat updates one of the survey's
+/// archives (`mesh --watch`). Checks every `poll_interval_minutes` until Ctrl+C.
+pub async fn watch_mesh(config: MeshImportConfig, poll_interval_minutes: NonZeroU64) -> Result<()> {
+    let http_client = prepare(
+        &config.tmp_dir,
+        config.proxy.as_deref(),
+        config.user_agent.as_deref(),
+    )
+    .await?;
+    let archives = mesh::watched_archives(
+        config.level,
+        config.year,
+        &config.survey,
+        config.geo_filter.as_ref(),
+    )?;
+    watch::watch(
+        &http_client,
+        &config.tmp_dir,
+        &archives,
+        poll_interval_minutes,
+        || {
+            let config = config.clone();
+            async move { import_mesh(config).await.map(|_| ()) }
+        },
+    )
+    .await
+}
+
+/// Options for [`import_areamap`] (the `areamap` subcommand).
+#[derive(Debug, Clone)]
+pub struct AreamapImportConfig {
+    pub(crate) output: String,
+    pub(crate) output_format: Option<String>,
+    pub(crate) output_crs: Option<String>,
+    pub(crate) year: Option<u32>,
+    pub(crate) years_range: Option<(u32, u32)>,
+    pub(crate) surveys_file: Option<PathBuf>,
+    pub(crate) pref_codes: Option<Vec<String>>,
+    pub(crate) pref_names: Option<Vec<String>>,
+    pub(crate) tmp_dir: PathBuf,
+    pub(crate) proxy: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) table_prefix: String,
+    pub(crate) before_import_sql: Option<PathBuf>,
+    pub(crate) after_import_sql: Option<PathBuf>,
+    pub(crate) concurrency: usize,
+    pub(crate) shape_validation: ShapeValidation,
+    pub(crate) materialized_view: MaterializedView,
+    pub(crate) cleanup: bool,
+}
+
+impl AreamapImportConfig {
+    /// `output` is a PostgreSQL connection string or an ogr2ogr datasource path.
+    pub fn new(output: impl Into<String>) -> Self {
+        Self {
+            output: output.into(),
+            output_format: None,
+            output_crs: None,
+            year: None,
+            years_range: None,
+            surveys_file: None,
+            pref_codes: None,
+            pref_names: None,
+            tmp_dir: PathBuf::from(DEFAULT_TMP_DIR),
+            proxy: None,
+            user_agent: None,
+            table_prefix: DEFAULT_TABLE_PREFIX.to_string(),
+            before_import_sql: None,
+            after_import_sql: None,
+            concurrency: areamap::DEFAULT_CONCURRENCY.get(),
+            shape_validation: ShapeValidation::None,
+            materialized_view: MaterializedView::None,
+            cleanup: false,
+        }
+    }
+
+    pub fn with_output_format(mut self, format: Option<String>) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    pub fn with_output_crs(mut self, crs: Option<String>) -> Self {
+        self.output_crs = crs;
+        self
+    }
+
+    /// Restricts the import to one survey year; all years are imported by default.
+    pub fn with_year(mut self, year: Option<u32>) -> Self {
+        self.year = year;
+        self
+    }
+
+    /// Restricts the import to the survey years in `start..=end`; takes precedence over
+    /// [`Self::with_year`].
+    pub fn with_years_range(mut self, years_range: Option<(u32, u32)>) -> Self {
+        self.years_range = years_range;
+        self
+    }
+
+    /// JSON list of `{ "year", "id", "datum" }` surveys that supplements the compiled-in
+    /// survey years; an entry for a year already known replaces it.
+    pub fn with_surveys_file(mut self, path: Option<PathBuf>) -> Self {
+        self.surveys_file = path;
+        self
+    }
+
+    /// Restricts the import to these two-digit prefecture codes (`"13"`). Combined with
+    /// [`Self::with_pref_names`]; all prefectures are imported when neither is set.
+    pub fn with_pref_codes(mut self, codes: Option<Vec<String>>) -> Self {
+        self.pref_codes = codes;
+        self
+    }
+
+    /// Restricts the import to these prefectures by name (`"東京都"`).
+    pub fn with_pref_names(mut self, names: Option<Vec<String>>) -> Self {
+        self.pref_names = names;
+        self
+    }
+
+    pub fn with_tmp_dir(mut self, tmp_dir: impl Into<PathBuf>) -> Self {
+        self.tmp_dir = tmp_dir.into();
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// `User-Agent` of the HTTP requests; [`download::DEFAULT_USER_AGENT`] by default.
+    pub fn with_user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    pub fn with_table_prefix(mut self, table_prefix: impl Into<String>) -> Self {
+        self.table_prefix = table_prefix.into();
+        self
+    }
+
+    pub fn with_before_import_sql(mut self, path: Option<PathBuf>) -> Self {
+        self.before_import_sql = path;
+        self
+    }
+
+    pub fn with_after_import_sql(mut self, path: Option<PathBuf>) -> Self {
+        self.after_import_sql = path;
+        self
+    }
+
+    /// Concurrent downloads, and concurrent per-year imports for PostgreSQL outputs.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Validates every downloaded shapefile with `ogrinfo` before importing any year.
+    pub fn with_shape_validation(mut self, validation: ShapeValidation) -> Self {
+        self.shape_validation = validation;
+        self
+    }
+
+    /// Creates or refreshes the cross-year materialized view after a PostgreSQL import.
+    pub fn with_materialized_view(mut self, materialized_view: MaterializedView) -> Self {
+        self.materialized_view = materialized_view;
+        self
+    }
+
+    /// Removes the extracted shapefiles once all years have been imported.
+    pub fn with_cleanup(mut self, cleanup: bool) -> Self {
+        self.cleanup = cleanup;
+        self
+    }
+}
+
+/// Downloads the 小地域 boundary shapefiles and imports them with ogr2ogr.
+pub async fn import_areamap(config: AreamapImportConfig) -> Result<AreamapImportReport> {
+    let http_client = prepare(
+        &config.tmp_dir,
+        config.proxy.as_deref(),
+        config.user_agent.as_deref(),
+    )
+    .await?;
+    areamap::process_areamap(&http_client, &config).await
+}
+
+/// Runs [`import_areamap`], then imports again whenever e-Stat updates one of the
+/// shapefile archives (`areamap --watch`). Checks every `poll_interval_minutes` until Ctrl+C.
+pub async fn watch_areamap(
+    config: AreamapImportConfig,
+    poll_interval_minutes: NonZeroU64,
+) -> Result<()> {
+    let http_client = prepare(
+        &config.tmp_dir,
+        config.proxy.as_deref(),
+        config.user_agent.as_deref(),
+    )
+    .await?;
+    let archives = areamap::watched_archives(
+        config.year,
+        config.years_range,
+        config.surveys_file.as_deref(),
+        config.pref_codes.as_deref(),
+        config.pref_names.as_deref(),
+    )?;
+    watch::watch(
+        &http_client,
+        &config.tmp_dir,
+        &archives,
+        poll_interval_minutes,
+        || {
+            let config = config.clone();
+            async move { import_areamap(config).await.map(|_| ()) }
+        },
+    )
+    .await
+}
+
+/// Options for [`encode_mesh_tiles`] (the `mesh-tile` subcommand).
+#[derive(Debug, Clone)]
+pub struct MeshTileConfig {
+    pub(crate) level: u8,
+    pub(crate) year: u16,
+    pub(crate) survey: String,
+    pub(crate) output_dir: PathBuf,
+    pub(crate) tile_level: Option<u8>,
+    pub(crate) bands: Option<Vec<String>>,
+    pub(crate) column_alias_file: Option<PathBuf>,
+    pub(crate) min_nonnull_fraction: Option<f64>,
+    pub(crate) ogc_features_output: Option<PathBuf>,
+    pub(crate) compression: TileCompression,
+    pub(crate) dtype: TileDType,
+    pub(crate) geo_filter: Option<GeoFilter>,
+    pub(crate) shard_dir: bool,
+    pub(crate) overwrite: bool,
+    pub(crate) encode_threads: Option<NonZeroUsize>,
+    pub(crate) pyramid: bool,
+    pub(crate) aggregate_fn: AggFn,
+    pub(crate) split_by_l1: bool,
+    pub(crate) max_tile_count: usize,
+    pub(crate) s3_upload: Option<S3Upload>,
+    pub(crate) tmp_dir: PathBuf,
+    pub(crate) proxy: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) cleanup: bool,
+}
+
+impl MeshTileConfig {
+    pub fn new(
+        level: u8,
+        year: u16,
+        survey: impl Into<String>,
+        output_dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            level,
+            year,
+            survey: survey.into(),
+            output_dir: output_dir.into(),
+            tile_level: None,
+            bands: None,
+            column_alias_file: None,
+            min_nonnull_fraction: None,
+            ogc_features_output: None,
+            compression: TileCompression::DeflateRaw,
+            dtype: TileDType::Int32,
+            geo_filter: None,
+            shard_dir: false,
+            overwrite: true,
+            encode_threads: None,
+            pyramid: false,
+            aggregate_fn: AggFn::Sum,
+            split_by_l1: false,
+            max_tile_count: mesh_tile::DEFAULT_MAX_TILE_COUNT,
+            s3_upload: None,
+            tmp_dir: PathBuf::from(DEFAULT_TMP_DIR),
+            proxy: None,
+            user_agent: None,
+            cleanup: false,
+        }
+    }
+
+    /// Mesh level of each tile; defaults to the data level (one cell per tile).
+    pub fn with_tile_level(mut self, tile_level: Option<u8>) -> Self {
+        self.tile_level = tile_level;
+        self
+    }
+
+    /// Column names to encode as bands; all numeric columns by default.
+    pub fn with_bands(mut self, bands: Option<Vec<String>>) -> Self {
+        self.bands = bands;
+        self
+    }
+
+    /// JSON object mapping column names to the band names written to `metadata.json`.
+    pub fn with_column_alias_file(mut self, path: Option<PathBuf>) -> Self {
+        self.column_alias_file = path;
+        self
+    }
+
+    /// Drops bands whose share of non-null cells in the first downloaded file is below this.
+    pub fn with_min_nonnull_fraction(mut self, fraction: Option<f64>) -> Self {
+        self.min_nonnull_fraction = fraction;
+        self
+    }
+
+    pub fn with_ogc_features_output(mut self, path: Option<PathBuf>) -> Self {
+        self.ogc_features_output = path;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: TileCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_dtype(mut self, dtype: TileDType) -> Self {
+        self.dtype = dtype;
+        self
+    }
+
+    pub fn with_geo_filter(mut self, geo_filter: Option<GeoFilter>) -> Self {
+        self.geo_filter = geo_filter;
+        self
+    }
+
+    pub fn with_shard_dir(mut self, shard_dir: bool) -> Self {
+        self.shard_dir = shard_dir;
+        self
+    }
+
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn with_encode_threads(mut self, threads: Option<NonZeroUsize>) -> Self {
+        self.encode_threads = threads;
+        self
+    }
+
+    /// Writes every level from the data level down to the tile level into `lv{N}/`
+    /// subdirectories, combining fine cells into coarse ones with [`Self::with_aggregate_fn`].
+    pub fn with_pyramid(mut self, pyramid: bool) -> Self {
+        self.pyramid = pyramid;
+        self
+    }
+
+    /// How `--pyramid` combines fine cells; defaults to [`AggFn::Sum`].
+    pub fn with_aggregate_fn(mut self, aggregate_fn: AggFn) -> Self {
+        self.aggregate_fn = aggregate_fn;
+        self
+    }
+
+    /// Writes each level-1 code's tiles into its own subdirectory with a `metadata.json`,
+    /// plus a top-level `manifest.json` listing the subdirectories. Implies the shard layout.
+    pub fn with_split_by_l1(mut self, split_by_l1: bool) -> Self {
+        self.split_by_l1 = split_by_l1;
+        self
+    }
+
+    /// Aborts once more tiles than this would be generated (0: no limit), to catch a
+    /// `tile_level` far below the data level.
+    pub fn with_max_tile_count(mut self, max_tile_count: usize) -> Self {
+        self.max_tile_count = max_tile_count;
+        self
+    }
+
+    /// Uploads the output directory to S3 once the tiles are written, `metadata.json` last.
+    /// Needs the `s3` feature.
+    pub fn with_s3_upload(mut self, s3_upload: Option<S3Upload>) -> Self {
+        self.s3_upload = s3_upload;
+        self
+    }
+
+    pub fn with_tmp_dir(mut self, tmp_dir: impl Into<PathBuf>) -> Self {
+        self.tmp_dir = tmp_dir.into();
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// `User-Agent` of the HTTP requests; [`download::DEFAULT_USER_AGENT`] by default.
+    pub fn with_user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    /// Removes the extracted CSV files once their tiles have been written.
+    pub fn with_cleanup(mut self, cleanup: bool) -> Self {
+        self.cleanup = cleanup;
+        self
+    }
+}
+
+/// Downloads a mesh survey and encodes it as mesh data tiles.
+pub async fn encode_mesh_tiles(config: MeshTileConfig) -> Result<TileEncodeReport> {
+    if config.s3_upload.is_some() {
+        s3_upload::ensure_available()?;
+    }
+    let http_client = prepare(
+        &config.tmp_dir,
+        config.proxy.as_deref(),
+        config.user_agent.as_deref(),
+    )
+    .await?;
+    let report = mesh_tile::process_mesh_tile(&http_client, &config, None).await?;
+    if let Some(upload) = &config.s3_upload {
+        s3_upload::upload_dir(&report.output_dir, upload).await?;
+    }
+    Ok(report)
+}
+
+/// Options for [`export_mesh_csv`] (the `mesh-csv` subcommand).
+#[derive(Debug, Clone)]
+pub struct MeshCsvConfig {
+    pub(crate) level: u8,
+    pub(crate) year: u16,
+    pub(crate) survey: String,
+    pub(crate) output: PathBuf,
+    pub(crate) geo_filter: Option<GeoFilter>,
+    pub(crate) column_order: Option<PathBuf>,
+    pub(crate) column_alias_file: Option<PathBuf>,
+    pub(crate) min_nonnull_fraction: Option<f64>,
+    pub(crate) add_geometry_wkt: bool,
+    pub(crate) tmp_dir: PathBuf,
+    pub(crate) proxy: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) cleanup: bool,
+}
+
+impl MeshCsvConfig {
+    pub fn new(
+        level: u8,
+        year: u16,
+        survey: impl Into<String>,
+        output: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            level,
+            year,
+            survey: survey.into(),
+            output: output.into(),
+            geo_filter: None,
+            column_order: None,
+            column_alias_file: None,
+            min_nonnull_fraction: None,
+            add_geometry_wkt: false,
+            tmp_dir: PathBuf::from(DEFAULT_TMP_DIR),
+            proxy: None,
+            user_agent: None,
+            cleanup: false,
+        }
+    }
+
+    /// Only writes cells whose centroid falls inside the polygon.
+    pub fn with_geo_filter(mut self, geo_filter: Option<GeoFilter>) -> Self {
+        self.geo_filter = geo_filter;
+        self
+    }
+
+    /// File listing column names, one per line; the listed columns come first in that order.
+    pub fn with_column_order(mut self, path: Option<PathBuf>) -> Self {
+        self.column_order = path;
+        self
+    }
+
+    /// JSON object mapping column names to the names written to the header.
+    pub fn with_column_alias_file(mut self, path: Option<PathBuf>) -> Self {
+        self.column_alias_file = path;
+        self
+    }
+
+    /// Drops columns whose share of non-null cells in the first downloaded file is below this.
+    pub fn with_min_nonnull_fraction(mut self, fraction: Option<f64>) -> Self {
+        self.min_nonnull_fraction = fraction;
+        self
+    }
+
+    /// Appends a `geometry_wkt` column with each cell's polygon.
+    pub fn with_add_geometry_wkt(mut self, add_geometry_wkt: bool) -> Self {
+        self.add_geometry_wkt = add_geometry_wkt;
+        self
+    }
+
+    pub fn with_tmp_dir(mut self, tmp_dir: impl Into<PathBuf>) -> Self {
+        self.tmp_dir = tmp_dir.into();
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// `User-Agent` of the HTTP requests; [`download::DEFAULT_USER_AGENT`] by default.
+    pub fn with_user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    /// Removes the extracted CSV files once they have been merged.
+    pub fn with_cleanup(mut self, cleanup: bool) -> Self {
+        self.cleanup = cleanup;
+        self
+    }
+}
+
+/// Downloads a mesh survey and merges its CSV files into one UTF-8 CSV.
+pub async fn export_mesh_csv(config: MeshCsvConfig) -> Result<()> {
+    let http_client = prepare(
+        &config.tmp_dir,
+        config.proxy.as_deref(),
+        config.user_agent.as_deref(),
+    )
+    .await?;
+    mesh_csv::process_mesh_csv(&http_client, &config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_default_to_cli_defaults() {
+        let mesh = MeshImportConfig::new("host=127.0.0.1", 3, 2020, "人口及び世帯");
+        assert_eq!(mesh.tmp_dir, PathBuf::from("./tmp"));
+        assert_eq!(mesh.table_prefix, "jp_estat_");
+        assert!(!mesh.cleanup);
+
+        let tile = MeshTileConfig::new(3, 2020, "人口及び世帯", "out")
+            .with_dtype(TileDType::Uint16)
+            .with_overwrite(false)
+            .with_tile_level(Some(1));
+        assert_eq!(tile.compression, TileCompression::DeflateRaw);
+        assert_eq!(tile.dtype, TileDType::Uint16);
+        assert_eq!(tile.tile_level, Some(1));
+        assert!(!tile.overwrite);
+
+        let csv =
+            MeshCsvConfig::new(3, 2020, "人口及び世帯", "out.csv").with_add_geometry_wkt(true);
+        assert_eq!(csv.tmp_dir, PathBuf::from("./tmp"));
+        assert!(csv.add_geometry_wkt);
+        assert!(csv.geo_filter.is_none());
+    }
+}
@@ -1,14 +1,21 @@
 use anyhow::{Context as _, Result, bail};
-use futures::stream;
-use indicatif::{ProgressBar, ProgressStyle};
+use futures::{StreamExt as _, stream};
 use km_to_sql::metadata::{ColumnMetadata, TableMetadata};
-use std::path::Path;
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::task::JoinSet;
 use tokio_postgres::NoTls;
 use url::Url;
 
 use crate::{
+    AreamapImportConfig,
     download::{self, DownloadedItem},
-    gdal,
+    gdal, memory_report, postgres, progress,
 };
 
 const PREF_CODES: [&str; 47] = [
@@ -17,41 +24,190 @@ const PREF_CODES: [&str; 47] = [
     "33", "34", "35", "36", "37", "38", "39", "40", "41", "42", "43", "44", "45", "46", "47",
 ];
 
-#[derive(Clone, Debug)]
+/// `(code, name)` of every prefecture, in code order.
+const PREF_NAMES: [(&str, &str); 47] = [
+    ("01", "北海道"),
+    ("02", "青森県"),
+    ("03", "岩手県"),
+    ("04", "宮城県"),
+    ("05", "秋田県"),
+    ("06", "山形県"),
+    ("07", "福島県"),
+    ("08", "茨城県"),
+    ("09", "栃木県"),
+    ("10", "群馬県"),
+    ("11", "埼玉県"),
+    ("12", "千葉県"),
+    ("13", "東京都"),
+    ("14", "神奈川県"),
+    ("15", "新潟県"),
+    ("16", "富山県"),
+    ("17", "石川県"),
+    ("18", "福井県"),
+    ("19", "山梨県"),
+    ("20", "長野県"),
+    ("21", "岐阜県"),
+    ("22", "静岡県"),
+    ("23", "愛知県"),
+    ("24", "三重県"),
+    ("25", "滋賀県"),
+    ("26", "京都府"),
+    ("27", "大阪府"),
+    ("28", "兵庫県"),
+    ("29", "奈良県"),
+    ("30", "和歌山県"),
+    ("31", "鳥取県"),
+    ("32", "島根県"),
+    ("33", "岡山県"),
+    ("34", "広島県"),
+    ("35", "山口県"),
+    ("36", "徳島県"),
+    ("37", "香川県"),
+    ("38", "愛媛県"),
+    ("39", "高知県"),
+    ("40", "福岡県"),
+    ("41", "佐賀県"),
+    ("42", "長崎県"),
+    ("43", "熊本県"),
+    ("44", "大分県"),
+    ("45", "宮崎県"),
+    ("46", "鹿児島県"),
+    ("47", "沖縄県"),
+];
+
+fn pref_name_to_code(name: &str) -> Option<&'static str> {
+    PREF_NAMES
+        .iter()
+        .find(|(_, pref_name)| *pref_name == name.trim())
+        .map(|(code, _)| *code)
+}
+
+/// The prefectures selected by `--filter-pref` (codes) and `--pref-names` (names), in code
+/// order. All prefectures when neither is given.
+fn target_prefectures(
+    pref_codes: Option<&[String]>,
+    pref_names: Option<&[String]>,
+) -> Result<Vec<&'static str>> {
+    if pref_codes.is_none() && pref_names.is_none() {
+        return Ok(PREF_CODES.to_vec());
+    }
+    let mut selected = Vec::new();
+    for code in pref_codes.unwrap_or_default() {
+        let code = code.trim();
+        let Some(known) = PREF_CODES.iter().find(|known| **known == code) else {
+            bail!(
+                "unknown prefecture code: {} (expected two digits, 01-47)",
+                code
+            );
+        };
+        selected.push(*known);
+    }
+    for name in pref_names.unwrap_or_default() {
+        let Some(code) = pref_name_to_code(name) else {
+            bail!(
+                "unknown prefecture name: {} (use the full name, e.g. 東京都, 大阪府)",
+                name
+            );
+        };
+        selected.push(code);
+    }
+    selected.sort_unstable();
+    selected.dedup();
+    Ok(selected)
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct DlServey<'a> {
     year: u32,
-    id: &'a str,
-    datum: &'a str,
+    id: Cow<'a, str>,
+    datum: Cow<'a, str>,
 }
 
 const DL_SERVEY_IDS: [DlServey; 5] = [
     DlServey {
         year: 2020,
-        id: "A002005212020",
-        datum: "2011",
+        id: Cow::Borrowed("A002005212020"),
+        datum: Cow::Borrowed("2011"),
     }, // 2020年
     DlServey {
         year: 2015,
-        id: "A002005212015",
-        datum: "2011",
+        id: Cow::Borrowed("A002005212015"),
+        datum: Cow::Borrowed("2011"),
     }, // 2015年
     DlServey {
         year: 2010,
-        id: "A002005212010",
-        datum: "2000",
+        id: Cow::Borrowed("A002005212010"),
+        datum: Cow::Borrowed("2000"),
     }, // 2010年
     DlServey {
         year: 2005,
-        id: "A002005212005",
-        datum: "2000",
+        id: Cow::Borrowed("A002005212005"),
+        datum: Cow::Borrowed("2000"),
     }, // 2005年
     DlServey {
         year: 2000,
-        id: "A002005212000",
-        datum: "2000",
+        id: Cow::Borrowed("A002005212000"),
+        datum: Cow::Borrowed("2000"),
     }, // 2000年
 ];
 
+/// Values of the `datum` query parameter e-Stat accepts for boundary shapefiles.
+const KNOWN_DATUMS: [&str; 2] = ["2000", "2011"];
+
+/// One entry of `--surveys-file`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SurveyFileEntry {
+    year: u32,
+    id: String,
+    datum: String,
+}
+
+fn parse_surveys(json: &str) -> Result<Vec<DlServey<'static>>> {
+    let entries: Vec<SurveyFileEntry> = serde_json::from_str(json)?;
+    let mut serveys: Vec<DlServey<'static>> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if !KNOWN_DATUMS.contains(&entry.datum.as_str()) {
+            bail!(
+                "unknown datum \"{}\" for {} (expected one of: {})",
+                entry.datum,
+                entry.year,
+                KNOWN_DATUMS.join(", ")
+            );
+        }
+        if entry.id.is_empty() || !entry.id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            bail!("invalid survey id \"{}\" for {}", entry.id, entry.year);
+        }
+        if serveys.iter().any(|servey| servey.year == entry.year) {
+            bail!("year {} is listed more than once", entry.year);
+        }
+        serveys.push(DlServey {
+            year: entry.year,
+            id: Cow::Owned(entry.id),
+            datum: Cow::Owned(entry.datum),
+        });
+    }
+    Ok(serveys)
+}
+
+/// The compiled-in surveys, supplemented (or, for the same year, overridden) by
+/// `surveys_file`. Newest year first, like [`DL_SERVEY_IDS`].
+fn load_available_serveys(surveys_file: Option<&Path>) -> Result<Vec<DlServey<'static>>> {
+    let mut serveys = DL_SERVEY_IDS.to_vec();
+    if let Some(path) = surveys_file {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("when reading {}", path.display()))?;
+        let extra = parse_surveys(&json).with_context(|| format!("in {}", path.display()))?;
+        serveys.retain(|servey| extra.iter().all(|e| e.year != servey.year));
+        serveys.extend(extra);
+        serveys.sort_by_key(|servey| std::cmp::Reverse(servey.year));
+    }
+    Ok(serveys)
+}
+
+/// Default of `areamap --concurrency`.
+pub const DEFAULT_CONCURRENCY: NonZeroUsize = NonZeroUsize::new(10).unwrap();
+
 const AREAMAP_OGR2OGR_WHERE: &str = "HCODE IS NULL OR HCODE <> 8154";
 
 fn get_shape_url(dlservey_id: &str, code: &str, datum: &str) -> String {
@@ -68,30 +224,75 @@ struct ShapeUrlMeta {
     url: Url,
 }
 
-fn get_target_serveys(survey_year: Option<u32>) -> Result<Vec<DlServey<'static>>> {
+/// Parses `--years-range` (`2000-2020`) into an inclusive `(start, end)`.
+pub fn parse_years_range(value: &str) -> Result<(u32, u32)> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| anyhow::anyhow!("expected <START>-<END>, e.g. 2000-2020"))?;
+    let start: u32 = start
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid start year: {}", start))?;
+    let end: u32 = end
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid end year: {}", end))?;
+    if start > end {
+        bail!("start year {} is after end year {}", start, end);
+    }
+    Ok((start, end))
+}
+
+fn get_target_serveys(
+    available: &[DlServey<'static>],
+    survey_year: Option<u32>,
+    years_range: Option<(u32, u32)>,
+) -> Result<Vec<DlServey<'static>>> {
+    if let Some((start, end)) = years_range {
+        let serveys: Vec<DlServey<'static>> = available
+            .iter()
+            .filter(|servey| (start..=end).contains(&servey.year))
+            .cloned()
+            .collect();
+        if serveys.is_empty() {
+            bail!(
+                "No survey years between {} and {}. Available years: {}",
+                start,
+                end,
+                available_years(available)
+            );
+        }
+        return Ok(serveys);
+    }
     if let Some(year) = survey_year {
-        if let Some(servey) = DL_SERVEY_IDS.iter().find(|servey| servey.year == year) {
+        if let Some(servey) = available.iter().find(|servey| servey.year == year) {
             return Ok(vec![servey.clone()]);
         }
-        let available_years = DL_SERVEY_IDS
-            .iter()
-            .map(|servey| servey.year.to_string())
-            .collect::<Vec<_>>()
-            .join(", ");
         bail!(
             "Unsupported survey year: {}. Available years: {}",
             year,
-            available_years
+            available_years(available)
         );
     }
-    Ok(DL_SERVEY_IDS.iter().cloned().collect())
+    Ok(available.to_vec())
 }
 
-fn get_all_shape_urls(target_serveys: &[DlServey<'static>]) -> Vec<ShapeUrlMeta> {
+fn available_years(available: &[DlServey<'_>]) -> String {
+    available
+        .iter()
+        .map(|servey| servey.year.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn get_all_shape_urls(
+    target_serveys: &[DlServey<'static>],
+    prefectures: &[&'static str],
+) -> Vec<ShapeUrlMeta> {
     let mut urls = Vec::new();
-    for code in PREF_CODES.iter() {
+    for code in prefectures {
         for dlservey in target_serveys.iter() {
-            let url_str = get_shape_url(dlservey.id, code, dlservey.datum);
+            let url_str = get_shape_url(&dlservey.id, code, &dlservey.datum);
             urls.push(ShapeUrlMeta {
                 dlservey: dlservey.clone(),
                 pref_code: code,
@@ -102,6 +303,34 @@ fn get_all_shape_urls(target_serveys: &[DlServey<'static>]) -> Vec<ShapeUrlMeta>
     urls
 }
 
+/// Name of a prefecture's shapefile archive in `--tmp-dir`.
+fn archive_filename(meta: &ShapeUrlMeta) -> String {
+    format!("{}-{}.zip", meta.dlservey.year, meta.pref_code)
+}
+
+/// The archives `process_areamap` downloads, as (file name in `--tmp-dir`, URL), for
+/// `--watch`.
+pub fn watched_archives(
+    survey_year: Option<u32>,
+    years_range: Option<(u32, u32)>,
+    surveys_file: Option<&Path>,
+    pref_codes: Option<&[String]>,
+    pref_names: Option<&[String]>,
+) -> Result<Vec<(String, Url)>> {
+    let available = load_available_serveys(surveys_file)?;
+    let target_serveys = get_target_serveys(&available, survey_year, years_range)?;
+    let prefectures = target_prefectures(pref_codes, pref_names)?;
+    Ok(get_all_shape_urls(&target_serveys, &prefectures)
+        .into_iter()
+        .map(|meta| (archive_filename(&meta), meta.url))
+        .collect())
+}
+
+/// Name of the table (or layer) that holds one survey year.
+pub fn areamap_table_name(table_prefix: &str, year: u32) -> String {
+    format!("{}areamap_{}", table_prefix, year)
+}
+
 fn is_single_layer_output(output: &str, output_format: Option<&str>) -> bool {
     if as_postgres_url(output, output_format).is_some() {
         return false;
@@ -140,30 +369,83 @@ fn output_layer_name_from_destination(output: &str) -> Option<String> {
         .map(|stem| stem.to_string())
 }
 
-async fn import_shapes(
-    downloaded_shapes: Vec<DownloadedItem<ShapeUrlMeta>>,
+/// The ogr2ogr destination shared by the per-year import tasks.
+struct ImportTarget {
+    output: String,
+    output_format: Option<String>,
+    output_layer_name: Option<String>,
+    output_crs: Option<String>,
+}
+
+/// Builds the VRT of one year and loads it with ogr2ogr into `layer_name`, unless the output
+/// is a single-layer file named after its own path. Runs as its own task, so it owns
+/// everything it touches.
+async fn import_year(
+    target: Arc<ImportTarget>,
+    vrt_path: PathBuf,
+    shapes: Vec<PathBuf>,
+    layer_name: String,
+) -> Result<()> {
+    gdal::create_vrt(&vrt_path, &shapes)
+        .await
+        .with_context(|| format!("when creating VRT: {}", &vrt_path.display()))?;
+    gdal::load(
+        &vrt_path,
+        &target.output,
+        target.output_format.as_deref(),
+        target.output_layer_name.as_deref().unwrap_or(&layer_name),
+        Some(AREAMAP_OGR2OGR_WHERE),
+        target.output_crs.as_deref(),
+    )
+    .await
+    .with_context(|| format!("when loading VRT: {}", &vrt_path.display()))
+}
+
+/// Downloads the shapes year by year and starts each year's VRT creation and import as soon
+/// as its prefectures are in, while the next year downloads. At most `import_concurrency`
+/// imports run at once. Returns every downloaded item.
+#[allow(clippy::too_many_arguments)]
+async fn download_and_import_shapes(
+    http_client: &reqwest::Client,
+    table_prefix: &str,
     target_serveys: &[DlServey<'static>],
-    output: &str,
-    output_format: Option<&str>,
-    output_layer_name: Option<&str>,
-    output_crs: Option<&str>,
+    prefectures: &[&'static str],
+    target: ImportTarget,
     tmp_dir: &Path,
-) -> Result<()> {
-    let pb = ProgressBar::new(target_serveys.len() as u64);
-    let bar_style = ProgressStyle::default_bar()
-        .template("{msg} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7}")?
-        .progress_chars("##-");
-    pb.set_style(bar_style);
-    pb.set_message("Importing shapes with ogr2ogr...");
+    concurrency: usize,
+    import_concurrency: usize,
+    before_import: Option<(&str, &str)>,
+) -> Result<Vec<DownloadedItem<ShapeUrlMeta>>> {
+    let target = Arc::new(target);
+    let pb = progress::new_bar(
+        target_serveys.len() as u64,
+        "Importing shapes with ogr2ogr...",
+    )?;
 
-    for servey in target_serveys.iter() {
-        let shapes_for_year = downloaded_shapes
-            .iter()
-            .filter(|item| item.metadata.dlservey.year == servey.year)
-            .map(|item| item.extracted_path.clone())
-            .collect::<Vec<_>>();
+    let mut before_import = before_import;
+    let mut imports: JoinSet<Result<()>> = JoinSet::new();
+    let mut downloaded_shapes = Vec::new();
+    for servey in target_serveys {
+        let downloaded: Vec<DownloadedItem<ShapeUrlMeta>> = download::into_result(
+            download::download_and_extract_all(
+                http_client,
+                stream::iter(get_all_shape_urls(
+                    std::slice::from_ref(servey),
+                    prefectures,
+                )),
+                |meta| meta.url.clone(),
+                archive_filename,
+                "shp", // Target extension is .shp
+                tmp_dir,
+                "Downloading Shapes...",
+                "Extracting Shapes...",
+                concurrency,
+            )
+            .await?,
+        )
+        .with_context(|| format!("when downloading and extracting shapes for {}", servey.year))?;
 
-        if shapes_for_year.is_empty() {
+        if downloaded.is_empty() {
             println!(
                 "No shapes found for year {}, skipping VRT creation and import.",
                 servey.year
@@ -172,25 +454,354 @@ async fn import_shapes(
             continue;
         }
 
-        let vrt_path = tmp_dir.join(format!("jp_estat_areamap_{}.vrt", servey.year));
-        gdal::create_vrt(&vrt_path, &shapes_for_year)
+        if let Some((postgres_url, sql)) = before_import.take() {
+            let client = postgres::connect(postgres_url).await?;
+            postgres::run_sql_hook(&client, "before-import", sql).await?;
+        }
+
+        while imports.len() >= import_concurrency {
+            if let Some(result) = imports.join_next().await {
+                result??;
+            }
+        }
+        let layer_name = areamap_table_name(table_prefix, servey.year);
+        let vrt_path = tmp_dir.join(format!("{}.vrt", layer_name));
+        let shapes = downloaded
+            .iter()
+            .map(|item| item.extracted_path.clone())
+            .collect();
+        let target = target.clone();
+        let pb = pb.clone();
+        imports.spawn(async move {
+            import_year(target, vrt_path, shapes, layer_name).await?;
+            pb.inc(1);
+            Ok(())
+        });
+        downloaded_shapes.extend(downloaded);
+    }
+
+    while let Some(result) = imports.join_next().await {
+        result??;
+    }
+    pb.finish();
+    println!("All imports completed.");
+    Ok(downloaded_shapes)
+}
+
+/// What to do with the cross-year materialized view (`{prefix}areamap_all`) after an import
+/// to PostgreSQL.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MaterializedView {
+    /// Leave it alone.
+    #[default]
+    None,
+    /// (Re)create it over every year table in the database (`--create-materialized-view`).
+    Create,
+    /// Refresh an existing one, concurrently when it already has data
+    /// (`--refresh-materialized-view`).
+    Refresh,
+    /// Recreate it only when it already exists, so a newly added year shows up in it
+    /// (`areamap-add-year`).
+    RecreateIfExists,
+}
+
+/// Name of the materialized view that unions the boundaries of every year.
+pub fn materialized_view_name(table_prefix: &str) -> String {
+    format!("{}areamap_all", table_prefix)
+}
+
+/// Survey years of the `{prefix}areamap_YYYY` tables among `table_names`, in order.
+fn areamap_years<'a>(table_prefix: &str, table_names: impl Iterator<Item = &'a str>) -> Vec<u32> {
+    let prefix = format!("{}areamap_", table_prefix);
+    let mut years: Vec<u32> = table_names
+        .filter_map(|name| name.strip_prefix(&prefix))
+        .filter(|year| year.len() == 4 && year.bytes().all(|b| b.is_ascii_digit()))
+        .filter_map(|year| year.parse().ok())
+        .collect();
+    years.sort_unstable();
+    years
+}
+
+/// Statements that recreate the materialized view over the tables of `years`. Every year is
+/// transformed to `srid` (that of the newest year), and the unique index on
+/// `(year, ogc_fid)` is what `REFRESH MATERIALIZED VIEW CONCURRENTLY` needs.
+fn create_materialized_view_statements(
+    table_prefix: &str,
+    years: &[u32],
+    srid: i32,
+) -> Vec<String> {
+    let view = postgres::pg_ident(&materialized_view_name(table_prefix));
+    let selects: Vec<String> = years
+        .iter()
+        .map(|year| {
+            format!(
+                "SELECT {year} AS year, ogc_fid, key_code::text AS key_code, pref_name::text AS pref_name, \
+                 city_name::text AS city_name, s_name::text AS s_name, jinko::integer AS jinko, \
+                 setai::integer AS setai, ST_Transform(geom, {srid}) AS geom FROM {}",
+                postgres::pg_ident(&areamap_table_name(table_prefix, *year))
+            )
+        })
+        .collect();
+    vec![
+        format!("DROP MATERIALIZED VIEW IF EXISTS {}", view),
+        format!(
+            "CREATE MATERIALIZED VIEW {} AS\n{}",
+            view,
+            selects.join("\nUNION ALL\n")
+        ),
+        format!("CREATE UNIQUE INDEX ON {} (year, ogc_fid)", view),
+        format!("CREATE INDEX ON {} USING GIST (geom)", view),
+    ]
+}
+
+/// (Re)creates the materialized view over every `{prefix}areamap_YYYY` table in the database.
+async fn create_materialized_view(
+    client: &tokio_postgres::Client,
+    table_prefix: &str,
+    view_name: &str,
+) -> Result<()> {
+    let rows = client
+        .query(
+            "SELECT table_name::text FROM information_schema.tables WHERE table_schema = current_schema()",
+            &[],
+        )
+        .await?;
+    let years = areamap_years(table_prefix, rows.iter().map(|row| row.get::<_, &str>(0)));
+    let Some(newest) = years.last() else {
+        bail!(
+            "no {}areamap_YYYY tables to build {} from",
+            table_prefix,
+            view_name
+        );
+    };
+    let newest_table = areamap_table_name(table_prefix, *newest);
+    let srid: Option<i32> = client
+        .query_opt(
+            &format!(
+                "SELECT ST_SRID(geom) FROM {} LIMIT 1",
+                postgres::pg_ident(&newest_table)
+            ),
+            &[],
+        )
+        .await?
+        .map(|row| row.get(0));
+    // ST_Transform(geom, 0) fails on every row, so there is nothing sensible to fall back to.
+    let srid = match srid {
+        None => bail!(
+            "{} has no rows to take the SRID of {} from",
+            newest_table,
+            view_name
+        ),
+        Some(0) => bail!(
+            "{} has geometries without an SRID; {} cannot be built from it",
+            newest_table,
+            view_name
+        ),
+        Some(srid) => srid,
+    };
+    for statement in create_materialized_view_statements(table_prefix, &years, srid) {
+        client
+            .execute(&statement, &[])
             .await
-            .with_context(|| format!("when creating VRT: {}", &vrt_path.display()))?;
-        gdal::load(
-            &vrt_path,
-            output,
-            output_format,
-            output_layer_name,
-            Some(AREAMAP_OGR2OGR_WHERE),
-            output_crs,
+            .with_context(|| format!("when creating {}", view_name))?;
+    }
+    println!(
+        "Created materialized view {} ({} years)",
+        view_name,
+        years.len()
+    );
+    Ok(())
+}
+
+/// `pg_matviews.ispopulated` of the materialized view, or `None` when it does not exist.
+async fn materialized_view_populated(
+    client: &tokio_postgres::Client,
+    view_name: &str,
+) -> Result<Option<bool>> {
+    Ok(client
+        .query_opt(
+            "SELECT ispopulated FROM pg_matviews WHERE schemaname = current_schema() AND matviewname = $1",
+            &[&view_name],
         )
-        .await
-        .with_context(|| format!("when loading VRT: {}", &vrt_path.display()))?;
-        pb.inc(1);
+        .await?
+        .map(|row| row.get(0)))
+}
+
+async fn update_materialized_view(
+    table_prefix: &str,
+    postgres_url: &str,
+    mode: MaterializedView,
+) -> Result<()> {
+    let view_name = materialized_view_name(table_prefix);
+    let client = postgres::connect(postgres_url).await?;
+    match mode {
+        MaterializedView::None => {}
+        MaterializedView::Create => {
+            create_materialized_view(&client, table_prefix, &view_name).await?;
+        }
+        MaterializedView::RecreateIfExists => {
+            if materialized_view_populated(&client, &view_name)
+                .await?
+                .is_some()
+            {
+                create_materialized_view(&client, table_prefix, &view_name).await?;
+            }
+        }
+        MaterializedView::Refresh => {
+            let Some(populated) = materialized_view_populated(&client, &view_name).await? else {
+                bail!(
+                    "materialized view {} does not exist; create it with --create-materialized-view",
+                    view_name
+                );
+            };
+            // CONCURRENTLY keeps the view readable, but PostgreSQL rejects it on a view that
+            // was never populated.
+            let concurrently = if populated { " CONCURRENTLY" } else { "" };
+            client
+                .execute(
+                    &format!(
+                        "REFRESH MATERIALIZED VIEW{} {}",
+                        concurrently,
+                        postgres::pg_ident(&view_name)
+                    ),
+                    &[],
+                )
+                .await
+                .with_context(|| format!("when refreshing {}", view_name))?;
+            println!("Refreshed materialized view {}", view_name);
+        }
     }
+    Ok(())
+}
+
+/// Whether shapefiles are checked with `ogrinfo` after all downloads, before any import
+/// (`areamap-prepare`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShapeValidation {
+    /// Import each year as soon as it is downloaded (`areamap`).
+    #[default]
+    None,
+    /// Import nothing unless every shapefile opens.
+    Strict,
+    /// Import the shapefiles that open and skip the rest (`--continue-on-validation-error`).
+    SkipInvalid,
+}
 
+/// Checks every shapefile with [`gdal::validate_shapefile`] and prints the result of each.
+/// Returns the files that failed with the reason.
+async fn validate_shapes(
+    items: &[DownloadedItem<ShapeUrlMeta>],
+    concurrency: usize,
+) -> Vec<(PathBuf, String)> {
+    let results: Vec<(PathBuf, Result<()>)> = stream::iter(items)
+        .map(|item| async move {
+            let path = item.extracted_path.clone();
+            let result = gdal::validate_shapefile(&path).await;
+            (path, result)
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+    let mut failed = Vec::new();
+    for (path, result) in results {
+        match result {
+            Ok(()) => println!("ok: {}", path.display()),
+            Err(e) => {
+                println!("FAIL: {}: {:#}", path.display(), e);
+                failed.push((path, format!("{:#}", e)));
+            }
+        }
+    }
+    println!(
+        "{} of {} shapefiles passed validation",
+        items.len() - failed.len(),
+        items.len()
+    );
+    failed
+}
+
+/// `areamap-prepare`: downloads every year first, validates all shapefiles, and only then
+/// builds the VRTs and imports. With [`ShapeValidation::SkipInvalid`] the failed files are
+/// left out of the VRTs; otherwise any failure stops before the first import.
+#[allow(clippy::too_many_arguments)]
+async fn download_validate_and_import_shapes(
+    http_client: &reqwest::Client,
+    table_prefix: &str,
+    target_serveys: &[DlServey<'static>],
+    prefectures: &[&'static str],
+    target: ImportTarget,
+    tmp_dir: &Path,
+    concurrency: usize,
+    import_concurrency: usize,
+    before_import: Option<(&str, &str)>,
+    validation: ShapeValidation,
+) -> Result<Vec<DownloadedItem<ShapeUrlMeta>>> {
+    let downloaded: Vec<DownloadedItem<ShapeUrlMeta>> = download::into_result(
+        download::download_and_extract_all(
+            http_client,
+            stream::iter(get_all_shape_urls(target_serveys, prefectures)),
+            |meta| meta.url.clone(),
+            archive_filename,
+            "shp",
+            tmp_dir,
+            "Downloading Shapes...",
+            "Extracting Shapes...",
+            concurrency,
+        )
+        .await?,
+    )
+    .context("when downloading and extracting shapes")?;
+
+    let failed = validate_shapes(&downloaded, concurrency).await;
+    if !failed.is_empty() && validation != ShapeValidation::SkipInvalid {
+        bail!(
+            "{} of {} shapefiles failed validation; nothing was imported (use --continue-on-validation-error to import the rest)",
+            failed.len(),
+            downloaded.len()
+        );
+    }
+    if !failed.is_empty() {
+        eprintln!(
+            "Warning: skipping {} shapefiles that failed validation",
+            failed.len()
+        );
+    }
+
+    if let Some((postgres_url, sql)) = before_import {
+        let client = postgres::connect(postgres_url).await?;
+        postgres::run_sql_hook(&client, "before-import", sql).await?;
+    }
+
+    let target = Arc::new(target);
+    let mut imports: JoinSet<Result<()>> = JoinSet::new();
+    for servey in target_serveys {
+        let shapes: Vec<PathBuf> = downloaded
+            .iter()
+            .filter(|item| item.metadata.dlservey.year == servey.year)
+            .map(|item| item.extracted_path.clone())
+            .filter(|path| failed.iter().all(|(failed, _)| failed != path))
+            .collect();
+        if shapes.is_empty() {
+            println!(
+                "No valid shapes for year {}, skipping VRT creation and import.",
+                servey.year
+            );
+            continue;
+        }
+        while imports.len() >= import_concurrency {
+            if let Some(result) = imports.join_next().await {
+                result??;
+            }
+        }
+        let layer_name = areamap_table_name(table_prefix, servey.year);
+        let vrt_path = tmp_dir.join(format!("{}.vrt", layer_name));
+        imports.spawn(import_year(target.clone(), vrt_path, shapes, layer_name));
+    }
+    while let Some(result) = imports.join_next().await {
+        result??;
+    }
     println!("All imports completed.");
-    Ok(())
+    Ok(downloaded)
 }
 
 fn as_postgres_url<'a>(output: &'a str, output_format: Option<&str>) -> Option<&'a str> {
@@ -210,6 +821,7 @@ fn as_postgres_url<'a>(output: &'a str, output_format: Option<&str>) -> Option<&
 }
 
 async fn insert_postgres_metadata(
+    table_prefix: &str,
     postgres_url: &str,
     target_serveys: &[DlServey<'static>],
     output_crs: Option<&str>,
@@ -225,17 +837,17 @@ async fn insert_postgres_metadata(
 
     km_to_sql::postgres::init_schema(&client).await?;
 
-    if let Some(crs) = output_crs {
-        if parse_output_srid(crs).is_none() {
-            println!(
-                "Warning: could not infer EPSG SRID from --output-crs='{}'. PostgreSQL metadata will use geometry(polygon) without SRID.",
-                crs
-            );
-        }
+    if let Some(crs) = output_crs
+        && parse_output_srid(crs).is_none()
+    {
+        println!(
+            "Warning: could not infer EPSG SRID from --output-crs='{}'. PostgreSQL metadata will use geometry(polygon) without SRID.",
+            crs
+        );
     }
 
     for servey in target_serveys.iter() {
-        let table_name = format!("jp_estat_areamap_{}", servey.year);
+        let table_name = areamap_table_name(table_prefix, servey.year);
         let geom_data_type = metadata_geom_data_type(servey, output_crs);
 
         let columns: Vec<ColumnMetadata> = vec![
@@ -345,10 +957,10 @@ fn parse_output_srid(output_crs: &str) -> Option<i32> {
                 .chars()
                 .take_while(|c| c.is_ascii_digit())
                 .collect::<String>();
-            if !digits.is_empty() {
-                if let Ok(srid) = digits.parse::<i32>() {
-                    return Some(srid);
-                }
+            if !digits.is_empty()
+                && let Ok(srid) = digits.parse::<i32>()
+            {
+                return Some(srid);
             }
         }
     }
@@ -362,18 +974,62 @@ fn metadata_geom_data_type(servey: &DlServey<'_>, output_crs: Option<&str>) -> S
             Some(srid) => format!("geometry(polygon, {})", srid),
             None => "geometry(polygon)".to_string(),
         },
-        None => format!("geometry(polygon, {})", default_geom_srid(servey.datum)),
+        None => format!("geometry(polygon, {})", default_geom_srid(&servey.datum)),
     }
 }
 
+/// Outcome of [`process_areamap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AreamapImportReport {
+    /// Survey years that were imported.
+    pub years: Vec<u32>,
+    /// Number of extracted shapefile archives.
+    pub files: usize,
+    /// Whether the `jp_estat_` metadata tables were written (PostgreSQL outputs only).
+    pub metadata_written: bool,
+}
+
 pub async fn process_areamap(
-    output: &str,
-    output_format: Option<&str>,
-    output_crs: Option<&str>,
-    tmp_dir: &Path,
-    survey_year: Option<u32>,
-) -> Result<()> {
-    let target_serveys = get_target_serveys(survey_year)?;
+    http_client: &reqwest::Client,
+    config: &AreamapImportConfig,
+) -> Result<AreamapImportReport> {
+    let table_prefix = config.table_prefix.as_str();
+    let output = config.output.as_str();
+    let output_format = config.output_format.as_deref();
+    let output_crs = config.output_crs.as_deref();
+    let tmp_dir = config.tmp_dir.as_path();
+    let survey_year = config.year;
+    let years_range = config.years_range;
+    let surveys_file = config.surveys_file.as_deref();
+    let pref_codes = config.pref_codes.as_deref();
+    let pref_names = config.pref_names.as_deref();
+    let before_import_sql = config.before_import_sql.as_deref();
+    let after_import_sql = config.after_import_sql.as_deref();
+    let concurrency = config.concurrency;
+    let shape_validation = config.shape_validation;
+    let materialized_view = config.materialized_view;
+    let cleanup = config.cleanup;
+
+    let available = load_available_serveys(surveys_file)?;
+    let target_serveys = get_target_serveys(&available, survey_year, years_range)?;
+    let prefectures = target_prefectures(pref_codes, pref_names)?;
+    let postgres_url = as_postgres_url(output, output_format);
+    if postgres_url.is_none() && (before_import_sql.is_some() || after_import_sql.is_some()) {
+        bail!("--before-import-sql / --after-import-sql require a PostgreSQL output");
+    }
+    if postgres_url.is_none() && materialized_view != MaterializedView::None {
+        bail!(
+            "--create-materialized-view / --refresh-materialized-view require a PostgreSQL output"
+        );
+    }
+    let before_sql = match before_import_sql {
+        Some(path) => Some(postgres::read_sql_file(path).await?),
+        None => None,
+    };
+    let after_sql = match after_import_sql {
+        Some(path) => Some(postgres::read_sql_file(path).await?),
+        None => None,
+    };
     let single_layer_output = is_single_layer_output(output, output_format);
     if single_layer_output && target_serveys.len() > 1 {
         bail!(
@@ -391,52 +1047,274 @@ pub async fn process_areamap(
     gdal::ensure_available()
         .await
         .with_context(|| "when checking GDAL availability with `ogrinfo --version`")?;
+    let gdal_version = gdal::check_ogr2ogr_available().await?;
+    println!("Using {}", gdal_version);
 
-    // 1. Get URLs and metadata
-    let shape_url_metas = get_all_shape_urls(&target_serveys);
-
-    // 2. Download all shapes and unzip them using the generic function
-    let downloaded_items: Vec<DownloadedItem<ShapeUrlMeta>> = download::download_and_extract_all(
-        stream::iter(shape_url_metas),
-        |meta| meta.url.clone(),
-        |meta| format!("{}-{}.zip", meta.dlservey.year, meta.pref_code),
-        "shp", // Target extension is .shp
-        tmp_dir,
-        "Downloading Shapes...",
-        "Extracting Shapes...",
-        10, // Concurrency level
-    )
-    .await
-    .with_context(|| format!("when downloading and extracting shapes"))?;
-
-    // 3. Import the shapefiles using ogr2ogr
-    import_shapes(
-        downloaded_items,
-        &target_serveys,
-        output,
-        output_format,
-        output_layer_name.as_deref(),
-        output_crs,
-        tmp_dir,
-    )
-    .await
-    .with_context(|| format!("when importing to ogr2ogr"))?;
+    // 1-3. Download the shapes and import them with ogr2ogr, one year at a time. Each year
+    // goes to its own table, so PostgreSQL imports can run side by side; file datasources
+    // (GPKG etc.) accept a single writer, so those imports stay sequential.
+    let import_concurrency = if postgres_url.is_some() {
+        concurrency
+    } else {
+        1
+    };
+    let target = ImportTarget {
+        output: output.to_string(),
+        output_format: output_format.map(str::to_string),
+        output_layer_name,
+        output_crs: output_crs.map(str::to_string),
+    };
+    let before_import = postgres_url.zip(before_sql.as_deref());
+    let downloaded_items = if shape_validation == ShapeValidation::None {
+        download_and_import_shapes(
+            http_client,
+            table_prefix,
+            &target_serveys,
+            &prefectures,
+            target,
+            tmp_dir,
+            concurrency,
+            import_concurrency,
+            before_import,
+        )
+        .await
+    } else {
+        download_validate_and_import_shapes(
+            http_client,
+            table_prefix,
+            &target_serveys,
+            &prefectures,
+            target,
+            tmp_dir,
+            concurrency,
+            import_concurrency,
+            before_import,
+            shape_validation,
+        )
+        .await
+    }
+    .with_context(|| "when importing to ogr2ogr")?;
+    memory_report::record("import");
+
+    // The VRTs reference every extracted shapefile, so cleanup waits until all years are loaded.
+    if cleanup {
+        for item in &downloaded_items {
+            download::cleanup_extracted(item)?;
+        }
+    }
 
     // 4. For PostgreSQL outputs, insert metadata
-    if let Some(postgres_url) = as_postgres_url(output, output_format) {
-        insert_postgres_metadata(postgres_url, &target_serveys, output_crs).await?;
+    if let Some(postgres_url) = postgres_url {
+        insert_postgres_metadata(table_prefix, postgres_url, &target_serveys, output_crs).await?;
+        update_materialized_view(table_prefix, postgres_url, materialized_view).await?;
+        if let Some(sql) = after_sql.as_deref() {
+            let client = postgres::connect(postgres_url).await?;
+            postgres::run_sql_hook(&client, "after-import", sql).await?;
+        }
     } else {
         println!(
             "PostgreSQL metadata insertion was skipped because output is not a PostgreSQL datasource."
         );
     }
 
-    Ok(())
+    Ok(AreamapImportReport {
+        years: target_serveys.iter().map(|servey| servey.year).collect(),
+        files: downloaded_items.len(),
+        metadata_written: postgres_url.is_some(),
+    })
+}
+
+/// Whether the table (or layer, or single-layer file) of `year` is already in `output`.
+async fn year_exists(
+    table_prefix: &str,
+    output: &str,
+    output_format: Option<&str>,
+    year: u32,
+) -> Result<bool> {
+    let table_name = areamap_table_name(table_prefix, year);
+    if let Some(postgres_url) = as_postgres_url(output, output_format) {
+        let client = postgres::connect(postgres_url).await?;
+        return Ok(client
+            .query_one("SELECT to_regclass($1) IS NOT NULL", &[&table_name])
+            .await?
+            .get(0));
+    }
+    if is_single_layer_output(output, output_format) {
+        return Ok(Path::new(output).exists());
+    }
+    gdal::layer_exists(output, &table_name).await
+}
+
+/// Imports one more survey year into an existing installation (`areamap-add-year`), leaving
+/// the tables of the other years alone. The year filters of `config` are replaced by `year`.
+/// Returns `None` when the year is already there and `skip_existing` is set.
+pub async fn process_areamap_add_year(
+    http_client: &reqwest::Client,
+    config: &AreamapImportConfig,
+    year: u32,
+    skip_existing: bool,
+) -> Result<Option<AreamapImportReport>> {
+    let table_prefix = config.table_prefix.as_str();
+    let output = config.output.as_str();
+    // Fail on an unknown year before connecting anywhere.
+    get_target_serveys(
+        &load_available_serveys(config.surveys_file.as_deref())?,
+        Some(year),
+        None,
+    )?;
+    gdal::ensure_available()
+        .await
+        .with_context(|| "when checking GDAL availability with `ogrinfo --version`")?;
+    if year_exists(table_prefix, output, config.output_format.as_deref(), year).await? {
+        let table_name = areamap_table_name(table_prefix, year);
+        if skip_existing {
+            eprintln!(
+                "Warning: {} already exists in {}, skipping {}",
+                table_name, output, year
+            );
+            return Ok(None);
+        }
+        bail!(
+            "{} already exists in {}; pass --skip-existing to leave it, or use `areamap --year {}` to import it again",
+            table_name,
+            output,
+            year
+        );
+    }
+
+    let config = config
+        .clone()
+        .with_year(Some(year))
+        .with_years_range(None)
+        .with_materialized_view(MaterializedView::RecreateIfExists);
+    let report = process_areamap(http_client, &config).await?;
+    println!(
+        "Added {} ({} files)",
+        areamap_table_name(table_prefix, year),
+        report.files
+    );
+    Ok(Some(report))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{is_single_layer_output, output_layer_name_from_destination, parse_output_srid};
+    use super::{
+        DL_SERVEY_IDS, areamap_years, create_materialized_view_statements, get_target_serveys,
+        is_single_layer_output, load_available_serveys, output_layer_name_from_destination,
+        parse_output_srid, parse_surveys, parse_years_range, pref_name_to_code, target_prefectures,
+        validate_shapes,
+    };
+
+    #[tokio::test]
+    async fn reports_shapefiles_that_fail_validation() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        // Truncated: ogrinfo cannot open it (and the check also fails without GDAL).
+        let shp = dir.join("h27ka13.shp");
+        std::fs::write(&shp, b"\0\0\x27\x0a").unwrap();
+        let items: Vec<_> = super::get_all_shape_urls(&DL_SERVEY_IDS[..1], &["13"])
+            .into_iter()
+            .map(|metadata| crate::download::DownloadedItem {
+                metadata,
+                extracted_path: shp.clone(),
+                extracted_dir: dir.to_path_buf(),
+            })
+            .collect();
+        let failed = validate_shapes(&items, 2).await;
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, shp);
+    }
+
+    #[tokio::test]
+    async fn add_year_rejects_unknown_years_and_checks_single_layer_files() {
+        let config = crate::AreamapImportConfig::new("./does-not-exist.gpkg").with_concurrency(1);
+        let err = super::process_areamap_add_year(&reqwest::Client::new(), &config, 1999, false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Unsupported survey year: 1999"));
+
+        let tmp = tempfile::tempdir().unwrap();
+        let missing = tmp.path().join("missing.geojson");
+        assert!(
+            !super::year_exists("jp_estat_", missing.to_str().unwrap(), None, 2020)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn combines_prefecture_codes_and_names() {
+        assert_eq!(pref_name_to_code("東京都"), Some("13"));
+        assert_eq!(pref_name_to_code("北海道"), Some("01"));
+        assert_eq!(pref_name_to_code("東京"), None);
+        assert_eq!(target_prefectures(None, None).unwrap().len(), 47);
+        let codes = ["27".to_string(), "13".to_string()];
+        let names = ["東京都".to_string(), "沖縄県".to_string()];
+        assert_eq!(
+            target_prefectures(Some(&codes), Some(&names)).unwrap(),
+            vec!["13", "27", "47"]
+        );
+        assert!(target_prefectures(Some(&["48".to_string()]), None).is_err());
+        assert!(target_prefectures(None, Some(&["大阪".to_string()])).is_err());
+    }
+
+    #[test]
+    fn expands_years_range_to_survey_years() {
+        assert_eq!(parse_years_range("2000-2020").unwrap(), (2000, 2020));
+        let years = |range: &str| -> Vec<u32> {
+            get_target_serveys(
+                &DL_SERVEY_IDS,
+                None,
+                Some(parse_years_range(range).unwrap()),
+            )
+            .unwrap()
+            .iter()
+            .map(|servey| servey.year)
+            .collect()
+        };
+        assert_eq!(years("2000-2020"), vec![2020, 2015, 2010, 2005, 2000]);
+        assert_eq!(years("2008-2016"), vec![2015, 2010]);
+        assert!(get_target_serveys(&DL_SERVEY_IDS, None, Some((2021, 2030))).is_err());
+        assert!(parse_years_range("2020-2000").is_err());
+        assert!(parse_years_range("2020").is_err());
+    }
+
+    #[test]
+    fn merges_surveys_file_by_year() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("areamap_surveys.json");
+        std::fs::write(
+            &path,
+            r#"[
+                { "year": 2025, "id": "A002005212025", "datum": "2011" },
+                { "year": 2020, "id": "A002005212020X", "datum": "2011" }
+            ]"#,
+        )
+        .unwrap();
+        let serveys = load_available_serveys(Some(&path)).unwrap();
+        let years: Vec<u32> = serveys.iter().map(|servey| servey.year).collect();
+        assert_eq!(years, vec![2025, 2020, 2015, 2010, 2005, 2000]);
+        assert_eq!(serveys[1].id, "A002005212020X");
+        let target = get_target_serveys(&serveys, Some(2025), None).unwrap();
+        assert_eq!(target[0].id, "A002005212025");
+        assert_eq!(
+            load_available_serveys(None).unwrap(),
+            DL_SERVEY_IDS.to_vec()
+        );
+
+        let err = parse_surveys(r#"[{ "year": 2025, "id": "A002005212025", "datum": "2024" }]"#)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("unknown datum \"2024\""));
+        assert!(
+            parse_surveys(
+                r#"[{ "year": 2025, "id": "A1", "datum": "2011" },
+                    { "year": 2025, "id": "A2", "datum": "2011" }]"#
+            )
+            .is_err()
+        );
+        assert!(parse_surveys(r#"[{ "year": 2025, "id": "A&B", "datum": "2011" }]"#).is_err());
+    }
 
     #[test]
     fn detects_single_layer_by_extension() {
@@ -474,4 +1352,37 @@ mod tests {
         assert_eq!(parse_output_srid("epsg:3857"), Some(3857));
         assert_eq!(parse_output_srid("CRS84"), None);
     }
+
+    #[test]
+    fn builds_materialized_view_over_every_year_table() {
+        let tables = [
+            "jp_estat_areamap_2020",
+            "jp_estat_areamap_2015",
+            "jp_estat_areamap_all",
+            "jp_estat_areamap_diff_2015_2020",
+            "jp_estat_mesh_2020_3",
+            "other_areamap_2010",
+        ];
+        let years = areamap_years("jp_estat_", tables.into_iter());
+        assert_eq!(years, [2015, 2020]);
+
+        let statements = create_materialized_view_statements("jp_estat_", &years, 6668);
+        assert_eq!(
+            statements[0],
+            "DROP MATERIALIZED VIEW IF EXISTS \"jp_estat_areamap_all\""
+        );
+        let create = &statements[1];
+        assert!(create.starts_with(
+            "CREATE MATERIALIZED VIEW \"jp_estat_areamap_all\" AS\nSELECT 2015 AS year"
+        ));
+        assert!(create.contains("ST_Transform(geom, 6668) AS geom FROM \"jp_estat_areamap_2015\"\nUNION ALL\nSELECT 2020 AS year"));
+        assert!(create.ends_with("FROM \"jp_estat_areamap_2020\""));
+        assert_eq!(
+            statements[2..],
+            [
+                "CREATE UNIQUE INDEX ON \"jp_estat_areamap_all\" (year, ogc_fid)",
+                "CREATE INDEX ON \"jp_estat_areamap_all\" USING GIST (geom)",
+            ]
+        );
+    }
 }
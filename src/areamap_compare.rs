@@ -0,0 +1,133 @@
+use crate::{areamap::areamap_table_name, postgres};
+use anyhow::{Context, Result, bail};
+
+/// Builds the statements that (re)create `output_table` from the boundaries of two years.
+///
+/// Polygons are dissolved by `key_code` first, since one small area can be split into several
+/// polygons in the source shapefiles, and year B is transformed to the SRID of year A. For
+/// areas in both years `geom` is the changed part (`ST_Union` minus `ST_Intersection`) and
+/// `overlap_fraction` is the intersection area over the union area. `change_type` is one of
+/// `only_in_a`, `only_in_b`, `changed` or `unchanged`; areas are geodesic km².
+fn build_compare_statements(
+    table_prefix: &str,
+    year_a: u32,
+    year_b: u32,
+    output_table: &str,
+) -> String {
+    let table_a = postgres::pg_ident(&areamap_table_name(table_prefix, year_a));
+    let table_b = postgres::pg_ident(&areamap_table_name(table_prefix, year_b));
+    let output_table = postgres::pg_ident(output_table);
+    format!(
+        r#"BEGIN;
+DROP TABLE IF EXISTS {output_table};
+CREATE TABLE {output_table} AS
+WITH a AS (
+    SELECT key_code::text AS key_code, ST_Union(geom) AS geom
+    FROM {table_a}
+    GROUP BY key_code
+), b AS (
+    SELECT
+        key_code::text AS key_code,
+        ST_Transform(ST_Union(geom), (SELECT ST_SRID(geom) FROM {table_a} LIMIT 1)) AS geom
+    FROM {table_b}
+    GROUP BY key_code
+), pairs AS (
+    SELECT
+        COALESCE(a.key_code, b.key_code) AS key_code,
+        a.geom AS geom_a,
+        b.geom AS geom_b,
+        ST_Intersection(a.geom, b.geom) AS geom_overlap,
+        ST_Union(a.geom, b.geom) AS geom_union
+    FROM a
+    FULL OUTER JOIN b ON a.key_code = b.key_code
+)
+SELECT
+    key_code,
+    CASE
+        WHEN geom_b IS NULL THEN 'only_in_a'
+        WHEN geom_a IS NULL THEN 'only_in_b'
+        WHEN ST_Equals(geom_a, geom_b) THEN 'unchanged'
+        ELSE 'changed'
+    END AS change_type,
+    CASE
+        WHEN geom_b IS NULL THEN geom_a
+        WHEN geom_a IS NULL THEN geom_b
+        ELSE ST_Difference(geom_union, geom_overlap)
+    END AS geom,
+    ST_Area(geom_a::geography) / 1000000.0 AS area_a_km2,
+    ST_Area(geom_b::geography) / 1000000.0 AS area_b_km2,
+    ST_Area(geom_overlap::geography) / NULLIF(ST_Area(geom_union::geography), 0) AS overlap_fraction
+FROM pairs
+ORDER BY key_code;
+CREATE INDEX ON {output_table} USING GIST (geom);
+COMMIT;"#
+    )
+}
+
+pub async fn process_areamap_compare(
+    table_prefix: &str,
+    postgres_url: &str,
+    year_a: u32,
+    year_b: u32,
+    output_table: &str,
+) -> Result<()> {
+    if year_a == year_b {
+        bail!("--year-a and --year-b must be different years");
+    }
+    let client = postgres::connect(postgres_url).await?;
+    client
+        .batch_execute(&build_compare_statements(
+            table_prefix,
+            year_a,
+            year_b,
+            output_table,
+        ))
+        .await
+        .with_context(|| {
+            format!(
+                "when comparing the boundaries of {} and {}",
+                areamap_table_name(table_prefix, year_a),
+                areamap_table_name(table_prefix, year_b)
+            )
+        })?;
+
+    let rows = client
+        .query(
+            &format!(
+                "SELECT change_type, COUNT(*) FROM {} GROUP BY 1 ORDER BY 1",
+                postgres::pg_ident(output_table)
+            ),
+            &[],
+        )
+        .await?;
+    let counts: Vec<String> = rows
+        .iter()
+        .map(|row| format!("{}: {}", row.get::<_, String>(0), row.get::<_, i64>(1)))
+        .collect();
+    println!("Wrote {} ({})", output_table, counts.join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_matched_key_codes_of_both_years() {
+        let sql = build_compare_statements("jp_estat_", 2015, 2020, "areamap_changes");
+        assert!(sql.starts_with("BEGIN;\nDROP TABLE IF EXISTS \"areamap_changes\";"));
+        assert!(sql.contains("FROM \"jp_estat_areamap_2015\"\n    GROUP BY key_code"));
+        assert!(sql.contains("FROM \"jp_estat_areamap_2020\"\n    GROUP BY key_code"));
+        assert!(sql.contains("FULL OUTER JOIN b ON a.key_code = b.key_code"));
+        for column in [
+            "AS change_type",
+            "AS geom,",
+            "AS area_a_km2",
+            "AS area_b_km2",
+            "AS overlap_fraction",
+        ] {
+            assert!(sql.contains(column), "{}", column);
+        }
+        assert!(sql.trim_end().ends_with("COMMIT;"));
+    }
+}
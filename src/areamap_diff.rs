@@ -0,0 +1,176 @@
+use crate::{areamap::areamap_table_name, postgres};
+use anyhow::{Context, Result, bail};
+use csv::WriterBuilder;
+use std::{io::Write, path::Path};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffOutputFormat {
+    /// `CREATE OR REPLACE VIEW` 文を出力
+    Sql,
+    /// 差分を CSV として出力
+    Csv,
+}
+
+const CSV_HEADERS: [&str; 5] = [
+    "key_code",
+    "change_type",
+    "year_a_jinko",
+    "year_b_jinko",
+    "delta_jinko",
+];
+
+fn diff_view_name(table_prefix: &str, year_a: u32, year_b: u32) -> String {
+    format!("{}areamap_diff_{}_{}", table_prefix, year_a, year_b)
+}
+
+/// Builds the comparison query. Rows are aggregated by `key_code` first because a single
+/// small area can be split into several polygons in the source shapefiles.
+///
+/// `change_type` is one of `only_in_a`, `only_in_b` or `changed` (`jinko` or `setai` differ).
+fn build_diff_query(table_prefix: &str, year_a: u32, year_b: u32) -> String {
+    let table_a = postgres::pg_ident(&areamap_table_name(table_prefix, year_a));
+    let table_b = postgres::pg_ident(&areamap_table_name(table_prefix, year_b));
+    format!(
+        r#"WITH a AS (
+    SELECT key_code, SUM(jinko)::bigint AS jinko, SUM(setai)::bigint AS setai
+    FROM {table_a}
+    GROUP BY key_code
+), b AS (
+    SELECT key_code, SUM(jinko)::bigint AS jinko, SUM(setai)::bigint AS setai
+    FROM {table_b}
+    GROUP BY key_code
+)
+SELECT
+    COALESCE(a.key_code, b.key_code)::text AS key_code,
+    CASE
+        WHEN b.key_code IS NULL THEN 'only_in_a'
+        WHEN a.key_code IS NULL THEN 'only_in_b'
+        ELSE 'changed'
+    END AS change_type,
+    a.jinko AS year_a_jinko,
+    b.jinko AS year_b_jinko,
+    b.jinko - a.jinko AS delta_jinko
+FROM a
+FULL OUTER JOIN b ON a.key_code = b.key_code
+WHERE a.key_code IS NULL
+    OR b.key_code IS NULL
+    OR a.jinko IS DISTINCT FROM b.jinko
+    OR a.setai IS DISTINCT FROM b.setai
+ORDER BY 1"#
+    )
+}
+
+fn build_view_definition(table_prefix: &str, year_a: u32, year_b: u32) -> String {
+    format!(
+        "CREATE OR REPLACE VIEW {} AS\n{};\n",
+        postgres::pg_ident(&diff_view_name(table_prefix, year_a, year_b)),
+        build_diff_query(table_prefix, year_a, year_b)
+    )
+}
+
+fn open_output(output: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match output {
+        Some(path) => Box::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("when creating {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    })
+}
+
+async fn write_diff_csv(
+    table_prefix: &str,
+    postgres_url: &str,
+    year_a: u32,
+    year_b: u32,
+    writer: Box<dyn Write>,
+) -> Result<usize> {
+    let client = postgres::connect(postgres_url).await?;
+    let rows = client
+        .query(&build_diff_query(table_prefix, year_a, year_b), &[])
+        .await
+        .with_context(|| {
+            format!(
+                "when comparing {} and {}",
+                areamap_table_name(table_prefix, year_a),
+                areamap_table_name(table_prefix, year_b)
+            )
+        })?;
+
+    let mut writer = WriterBuilder::new().from_writer(writer);
+    writer.write_record(CSV_HEADERS)?;
+    let format_opt = |v: Option<i64>| v.map(|v| v.to_string()).unwrap_or_default();
+    for row in &rows {
+        let key_code: String = row.get(0);
+        let change_type: String = row.get(1);
+        writer.write_record([
+            key_code,
+            change_type,
+            format_opt(row.get(2)),
+            format_opt(row.get(3)),
+            format_opt(row.get(4)),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(rows.len())
+}
+
+pub async fn process_areamap_diff(
+    table_prefix: &str,
+    postgres_url: Option<&str>,
+    year_a: u32,
+    year_b: u32,
+    output_format: DiffOutputFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    if year_a == year_b {
+        bail!("--year-a and --year-b must be different years");
+    }
+
+    match output_format {
+        DiffOutputFormat::Sql => {
+            let mut writer = open_output(output)?;
+            writer.write_all(build_view_definition(table_prefix, year_a, year_b).as_bytes())?;
+            writer.flush()?;
+        }
+        DiffOutputFormat::Csv => {
+            let Some(postgres_url) = postgres_url else {
+                bail!("--postgres-url is required for --output-format csv");
+            };
+            let rows = write_diff_csv(
+                table_prefix,
+                postgres_url,
+                year_a,
+                year_b,
+                open_output(output)?,
+            )
+            .await?;
+            if output.is_some() {
+                println!("Wrote {} changed areas.", rows);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_definition_references_both_years() {
+        let sql = build_view_definition("jp_estat_", 2015, 2020);
+        assert!(sql.starts_with("CREATE OR REPLACE VIEW \"jp_estat_areamap_diff_2015_2020\""));
+        assert!(sql.contains("FROM \"jp_estat_areamap_2015\""));
+        assert!(sql.contains("FROM \"jp_estat_areamap_2020\""));
+        assert!(sql.contains("FULL OUTER JOIN b ON a.key_code = b.key_code"));
+    }
+
+    #[tokio::test]
+    async fn csv_output_requires_postgres_url() {
+        let err = process_areamap_diff("jp_estat_", None, 2015, 2020, DiffOutputFormat::Csv, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("--postgres-url"));
+    }
+}
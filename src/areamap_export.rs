@@ -0,0 +1,141 @@
+use crate::{areamap::areamap_table_name, postgres};
+use anyhow::{Context, Result, bail};
+use futures::{StreamExt as _, pin_mut};
+use serde_json::{Value, json};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use tokio_postgres::types::ToSql;
+
+/// Builds the export query. `$1` is the prefecture filter (matched against both the `pref`
+/// code and `pref_name` columns) and `$2` the simplification tolerance, when present.
+///
+/// Geometries are reprojected to WGS84 as required by GeoJSON (RFC 7946), except when the
+/// column has no SRID and cannot be transformed.
+fn build_export_query(table_name: &str, filter_pref: bool, simplify: bool) -> String {
+    let (pref_param, tolerance_param) = match (filter_pref, simplify) {
+        (true, true) => ("$1", "$2"),
+        (true, false) => ("$1", ""),
+        (false, true) => ("", "$1"),
+        (false, false) => ("", ""),
+    };
+    let geom = if simplify {
+        format!("ST_Simplify(t.geom, {})", tolerance_param)
+    } else {
+        "t.geom".to_string()
+    };
+    let where_clause = if filter_pref {
+        format!(
+            "\nWHERE t.pref::text = ANY({p}) OR t.pref_name = ANY({p})",
+            p = pref_param
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        r#"SELECT
+    t.ogc_fid,
+    ST_AsGeoJSON(CASE WHEN ST_SRID({geom}) = 0 THEN {geom} ELSE ST_Transform({geom}, 4326) END)::jsonb AS geometry,
+    to_jsonb(t) - 'geom' - 'ogc_fid' AS properties
+FROM {table} t{where_clause}
+ORDER BY t.ogc_fid"#,
+        table = postgres::pg_ident(table_name)
+    )
+}
+
+pub async fn process_areamap_export_geojson(
+    table_prefix: &str,
+    postgres_url: &str,
+    year: u32,
+    output: &Path,
+    pref_codes: Option<&[String]>,
+    simplify_tolerance: Option<f64>,
+) -> Result<()> {
+    if let Some(tolerance) = simplify_tolerance
+        && !(tolerance.is_finite() && tolerance >= 0.0)
+    {
+        bail!("--simplify-tolerance must be a non-negative number");
+    }
+
+    let table_name = areamap_table_name(table_prefix, year);
+    let pref_codes: Option<Vec<String>> = pref_codes.map(|codes| codes.to_vec());
+    let sql = build_export_query(
+        &table_name,
+        pref_codes.is_some(),
+        simplify_tolerance.is_some(),
+    );
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    if let Some(codes) = pref_codes.as_ref() {
+        params.push(codes);
+    }
+    if let Some(tolerance) = simplify_tolerance.as_ref() {
+        params.push(tolerance);
+    }
+
+    let client = postgres::connect(postgres_url).await?;
+    let rows = client
+        .query_raw(&sql, params)
+        .await
+        .with_context(|| format!("when querying {}", table_name))?;
+    pin_mut!(rows);
+
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let file =
+        File::create(output).with_context(|| format!("when creating {}", output.display()))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(br#"{"type":"FeatureCollection","features":["#)?;
+
+    let mut count = 0usize;
+    while let Some(row) = rows.next().await {
+        let row = row.with_context(|| format!("when reading {}", table_name))?;
+        let id: i32 = row.get(0);
+        let geometry: Option<Value> = row.get(1);
+        let properties: Value = row.get(2);
+        if count > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(b"\n")?;
+        serde_json::to_writer(
+            &mut writer,
+            &json!({
+                "type": "Feature",
+                "id": id,
+                "geometry": geometry,
+                "properties": properties,
+            }),
+        )?;
+        count += 1;
+    }
+
+    writer.write_all(b"\n]}\n")?;
+    writer.flush()?;
+
+    println!("Exported {} features to {}", count, output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_parameters_by_enabled_options() {
+        let sql = build_export_query("jp_estat_areamap_2020", true, true);
+        assert!(sql.contains("ST_Simplify(t.geom, $2)"));
+        assert!(sql.contains("t.pref_name = ANY($1)"));
+
+        let sql = build_export_query("jp_estat_areamap_2020", false, true);
+        assert!(sql.contains("ST_Simplify(t.geom, $1)"));
+        assert!(!sql.contains("WHERE"));
+
+        let sql = build_export_query("jp_estat_areamap_2020", false, false);
+        assert!(sql.contains("FROM \"jp_estat_areamap_2020\" t\nORDER BY"));
+        assert!(!sql.contains("$"));
+    }
+}
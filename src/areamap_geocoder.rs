@@ -0,0 +1,122 @@
+use crate::{areamap::areamap_table_name, postgres};
+use anyhow::{Context, Result, bail};
+
+/// The searched text. Kept to `||` and `coalesce` so the expression is immutable and can be
+/// indexed; the `simple` configuration does no stemming, which suits Japanese names.
+const SEARCH_DOCUMENT: &str = "to_tsvector('simple', coalesce(pref_name, '') || ' ' || coalesce(city_name, '') || ' ' || coalesce(s_name, ''))";
+
+fn index_name(table_name: &str) -> String {
+    format!("{}_geocoder_idx", table_name)
+}
+
+/// GIN index on [`SEARCH_DOCUMENT`], so the search does not scan every small area.
+fn build_index_statement(table_name: &str) -> String {
+    format!(
+        "CREATE INDEX IF NOT EXISTS {} ON {} USING GIN ({})",
+        postgres::pg_ident(&index_name(table_name)),
+        postgres::pg_ident(table_name),
+        SEARCH_DOCUMENT
+    )
+}
+
+/// `$1` is the `to_tsquery` text and `$2` the row limit. Polygons of the same `key_code`
+/// are summed, as in `diff`.
+fn build_search_query(table_name: &str) -> String {
+    format!(
+        r#"SELECT
+    key_code::text AS key_code,
+    concat_ws(' ', pref_name, city_name, s_name) AS name,
+    SUM(jinko)::bigint AS jinko,
+    SUM(setai)::bigint AS setai
+FROM {table}
+WHERE {SEARCH_DOCUMENT} @@ to_tsquery('simple', $1)
+GROUP BY 1, 2
+ORDER BY MAX(ts_rank({SEARCH_DOCUMENT}, to_tsquery('simple', $1))) DESC, 1
+LIMIT $2"#,
+        table = postgres::pg_ident(table_name)
+    )
+}
+
+/// Turns whitespace-separated words into a prefix query that requires all of them, e.g.
+/// `千代田区 丸の内` into `'千代田区':* & '丸の内':*`. Words are quoted so `&`, `:` and
+/// other operators in the input are searched for literally.
+fn build_tsquery(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("'{}':*", term.replace('\\', "\\\\").replace('\'', "''")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" & "))
+}
+
+pub async fn process_areamap_geocoder(
+    table_prefix: &str,
+    postgres_url: &str,
+    query: &str,
+    year: u32,
+    limit: i64,
+) -> Result<()> {
+    let Some(tsquery) = build_tsquery(query) else {
+        bail!("the query is empty");
+    };
+    let table_name = areamap_table_name(table_prefix, year);
+    let client = postgres::connect(postgres_url).await?;
+    client
+        .batch_execute(&build_index_statement(&table_name))
+        .await
+        .with_context(|| format!("when indexing {} for search", table_name))?;
+
+    let rows = client
+        .query(&build_search_query(&table_name), &[&tsquery, &limit])
+        .await
+        .with_context(|| format!("when searching {}", table_name))?;
+    if rows.is_empty() {
+        bail!("no areas in {} match \"{}\"", table_name, query);
+    }
+    let format_opt = |v: Option<i64>| v.map(|v| v.to_string()).unwrap_or_default();
+    println!("key_code\tname\tjinko\tsetai");
+    for row in &rows {
+        let key_code: String = row.get(0);
+        let name: String = row.get(1);
+        println!(
+            "{}\t{}\t{}\t{}",
+            key_code,
+            name,
+            format_opt(row.get(2)),
+            format_opt(row.get(3))
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_prefix_queries_from_words() {
+        assert_eq!(
+            build_tsquery("千代田区\u{3000}丸の内 ").as_deref(),
+            Some("'千代田区':* & '丸の内':*")
+        );
+        assert_eq!(build_tsquery("o'hara&").as_deref(), Some("'o''hara&':*"));
+        assert_eq!(build_tsquery("  "), None);
+    }
+
+    #[test]
+    fn search_uses_the_indexed_expression() {
+        let table = areamap_table_name("jp_estat_", 2020);
+        assert_eq!(
+            build_index_statement(&table),
+            format!(
+                "CREATE INDEX IF NOT EXISTS \"jp_estat_areamap_2020_geocoder_idx\" ON \"jp_estat_areamap_2020\" USING GIN ({})",
+                SEARCH_DOCUMENT
+            )
+        );
+        let sql = build_search_query(&table);
+        assert!(sql.contains(&format!(
+            "WHERE {} @@ to_tsquery('simple', $1)",
+            SEARCH_DOCUMENT
+        )));
+        assert!(sql.contains("FROM \"jp_estat_areamap_2020\""));
+    }
+}
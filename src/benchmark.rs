@@ -0,0 +1,379 @@
+use crate::{
+    error::JpEstatError,
+    mesh,
+    mesh_math::subdivisions_per_axis,
+    mesh_tile::{TileCompression, TileDType, build_payload_i32, encode_tile_bytes},
+    postgres,
+};
+use anyhow::{Context, Result, anyhow, bail};
+use mesh_data_tile::{
+    CompressionMode, DType, Endianness, MeshKind, TileDimensions, TileEncodeInput, encode_tile,
+};
+use serde_json::{Value, json};
+use std::{path::Path, time::Instant};
+
+/// Dataset used by the download and import benchmarks: the Lv1 file 5339 (central Tokyo)
+/// of the 2020 census population at Lv3.
+const BENCH_SURVEY: &str = "人口及び世帯";
+const BENCH_YEAR: u16 = 2020;
+const BENCH_LEVEL: u8 = 3;
+const BENCH_LV1: u64 = 5339;
+
+/// Synthetic tile for the encode benchmark: one Lv1 tile of Lv3 cells with 16 bands.
+const BENCH_ROWS: usize = 80;
+const BENCH_BANDS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum BenchmarkOp {
+    /// 1次メッシュ1ファイル分のCSVをダウンロード (キャッシュを使わない)
+    Download,
+    /// キャッシュ済みのCSVを一時テーブルに取り込む
+    Import,
+    /// 合成データのタイルをエンコードする
+    Encode,
+}
+
+impl BenchmarkOp {
+    fn as_str(self) -> &'static str {
+        match self {
+            BenchmarkOp::Download => "download",
+            BenchmarkOp::Import => "import",
+            BenchmarkOp::Encode => "encode",
+        }
+    }
+}
+
+fn bench_stats() -> Result<&'static mesh::MeshStats> {
+    Ok(
+        mesh::get_matching_mesh_stats(BENCH_LEVEL, BENCH_YEAR, BENCH_SURVEY).ok_or_else(|| {
+            JpEstatError::NotFound {
+                survey: BENCH_SURVEY.to_string(),
+                level: BENCH_LEVEL,
+                year: BENCH_YEAR,
+            }
+        })?,
+    )
+}
+
+fn megabytes_per_sec(bytes: usize, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        bytes as f64 / 1_000_000.0 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// Summarises per-iteration results: `rate_key` is averaged into `mean_{rate_key}`.
+fn summarize(operation: BenchmarkOp, iterations: Vec<Value>, rate_key: &str) -> Value {
+    let rates: Vec<f64> = iterations
+        .iter()
+        .filter_map(|it| it[rate_key].as_f64())
+        .collect();
+    let mean = if rates.is_empty() {
+        0.0
+    } else {
+        rates.iter().sum::<f64>() / rates.len() as f64
+    };
+    let mut summary = json!({
+        "operation": operation.as_str(),
+        "iterations": iterations,
+    });
+    summary[format!("mean_{}", rate_key)] = json!(mean);
+    summary
+}
+
+async fn bench_download(http_client: &reqwest::Client, iterations: u8) -> Result<Vec<Value>> {
+    let url = mesh::lv1_download_url(bench_stats()?, BENCH_LV1);
+    let mut results = Vec::new();
+    for i in 1..=iterations {
+        let started = Instant::now();
+        let response = http_client.get(url.clone()).send().await?;
+        if !response.status().is_success() {
+            return Err(JpEstatError::DownloadFailed {
+                url: url.to_string(),
+                status: response.status().as_u16(),
+            }
+            .into());
+        }
+        let bytes = response.bytes().await?.len();
+        let seconds = started.elapsed().as_secs_f64();
+        results.push(json!({
+            "iteration": i,
+            "bytes": bytes,
+            "seconds": seconds,
+            "mb_per_sec": megabytes_per_sec(bytes, seconds),
+        }));
+    }
+    Ok(results)
+}
+
+async fn bench_import(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    table_prefix: &str,
+    postgres_url: &str,
+    iterations: u8,
+) -> Result<Vec<Value>> {
+    let mesh_stats = bench_stats()?;
+    let items = mesh::download_lv1_csvs(
+        http_client,
+        tmp_dir,
+        mesh_stats,
+        vec![(BENCH_LV1, mesh::lv1_download_url(mesh_stats, BENCH_LV1))],
+    )
+    .await?;
+    let Some(item) = items.first() else {
+        bail!("benchmark CSV is not available");
+    };
+
+    let mut client = postgres::connect(postgres_url).await?;
+    let mut results = Vec::new();
+    for i in 1..=iterations {
+        let table_name = format!("{}benchmark_{}", table_prefix, i);
+        let columns = mesh::create_temp_table(&client, &table_name, &item.extracted_path)
+            .await
+            .with_context(|| format!("when creating {}", table_name))?;
+        let started = Instant::now();
+        let rows = mesh::import_csv_to_postgres(
+            &mut client,
+            &item.extracted_path,
+            &table_name,
+            &columns,
+            &mesh::ColumnTypes::new(),
+            None,
+            mesh::DEFAULT_INSERT_BATCH_SIZE,
+        )
+        .await?;
+        let seconds = started.elapsed().as_secs_f64();
+        client
+            .batch_execute(&format!(
+                "DROP TABLE {}",
+                postgres::folded_ident(&table_name)
+            ))
+            .await?;
+        results.push(json!({
+            "iteration": i,
+            "rows": rows,
+            "seconds": seconds,
+            "rows_per_sec": if seconds > 0.0 { rows as f64 / seconds } else { 0.0 },
+        }));
+    }
+    Ok(results)
+}
+
+/// Deterministic values with a census-like spread (mostly small counts, some empty cells),
+/// so deflate sees realistic input rather than a constant buffer.
+fn synthetic_values(len: usize) -> Vec<i32> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if state.is_multiple_of(5) {
+                TileDType::Int32.no_data()
+            } else {
+                (state % 3000) as i32
+            }
+        })
+        .collect()
+}
+
+fn bench_encode(iterations: u8) -> Result<Vec<Value>> {
+    let values = synthetic_values(BENCH_ROWS * BENCH_ROWS * BENCH_BANDS);
+    let bytes = std::mem::size_of_val(values.as_slice());
+    let mut results = Vec::new();
+    for i in 1..=iterations {
+        let started = Instant::now();
+        let encoded = encode_tile_bytes(
+            BENCH_LV1,
+            BENCH_ROWS,
+            BENCH_BANDS,
+            &values,
+            TileDType::Int32,
+            CompressionMode::DeflateRaw,
+        )?;
+        let seconds = started.elapsed().as_secs_f64();
+        results.push(json!({
+            "iteration": i,
+            "bytes": bytes,
+            "encoded_bytes": encoded.len(),
+            "seconds": seconds,
+            "mb_per_sec": megabytes_per_sec(bytes, seconds),
+        }));
+    }
+    Ok(results)
+}
+
+pub async fn process_benchmark(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    table_prefix: &str,
+    operation: BenchmarkOp,
+    iterations: u8,
+    postgres_url: Option<&str>,
+) -> Result<()> {
+    let summary = match operation {
+        BenchmarkOp::Download => summarize(
+            operation,
+            bench_download(http_client, iterations).await?,
+            "mb_per_sec",
+        ),
+        BenchmarkOp::Import => {
+            let Some(postgres_url) = postgres_url else {
+                bail!("--postgres-url is required for the import benchmark");
+            };
+            summarize(
+                operation,
+                bench_import(http_client, tmp_dir, table_prefix, postgres_url, iterations).await?,
+                "rows_per_sec",
+            )
+        }
+        BenchmarkOp::Encode => summarize(
+            operation,
+            tokio::task::spawn_blocking(move || bench_encode(iterations)).await??,
+            "mb_per_sec",
+        ),
+    };
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(())
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+/// Nearest-rank percentile of `values`, e.g. `p = 0.99`.
+fn percentile(values: &[f64], p: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = ((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len().max(1));
+    sorted.get(rank - 1).copied().unwrap_or(0.0)
+}
+
+/// Times `build_payload_i32` for a Lv1 tile of each finer level (8×8 Lv2 cells up to
+/// 640×640 Lv6 cells) with `bands` bands, to show how the payload cost grows with the grid.
+fn bench_payload_sizes(bands: usize, iterations: u8) -> Result<Vec<Value>> {
+    let mut results = Vec::new();
+    for level in 2..=6 {
+        let rows = subdivisions_per_axis(1, level)?;
+        let values = synthetic_values(rows * rows * bands);
+        let bytes = std::mem::size_of_val(values.as_slice());
+        let mut seconds = Vec::with_capacity(usize::from(iterations));
+        for _ in 0..iterations {
+            let started = Instant::now();
+            std::hint::black_box(build_payload_i32(std::hint::black_box(&values)));
+            seconds.push(started.elapsed().as_secs_f64());
+        }
+        let mean_seconds = mean(&seconds);
+        results.push(json!({
+            "level": level,
+            "rows": rows,
+            "cols": rows,
+            "bytes": bytes,
+            "mean_seconds": mean_seconds,
+            "mb_per_sec": megabytes_per_sec(bytes, mean_seconds),
+        }));
+    }
+    Ok(results)
+}
+
+fn bench_tile_encode(
+    rows: u32,
+    cols: u32,
+    bands: u8,
+    compression: TileCompression,
+    iterations: u8,
+) -> Result<Value> {
+    let mode = compression.to_mode();
+    let values = synthetic_values(rows as usize * cols as usize * usize::from(bands));
+    let bytes = std::mem::size_of_val(values.as_slice());
+    let mut seconds = Vec::with_capacity(usize::from(iterations));
+    let mut encoded_bytes = 0;
+    for _ in 0..iterations {
+        let started = Instant::now();
+        let payload = build_payload_i32(&values);
+        let encoded = encode_tile(TileEncodeInput {
+            tile_id: BENCH_LV1,
+            mesh_kind: MeshKind::JisX0410,
+            dtype: DType::Int32,
+            endianness: Endianness::Little,
+            compression: mode,
+            dimensions: TileDimensions { rows, cols, bands },
+            no_data: Some(f64::from(TileDType::Int32.no_data())),
+            payload: &payload,
+        })
+        .map_err(|e| anyhow!("failed to encode the benchmark tile: {}", e))?;
+        seconds.push(started.elapsed().as_secs_f64());
+        encoded_bytes = encoded.bytes.len();
+    }
+    let mean_seconds = mean(&seconds);
+    Ok(json!({
+        "operation": "mesh-tile-encode",
+        "rows": rows,
+        "cols": cols,
+        "bands": bands,
+        "compression": compression.as_str(),
+        "bytes": bytes,
+        "encoded_bytes": encoded_bytes,
+        "iterations": seconds.len(),
+        "mean_seconds": mean_seconds,
+        "p99_seconds": percentile(&seconds, 0.99),
+        "mb_per_sec": megabytes_per_sec(bytes, mean_seconds),
+        "payload": bench_payload_sizes(usize::from(bands), iterations)?,
+    }))
+}
+
+/// Encodes a synthetic `rows × cols × bands` `i32` tile `iterations` times and prints the
+/// latency and throughput as JSON, followed by the payload build time at each tile size.
+pub async fn process_mesh_tile_benchmark(
+    rows: u32,
+    cols: u32,
+    bands: u8,
+    compression: TileCompression,
+    iterations: u8,
+) -> Result<()> {
+    if rows == 0 || cols == 0 || bands == 0 {
+        bail!("--rows, --cols and --bands must be at least 1");
+    }
+    let summary = tokio::task::spawn_blocking(move || {
+        bench_tile_encode(rows, cols, bands, compression, iterations)
+    })
+    .await??;
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_benchmark_reports_throughput() {
+        let summary = summarize(BenchmarkOp::Encode, bench_encode(2).unwrap(), "mb_per_sec");
+        assert_eq!(summary["operation"], "encode");
+        assert_eq!(summary["iterations"].as_array().unwrap().len(), 2);
+        let first = &summary["iterations"][0];
+        assert_eq!(first["bytes"], BENCH_ROWS * BENCH_ROWS * BENCH_BANDS * 4);
+        assert!(first["encoded_bytes"].as_u64().unwrap() < first["bytes"].as_u64().unwrap());
+        assert!(summary["mean_mb_per_sec"].as_f64().is_some());
+    }
+
+    #[test]
+    fn tile_benchmark_reports_latency_and_payload_sizes() {
+        let summary = bench_tile_encode(10, 20, 3, TileCompression::None, 2).unwrap();
+        assert_eq!(summary["bytes"], 10 * 20 * 3 * 4);
+        assert_eq!(summary["iterations"], 2);
+        assert!(summary["p99_seconds"].as_f64().unwrap() >= 0.0);
+        let payload = summary["payload"].as_array().unwrap();
+        assert_eq!(payload.len(), 5);
+        assert_eq!(payload[4]["rows"], 640);
+
+        assert_eq!(percentile(&[3.0, 1.0, 2.0], 0.99), 3.0);
+        assert_eq!(percentile(&[3.0, 1.0, 2.0], 0.5), 2.0);
+    }
+}
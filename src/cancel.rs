@@ -0,0 +1,26 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+/// Set once when Ctrl+C is pressed during `mesh` (or a `--watch` run). Long-running loops
+/// check it between rows and commit what they have instead of rolling back.
+pub static CANCEL: AtomicBool = AtomicBool::new(false);
+
+static CANCEL_NOTIFY: Notify = Notify::const_new();
+
+pub fn request() {
+    CANCEL.store(true, Ordering::SeqCst);
+    CANCEL_NOTIFY.notify_waiters();
+}
+
+pub fn is_cancelled() -> bool {
+    CANCEL.load(Ordering::Relaxed)
+}
+
+/// Resolves once [`request`] has been called, for waits that should end on Ctrl+C.
+pub async fn cancelled() {
+    let notified = CANCEL_NOTIFY.notified();
+    if is_cancelled() {
+        return;
+    }
+    notified.await;
+}
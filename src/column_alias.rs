@@ -0,0 +1,91 @@
+use anyhow::{Context, Result, bail};
+use std::{collections::HashMap, path::Path};
+
+/// Output names for columns, read from a `--column-alias-file` JSON object
+/// (`{"人口（総数）": "population_total", ...}`). Columns without an alias keep their name.
+#[derive(Debug, Default)]
+pub(crate) struct ColumnAliases(HashMap<String, String>);
+
+impl ColumnAliases {
+    pub(crate) async fn read(path: &Path) -> Result<Self> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("when reading {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("when parsing {}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let aliases: HashMap<String, String> = serde_json::from_str(text)?;
+        for (column, alias) in &aliases {
+            if alias.trim().is_empty() || alias.contains([',', '\n', '\r']) {
+                bail!(
+                    "alias {:?} for column \"{}\" must be non-empty and contain no commas or newlines",
+                    alias,
+                    column
+                );
+            }
+        }
+        Ok(Self(aliases))
+    }
+
+    /// Aliased names of `header`. Fails when two columns would end up with the same name,
+    /// and warns about aliases for columns that are not in `header`.
+    pub(crate) fn apply(&self, header: &[String]) -> Result<Vec<String>> {
+        let renamed: Vec<String> = header
+            .iter()
+            .map(|name| self.0.get(name).unwrap_or(name).clone())
+            .collect();
+        for (i, name) in renamed.iter().enumerate() {
+            if renamed[..i].contains(name) {
+                bail!("more than one column would be named \"{}\"", name);
+            }
+        }
+        let mut unused: Vec<&str> = self
+            .0
+            .keys()
+            .filter(|column| !header.contains(column))
+            .map(String::as_str)
+            .collect();
+        if !unused.is_empty() {
+            unused.sort_unstable();
+            eprintln!(
+                "Warning: --column-alias-file lists columns not in the output: {}",
+                unused.join(", ")
+            );
+        }
+        Ok(renamed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn renames_listed_columns_only() {
+        let aliases =
+            ColumnAliases::parse(r#"{"人口（総数）": "population_total", "世帯数": "households"}"#)
+                .unwrap();
+        assert_eq!(
+            aliases
+                .apply(&header(&["KEY_CODE", "人口（総数）", "人口（総数）男"]))
+                .unwrap(),
+            header(&["KEY_CODE", "population_total", "人口（総数）男"])
+        );
+        let clash = ColumnAliases::parse(r#"{"人口（総数）": "KEY_CODE"}"#).unwrap();
+        assert!(clash.apply(&header(&["KEY_CODE", "人口（総数）"])).is_err());
+    }
+
+    #[test]
+    fn rejects_aliases_that_break_csv_headers() {
+        for alias in [r#""a,b""#, r#""a\nb""#, r#""""#] {
+            let json = format!(r#"{{"人口（総数）": {}}}"#, alias);
+            assert!(ColumnAliases::parse(&json).is_err(), "{}", alias);
+        }
+        assert!(ColumnAliases::parse(r#"["KEY_CODE"]"#).is_err());
+    }
+}
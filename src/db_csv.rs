@@ -1,8 +1,7 @@
-use crate::estat_api::EStatApiClient;
+use crate::{estat_api::EStatApiClient, progress};
 use anyhow::{Context, Result, anyhow, bail};
 use csv::{ReaderBuilder, WriterBuilder};
 use futures::{StreamExt as _, stream};
-use indicatif::{ProgressBar, ProgressStyle};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
@@ -240,7 +239,9 @@ impl HasStatsDataId for DimensionItemRow {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_db_csv(
+    http_client: &reqwest::Client,
     app_id: &str,
     output_dir: &Path,
     stats_data_ids: &[String],
@@ -275,15 +276,10 @@ pub async fn process_db_csv(
     let plans = build_dataset_plans(output_dir, stats_data_ids, resume, raw_json, &existing)?;
     let reuse_count = plans.iter().filter(|plan| plan.reuse_existing).count() as u64;
 
-    let pb_style = ProgressStyle::default_bar()
-        .template("{msg} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7}")?
-        .progress_chars("##-");
-    let pb = ProgressBar::new(stats_data_ids.len() as u64);
-    pb.set_style(pb_style);
-    pb.set_message("Exporting DB tables...");
+    let pb = progress::new_bar(stats_data_ids.len() as u64, "Exporting DB tables...")?;
     pb.inc(reuse_count);
 
-    let api = EStatApiClient::new();
+    let api = EStatApiClient::with_client(http_client.clone());
     let fetch_plans: Vec<DatasetPlan> = plans
         .iter()
         .filter(|plan| !plan.reuse_existing)
@@ -0,0 +1,176 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const MANIFEST_FILENAME: &str = "downloads_manifest.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ManifestEntry {
+    pub filename: String,
+    pub extracted_path: PathBuf,
+    pub extracted_ok: bool,
+    /// Modification time of the archive when it was extracted, in milliseconds since the epoch.
+    /// A different mtime means the archive was replaced and must be extracted again.
+    pub zip_mtime_ms: u64,
+    /// `Last-Modified` of the response the archive was downloaded from, used by `--watch` to
+    /// ask e-Stat whether the file changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+/// Records which archives in `tmp_dir` were already extracted, so restarts can skip them.
+/// Entries are keyed by the archive filename, which is unique within `tmp_dir`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DownloadManifest {
+    entries: BTreeMap<String, ManifestEntry>,
+}
+
+fn mtime_ms(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+impl DownloadManifest {
+    fn path(tmp_dir: &Path) -> PathBuf {
+        tmp_dir.join(MANIFEST_FILENAME)
+    }
+
+    /// Loads the manifest. A missing or unreadable manifest is treated as empty.
+    pub async fn load(tmp_dir: &Path) -> Self {
+        let path = Self::path(tmp_dir);
+        let Ok(body) = tokio::fs::read(&path).await else {
+            return Self::default();
+        };
+        match serde_json::from_slice(&body) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                eprintln!(
+                    "Warning: ignoring corrupt {} ({}); archives will be re-extracted.",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Returns the extracted path for `filename` if it is still valid for `zip_path`.
+    pub fn lookup(&self, filename: &str, zip_path: &Path) -> Option<PathBuf> {
+        let entry = self.entries.get(filename)?;
+        if !entry.extracted_ok || !entry.extracted_path.exists() {
+            return None;
+        }
+        if mtime_ms(zip_path)? != entry.zip_mtime_ms {
+            return None;
+        }
+        Some(entry.extracted_path.clone())
+    }
+
+    /// `last_modified` is `None` when the archive was not downloaded in this run; the value
+    /// recorded for the same archive (same mtime) is kept then.
+    pub fn record(
+        &mut self,
+        filename: &str,
+        zip_path: &Path,
+        extracted_path: &Path,
+        last_modified: Option<&str>,
+    ) {
+        let Some(zip_mtime_ms) = mtime_ms(zip_path) else {
+            return;
+        };
+        let last_modified = last_modified.map(str::to_string).or_else(|| {
+            self.entries
+                .get(filename)
+                .filter(|entry| entry.zip_mtime_ms == zip_mtime_ms)
+                .and_then(|entry| entry.last_modified.clone())
+        });
+        self.entries.insert(
+            filename.to_string(),
+            ManifestEntry {
+                filename: filename.to_string(),
+                extracted_path: extracted_path.to_path_buf(),
+                extracted_ok: true,
+                zip_mtime_ms,
+                last_modified,
+            },
+        );
+    }
+
+    pub fn last_modified(&self, filename: &str) -> Option<&str> {
+        self.entries.get(filename)?.last_modified.as_deref()
+    }
+
+    /// Writes the manifest via a temporary file so an interrupted run never leaves it half-written.
+    pub async fn save(&self, tmp_dir: &Path) -> Result<()> {
+        let path = Self::path(tmp_dir);
+        let tmp_path = path.with_extension("json.tmp");
+        let body = serde_json::to_vec_pretty(self)?;
+        tokio::fs::write(&tmp_path, body)
+            .await
+            .with_context(|| format!("when writing {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .with_context(|| format!("when writing {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trips_and_invalidates_on_mtime_change() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let zip_path = dir.join("2020-T001140-5339.zip");
+        let extracted = dir.join("2020-T001140-5339.txt");
+        tokio::fs::write(&zip_path, b"zip").await.unwrap();
+        tokio::fs::write(&extracted, b"csv").await.unwrap();
+
+        let mut manifest = DownloadManifest::default();
+        let last_modified = "Wed, 01 Apr 2026 00:00:00 GMT";
+        manifest.record(
+            "2020-T001140-5339.zip",
+            &zip_path,
+            &extracted,
+            Some(last_modified),
+        );
+        // Re-extracting the same archive keeps the recorded Last-Modified.
+        manifest.record("2020-T001140-5339.zip", &zip_path, &extracted, None);
+        manifest.save(dir).await.unwrap();
+
+        let loaded = DownloadManifest::load(dir).await;
+        assert_eq!(
+            loaded.lookup("2020-T001140-5339.zip", &zip_path),
+            Some(extracted.clone())
+        );
+        assert_eq!(loaded.lookup("other.zip", &zip_path), None);
+        assert_eq!(
+            loaded.last_modified("2020-T001140-5339.zip"),
+            Some(last_modified)
+        );
+
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&zip_path)
+            .unwrap();
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(loaded.lookup("2020-T001140-5339.zip", &zip_path), None);
+    }
+
+    #[tokio::test]
+    async fn treats_corrupt_manifest_as_empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        tokio::fs::write(dir.join(MANIFEST_FILENAME), b"{not json")
+            .await
+            .unwrap();
+        assert!(DownloadManifest::load(dir).await.entries.is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{
+    MeshCsvConfig,
+    duckdb_script::{duckdb_type, quote_literal},
+    error::JpEstatError,
+    list_cached::human_size,
+    mesh::{self, get_matching_mesh_stats},
+    mesh_csv,
+};
+use anyhow::{Context, Result, anyhow, bail};
+use std::{path::Path, process::Stdio};
+use tokio::{io::AsyncWriteExt as _, process::Command};
+
+/// Checks that the `duckdb` CLI is on PATH; there is no stable DuckDB crate to link instead.
+pub async fn check_duckdb_available() -> Result<()> {
+    let output = Command::new("duckdb")
+        .arg("--version")
+        .output()
+        .await
+        .map_err(|err| {
+            anyhow!(
+                "the duckdb CLI is required for this command (https://duckdb.org/docs/installation/). Failed to run `duckdb --version`: {}",
+                err
+            )
+        })?;
+    if !output.status.success() {
+        bail!(
+            "`duckdb --version` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Loads the merged UTF-8 CSV into `table_name` with the column types `mesh` creates in
+/// PostgreSQL: `GASSAN` is read as text and split into `BIGINT[]`. Ends with the row count.
+fn build_import_script(table_name: &str, csv_path: &str, columns: &[String]) -> String {
+    let types = columns
+        .iter()
+        .map(|col| {
+            format!(
+                "{}: {}",
+                quote_literal(col),
+                quote_literal(duckdb_type(col))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let replace = if columns.iter().any(|col| col == "GASSAN") {
+        " REPLACE (string_split(\"GASSAN\", ';')::BIGINT[] AS \"GASSAN\")"
+    } else {
+        ""
+    };
+    let table = quote_ident(table_name);
+    format!(
+        "CREATE OR REPLACE TABLE {table} AS\n\
+         SELECT *{replace} FROM read_csv_auto({csv}, header=true, nullstr=['', '*'], types={{{types}}});\n\
+         SELECT count(*) FROM {table};\n",
+        csv = quote_literal(csv_path),
+    )
+}
+
+/// Runs `script` against the database file `output` and returns what it printed.
+async fn run_duckdb(output: &Path, script: &str) -> Result<String> {
+    let mut child = Command::new("duckdb")
+        .arg("-bail")
+        .arg("-csv")
+        .arg("-noheader")
+        .arg(output)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("when starting duckdb")?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("duckdb stdin is not available"))?;
+    stdin.write_all(script.as_bytes()).await?;
+    drop(stdin);
+    let result = child.wait_with_output().await?;
+    if !result.status.success() {
+        bail!(
+            "duckdb failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&result.stdout).into_owned())
+}
+
+/// Imports a mesh survey into a table of a DuckDB database file with the `duckdb` CLI. The
+/// CSVs are merged into UTF-8 in `tmp_dir` first (as `mesh-csv` writes them), since
+/// `read_csv_auto` does not read Shift_JIS.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_mesh_to_duckdb(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    table_prefix: &str,
+    level: u8,
+    year: u16,
+    survey: &str,
+    output: &Path,
+    cleanup: bool,
+) -> Result<()> {
+    check_duckdb_available().await?;
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+    let table_name = mesh::table_name(table_prefix, mesh_stats);
+    let csv_path = tmp_dir.join(format!("{}.csv", table_name));
+    let config = MeshCsvConfig::new(level, year, survey, &csv_path)
+        .with_tmp_dir(tmp_dir)
+        .with_cleanup(cleanup);
+    mesh_csv::process_mesh_csv(http_client, &config).await?;
+
+    let columns: Vec<String> = csv::Reader::from_path(&csv_path)
+        .and_then(|mut rdr| rdr.headers().cloned())
+        .with_context(|| format!("when reading the header of {}", csv_path.display()))?
+        .iter()
+        .map(str::to_string)
+        .collect();
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // duckdb resolves the CSV path relative to its own working directory.
+    let csv_abs = std::path::absolute(&csv_path)?;
+    let printed = run_duckdb(
+        output,
+        &build_import_script(&table_name, &csv_abs.to_string_lossy(), &columns),
+    )
+    .await
+    .with_context(|| format!("when importing into {}", output.display()))?;
+    tokio::fs::remove_file(&csv_path).await?;
+
+    let rows = printed.lines().last().unwrap_or("").trim();
+    let size = tokio::fs::metadata(output).await?.len();
+    println!(
+        "Imported {} rows into {} in {} ({})",
+        rows,
+        table_name,
+        output.display(),
+        human_size(size)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_matches_the_postgres_schema() {
+        let columns: Vec<String> = ["KEY_CODE", "HTKSYORI", "HTKSAKI", "GASSAN", "人口（総数）"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let script = build_import_script("jp_estat_mesh_2020_T001140_3", "/tmp/it's.csv", &columns);
+        assert_eq!(
+            script,
+            "CREATE OR REPLACE TABLE \"jp_estat_mesh_2020_T001140_3\" AS\n\
+             SELECT * REPLACE (string_split(\"GASSAN\", ';')::BIGINT[] AS \"GASSAN\") \
+             FROM read_csv_auto('/tmp/it''s.csv', header=true, nullstr=['', '*'], \
+             types={'KEY_CODE': 'BIGINT', 'HTKSYORI': 'SMALLINT', 'HTKSAKI': 'BIGINT', \
+             'GASSAN': 'VARCHAR', '人口（総数）': 'INTEGER'});\n\
+             SELECT count(*) FROM \"jp_estat_mesh_2020_T001140_3\";\n"
+        );
+    }
+}
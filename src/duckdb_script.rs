@@ -0,0 +1,111 @@
+use crate::{
+    error::JpEstatError,
+    mesh::{get_matching_mesh_stats, infer_column_type},
+};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// DuckDB type for a mesh column, following the PostgreSQL mapping of `mesh`.
+/// `GASSAN` stays text because `read_csv` cannot split the `;`-separated list itself.
+pub(crate) fn duckdb_type(column: &str) -> &'static str {
+    match infer_column_type(column) {
+        "BIGINT[]" => "VARCHAR",
+        other => other,
+    }
+}
+
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn build_script(table_name: &str, csv_path: &str, columns: &[String], description: &str) -> String {
+    let types = columns
+        .iter()
+        .map(|col| {
+            format!(
+                "{}: {}",
+                quote_literal(col),
+                quote_literal(duckdb_type(col))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut script = format!("-- {}\n", description);
+    if columns.iter().any(|col| col == "GASSAN") {
+        script.push_str(
+            "-- GASSAN (merged mesh codes) is kept as VARCHAR; convert it with\n\
+             -- string_split(\"GASSAN\", ';')::BIGINT[] when needed.\n",
+        );
+    }
+    script.push_str(&format!(
+        "CREATE TABLE IF NOT EXISTS {} AS\nSELECT * FROM read_csv_auto({}, header=true, nullstr=['', '*'], types={{{}}});\n",
+        table_name,
+        quote_literal(csv_path),
+        types
+    ));
+    script
+}
+
+/// Writes a DuckDB script that loads the merged CSV produced by `mesh-csv`. Column names are
+/// read from the CSV header so the script matches the file exactly.
+pub async fn process_duckdb_script(
+    table_prefix: &str,
+    level: u8,
+    year: u16,
+    survey: &str,
+    csv_path: &Path,
+    output_sql: &Path,
+) -> Result<()> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+    let columns: Vec<String> = csv::Reader::from_path(csv_path)
+        .and_then(|mut rdr| rdr.headers().cloned())
+        .with_context(|| format!("when reading the header of {}", csv_path.display()))?
+        .iter()
+        .map(str::to_string)
+        .collect();
+
+    let table_name = format!(
+        "{}mesh_{}_{}_{}",
+        table_prefix, mesh_stats.year, mesh_stats.stats_id, level
+    );
+    let description = format!(
+        "{} {} Lv{} ({}), generated by jp-estat-util from mesh-csv output",
+        year, survey, level, mesh_stats.stats_id
+    );
+    let script = build_script(
+        &table_name,
+        &csv_path.to_string_lossy(),
+        &columns,
+        &description,
+    );
+    tokio::fs::write(output_sql, script)
+        .await
+        .with_context(|| format!("when writing {}", output_sql.display()))?;
+    println!("DuckDB script written to {}", output_sql.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_read_csv_auto_script() {
+        let columns: Vec<String> = ["KEY_CODE", "HTKSYORI", "GASSAN", "人口（総数）"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let script = build_script("mesh_2020_T001140_3", "out/it's.csv", &columns, "test");
+        assert!(script.contains("string_split(\"GASSAN\", ';')"));
+        assert!(script.ends_with(
+            "CREATE TABLE IF NOT EXISTS mesh_2020_T001140_3 AS\n\
+             SELECT * FROM read_csv_auto('out/it''s.csv', header=true, nullstr=['', '*'], \
+             types={'KEY_CODE': 'BIGINT', 'HTKSYORI': 'SMALLINT', 'GASSAN': 'VARCHAR', '人口（総数）': 'INTEGER'});\n"
+        ));
+    }
+}
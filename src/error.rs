@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors that callers may want to tell apart. They are still propagated as `anyhow::Error`,
+/// so match on them with `err.downcast_ref::<JpEstatError>()`.
+#[derive(Debug, Error)]
+pub enum JpEstatError {
+    #[error("一致する統計データが見つかりません (survey: {survey}, level: {level}, year: {year})")]
+    NotFound {
+        survey: String,
+        level: u8,
+        year: u16,
+    },
+
+    #[error("failed to download {url} (HTTP {status})")]
+    DownloadFailed { url: String, status: u16 },
+
+    #[error(
+        "{file}: {file_mb:.1} MB would bring the total download to {total_mb:.1} MB, over the limit of {limit_mb} MB (raise --max-download-size-mb)",
+        file_mb = *.file_bytes as f64 / 1_000_000.0,
+        total_mb = *.total_bytes as f64 / 1_000_000.0,
+        limit_mb = .limit_bytes / 1_000_000,
+    )]
+    DownloadSizeExceeded {
+        file: String,
+        file_bytes: u64,
+        total_bytes: u64,
+        limit_bytes: u64,
+    },
+
+    #[error("failed to extract {}", path.display())]
+    ExtractFailed { path: PathBuf },
+
+    #[error("CSV header mismatch: {}", path.display())]
+    CsvMismatch { path: PathBuf },
+
+    #[error("mesh code {code} is not a valid level {expected_level} mesh code")]
+    MeshCodeInvalid { code: u64, expected_level: u8 },
+
+    #[error("database error: {0}")]
+    DatabaseError(#[from] tokio_postgres::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_be_recovered_from_anyhow() {
+        let err: anyhow::Error = JpEstatError::NotFound {
+            survey: "国勢調査".to_string(),
+            level: 3,
+            year: 2020,
+        }
+        .into();
+        let err = err.context("when processing mesh");
+
+        assert!(matches!(
+            err.downcast_ref::<JpEstatError>(),
+            Some(JpEstatError::NotFound { level: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn formats_download_failures() {
+        let err = JpEstatError::DownloadFailed {
+            url: "https://example.com/a.zip".to_string(),
+            status: 503,
+        };
+        assert_eq!(
+            err.to_string(),
+            "failed to download https://example.com/a.zip (HTTP 503)"
+        );
+    }
+}
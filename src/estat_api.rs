@@ -1,3 +1,4 @@
+use crate::download;
 use anyhow::{Context, Result, anyhow, bail};
 use reqwest::Client;
 use serde_json::Value;
@@ -19,9 +20,12 @@ impl Default for EStatApiClient {
 
 impl EStatApiClient {
     pub fn new() -> Self {
-        Self {
-            client: Client::new(),
-        }
+        Self::with_client(Client::new())
+    }
+
+    /// Uses a preconfigured HTTP client (e.g. one with `--proxy` applied).
+    pub fn with_client(client: Client) -> Self {
+        Self { client }
     }
 
     pub async fn get_meta_info(&self, app_id: &str, stats_data_id: &str) -> Result<Value> {
@@ -57,7 +61,40 @@ impl EStatApiClient {
             let page = self
                 .fetch_json("getStatsData", "GET_STATS_DATA", &params)
                 .await?;
-            start_position = next_key(&page);
+            start_position = next_key(&page, "GET_STATS_DATA", "STATISTICAL_DATA");
+            pages.push(page);
+
+            if start_position.is_none() {
+                break;
+            }
+        }
+
+        Ok(pages)
+    }
+
+    /// All pages of `getStatsList` for `params` (besides `appId`), following `NEXT_KEY`.
+    pub async fn get_stats_list_pages(
+        &self,
+        app_id: &str,
+        params: &[(&str, String)],
+    ) -> Result<Vec<Value>> {
+        let mut pages = Vec::new();
+        let mut start_position: Option<String> = None;
+
+        loop {
+            let mut page_params = vec![
+                ("appId", app_id.to_string()),
+                ("limit", DEFAULT_PAGE_LIMIT.to_string()),
+            ];
+            page_params.extend(params.iter().cloned());
+            if let Some(next) = start_position.as_ref() {
+                page_params.push(("startPosition", next.clone()));
+            }
+
+            let page = self
+                .fetch_json("getStatsList", "GET_STATS_LIST", &page_params)
+                .await?;
+            start_position = next_key(&page, "GET_STATS_LIST", "DATALIST_INF");
             pages.push(page);
 
             if start_position.is_none() {
@@ -78,6 +115,7 @@ impl EStatApiClient {
             &format!("{}/{}", API_BASE_URL, endpoint),
             params.iter().map(|(key, value)| (*key, value.as_str())),
         )?;
+        download::RATE_LIMITER.acquire().await;
         let response = self.client.get(url.clone()).send().await?;
         let status = response.status();
         let body = response.bytes().await?;
@@ -119,11 +157,11 @@ fn ensure_api_success(value: &Value, response_root_key: &str) -> Result<()> {
     Ok(())
 }
 
-fn next_key(value: &Value) -> Option<String> {
+fn next_key(value: &Value, response_root_key: &str, container_key: &str) -> Option<String> {
     value
-        .get("GET_STATS_DATA")
-        .and_then(|root| root.get("STATISTICAL_DATA"))
-        .and_then(|statistical_data| statistical_data.get("RESULT_INF"))
+        .get(response_root_key)
+        .and_then(|root| root.get(container_key))
+        .and_then(|container| container.get("RESULT_INF"))
         .and_then(|result_inf| result_inf.get("NEXT_KEY"))
         .and_then(scalar_to_string)
         .filter(|key| !key.trim().is_empty())
@@ -1,6 +1,11 @@
-use anyhow::Result;
-use std::path::PathBuf;
-use tokio::process::Command;
+use anyhow::{Context, Result};
+use futures::future;
+use quick_xml::{Writer, events::BytesText};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use tokio::{io::AsyncReadExt as _, process::Command};
 
 pub async fn ensure_available() -> Result<()> {
     let output = Command::new("ogrinfo")
@@ -42,49 +47,292 @@ pub async fn ensure_available() -> Result<()> {
     Ok(())
 }
 
-pub async fn create_vrt(out: &PathBuf, shapes: &Vec<PathBuf>) -> Result<()> {
+/// Oldest GDAL whose `ogr2ogr` supports every option passed by [`load`].
+const MIN_OGR2OGR_VERSION: (u32, u32, u32) = (3, 0, 0);
+
+const GDAL_INSTALL_HINT: &str = "Install GDAL 3.0 or later (e.g. `sudo apt-get install gdal-bin`, `brew install gdal`, or OSGeo4W on Windows) and make sure `ogr2ogr` is on PATH.";
+
+/// Parses the `GDAL x.y.z, released ...` line printed by `ogr2ogr --version`.
+fn parse_gdal_version(output: &str) -> Option<(u32, u32, u32)> {
+    let version = output
+        .trim()
+        .strip_prefix("GDAL ")?
+        .split([',', ' '])
+        .next()?;
+    let mut parts = version.split('.').map(|part| {
+        // Pre-release builds look like `3.9.0dev`.
+        let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+        digits.parse::<u32>().ok()
+    });
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+/// Checks that `ogr2ogr` is installed and recent enough. Returns the reported version string.
+pub async fn check_ogr2ogr_available() -> Result<String> {
+    let output = Command::new("ogr2ogr")
+        .arg("--version")
+        .output()
+        .await
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "`ogr2ogr` could not be run ({}). {}",
+                err,
+                GDAL_INSTALL_HINT
+            )
+        })?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`ogr2ogr --version` exited with status {}. {}",
+            output.status,
+            GDAL_INSTALL_HINT
+        );
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version_line = stdout.trim().to_string();
+    let Some(version) = parse_gdal_version(&version_line) else {
+        anyhow::bail!(
+            "could not parse `ogr2ogr --version` output: {}",
+            version_line
+        );
+    };
+    if version < MIN_OGR2OGR_VERSION {
+        anyhow::bail!(
+            "ogr2ogr {}.{}.{} is too old; {}.{}.{} or later is required. {}",
+            version.0,
+            version.1,
+            version.2,
+            MIN_OGR2OGR_VERSION.0,
+            MIN_OGR2OGR_VERSION.1,
+            MIN_OGR2OGR_VERSION.2,
+            GDAL_INSTALL_HINT
+        );
+    }
+    Ok(version_line)
+}
+
+/// Whether `ogr2ogr --formats` lists the PostgreSQL (PostGIS) vector driver. `PGDUMP`
+/// (`PostgreSQL SQL dump`) only writes SQL files, so it does not count.
+fn has_postgresql_driver(formats: &str) -> bool {
+    formats
+        .lines()
+        .any(|line| line.trim_start().starts_with("PostgreSQL "))
+}
+
+/// Fails unless this GDAL build can write to PostgreSQL, which `areamap` needs.
+pub async fn check_postgresql_driver() -> Result<()> {
+    let output = Command::new("ogr2ogr")
+        .arg("--formats")
+        .output()
+        .await
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "`ogr2ogr` could not be run ({}). {}",
+                err,
+                GDAL_INSTALL_HINT
+            )
+        })?;
+    if !output.status.success() {
+        anyhow::bail!("`ogr2ogr --formats` exited with status {}", output.status);
+    }
+    if !has_postgresql_driver(&String::from_utf8_lossy(&output.stdout)) {
+        anyhow::bail!(
+            "`ogr2ogr --formats` does not list the PostgreSQL driver; install a GDAL build with PostgreSQL/PostGIS support"
+        );
+    }
+    Ok(())
+}
+
+/// Offset of the language driver ID in the DBF header.
+const DBF_LANGUAGE_DRIVER_OFFSET: usize = 29;
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Picks the shapefile `ENCODING` open option from the companion `.dbf` header.
+/// A language driver ID of 0 means "not set"; such files are read as UTF-8 only when the
+/// `.cpg` sidecar says so (with or without a BOM). Everything else falls back to CP932, which
+/// is what e-Stat has used for most years.
+async fn detect_dbf_encoding(shp_path: &Path) -> &'static str {
+    const DEFAULT: &str = "CP932";
+    let mut header = [0u8; DBF_LANGUAGE_DRIVER_OFFSET + 1];
+    let read = async {
+        let mut file = tokio::fs::File::open(shp_path.with_extension("dbf")).await?;
+        file.read_exact(&mut header).await
+    };
+    if read.await.is_err() {
+        return DEFAULT;
+    }
+    match header[DBF_LANGUAGE_DRIVER_OFFSET] {
+        0x58 => "CP932",
+        0x00 => match tokio::fs::read(shp_path.with_extension("cpg")).await {
+            Ok(cpg) if cpg.starts_with(UTF8_BOM) => "UTF-8",
+            Ok(cpg) => {
+                let name = String::from_utf8_lossy(&cpg);
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("UTF-8") || name.eq_ignore_ascii_case("UTF8") {
+                    "UTF-8"
+                } else {
+                    DEFAULT
+                }
+            }
+            Err(_) => DEFAULT,
+        },
+        _ => DEFAULT,
+    }
+}
+
+/// One `<OGRVRTLayer>` of the union layer.
+struct VrtLayer {
+    name: String,
+    src: String,
+    encoding: &'static str,
+}
+
+/// Resolves a shapefile to the absolute path written to the VRT (GDAL resolves relative
+/// paths against the VRT, not the working directory) and detects its DBF encoding.
+async fn vrt_layer(shape: &Path) -> Result<VrtLayer> {
+    let src = tokio::fs::canonicalize(shape)
+        .await
+        .with_context(|| format!("shapefile not found: {}", shape.display()))?;
+    let name = shape
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .with_context(|| format!("invalid shapefile name: {}", shape.display()))?
+        .to_string();
+    let src = src
+        .to_str()
+        .with_context(|| format!("shapefile path is not UTF-8: {}", src.display()))?
+        .to_string();
+    Ok(VrtLayer {
+        name,
+        src,
+        encoding: detect_dbf_encoding(shape).await,
+    })
+}
+
+fn vrt_xml(layer_name: &str, layers: &[VrtLayer]) -> Result<Vec<u8>> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer
+        .create_element("OGRVRTDataSource")
+        .write_inner_content(|w| {
+            w.create_element("OGRVRTUnionLayer")
+                .with_attribute(("name", layer_name))
+                .write_inner_content(|w| {
+                    for layer in layers {
+                        w.create_element("OGRVRTLayer")
+                            .with_attribute(("name", layer.name.as_str()))
+                            .write_inner_content(|w| {
+                                w.create_element("SrcDataSource")
+                                    .write_text_content(BytesText::new(&layer.src))?;
+                                w.create_element("OpenOptions").write_inner_content(|w| {
+                                    w.create_element("OOI")
+                                        .with_attribute(("key", "ENCODING"))
+                                        .write_text_content(BytesText::new(layer.encoding))?;
+                                    Ok::<_, std::io::Error>(())
+                                })?;
+                                Ok::<_, std::io::Error>(())
+                            })?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                })?;
+            Ok::<_, std::io::Error>(())
+        })?;
+    let mut xml = writer.into_inner();
+    xml.push(b'\n');
+    Ok(xml)
+}
+
+pub async fn create_vrt(out: &PathBuf, shapes: &[PathBuf]) -> Result<()> {
     if shapes.is_empty() {
         anyhow::bail!("No shapefiles found");
     }
 
-    let bare_vrt = out.with_extension("");
-    let layer_name = bare_vrt.file_name().unwrap().to_str().unwrap();
-    // let vrt_path = shape.with_extension("vrt");
-
-    let mut layers = String::new();
-    for shape in shapes {
-        let bare_shape = shape.with_extension("");
-        let shape_filename = bare_shape.file_name().unwrap().to_str().unwrap();
-        let encoding = "CP932"; // detect_encoding(shape).await?;
-        layers.push_str(&format!(
-            r#"
-                <OGRVRTLayer name="{}">
-                <SrcDataSource>{}</SrcDataSource>
-                <OpenOptions><OOI key="ENCODING">{}</OOI></OpenOptions>
-                </OGRVRTLayer>
-            "#,
-            shape_filename,
-            shape.canonicalize().unwrap().to_str().unwrap(),
-            encoding,
-        ));
-    }
-
-    let vrt = format!(
-        r#"
-        <OGRVRTDataSource>
-        <OGRVRTUnionLayer name="{}">
-        {}
-        </OGRVRTUnionLayer>
-        </OGRVRTDataSource>
-    "#,
-        layer_name, layers
-    );
-
-    tokio::fs::write(&out, vrt).await?;
+    let layer_name = out
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .with_context(|| format!("invalid VRT name: {}", out.display()))?;
+    // Every shapefile is checked before anything is written, so a missing one fails the
+    // year instead of producing a VRT that ogr2ogr cannot open.
+    let layers = future::try_join_all(shapes.iter().map(|shape| vrt_layer(shape))).await?;
+
+    tokio::fs::write(&out, vrt_xml(layer_name, &layers)?).await?;
+
+    Ok(())
+}
+
+/// `ogr2ogr` arguments that copy `input` into the layer `layer_name` of the GeoPackage
+/// `output`, tagging it with `srid` (the coordinates are already in that CRS).
+fn gpkg_args(input: &Path, output: &Path, layer_name: &str, srid: u16) -> Vec<OsString> {
+    let mut args: Vec<OsString> = [
+        "-f",
+        "GPKG",
+        "-overwrite",
+        "-nln",
+        layer_name,
+        "-nlt",
+        "POLYGON",
+        "-a_srs",
+        &format!("EPSG:{}", srid),
+    ]
+    .into_iter()
+    .map(OsString::from)
+    .collect();
+    args.push(output.into());
+    args.push(input.into());
+    args
+}
+
+/// Writes `input` (any OGR-readable file) as one layer of a GeoPackage.
+pub async fn write_gpkg(input: &Path, output: &Path, layer_name: &str, srid: u16) -> Result<()> {
+    let output = Command::new("ogr2ogr")
+        .args(gpkg_args(input, output, layer_name, srid))
+        .output()
+        .await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ogr2ogr failed: {}", stderr);
+    }
+    Ok(())
+}
 
+/// Opens a shapefile read-only with `ogrinfo -ro -al -so`, which reads the header, the
+/// index and the layer summary and fails on truncated or corrupt archives.
+pub async fn validate_shapefile(shape: &Path) -> Result<()> {
+    let output = Command::new("ogrinfo")
+        .arg("-ro")
+        .arg("-al")
+        .arg("-so")
+        .arg(shape)
+        .output()
+        .await
+        .context("when running ogrinfo")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "ogrinfo could not open the file: {}",
+            stderr.lines().next().unwrap_or("").trim()
+        );
+    }
     Ok(())
 }
 
+/// Whether `datasource` has a layer named `layer`. A datasource that cannot be opened (a
+/// file that does not exist yet) has no layers.
+pub async fn layer_exists(datasource: &str, layer: &str) -> Result<bool> {
+    let output = Command::new("ogrinfo")
+        .arg("-ro")
+        .arg("-so")
+        .arg("-q")
+        .arg(datasource)
+        .arg(layer)
+        .output()
+        .await
+        .context("when running ogrinfo")?;
+    Ok(output.status.success())
+}
+
 fn is_postgresql_output(output: &str, output_format: Option<&str>) -> bool {
     output.starts_with("PG:")
         || output.starts_with("pg:")
@@ -93,45 +341,261 @@ fn is_postgresql_output(output: &str, output_format: Option<&str>) -> bool {
             .unwrap_or(false)
 }
 
-pub async fn load(
-    vrt: &PathBuf,
+/// `ogr2ogr` arguments that load `vrt` into the layer `layer_name` of `output`. The layer
+/// is always named with `-nln`, so the table does not depend on the VRT layer name.
+fn load_args(
+    vrt: &Path,
     output: &str,
     output_format: Option<&str>,
-    output_layer_name: Option<&str>,
+    layer_name: &str,
     where_clause: Option<&str>,
     output_crs: Option<&str>,
-) -> Result<()> {
-    let mut cmd = Command::new("ogr2ogr");
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = Vec::new();
     if let Some(format) = output_format {
-        cmd.arg("-f").arg(format);
-    }
-    cmd.arg("-overwrite");
-    if let Some(layer_name) = output_layer_name {
-        cmd.arg("-nln").arg(layer_name);
+        args.extend(["-f".into(), format.into()]);
     }
+    args.extend(["-overwrite".into(), "-nln".into(), layer_name.into()]);
     if let Some(where_clause) = where_clause {
-        cmd.arg("-where").arg(where_clause);
+        args.extend(["-where".into(), where_clause.into()]);
     }
     if let Some(output_crs) = output_crs {
-        cmd.arg("-t_srs").arg(output_crs);
+        args.extend(["-t_srs".into(), output_crs.into()]);
     }
 
     if is_postgresql_output(output, output_format) {
-        cmd.arg("-lco")
-            .arg("GEOM_TYPE=geometry")
-            .arg("-lco")
-            .arg("GEOMETRY_NAME=geom")
-            .arg("--config")
-            .arg("PG_USE_COPY=YES");
+        args.extend(
+            [
+                "-lco",
+                "GEOM_TYPE=geometry",
+                "-lco",
+                "GEOMETRY_NAME=geom",
+                "--config",
+                "PG_USE_COPY=YES",
+            ]
+            .map(OsString::from),
+        );
     }
+    args.extend([output.into(), vrt.as_os_str().to_owned()]);
+    args
+}
 
-    let output = cmd.arg(output).arg(vrt).output().await?;
+/// Loads `vrt` into the layer `layer_name` of `output`. For PostgreSQL outputs the layer is
+/// looked up afterwards, so a table that ended up under another name is an error here rather
+/// than in the metadata and post-processing steps that expect `layer_name`.
+pub async fn load(
+    vrt: &Path,
+    output: &str,
+    output_format: Option<&str>,
+    layer_name: &str,
+    where_clause: Option<&str>,
+    output_crs: Option<&str>,
+) -> Result<()> {
+    let result = Command::new("ogr2ogr")
+        .args(load_args(
+            vrt,
+            output,
+            output_format,
+            layer_name,
+            where_clause,
+            output_crs,
+        ))
+        .output()
+        .await?;
 
-    if !output.status.success() {
+    if !result.status.success() {
         // the error message may contain malformed UTF8
-        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = String::from_utf8_lossy(&result.stderr);
         anyhow::bail!("ogr2ogr failed: {}", stderr);
     }
 
+    if is_postgresql_output(output, output_format) && !layer_exists(output, layer_name).await? {
+        anyhow::bail!(
+            "ogr2ogr finished, but {} has no layer named {}",
+            output,
+            layer_name
+        );
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_vrt, detect_dbf_encoding, gpkg_args, has_postgresql_driver, load_args,
+        parse_gdal_version,
+    };
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn detects_dbf_encoding_from_language_driver_id() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let write_dbf = |name: &str, ldid: u8| {
+            let mut header = vec![0u8; 32];
+            header[0] = 0x03;
+            header[29] = ldid;
+            std::fs::write(dir.join(name).with_extension("dbf"), header).unwrap();
+            dir.join(name).with_extension("shp")
+        };
+
+        let sjis = write_dbf("sjis", 0x58);
+        assert_eq!(detect_dbf_encoding(&sjis).await, "CP932");
+
+        let utf8 = write_dbf("utf8", 0x00);
+        std::fs::write(utf8.with_extension("cpg"), b"\xEF\xBB\xBFUTF-8").unwrap();
+        assert_eq!(detect_dbf_encoding(&utf8).await, "UTF-8");
+
+        let unset = write_dbf("unset", 0x00);
+        assert_eq!(detect_dbf_encoding(&unset).await, "CP932");
+        assert_eq!(detect_dbf_encoding(&dir.join("missing.shp")).await, "CP932");
+    }
+
+    #[tokio::test]
+    async fn writes_escaped_vrt_and_rejects_missing_shapefiles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let shapes: Vec<_> = ["h27ka13", "a&b<c"]
+            .iter()
+            .map(|name| {
+                let shp = dir.join(name).with_extension("shp");
+                std::fs::write(&shp, b"").unwrap();
+                shp
+            })
+            .collect();
+        let vrt = dir.join("2015.vrt");
+        create_vrt(&vrt, &shapes).await.unwrap();
+
+        let xml = std::fs::read_to_string(&vrt).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        assert!(xml.starts_with("<OGRVRTDataSource>\n  <OGRVRTUnionLayer name=\"2015\">"));
+        assert!(xml.contains(&format!(
+            "<SrcDataSource>{}</SrcDataSource>",
+            dir.join("h27ka13.shp").display()
+        )));
+        assert!(xml.contains("<OGRVRTLayer name=\"a&amp;b&lt;c\">"));
+        assert!(xml.contains("<OOI key=\"ENCODING\">CP932</OOI>"));
+
+        let mut missing = shapes.clone();
+        missing.push(dir.join("h27ka14.shp"));
+        let err = create_vrt(&vrt, &missing).await.unwrap_err();
+        assert!(
+            err.to_string().starts_with("shapefile not found: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn builds_gpkg_arguments() {
+        let args = gpkg_args(
+            Path::new("tmp/mesh.geojson"),
+            Path::new("out/mesh.gpkg"),
+            "jp_estat_mesh_2020_T001140_3",
+            6668,
+        );
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "-f",
+                "GPKG",
+                "-overwrite",
+                "-nln",
+                "jp_estat_mesh_2020_T001140_3",
+                "-nlt",
+                "POLYGON",
+                "-a_srs",
+                "EPSG:6668",
+                "out/mesh.gpkg",
+                "tmp/mesh.geojson",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_the_loaded_layer_after_the_table() {
+        let args = load_args(
+            Path::new("tmp/jp_estat_areamap_2020.vrt"),
+            "PG:host=127.0.0.1 dbname=jp_estat",
+            None,
+            "jp_estat_areamap_2020",
+            Some("HCODE <> 8154"),
+            None,
+        );
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "-overwrite",
+                "-nln",
+                "jp_estat_areamap_2020",
+                "-where",
+                "HCODE <> 8154",
+                "-lco",
+                "GEOM_TYPE=geometry",
+                "-lco",
+                "GEOMETRY_NAME=geom",
+                "--config",
+                "PG_USE_COPY=YES",
+                "PG:host=127.0.0.1 dbname=jp_estat",
+                "tmp/jp_estat_areamap_2020.vrt",
+            ]
+        );
+
+        let args = load_args(
+            Path::new("tmp/jp_estat_areamap_2020.vrt"),
+            "out/areamap.gpkg",
+            Some("GPKG"),
+            "jp_estat_areamap_2020",
+            None,
+            Some("EPSG:4326"),
+        );
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "-f",
+                "GPKG",
+                "-overwrite",
+                "-nln",
+                "jp_estat_areamap_2020",
+                "-t_srs",
+                "EPSG:4326",
+                "out/areamap.gpkg",
+                "tmp/jp_estat_areamap_2020.vrt",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_the_postgresql_driver() {
+        let formats = "Supported Formats:\n  \
+            PGDUMP -vector- (w+v): PostgreSQL SQL dump\n  \
+            ESRI Shapefile -vector- (rw+v): ESRI Shapefile\n";
+        assert!(!has_postgresql_driver(formats));
+        let formats = format!(
+            "{}  PostgreSQL -vector- (rw+): PostgreSQL/PostGIS\n",
+            formats
+        );
+        assert!(has_postgresql_driver(&formats));
+    }
+
+    #[test]
+    fn parses_gdal_version_lines() {
+        assert_eq!(
+            parse_gdal_version("GDAL 3.6.4, released 2023/04/17\n"),
+            Some((3, 6, 4))
+        );
+        assert_eq!(
+            parse_gdal_version("GDAL 3.9.0dev-abc, released 2024/01/01"),
+            Some((3, 9, 0))
+        );
+        assert_eq!(
+            parse_gdal_version("GDAL 2.4, released 2019"),
+            Some((2, 4, 0))
+        );
+        assert_eq!(parse_gdal_version("ogr2ogr: command not found"), None);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{mesh::get_matching_mesh_stats, mesh_code_hierarchy::children};
+use anyhow::{Result, anyhow, bail};
+use encoding_rs::SHIFT_JIS;
+use std::{fmt::Write as _, io::Write as _, path::Path};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+/// Default of `--output-dir`.
+pub const DEFAULT_OUTPUT_DIR: &str = "tests/fixtures/synthetic";
+
+/// The Lv1 area the synthetic codes are taken from (central Tokyo).
+const LV1_CODE: u64 = 5339;
+
+/// Every `NO_DATA_EVERY`th row is confidential (`*` in every statistics column).
+const NO_DATA_EVERY: u32 = 7;
+
+/// Statistics columns written after `GASSAN`, as (item number, Japanese name).
+const ITEMS: [(u32, &str); 4] = [
+    (1, "人口（総数）"),
+    (2, "人口（総数）　男"),
+    (3, "人口（総数）　女"),
+    (4, "世帯総数"),
+];
+
+/// Deterministic xorshift, so the same arguments always produce the same fixture.
+struct XorShift(u32);
+
+impl XorShift {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+/// The CSV as e-Stat serves it, before Shift_JIS encoding: item codes in the first header
+/// row, item names in the second, CRLF line endings.
+fn build_csv(stats_id: &str, codes: &[u64]) -> String {
+    let mut csv = String::from("KEY_CODE,HTKSYORI,HTKSAKI,GASSAN");
+    for (n, _) in ITEMS {
+        let _ = write!(csv, ",{}{:03}", stats_id, n);
+    }
+    csv.push_str("\r\n,,,");
+    for (_, name) in ITEMS {
+        let _ = write!(csv, ",{}", name);
+    }
+    csv.push_str("\r\n");
+
+    let mut rng = XorShift(0x2545_f491);
+    for (i, code) in codes.iter().enumerate() {
+        let _ = write!(csv, "{},0,,", code);
+        if (i as u32 + 1).is_multiple_of(NO_DATA_EVERY) {
+            csv.push_str(&",*".repeat(ITEMS.len()));
+        } else {
+            let male = rng.next() % 1500;
+            let female = rng.next() % 1500;
+            let households = (male + female) / 2 + rng.next() % 50;
+            let _ = write!(csv, ",{},{},{},{}", male + female, male, female, households);
+        }
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Writes `tbl{stats_id}S{lv1}.zip` with one Shift_JIS `.txt` CSV of `num_codes` mesh codes
+/// of `level`, in the layout of the mesh archives downloaded from e-Stat.
+pub fn process_gen_test_fixtures(output_dir: &Path, level: u8, num_codes: u32) -> Result<()> {
+    let mesh_stats = get_matching_mesh_stats(level, 2020, "人口及び世帯")
+        .ok_or_else(|| anyhow!("no 2020 人口及び世帯 survey at level {}", level))?;
+    let all_codes = children(LV1_CODE, level)?;
+    if num_codes == 0 || num_codes as usize > all_codes.len() {
+        bail!(
+            "--num-codes must be between 1 and {} (the Lv{} cells of {})",
+            all_codes.len(),
+            level,
+            LV1_CODE
+        );
+    }
+    let codes = &all_codes[..num_codes as usize];
+    let csv = build_csv(&mesh_stats.stats_id, codes);
+    let (bytes, _, had_errors) = SHIFT_JIS.encode(&csv);
+    if had_errors {
+        bail!("the fixture CSV is not representable in Shift_JIS");
+    }
+
+    std::fs::create_dir_all(output_dir)?;
+    let name = format!("tbl{}S{}", mesh_stats.stats_id, LV1_CODE);
+    let zip_path = output_dir.join(format!("{}.zip", name));
+    let mut zip = ZipWriter::new(std::fs::File::create(&zip_path)?);
+    zip.start_file(
+        format!("{}.txt", name),
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+    )?;
+    zip.write_all(&bytes)?;
+    zip.finish()?;
+
+    println!(
+        "Wrote {} (Lv{}, {} mesh codes, every {}th row no data)",
+        zip_path.display(),
+        level,
+        codes.len(),
+        NO_DATA_EVERY
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read as _;
+
+    #[test]
+    fn writes_an_estat_style_zip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        process_gen_test_fixtures(dir, 4, 10).unwrap();
+
+        let file = std::fs::File::open(dir.join("tblT001141S5339.zip")).unwrap();
+        let mut archive = zip::ZipArchive::new(file).unwrap();
+        let mut entry = archive.by_name("tblT001141S5339.txt").unwrap();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes).unwrap();
+        let (text, _, had_errors) = SHIFT_JIS.decode(&bytes);
+        assert!(!had_errors);
+        let lines: Vec<&str> = text.split("\r\n").collect();
+        assert_eq!(
+            lines[0],
+            "KEY_CODE,HTKSYORI,HTKSAKI,GASSAN,T001141001,T001141002,T001141003,T001141004"
+        );
+        assert_eq!(
+            lines[1],
+            ",,,,人口（総数）,人口（総数）　男,人口（総数）　女,世帯総数"
+        );
+        assert_eq!(lines.len(), 2 + 10 + 1);
+        assert!(lines[2].starts_with("533900001,0,,,"));
+        assert_eq!(lines[8], "533900013,0,,,*,*,*,*");
+
+        assert!(process_gen_test_fixtures(dir, 4, 0).is_err());
+        assert!(process_gen_test_fixtures(dir, 4, 25_601).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::mesh_geometry;
+use anyhow::{Context, Result, anyhow, bail};
+use geo::{Contains, Intersects, Point, Polygon, Rect, coord};
+use wkt::TryFromWkt;
+
+/// Polygon given with `--geo-filter`, in lon/lat order (WKT `x y`).
+#[derive(Debug, Clone)]
+pub struct GeoFilter {
+    polygon: Polygon<f64>,
+}
+
+impl GeoFilter {
+    /// Parses and validates a WKT `POLYGON`. Called before any downloads start.
+    pub fn parse(wkt: &str) -> Result<Self> {
+        let polygon = Polygon::<f64>::try_from_wkt_str(wkt)
+            .map_err(|e| anyhow!("{}", e))
+            .with_context(|| "--geo-filter must be a WKT POLYGON")?;
+        // A closed ring needs at least 3 distinct points plus the closing point.
+        if polygon.exterior().0.len() < 4 {
+            bail!("--geo-filter polygon must have at least 3 vertices");
+        }
+        Ok(Self { polygon })
+    }
+
+    /// Returns true when the centroid of `mesh_code` falls inside the polygon.
+    pub fn contains_mesh_centroid(&self, mesh_code: u64) -> Result<bool> {
+        let (lon, lat) = mesh_geometry::cell_bounds(mesh_code)?.center();
+        Ok(self.polygon.contains(&Point::new(lon, lat)))
+    }
+
+    /// Returns true when the cell of `mesh_code` overlaps the polygon at all.
+    /// Used to skip downloading level-1 areas that cannot contain any matching cell.
+    pub fn intersects_mesh(&self, mesh_code: u64) -> Result<bool> {
+        let bounds = mesh_geometry::cell_bounds(mesh_code)?;
+        let rect = Rect::new(
+            coord! { x: bounds.min_lon, y: bounds.min_lat },
+            coord! { x: bounds.max_lon, y: bounds.max_lat },
+        );
+        Ok(self.polygon.intersects(&rect))
+    }
+}
+
+/// Keeps only the level-1 areas worth downloading for `geo_filter`.
+pub fn filter_lv1_codes(codes: &[u64], geo_filter: Option<&GeoFilter>) -> Result<Vec<u64>> {
+    let Some(geo_filter) = geo_filter else {
+        return Ok(codes.to_vec());
+    };
+    let mut filtered = Vec::new();
+    for code in codes {
+        if geo_filter.intersects_mesh(*code)? {
+            filtered.push(*code);
+        }
+    }
+    if filtered.is_empty() {
+        bail!("--geo-filter does not overlap any mesh area");
+    }
+    Ok(filtered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Roughly central Tokyo.
+    const TOKYO: &str = "POLYGON((139.6 35.6, 139.9 35.6, 139.9 35.8, 139.6 35.8, 139.6 35.6))";
+
+    #[test]
+    fn rejects_non_polygon_wkt() {
+        assert!(GeoFilter::parse("POINT(139.7 35.7)").is_err());
+        assert!(GeoFilter::parse("not wkt").is_err());
+        assert!(GeoFilter::parse(TOKYO).is_ok());
+    }
+
+    #[test]
+    fn filters_by_centroid() {
+        let filter = GeoFilter::parse(TOKYO).unwrap();
+        // 53394611 is near Tokyo Station; 52354600 is in western Japan.
+        assert!(filter.contains_mesh_centroid(53394611).unwrap());
+        assert!(!filter.contains_mesh_centroid(52354600).unwrap());
+    }
+
+    #[test]
+    fn narrows_lv1_download_targets() {
+        let filter = GeoFilter::parse(TOKYO).unwrap();
+        let codes = filter_lv1_codes(&[5339, 5235, 6441], Some(&filter)).unwrap();
+        assert_eq!(codes, vec![5339]);
+        assert_eq!(filter_lv1_codes(&[5339, 5235], None).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,79 @@
+//! Import e-Stat statistics (mesh surveys and 小地域 boundaries) into PostgreSQL and other
+//! formats. The `jp-estat-util` binary is a thin CLI over this crate; see [`import_mesh`],
+//! [`import_areamap`], [`encode_mesh_tiles`] and [`export_mesh_csv`] for programmatic use.
+
+mod api;
+pub mod areamap;
+pub mod areamap_compare;
+pub mod areamap_diff;
+pub mod areamap_export;
+pub mod areamap_geocoder;
+pub mod benchmark;
+pub mod cancel;
+mod column_alias;
+pub mod db_csv;
+pub mod download;
+mod download_manifest;
+pub mod duckdb;
+pub mod duckdb_script;
+pub mod error;
+mod estat_api;
+mod gdal;
+pub mod gen_test_fixtures;
+pub mod geo_filter;
+pub mod list_cached;
+pub mod lockfile;
+pub mod memory_report;
+pub mod mesh;
+pub mod mesh_aggregate;
+pub mod mesh_bbox;
+pub mod mesh_code_decode;
+pub mod mesh_code_hierarchy;
+pub mod mesh_code_lookup;
+pub mod mesh_code_range;
+pub mod mesh_code_validate;
+pub mod mesh_csv;
+pub mod mesh_csv_convert;
+pub mod mesh_csv_stats;
+pub mod mesh_csv_validate;
+pub mod mesh_diff;
+pub mod mesh_export;
+mod mesh_geometry;
+pub mod mesh_info;
+pub mod mesh_level_info;
+pub mod mesh_math;
+pub mod mesh_mbtiles;
+pub mod mesh_merge;
+pub mod mesh_normalize;
+mod mesh_stats;
+pub mod mesh_stats2;
+pub mod mesh_tile;
+pub mod mesh_tile_server;
+pub mod mesh_tile_verify;
+pub mod mesh_to_arrow;
+pub mod mesh_to_postgres;
+pub mod mesh_to_sqlite;
+pub mod mesh_to_vector;
+mod mesh_values;
+mod ogc_features;
+pub mod pipeline;
+mod postgres;
+pub mod progress;
+pub mod s3_upload;
+pub mod schema_check;
+pub mod self_test;
+pub mod shiftjis_csv;
+mod stac;
+mod stats;
+pub mod telemetry;
+mod unzip;
+pub mod update_survey_list;
+pub mod watch;
+
+pub use api::{
+    AreamapImportConfig, MeshCsvConfig, MeshImportConfig, MeshTileConfig, encode_mesh_tiles,
+    export_mesh_csv, import_areamap, import_mesh, watch_areamap, watch_mesh,
+};
+pub use areamap::{AreamapImportReport, MaterializedView, ShapeValidation};
+pub use mesh::MeshImportReport;
+pub use mesh_tile::TileEncodeReport;
@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListCachedFormat {
+    /// 種類ごとの一覧表
+    Table,
+    /// スクリプト向けの JSON
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Category {
+    /// Downloaded archives directly under `tmp_dir`; kept as the download cache.
+    Archive,
+    /// Files inside the directories the archives were extracted into.
+    Extracted,
+    /// Everything else (the download manifest, VRTs, ...).
+    Other,
+}
+
+impl Category {
+    fn of(relative_path: &Path) -> Self {
+        let top_level = relative_path.components().count() == 1;
+        let is_zip = relative_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+        match (top_level, is_zip) {
+            (true, true) => Category::Archive,
+            (true, false) => Category::Other,
+            (false, _) => Category::Extracted,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Category::Archive => "ZIP archives",
+            Category::Extracted => "Extracted files",
+            Category::Other => "Other files",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CachedFile {
+    path: PathBuf,
+    category: Category,
+    size_bytes: u64,
+    /// RFC 3339, UTC.
+    modified: Option<String>,
+}
+
+fn collect_files(tmp_dir: &Path, dir: &Path, files: &mut Vec<CachedFile>) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("when reading {}", dir.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_files(tmp_dir, &path, files)?;
+            continue;
+        }
+        let relative_path = path.strip_prefix(tmp_dir).unwrap_or(&path).to_path_buf();
+        files.push(CachedFile {
+            category: Category::of(&relative_path),
+            path: relative_path,
+            size_bytes: metadata.len(),
+            modified: metadata.modified().ok().map(format_time),
+        });
+    }
+    Ok(())
+}
+
+fn format_time(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// Binary units, as `du -h` prints them.
+pub(crate) fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn print_table(files: &[CachedFile]) {
+    let path_width = files
+        .iter()
+        .map(|file| file.path.display().to_string().chars().count())
+        .max()
+        .unwrap_or(0);
+    for category in [Category::Archive, Category::Extracted, Category::Other] {
+        let in_category: Vec<&CachedFile> = files
+            .iter()
+            .filter(|file| file.category == category)
+            .collect();
+        if in_category.is_empty() {
+            continue;
+        }
+        let total: u64 = in_category.iter().map(|file| file.size_bytes).sum();
+        println!(
+            "{} ({} files, {})",
+            category.label(),
+            in_category.len(),
+            human_size(total)
+        );
+        for file in in_category {
+            let path = file.path.display().to_string();
+            let padding = path_width - path.chars().count();
+            println!(
+                "  {}{}  {:>10}  {}",
+                path,
+                " ".repeat(padding),
+                human_size(file.size_bytes),
+                file.modified.as_deref().unwrap_or("-")
+            );
+        }
+        println!();
+    }
+}
+
+pub async fn process_list_cached(tmp_dir: &Path, format: ListCachedFormat) -> Result<()> {
+    let mut files = Vec::new();
+    if tmp_dir.exists() {
+        collect_files(tmp_dir, tmp_dir, &mut files)?;
+    }
+    files.sort_by(|a, b| (a.category, &a.path).cmp(&(b.category, &b.path)));
+    let total_bytes: u64 = files.iter().map(|file| file.size_bytes).sum();
+
+    match format {
+        ListCachedFormat::Table => {
+            print_table(&files);
+            println!(
+                "Total: {} files, {} in {}",
+                files.len(),
+                human_size(total_bytes),
+                tmp_dir.display()
+            );
+        }
+        ListCachedFormat::Json => {
+            let summary = serde_json::json!({
+                "tmp_dir": tmp_dir,
+                "total_files": files.len(),
+                "total_bytes": total_bytes,
+                "files": files,
+            });
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_sizes_in_binary_units() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[test]
+    fn groups_archives_and_extracted_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("2020-T001140-5339")).unwrap();
+        std::fs::write(dir.join("2020-T001140-5339.zip"), [0u8; 10]).unwrap();
+        std::fs::write(dir.join("2020-T001140-5339").join("a.txt"), [0u8; 20]).unwrap();
+        std::fs::write(dir.join("downloads_manifest.json"), b"{}").unwrap();
+
+        let mut files = Vec::new();
+        collect_files(dir, dir, &mut files).unwrap();
+        files.sort_by(|a, b| (a.category, &a.path).cmp(&(b.category, &b.path)));
+        let summary: Vec<(Category, u64)> = files
+            .iter()
+            .map(|file| (file.category, file.size_bytes))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Category::Archive, 10),
+                (Category::Extracted, 20),
+                (Category::Other, 2)
+            ]
+        );
+        assert_eq!(files[1].path, Path::new("2020-T001140-5339").join("a.txt"));
+        assert!(files[0].modified.as_deref().unwrap().ends_with('Z'));
+    }
+}
@@ -0,0 +1,321 @@
+use crate::download;
+use anyhow::{Context, Result, bail};
+use futures::stream;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write as _,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+/// Written to the current directory by `mesh` unless `--no-lockfile` is given.
+pub const DEFAULT_LOCKFILE: &str = "jp-estat-lockfile.toml";
+
+/// One downloaded archive (one Lv1 file of a mesh survey).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedDataset {
+    pub survey: String,
+    pub year: u16,
+    pub level: u8,
+    pub stats_id: String,
+    /// Archive file name in `--tmp-dir`.
+    pub file: String,
+    pub download_url: String,
+    pub sha256: String,
+    /// RFC 3339, UTC. The archive's modification time, so cached downloads keep the time
+    /// they were actually fetched.
+    pub downloaded_at: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Lockfile {
+    #[serde(default, rename = "dataset")]
+    datasets: Vec<LockedDataset>,
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("when opening {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let mut hex = String::with_capacity(64);
+    for byte in hasher.finalize() {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    Ok(hex)
+}
+
+fn read_lockfile(path: &Path) -> Result<Lockfile> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("when reading {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("when parsing {}", path.display()))
+}
+
+/// Replaces the entries of the same survey, year and level as `datasets`, keeping the
+/// entries of other datasets. Entries are sorted so the file diffs cleanly.
+fn merge_datasets(
+    existing: Vec<LockedDataset>,
+    datasets: Vec<LockedDataset>,
+) -> Vec<LockedDataset> {
+    let replaced = |entry: &LockedDataset| {
+        datasets
+            .iter()
+            .any(|d| d.stats_id == entry.stats_id && d.year == entry.year && d.level == entry.level)
+    };
+    let mut merged: Vec<LockedDataset> = existing.into_iter().filter(|e| !replaced(e)).collect();
+    merged.extend(datasets);
+    merged.sort_by(|a, b| {
+        (a.year, &a.stats_id, a.level, &a.file).cmp(&(b.year, &b.stats_id, b.level, &b.file))
+    });
+    merged
+}
+
+/// Hashes `archives` (file name in `tmp_dir`, download URL) and records them in the
+/// lockfile at `path`.
+pub(crate) async fn record_downloads(
+    path: &Path,
+    tmp_dir: &Path,
+    survey: &str,
+    year: u16,
+    level: u8,
+    stats_id: &str,
+    archives: Vec<(String, Url)>,
+) -> Result<()> {
+    let count = archives.len();
+    let tmp_dir = tmp_dir.to_path_buf();
+    let hashed = tokio::task::spawn_blocking(move || {
+        archives
+            .into_iter()
+            .map(|(file, url)| {
+                let archive = tmp_dir.join(&file);
+                let modified = std::fs::metadata(&archive)?.modified()?;
+                Ok((
+                    file,
+                    url,
+                    sha256_file(&archive)?,
+                    humantime::format_rfc3339_seconds(modified).to_string(),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()
+    })
+    .await??;
+    let datasets = hashed
+        .into_iter()
+        .map(|(file, url, sha256, downloaded_at)| LockedDataset {
+            survey: survey.to_string(),
+            year,
+            level,
+            stats_id: stats_id.to_string(),
+            file,
+            download_url: url.to_string(),
+            sha256,
+            downloaded_at,
+        })
+        .collect();
+
+    let existing = if path.exists() {
+        read_lockfile(path)?.datasets
+    } else {
+        Vec::new()
+    };
+    let lockfile = Lockfile {
+        datasets: merge_datasets(existing, datasets),
+    };
+    tokio::fs::write(path, toml::to_string(&lockfile)?)
+        .await
+        .with_context(|| format!("when writing {}", path.display()))?;
+    println!("Recorded {} archives in {}", count, path.display());
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Ok,
+    Missing,
+    Mismatch { actual: String },
+}
+
+fn check(tmp_dir: &Path, dataset: &LockedDataset) -> Result<Status> {
+    let archive = tmp_dir.join(&dataset.file);
+    if !archive.exists() {
+        return Ok(Status::Missing);
+    }
+    let actual = sha256_file(&archive)?;
+    Ok(if actual == dataset.sha256 {
+        Status::Ok
+    } else {
+        Status::Mismatch { actual }
+    })
+}
+
+/// Prints problems and fails when any archive is missing or differs from the lockfile.
+fn report(lockfile: &Path, tmp_dir: &Path, datasets: &[LockedDataset]) -> Result<()> {
+    let mut problems = 0;
+    for dataset in datasets {
+        match check(tmp_dir, dataset)? {
+            Status::Ok => {}
+            Status::Missing => {
+                problems += 1;
+                println!("missing: {}", dataset.file);
+            }
+            Status::Mismatch { actual } => {
+                problems += 1;
+                println!(
+                    "sha256 mismatch: {} (expected {}, got {})",
+                    dataset.file, dataset.sha256, actual
+                );
+            }
+        }
+    }
+    if problems > 0 {
+        bail!(
+            "{} of {} archives do not match {}",
+            problems,
+            datasets.len(),
+            lockfile.display()
+        );
+    }
+    println!(
+        "All {} archives match {}",
+        datasets.len(),
+        lockfile.display()
+    );
+    Ok(())
+}
+
+/// Re-downloads the archives listed in the lockfile and verifies their checksums. Cached
+/// archives that already match are kept; ones that differ are downloaded again.
+pub async fn process_lock(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    lockfile: &Path,
+) -> Result<()> {
+    let datasets = read_lockfile(lockfile)?.datasets;
+    if datasets.is_empty() {
+        bail!("{} lists no datasets", lockfile.display());
+    }
+    let mut to_fetch: Vec<(String, Url)> = Vec::new();
+    for dataset in &datasets {
+        match check(tmp_dir, dataset)? {
+            Status::Ok => {}
+            status => {
+                if matches!(status, Status::Mismatch { .. }) {
+                    let archive = tmp_dir.join(&dataset.file);
+                    tokio::fs::remove_file(&archive).await?;
+                    // The extracted copy belongs to the old archive.
+                    let extracted: PathBuf = archive.with_extension("");
+                    if extracted.exists() {
+                        tokio::fs::remove_dir_all(&extracted).await?;
+                    }
+                }
+                let url = Url::parse(&dataset.download_url)
+                    .with_context(|| format!("invalid download_url for {}", dataset.file))?;
+                to_fetch.push((dataset.file.clone(), url));
+            }
+        }
+    }
+    println!(
+        "{} of {} archives cached, downloading {}",
+        datasets.len() - to_fetch.len(),
+        datasets.len(),
+        to_fetch.len()
+    );
+    if !to_fetch.is_empty() {
+        download::into_result(
+            download::download_and_extract_all(
+                http_client,
+                stream::iter(to_fetch),
+                |(_file, url)| url.clone(),
+                |(file, _url)| file.clone(),
+                "txt",
+                tmp_dir,
+                "Downloading locked archives...",
+                "Extracting locked archives...",
+                10,
+            )
+            .await?,
+        )?;
+    }
+    report(lockfile, tmp_dir, &datasets)
+}
+
+/// Checks the cached archives in `tmp_dir` against the lockfile without downloading.
+pub async fn process_verify(tmp_dir: &Path, lockfile: &Path) -> Result<()> {
+    let datasets = read_lockfile(lockfile)?.datasets;
+    report(lockfile, tmp_dir, &datasets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataset(stats_id: &str, file: &str, sha256: &str) -> LockedDataset {
+        LockedDataset {
+            survey: "人口及び世帯".to_string(),
+            year: 2020,
+            level: 3,
+            stats_id: stats_id.to_string(),
+            file: file.to_string(),
+            download_url: format!(
+                "https://www.e-stat.go.jp/gis/statmap-search/data?statsId={}&code=5339&downloadType=2",
+                stats_id
+            ),
+            sha256: sha256.to_string(),
+            downloaded_at: "2026-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    #[test]
+    fn replaces_entries_of_the_same_dataset() {
+        let existing = vec![
+            dataset("T001141", "2020-T001141-5339.zip", "aa"),
+            dataset("T001140", "2020-T001140-5340.zip", "old"),
+        ];
+        let merged = merge_datasets(
+            existing,
+            vec![dataset("T001140", "2020-T001140-5339.zip", "new")],
+        );
+        let files: Vec<&str> = merged.iter().map(|d| d.file.as_str()).collect();
+        assert_eq!(files, ["2020-T001140-5339.zip", "2020-T001141-5339.zip"]);
+    }
+
+    #[test]
+    fn round_trips_and_verifies_archives() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("2020-T001140-5339.zip"), b"abc").unwrap();
+        // SHA-256 of "abc".
+        let sha = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(
+            sha256_file(&dir.join("2020-T001140-5339.zip")).unwrap(),
+            sha
+        );
+
+        let lockfile = Lockfile {
+            datasets: vec![
+                dataset("T001140", "2020-T001140-5339.zip", sha),
+                dataset("T001140", "2020-T001140-5340.zip", "00"),
+            ],
+        };
+        let text = toml::to_string(&lockfile).unwrap();
+        assert!(text.starts_with("[[dataset]]\nsurvey = \"人口及び世帯\""));
+        let path = dir.join(DEFAULT_LOCKFILE);
+        std::fs::write(&path, text).unwrap();
+        let parsed = read_lockfile(&path).unwrap();
+        assert_eq!(parsed.datasets, lockfile.datasets);
+
+        assert_eq!(check(dir, &parsed.datasets[0]).unwrap(), Status::Ok);
+        assert_eq!(check(dir, &parsed.datasets[1]).unwrap(), Status::Missing);
+        let err = report(&path, dir, &parsed.datasets).unwrap_err();
+        assert!(err.to_string().starts_with("1 of 2 archives do not match"));
+    }
+}
@@ -0,0 +1,145 @@
+//! `--report-memory`: resident memory after each processing phase, read from
+//! `/proc/self/status` (Linux only).
+
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PHASES: Mutex<Vec<PhaseMemory>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PhaseMemory {
+    phase: &'static str,
+    /// `VmRSS` at the end of the phase, in KiB.
+    rss_kb: u64,
+    /// `VmHWM` (peak RSS) during the phase, in KiB. The peak is reset after each phase, so it
+    /// covers that phase only; where the kernel refuses the reset it is the peak so far.
+    peak_kb: u64,
+}
+
+pub fn enable() {
+    if !cfg!(target_os = "linux") {
+        eprintln!("Warning: --report-memory is only supported on Linux");
+        return;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    reset_peak();
+}
+
+/// `(VmRSS, VmHWM)` in KiB.
+fn parse_status(status: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))?
+            .trim()
+            .strip_suffix("kB")?
+            .trim()
+            .parse::<u64>()
+            .ok()
+    };
+    Some((field("VmRSS:")?, field("VmHWM:")?))
+}
+
+/// Writing `5` to `clear_refs` resets `VmHWM` to the current RSS (Linux 4.0+).
+fn reset_peak() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+/// Records the memory use of the phase that just finished. Does nothing unless enabled.
+pub(crate) fn record(phase: &'static str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some((rss_kb, peak_kb)) = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .as_deref()
+        .and_then(parse_status)
+    else {
+        return;
+    };
+    reset_peak();
+    tracing::info!(phase, rss_kb, peak_kb, "memory usage");
+    eprintln!(
+        "Memory after {}: RSS {} MiB, peak {} MiB",
+        phase,
+        rss_kb / 1024,
+        peak_kb / 1024
+    );
+    PHASES.lock().unwrap().push(PhaseMemory {
+        phase,
+        rss_kb,
+        peak_kb,
+    });
+}
+
+/// One row per phase label, in first-seen order: the highest peak and the last RSS of every
+/// run of that phase (`areamap` downloads once per year, for example).
+fn summarize(phases: &[PhaseMemory]) -> Vec<PhaseMemory> {
+    let mut rows: Vec<PhaseMemory> = Vec::new();
+    for phase in phases {
+        match rows.iter_mut().find(|row| row.phase == phase.phase) {
+            Some(row) => {
+                row.rss_kb = phase.rss_kb;
+                row.peak_kb = row.peak_kb.max(phase.peak_kb);
+            }
+            None => rows.push(*phase),
+        }
+    }
+    rows
+}
+
+/// Prints the per-phase peaks, if anything was recorded.
+pub fn print_summary() {
+    let rows = summarize(&PHASES.lock().unwrap());
+    if rows.is_empty() {
+        return;
+    }
+    let width = rows.iter().map(|row| row.phase.len()).max().unwrap_or(0);
+    println!("{:<width$}  {:>10}  {:>10}", "phase", "peak MiB", "RSS MiB");
+    for row in &rows {
+        println!(
+            "{:<width$}  {:>10.1}  {:>10.1}",
+            row.phase,
+            row.peak_kb as f64 / 1024.0,
+            row.rss_kb as f64 / 1024.0
+        );
+    }
+    let overall = rows.iter().map(|row| row.peak_kb).max().unwrap_or(0);
+    println!("Peak RSS: {:.1} MiB", overall as f64 / 1024.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_proc_status() {
+        let status = "Name:\tjp-estat-util\nVmPeak:\t  912340 kB\nVmHWM:\t  204800 kB\nVmRSS:\t  102400 kB\n";
+        assert_eq!(parse_status(status), Some((102_400, 204_800)));
+        assert_eq!(parse_status("Name:\tjp-estat-util\n"), None);
+    }
+
+    #[test]
+    fn keeps_the_highest_peak_per_phase() {
+        let phase = |phase, rss_kb, peak_kb| PhaseMemory {
+            phase,
+            rss_kb,
+            peak_kb,
+        };
+        let rows = summarize(&[
+            phase("download + extract", 100, 300),
+            phase("import", 120, 500),
+            phase("download + extract", 110, 200),
+        ]);
+        assert_eq!(
+            rows,
+            [
+                phase("download + extract", 110, 300),
+                phase("import", 120, 500)
+            ]
+        );
+    }
+}
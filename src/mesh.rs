@@ -1,30 +1,23 @@
-use crate::download::{self, DownloadedItem};
-use anyhow::{Context, Result, anyhow};
-use csv::ReaderBuilder;
-use encoding_rs::SHIFT_JIS;
-use encoding_rs_io::DecodeReaderBytesBuilder;
+use crate::{
+    MeshImportConfig, cancel,
+    download::{self, DownloadedItem},
+    error::JpEstatError,
+    geo_filter::{self, GeoFilter},
+    lockfile, memory_report, mesh_geometry, mesh_stats, postgres, progress,
+    shiftjis_csv::open_shiftjis_csv,
+    telemetry::SpanTimer,
+};
+use anyhow::{Context, Result, anyhow, bail};
 use futures::stream;
-use indicatif::{ProgressBar, ProgressStyle};
 use jismesh::codes::JAPAN_LV1;
-use serde::Deserialize;
-use std::{io::BufReader, path::Path, str::FromStr};
+use km_to_sql::metadata::{ColumnMetadata, TableMetadata};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, str::FromStr};
 use tokio_postgres::{NoTls, types::ToSql};
+use tracing::Span;
 use url::Url;
 
-fn open_shiftjis_csv(path: &str) -> csv::Reader<Box<dyn std::io::Read>> {
-    let file = std::fs::File::open(path).expect("failed to open file");
-    let reader = BufReader::new(file);
-
-    let transcoded = DecodeReaderBytesBuilder::new()
-        .encoding(Some(SHIFT_JIS))
-        .build(reader);
-
-    ReaderBuilder::new()
-        .has_headers(false) // we'll handle headers ourselves
-        .from_reader(Box::new(transcoded))
-}
-
-fn parse_nullable<T>(value: &str) -> Result<Option<T>>
+pub(crate) fn parse_nullable<T>(value: &str) -> Result<Option<T>>
 where
     T: FromStr,
     <T as FromStr>::Err: std::error::Error + Send + Sync + 'static,
@@ -37,22 +30,22 @@ where
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct MeshStatsConfig {
-    mesh_stats: Vec<MeshStats>,
+/// `mesh_stats.json`, also rewritten by `update-survey-list`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct MeshStatsConfig {
+    pub(crate) mesh_stats: Vec<MeshStats>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct MeshStats {
-    name: String,
-    year: u16,
-    meshlevel: u8,
-    stats_id: String,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct MeshStats {
+    pub(crate) name: String,
+    pub(crate) year: u16,
+    pub(crate) meshlevel: u8,
+    pub(crate) stats_id: String,
 
     /// The EPSG code the mesh code is based on.
     /// Valid values: 4301 (Tokyo Datum), 4612 (JGD2000), 6668 (JGD2011)
-    #[allow(dead_code)]
-    datum: u16,
+    pub(crate) datum: u16,
 }
 
 lazy_static::lazy_static! {
@@ -64,16 +57,17 @@ lazy_static::lazy_static! {
     };
 }
 
-fn get_matching_mesh_stats(level: u8, year: u16, survey: &str) -> Option<&'static MeshStats> {
-    for mesh in AVAILABLE.iter() {
-        if mesh.meshlevel == level && mesh.year == year && mesh.name == survey {
-            return Some(mesh);
-        }
-    }
-    None
+pub(crate) fn get_matching_mesh_stats(
+    level: u8,
+    year: u16,
+    survey: &str,
+) -> Option<&'static MeshStats> {
+    AVAILABLE
+        .iter()
+        .find(|mesh| mesh.meshlevel == level && mesh.year == year && mesh.name == survey)
 }
 
-fn infer_column_type(col: &str) -> &'static str {
+pub(crate) fn infer_column_type(col: &str) -> &'static str {
     if col == "KEY_CODE" || col == "HTKSAKI" {
         "BIGINT"
     } else if col == "GASSAN" {
@@ -85,14 +79,58 @@ fn infer_column_type(col: &str) -> &'static str {
     }
 }
 
-/// Given a path to a CSV file, create a schema in the Postgres database
-/// Returns a tuple of (table name, column names)
-async fn create_schema(
-    client: &tokio_postgres::Client,
-    mesh_stats: &MeshStats,
-    file: &Path,
-) -> Result<(String, Vec<String>)> {
-    let mut rdr = open_shiftjis_csv(file.to_str().unwrap());
+/// PostgreSQL types given with `--column-types`, keyed by column name. They replace the
+/// inferred types, and the CSV values of those columns are passed as text and cast.
+pub(crate) type ColumnTypes = HashMap<String, String>;
+
+/// Rejects anything that is not plausibly a type name, since the types are spliced into DDL.
+fn validate_column_type(data_type: &str) -> Result<()> {
+    let valid = !data_type.trim().is_empty()
+        && data_type
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || " _(),[]".contains(c));
+    if !valid {
+        bail!("invalid PostgreSQL type in --column-types: {:?}", data_type);
+    }
+    Ok(())
+}
+
+fn parse_column_types(json: &str) -> Result<ColumnTypes> {
+    let types: ColumnTypes =
+        serde_json::from_str(json).context("expected a JSON object of column name to type")?;
+    for data_type in types.values() {
+        validate_column_type(data_type)?;
+    }
+    Ok(types)
+}
+
+pub(crate) async fn load_column_types(path: &Path) -> Result<ColumnTypes> {
+    let json = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("when reading {}", path.display()))?;
+    parse_column_types(&json).with_context(|| format!("when parsing {}", path.display()))
+}
+
+/// Keys of `column_types` that do not name a CSV column, in sorted order.
+fn unknown_column_types<'a>(column_types: &'a ColumnTypes, columns: &[String]) -> Vec<&'a str> {
+    let mut unknown: Vec<&str> = column_types
+        .keys()
+        .map(String::as_str)
+        .filter(|key| !columns.iter().any(|col| col == key))
+        .collect();
+    unknown.sort_unstable();
+    unknown
+}
+
+/// Reads the column names of a mesh CSV from its two header rows.
+pub(crate) fn read_columns(file: &Path) -> Result<Vec<String>> {
+    read_header(file).map(|(_, columns)| columns)
+}
+
+/// Returns the first header row (KEY_CODE, ..., stat codes such as `T001140001`) and the
+/// column names derived from both rows.
+fn read_header(file: &Path) -> Result<(Vec<String>, Vec<String>)> {
+    let mut rdr = open_shiftjis_csv(file)?;
 
     // Read headers
     let header1 = rdr.records().next().unwrap()?; // first header row
@@ -114,61 +152,288 @@ async fn create_schema(
             col.trim().replace("\u{3000}", "").to_string()
         })
         .collect();
+    let codes = header1.iter().map(|code| code.trim().to_string()).collect();
+    Ok((codes, columns))
+}
 
-    let column_defs: Vec<String> = columns
-        .iter()
-        .map(|col| format!("\"{}\" {}", col, infer_column_type(col)))
+/// Descriptions of the fixed columns every mesh CSV starts with.
+fn fixed_column_desc(column: &str) -> Option<&'static str> {
+    match column {
+        "KEY_CODE" => Some("地域メッシュコード"),
+        "HTKSYORI" => Some("秘匿処理 (0: なし, 1: 秘匿, 2: 合算)"),
+        "HTKSAKI" => Some("秘匿先のメッシュコード"),
+        "GASSAN" => Some("合算したメッシュコード"),
+        _ => None,
+    }
+}
+
+/// Table and column descriptions for `--comment-table`. Stat columns are described by their
+/// name and e-Stat item code (`人口（総数） (T001140001)`).
+fn table_metadata(
+    mesh_stats: &MeshStats,
+    codes: &[String],
+    columns: &[String],
+    column_types: &ColumnTypes,
+) -> TableMetadata {
+    let columns = column_schema(columns, column_types)
+        .enumerate()
+        .map(|(i, (column, data_type))| {
+            let desc = match (fixed_column_desc(column), codes.get(i)) {
+                (Some(desc), _) => desc.to_string(),
+                (None, Some(code)) if !code.is_empty() && code != column => {
+                    format!("{} ({})", column, code)
+                }
+                (None, _) => column.to_string(),
+            };
+            ColumnMetadata {
+                name: column.to_string(),
+                desc: Some(desc),
+                data_type: data_type.to_string(),
+                foreign_key: None,
+                enum_values: None,
+            }
+        })
         .collect();
+    TableMetadata {
+        name: format!(
+            "{} {}年 Lv{}メッシュ",
+            mesh_stats.name, mesh_stats.year, mesh_stats.meshlevel
+        ),
+        desc: Some(format!(
+            "e-Stat 地域メッシュ統計 (統計表ID {}, EPSG:{})",
+            mesh_stats.stats_id, mesh_stats.datum
+        )),
+        source: Some("総務省統計局".to_string()),
+        source_url: None,
+        license: None,
+        license_url: Some(Url::parse("https://www.e-stat.go.jp/terms-of-use").unwrap()),
+        primary_key: None,
+        columns,
+    }
+}
 
-    let table_name = format!(
-        "jp_estat_mesh_{}_{}_{}",
-        mesh_stats.year, mesh_stats.stats_id, mesh_stats.meshlevel,
-    );
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// `COMMENT ON` statements for the table and each described column.
+fn comment_statements(table_name: &str, metadata: &TableMetadata) -> Vec<String> {
+    let table_comment = match metadata.desc.as_deref() {
+        Some(desc) => format!("{}: {}", metadata.name, desc),
+        None => metadata.name.clone(),
+    };
+    let table = postgres::folded_ident(table_name);
+    let mut statements = vec![format!(
+        "COMMENT ON TABLE {} IS {}",
+        table,
+        quote_literal(&table_comment)
+    )];
+    statements.extend(metadata.columns.iter().filter_map(|column| {
+        column.desc.as_deref().map(|desc| {
+            format!(
+                "COMMENT ON COLUMN {}.{} IS {}",
+                table,
+                postgres::pg_ident(&column.name),
+                quote_literal(desc)
+            )
+        })
+    }));
+    statements
+}
+
+/// PostgreSQL type of each column, as written by [`create_schema`].
+pub(crate) fn column_schema<'a>(
+    columns: &'a [String],
+    column_types: &'a ColumnTypes,
+) -> impl Iterator<Item = (&'a str, &'a str)> {
+    columns.iter().map(|col| {
+        let data_type = column_types
+            .get(col)
+            .map(String::as_str)
+            .unwrap_or_else(|| infer_column_type(col));
+        (col.as_str(), data_type)
+    })
+}
+
+fn column_defs(columns: &[String], column_types: &ColumnTypes) -> Vec<String> {
+    column_schema(columns, column_types)
+        .map(|(col, data_type)| format!("{} {}", postgres::pg_ident(col), data_type))
+        .collect()
+}
+
+pub(crate) fn table_name(table_prefix: &str, mesh_stats: &MeshStats) -> String {
+    format!(
+        "{}mesh_{}_{}_{}",
+        table_prefix, mesh_stats.year, mesh_stats.stats_id, mesh_stats.meshlevel,
+    )
+}
+
+/// Given a path to a CSV file, create a schema in the Postgres database
+/// Returns a tuple of (table name, column names)
+#[tracing::instrument(skip_all, fields(file.name = %file.display(), table.name, duration_ms))]
+async fn create_schema(
+    client: &tokio_postgres::Client,
+    table_name: &str,
+    mesh_stats: &MeshStats,
+    file: &Path,
+    column_types: &ColumnTypes,
+    comment_table: bool,
+) -> Result<Vec<String>> {
+    let _timer = SpanTimer::new(&Span::current());
+    let (codes, columns) = read_header(file)?;
+    for key in unknown_column_types(column_types, &columns) {
+        eprintln!(
+            "Warning: --column-types column \"{}\" is not in the CSV and was ignored",
+            key
+        );
+    }
+    let column_defs = column_defs(&columns, column_types);
+
+    Span::current().record("table.name", table_name);
+    let table = postgres::folded_ident(table_name);
     client
-        .execute(&format!("DROP TABLE IF EXISTS {}", &table_name), &[])
+        .execute(&format!("DROP TABLE IF EXISTS {}", table), &[])
         .await?;
-    let create_stmt = format!("CREATE TABLE {} ({});", &table_name, column_defs.join(", "));
+    let create_stmt = format!("CREATE TABLE {} ({});", table, column_defs.join(", "));
     client.execute(&create_stmt, &[]).await?;
+    if comment_table {
+        let metadata = table_metadata(mesh_stats, &codes, &columns, column_types);
+        client
+            .batch_execute(&comment_statements(table_name, &metadata).join(";\n"))
+            .await
+            .context("when adding table comments")?;
+    }
 
-    Ok((table_name, columns))
+    Ok(columns)
 }
 
-async fn import_csv_to_postgres(
-    client: &mut tokio_postgres::Client,
-    file: &Path,
+/// Creates a session-local table shaped like `file`, for measuring imports without touching
+/// real tables. Returns the column names.
+pub(crate) async fn create_temp_table(
+    client: &tokio_postgres::Client,
     table_name: &str,
+    file: &Path,
+) -> Result<Vec<String>> {
+    let columns = read_columns(file)?;
+    client
+        .batch_execute(&format!(
+            "CREATE TEMP TABLE {} ({});",
+            postgres::folded_ident(table_name),
+            column_defs(&columns, &ColumnTypes::new()).join(", ")
+        ))
+        .await?;
+    Ok(columns)
+}
+
+/// Rows per multi-row `INSERT` unless `--insert-batch-size` says otherwise.
+pub const DEFAULT_INSERT_BATCH_SIZE: usize = 500;
+
+/// PostgreSQL's limit on bind parameters in one statement.
+const MAX_BIND_PARAMS: usize = 65_535;
+
+type RowParams = Vec<Box<dyn ToSql + Sync>>;
+
+/// `INSERT INTO table (cols) VALUES (…), (…), …` with one placeholder tuple per row of
+/// `batch`. Overridden `column_types` are sent as text and cast on the server.
+pub(crate) fn build_batch_insert(
+    table: &str,
     columns: &[String],
-) -> Result<()> {
-    let mut rdr = open_shiftjis_csv(file.to_str().unwrap());
-    let insert_sql = format!(
-        "INSERT INTO {} ({}) VALUES ({})",
-        table_name,
+    column_types: &ColumnTypes,
+    batch: &[RowParams],
+) -> String {
+    let rows = (0..batch.len())
+        .map(|row| {
+            let values = columns
+                .iter()
+                .enumerate()
+                .map(|(i, col)| {
+                    let n = row * columns.len() + i + 1;
+                    match column_types.get(col) {
+                        Some(data_type) => format!("CAST(${}::text AS {})", n, data_type),
+                        None => format!("${}", n),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("({})", values)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "INSERT INTO {} ({}) VALUES {}",
+        postgres::folded_ident(table),
         columns
             .iter()
-            .map(|c| format!("\"{}\"", c))
+            .map(|c| postgres::pg_ident(c))
             .collect::<Vec<_>>()
             .join(", "),
-        columns
-            .iter()
-            .enumerate()
-            .map(|(i, _)| format!("${}", i + 1))
-            .collect::<Vec<_>>()
-            .join(", ")
-    );
-    let insert_stmt = client.prepare(&insert_sql).await?;
+        rows
+    )
+}
+
+async fn insert_batch(
+    tx: &tokio_postgres::Transaction<'_>,
+    statement: &tokio_postgres::Statement,
+    batch: &[RowParams],
+) -> Result<()> {
+    let params: Vec<&(dyn ToSql + Sync)> = batch
+        .iter()
+        .flat_map(|row| row.iter().map(|p| p.as_ref()))
+        .collect();
+    tx.execute(statement, &params).await?;
+    Ok(())
+}
+
+/// Imports one CSV file into `table_name`.
+/// Rows are sent `insert_batch_size` at a time in multi-row `INSERT`s.
+/// Returns the number of data rows (excluding the two header rows) that were inserted.
+#[tracing::instrument(
+    name = "import_csv_batch",
+    skip_all,
+    fields(file.name = %file.display(), table.name = table_name, rows.count, duration_ms)
+)]
+pub(crate) async fn import_csv_to_postgres(
+    client: &mut tokio_postgres::Client,
+    file: &Path,
+    table_name: &str,
+    columns: &[String],
+    column_types: &ColumnTypes,
+    geo_filter: Option<&GeoFilter>,
+    insert_batch_size: usize,
+) -> Result<u64> {
+    let _timer = SpanTimer::new(&Span::current());
+    let mut rdr = open_shiftjis_csv(file)?;
+    let batch_size = insert_batch_size
+        .min(MAX_BIND_PARAMS / columns.len().max(1))
+        .max(1);
 
     let tx = client.transaction().await?;
+    let mut full_batch_stmt = None;
 
     // Skip the first two header rows
     rdr.records().next().unwrap()?;
     rdr.records().next().unwrap()?;
 
+    let mut row_count = 0u64;
+    let mut batch: Vec<RowParams> = Vec::with_capacity(batch_size);
     for result in rdr.records() {
+        // Stop between rows so the rows inserted so far are committed below.
+        if cancel::is_cancelled() {
+            break;
+        }
         let record = result?;
-        let mut params: Vec<Box<dyn ToSql + Sync>> = Vec::with_capacity(columns.len());
+        if let Some(filter) = geo_filter {
+            let mesh_code: u64 = record.get(0).unwrap_or("").trim().parse()?;
+            if !filter.contains_mesh_centroid(mesh_code)? {
+                continue;
+            }
+        }
+        let mut params: RowParams = Vec::with_capacity(columns.len());
         for (i, col) in columns.iter().enumerate() {
             let value = record.get(i).unwrap_or("");
-            if col == "KEY_CODE" || col == "HTKSAKI" {
+            if column_types.contains_key(col) {
+                params.push(Box::new(parse_nullable::<String>(value)?));
+            } else if col == "KEY_CODE" || col == "HTKSAKI" {
                 params.push(Box::new(parse_nullable::<i64>(value)?));
             } else if col == "HTKSYORI" {
                 params.push(Box::new(parse_nullable::<i16>(value)?));
@@ -186,82 +451,611 @@ async fn import_csv_to_postgres(
                 params.push(Box::new(parse_nullable::<i32>(value)?));
             }
         }
-        tx.execute(
-            &insert_stmt,
-            &params.iter().map(|p| p.as_ref()).collect::<Vec<_>>(),
-        )
-        .await?;
+        batch.push(params);
+        if batch.len() == batch_size {
+            // Every full batch has the same shape, so it is prepared once per file.
+            let stmt = match &full_batch_stmt {
+                Some(stmt) => stmt,
+                None => full_batch_stmt.insert(
+                    tx.prepare(&build_batch_insert(
+                        table_name,
+                        columns,
+                        column_types,
+                        &batch,
+                    ))
+                    .await?,
+                ),
+            };
+            insert_batch(&tx, stmt, &batch).await?;
+            row_count += batch.len() as u64;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        let stmt = tx
+            .prepare(&build_batch_insert(
+                table_name,
+                columns,
+                column_types,
+                &batch,
+            ))
+            .await?;
+        insert_batch(&tx, &stmt, &batch).await?;
+        row_count += batch.len() as u64;
+    }
+
+    tx.commit().await?;
+    Span::current().record("rows.count", row_count);
+    Ok(row_count)
+}
+
+/// Number of mesh codes sent per `--add-centroid-column` UPDATE.
+const CENTROID_BATCH_SIZE: usize = 10_000;
+
+/// Computes cell centroids for `KEY_CODE` values, as parallel arrays for `unnest`.
+fn centroid_batch(codes: &[String]) -> Result<(Vec<f64>, Vec<f64>)> {
+    let mut lons = Vec::with_capacity(codes.len());
+    let mut lats = Vec::with_capacity(codes.len());
+    for code in codes {
+        let mesh_code: u64 = code
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid KEY_CODE {:?}", code))?;
+        let (lon, lat) = mesh_geometry::cell_bounds(mesh_code)?.center();
+        lons.push(lon);
+        lats.push(lat);
+    }
+    Ok((lons, lats))
+}
+
+/// The centroid expression for `lon` / `lat` given in the survey datum, reprojected with
+/// PostGIS when `--srid-override` asks for another CRS.
+fn centroid_expr(lon: &str, lat: &str, source_srid: u16, target_srid: u32) -> String {
+    let point = format!("ST_SetSRID(ST_Point({}, {}), {})", lon, lat, source_srid);
+    if u32::from(source_srid) == target_srid {
+        point
+    } else {
+        format!("ST_Transform({}, {})", point, target_srid)
     }
+}
 
+/// Fills the `centroid` column from `KEY_CODE`. The centroids are computed with jismesh in
+/// Rust and sent in batches; `KEY_CODE` is compared as text so `--column-types` overrides of
+/// that column still work. Returns the number of updated rows.
+async fn fill_centroids(
+    client: &mut tokio_postgres::Client,
+    table_name: &str,
+    source_srid: u16,
+    target_srid: u32,
+) -> Result<u64> {
+    let codes: Vec<String> = client
+        .query(
+            &format!(
+                "SELECT DISTINCT \"KEY_CODE\"::text FROM {} WHERE \"KEY_CODE\" IS NOT NULL",
+                postgres::folded_ident(table_name)
+            ),
+            &[],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    let update_sql = format!(
+        "UPDATE {} AS t SET centroid = {} \
+FROM unnest($1::text[], $2::float8[], $3::float8[]) AS v(code, lon, lat) \
+WHERE t.\"KEY_CODE\"::text = v.code",
+        postgres::folded_ident(table_name),
+        centroid_expr("v.lon", "v.lat", source_srid, target_srid)
+    );
+    let tx = client.transaction().await?;
+    let update_stmt = tx.prepare(&update_sql).await?;
+    let mut updated = 0u64;
+    for chunk in codes.chunks(CENTROID_BATCH_SIZE) {
+        let (lons, lats) = centroid_batch(chunk)?;
+        updated += tx.execute(&update_stmt, &[&chunk, &lons, &lats]).await?;
+    }
     tx.commit().await?;
+    Ok(updated)
+}
+
+/// Counts the data records of one e-Stat mesh CSV that should end up in the table, independently
+/// of the import: the two header rows and records with only empty fields are not counted, and
+/// records whose cell centroid lies outside `geo_filter` are excluded.
+fn count_csv_records(file: &Path, geo_filter: Option<&GeoFilter>) -> Result<u64> {
+    let mut rdr = open_shiftjis_csv(file)?;
+    let mut count = 0u64;
+    for result in rdr.records().skip(2) {
+        let record = result?;
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        if let Some(filter) = geo_filter {
+            let mesh_code: u64 = record.get(0).unwrap_or("").trim().parse()?;
+            if !filter.contains_mesh_centroid(mesh_code)? {
+                continue;
+            }
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Compares the number of data records counted in the CSV files with the number of rows in the
+/// table. Differences up to `threshold` rows are accepted.
+fn check_row_count(expected: u64, actual: u64, threshold: u64) -> Result<()> {
+    let diff = expected.abs_diff(actual);
+    if diff > threshold {
+        bail!(
+            "row count mismatch: CSV files contain {} data rows but table contains {} rows (difference {} exceeds threshold {})",
+            expected,
+            actual,
+            diff,
+            threshold
+        );
+    }
     Ok(())
 }
 
-pub async fn process_mesh(
-    postgres_url: &str,
+/// Outcome of [`process_mesh`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeshImportReport {
+    pub table_name: String,
+    /// Number of extracted CSV files imported.
+    pub files: usize,
+    /// Rows inserted by the import.
+    pub imported_rows: u64,
+    /// Data records counted in the CSV files, excluding those outside `--geo-filter`.
+    pub csv_rows: u64,
+    /// Rows counted in the table after the import.
+    pub table_rows: u64,
+}
+
+/// Download URL of one level-1 file of the survey.
+pub(crate) fn lv1_download_url(mesh_stats: &MeshStats, mesh: u64) -> Url {
+    let url = format!(
+        "https://www.e-stat.go.jp/gis/statmap-search/data?statsId={}&code={}&downloadType=2",
+        mesh_stats.stats_id, mesh
+    );
+    Url::parse(&url).unwrap()
+}
+
+/// Download URL of every level-1 file of the survey, skipping the ones outside `geo_filter`.
+pub(crate) fn lv1_download_urls(
+    mesh_stats: &MeshStats,
+    geo_filter: Option<&GeoFilter>,
+) -> Result<Vec<(u64, Url)>> {
+    Ok(geo_filter::filter_lv1_codes(JAPAN_LV1, geo_filter)?
+        .into_iter()
+        .map(|mesh| (mesh, lv1_download_url(mesh_stats, mesh)))
+        .collect())
+}
+
+/// Name of a level-1 archive in `--tmp-dir`.
+pub(crate) fn archive_filename(mesh_stats: &MeshStats, mesh: u64) -> String {
+    format!("{}-{}-{}.zip", mesh_stats.year, mesh_stats.stats_id, mesh)
+}
+
+/// Downloads and extracts the Lv1 CSVs of a survey. Codes e-Stat has no file for are skipped.
+pub(crate) async fn download_mesh_csvs(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    mesh_stats: &'static MeshStats,
+    geo_filter: Option<&GeoFilter>,
+) -> Result<Vec<DownloadedItem<(u64, Url)>>> {
+    download_lv1_csvs(
+        http_client,
+        tmp_dir,
+        mesh_stats,
+        lv1_download_urls(mesh_stats, geo_filter)?,
+    )
+    .await
+}
+
+/// Like [`download_mesh_csvs`], for the given (Lv1 code, URL) pairs only.
+pub(crate) async fn download_lv1_csvs(
+    http_client: &reqwest::Client,
     tmp_dir: &Path,
+    mesh_stats: &'static MeshStats,
+    urls: Vec<(u64, Url)>,
+) -> Result<Vec<DownloadedItem<(u64, Url)>>> {
+    download::into_result(
+        download::download_and_extract_all(
+            http_client,
+            stream::iter(urls),
+            |(_mesh, url)| url.clone(),
+            |(mesh, _url)| archive_filename(mesh_stats, *mesh),
+            "txt", // e-Stat mesh data uses .txt extension for CSVs inside zip
+            tmp_dir,
+            "Downloading Mesh CSVs...",
+            "Extracting Mesh CSVs...",
+            10, // Concurrency level
+        )
+        .await?,
+    )
+}
+
+/// The archives `process_mesh` downloads, as (file name in `--tmp-dir`, URL), for `--watch`.
+pub fn watched_archives(
     level: u8,
     year: u16,
     survey: &str,
-) -> Result<()> {
-    let mesh_stats = get_matching_mesh_stats(level, year, survey)
-        .ok_or(anyhow!("一致する統計データが見つかりません"))?;
+    geo_filter: Option<&GeoFilter>,
+) -> Result<Vec<(String, Url)>> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+    Ok(lv1_download_urls(mesh_stats, geo_filter)?
+        .into_iter()
+        .map(|(mesh, url)| (archive_filename(mesh_stats, mesh), url))
+        .collect())
+}
 
-    // Prepare items for download
-    let urls_with_metadata: Vec<(u64, Url)> = JAPAN_LV1
-        .iter()
-        .map(|mesh| {
-            let url = format!(
-                "https://www.e-stat.go.jp/gis/statmap-search/data?statsId={}&code={}&downloadType=2",
-                mesh_stats.stats_id, mesh
-            );
-            (*mesh, Url::parse(&url).unwrap())
-        })
-        .collect();
+/// Imports a mesh survey as configured by `config`. `prefetched` are CSVs already downloaded
+/// (by a pipeline `download` step); otherwise they are downloaded here. The proxy and
+/// `User-Agent` of `config` are those of `http_client`, which the caller builds.
+pub async fn process_mesh(
+    http_client: &reqwest::Client,
+    config: &MeshImportConfig,
+    prefetched: Option<Vec<DownloadedItem<(u64, Url)>>>,
+) -> Result<MeshImportReport> {
+    let table_prefix = config.table_prefix.as_str();
+    let postgres_url = config.postgres_url.as_str();
+    let tmp_dir = config.tmp_dir.as_path();
+    let (level, year, survey) = (config.level, config.year, config.survey.as_str());
+    let geo_filter = config.geo_filter.as_ref();
+    let (row_mismatch_threshold, allow_row_mismatch) =
+        (config.row_mismatch_threshold, config.allow_row_mismatch);
+    let before_import_sql = config.before_import_sql.as_deref();
+    let after_import_sql = config.after_import_sql.as_deref();
+    let column_types = config.column_types.as_deref();
+    let add_centroid_column = config.add_centroid_column;
+    let srid_override = config.srid_override;
+    let comment_table = config.comment_table;
+    let insert_batch_size = config.insert_batch_size;
+    let temp_postgres_url = config.temp_postgres_url.as_deref();
+    let drop_old_table = config.drop_old_table;
+    let lockfile = config.lockfile.as_deref();
+    let cleanup = config.cleanup;
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
 
-    // Use the generic download function
-    let downloaded_items: Vec<DownloadedItem<(u64, Url)>> = download::download_and_extract_all(
-        stream::iter(urls_with_metadata),
-        |(_mesh, url)| url.clone(),
-        |(mesh, _url)| format!("{}-{}-{}.zip", mesh_stats.year, mesh_stats.stats_id, mesh),
-        "txt", // e-Stat mesh data uses .txt extension for CSVs inside zip
-        tmp_dir,
-        "Downloading Mesh CSVs...",
-        "Extracting Mesh CSVs...",
-        10, // Concurrency level
-    )
-    .await?;
+    let before_sql = match before_import_sql {
+        Some(path) => Some(postgres::read_sql_file(path).await?),
+        None => None,
+    };
+    let after_sql = match after_import_sql {
+        Some(path) => Some(postgres::read_sql_file(path).await?),
+        None => None,
+    };
+    let column_types = match column_types {
+        Some(path) => load_column_types(path).await?,
+        None => ColumnTypes::new(),
+    };
 
-    println!("Files downloaded and extracted.");
+    let downloaded_items = match prefetched {
+        Some(items) => items,
+        None => {
+            let items = download_mesh_csvs(http_client, tmp_dir, mesh_stats, geo_filter).await?;
+            println!("Files downloaded and extracted.");
+            items
+        }
+    };
 
     let first_extracted_path = downloaded_items
         .first()
         .map(|item| item.extracted_path.clone())
         .ok_or(anyhow!("No files found after download/extraction"))?;
+    mesh_stats::warn_on_year_mismatch(&first_extracted_path, mesh_stats.year);
 
-    let (mut client, connection) = tokio_postgres::connect(postgres_url, NoTls).await?;
+    // With --temp-postgres-url the import goes into `{table}_tmp` over that connection and
+    // the live table is only replaced once it is complete.
+    let (mut client, connection) =
+        tokio_postgres::connect(temp_postgres_url.unwrap_or(postgres_url), NoTls).await?;
     tokio::spawn(async move {
         if let Err(e) = connection.await {
             eprintln!("DB error: {}", e);
         }
     });
 
-    let (table_name, columns) = create_schema(&client, mesh_stats, &first_extracted_path).await?;
+    if let Some(sql) = before_sql.as_deref() {
+        postgres::run_sql_hook(&client, "before-import", sql).await?;
+    }
+
+    let live_table = table_name(table_prefix, mesh_stats);
+    let table_name = match temp_postgres_url {
+        Some(_) => postgres::temp_table_name(&live_table),
+        None => live_table.clone(),
+    };
+    let columns = create_schema(
+        &client,
+        &table_name,
+        mesh_stats,
+        &first_extracted_path,
+        &column_types,
+        comment_table,
+    )
+    .await?;
     println!("Schema created: {}", table_name);
+    let centroid_srid = srid_override.unwrap_or_else(|| u32::from(mesh_stats.datum));
+    if add_centroid_column {
+        if centroid_srid != u32::from(mesh_stats.datum) {
+            eprintln!(
+                "Warning: centroids are reprojected from EPSG:{} to EPSG:{} point by point; \
+the cells themselves are not rectangles in the target CRS, so treat the points as approximate \
+for coarse meshes (Lv3 cells are about 1 km wide)",
+                mesh_stats.datum, centroid_srid
+            );
+        }
+        client
+            .batch_execute(&format!(
+                "ALTER TABLE {} ADD COLUMN centroid geometry(Point, {})",
+                postgres::folded_ident(&table_name),
+                centroid_srid
+            ))
+            .await
+            .context("when adding the centroid column (is PostGIS installed?)")?;
+        if comment_table {
+            client
+                .batch_execute(&format!(
+                    "COMMENT ON COLUMN {}.centroid IS 'メッシュの中心点 (EPSG:{})'",
+                    postgres::folded_ident(&table_name),
+                    centroid_srid
+                ))
+                .await?;
+        }
+    }
 
-    let pb_style = ProgressStyle::default_bar()
-        .template("{msg} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7}")?
-        .progress_chars("##-");
-    let pb = ProgressBar::new(downloaded_items.len() as u64);
-    pb.set_style(pb_style);
-    pb.set_message("Importing CSVs...");
+    let pb = progress::new_bar(downloaded_items.len() as u64, "Importing CSVs...")?;
+    let mut imported_rows = 0u64;
+    let mut csv_rows = 0u64;
     for item in downloaded_items.iter() {
-        import_csv_to_postgres(&mut client, &item.extracted_path, &table_name, &columns)
-            .await
-            .with_context(|| format!("when importing {}", &item.extracted_path.display()))?;
+        csv_rows += count_csv_records(&item.extracted_path, geo_filter)
+            .with_context(|| format!("when counting rows in {}", &item.extracted_path.display()))?;
+        imported_rows += import_csv_to_postgres(
+            &mut client,
+            &item.extracted_path,
+            &table_name,
+            &columns,
+            &column_types,
+            geo_filter,
+            insert_batch_size,
+        )
+        .await
+        .with_context(|| format!("when importing {}", &item.extracted_path.display()))?;
+        if cancel::is_cancelled() {
+            pb.abandon();
+            println!(
+                "Committed {} rows to {} before interruption ({} of {} files started).",
+                imported_rows,
+                table_name,
+                pb.position() + 1,
+                downloaded_items.len()
+            );
+            bail!("import interrupted by Ctrl+C");
+        }
+        if cleanup {
+            download::cleanup_extracted(item)?;
+        }
         pb.inc(1);
     }
     pb.finish();
+    memory_report::record("import");
 
-    Ok(())
+    if add_centroid_column {
+        let updated = fill_centroids(&mut client, &table_name, mesh_stats.datum, centroid_srid)
+            .await
+            .context("when filling the centroid column")?;
+        println!("Centroids computed for {} rows", updated);
+    }
+
+    let table_rows: i64 = client
+        .query_one(
+            &format!(
+                "SELECT COUNT(*) FROM {}",
+                postgres::folded_ident(&table_name)
+            ),
+            &[],
+        )
+        .await?
+        .get(0);
+    let table_rows = u64::try_from(table_rows).context("negative row count")?;
+    match check_row_count(csv_rows, table_rows, row_mismatch_threshold) {
+        Ok(()) => println!("Row count verified: {} rows in {}", table_rows, table_name),
+        Err(err) if allow_row_mismatch => println!("Warning: {}", err),
+        Err(err) => return Err(err.context("pass --allow-row-mismatch to ignore")),
+    }
+
+    let (client, table_name) = if temp_postgres_url.is_some() {
+        let live_client = postgres::connect(postgres_url).await?;
+        postgres::swap_in_table(&live_client, &live_table, drop_old_table)
+            .await
+            .context("--temp-postgres-url must point at the same database as --postgres-url")?;
+        (live_client, live_table)
+    } else {
+        (client, table_name)
+    };
+
+    if let Some(sql) = after_sql.as_deref() {
+        postgres::run_sql_hook(&client, "after-import", sql).await?;
+    }
+
+    if let Some(path) = lockfile {
+        let archives = downloaded_items
+            .iter()
+            .map(|item| {
+                let (mesh, url) = &item.metadata;
+                (archive_filename(mesh_stats, *mesh), url.clone())
+            })
+            .collect();
+        lockfile::record_downloads(
+            path,
+            tmp_dir,
+            &mesh_stats.name,
+            mesh_stats.year,
+            mesh_stats.meshlevel,
+            &mesh_stats.stats_id,
+            archives,
+        )
+        .await?;
+    }
+
+    Ok(MeshImportReport {
+        table_name,
+        files: downloaded_items.len(),
+        imported_rows,
+        csv_rows,
+        table_rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_multi_row_inserts() {
+        let columns = ["KEY_CODE".to_string(), "T001140001".to_string()];
+        let row = || -> RowParams { vec![Box::new(Some(53394611i64)), Box::new(Some(1i32))] };
+        let mut column_types = ColumnTypes::new();
+        assert_eq!(
+            build_batch_insert("t", &columns, &column_types, &[row(), row(), row()]),
+            "INSERT INTO \"t\" (\"KEY_CODE\", \"T001140001\") VALUES ($1, $2), ($3, $4), ($5, $6)"
+        );
+        column_types.insert("T001140001".to_string(), "NUMERIC".to_string());
+        assert_eq!(
+            build_batch_insert("t", &columns, &column_types, &[row()]),
+            "INSERT INTO \"t\" (\"KEY_CODE\", \"T001140001\") VALUES ($1, CAST($2::text AS NUMERIC))"
+        );
+    }
+
+    #[test]
+    fn builds_comment_statements() {
+        let stats = get_matching_mesh_stats(3, 2020, "人口及び世帯").unwrap();
+        let codes: Vec<String> = ["KEY_CODE", "HTKSYORI", "T001140001"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let columns: Vec<String> = ["KEY_CODE", "HTKSYORI", "人口（総数）"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut metadata = table_metadata(stats, &codes, &columns, &ColumnTypes::new());
+        assert_eq!(
+            metadata.columns[2].desc.as_deref(),
+            Some("人口（総数） (T001140001)")
+        );
+        metadata.name = "O'Brien".to_string();
+        let statements = comment_statements("jp_estat_t001140_2020", &metadata);
+        assert_eq!(statements.len(), 4);
+        assert!(
+            statements[0].starts_with("COMMENT ON TABLE \"jp_estat_t001140_2020\" IS 'O''Brien: ")
+        );
+        assert_eq!(
+            statements[1],
+            "COMMENT ON COLUMN \"jp_estat_t001140_2020\".\"KEY_CODE\" IS '地域メッシュコード'"
+        );
+    }
+
+    #[test]
+    fn resolves_level6_stats() {
+        let stats = get_matching_mesh_stats(6, 2020, "人口及び世帯").unwrap();
+        assert_eq!(stats.stats_id, "T001231");
+        // Level-6 KEY_CODE values have 11 digits and do not fit in INTEGER.
+        assert_eq!(infer_column_type("KEY_CODE"), "BIGINT");
+    }
+
+    #[test]
+    fn accepts_matching_row_counts() {
+        assert!(check_row_count(100, 100, 0).is_ok());
+    }
+
+    #[test]
+    fn accepts_difference_within_threshold() {
+        assert!(check_row_count(100, 98, 2).is_ok());
+        assert!(check_row_count(98, 100, 2).is_ok());
+    }
+
+    #[test]
+    fn rejects_difference_over_threshold() {
+        let err = check_row_count(100, 97, 2).unwrap_err();
+        assert!(err.to_string().contains("row count mismatch"));
+    }
+
+    #[test]
+    fn counts_csv_records_apart_from_imported_rows() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("tblT001140S5339.txt");
+        // 53394611 is near Tokyo Station; 52354600 is in western Japan.
+        std::fs::write(
+            &path,
+            "KEY_CODE,HTKSYORI,T001140001\n,,population\n53394611,0,120\n52354600,0,80\n,,\n53394612,0,95\n",
+        )
+        .unwrap();
+        assert_eq!(count_csv_records(&path, None).unwrap(), 3);
+
+        let tokyo = GeoFilter::parse(
+            "POLYGON((139.6 35.6, 139.9 35.6, 139.9 35.8, 139.6 35.8, 139.6 35.6))",
+        )
+        .unwrap();
+        let expected = count_csv_records(&path, Some(&tokyo)).unwrap();
+        assert_eq!(expected, 2);
+        // A table that also took the empty record or the row outside the filter is rejected.
+        let err = check_row_count(expected, 3, 0).unwrap_err();
+        assert!(err.to_string().contains("2 data rows"));
+    }
+
+    #[test]
+    fn column_types_override_inferred_types() {
+        let column_types = parse_column_types(
+            r#"{"T001102001": "NUMERIC(10,2)", "KEY_CODE": "VARCHAR(16)", "不明": "TEXT"}"#,
+        )
+        .unwrap();
+        let columns = vec![
+            "KEY_CODE".to_string(),
+            "T001102001".to_string(),
+            "GASSAN".to_string(),
+        ];
+        assert_eq!(
+            column_defs(&columns, &column_types),
+            vec![
+                "\"KEY_CODE\" VARCHAR(16)",
+                "\"T001102001\" NUMERIC(10,2)",
+                "\"GASSAN\" BIGINT[]",
+            ]
+        );
+        assert_eq!(unknown_column_types(&column_types, &columns), vec!["不明"]);
+    }
+
+    #[test]
+    fn computes_centroids_from_key_codes() {
+        let (lons, lats) = centroid_batch(&["53394526".to_string()]).unwrap();
+        assert!((lons[0] - 139.70625).abs() < 1e-9);
+        assert!((lats[0] - 35.6875).abs() < 1e-9);
+        assert!(centroid_batch(&["KEY".to_string()]).is_err());
+    }
+
+    #[test]
+    fn transforms_centroids_only_when_srid_differs() {
+        assert_eq!(
+            centroid_expr("lon", "lat", 6668, 6668),
+            "ST_SetSRID(ST_Point(lon, lat), 6668)"
+        );
+        assert_eq!(
+            centroid_expr("lon", "lat", 6668, 4326),
+            "ST_Transform(ST_SetSRID(ST_Point(lon, lat), 6668), 4326)"
+        );
+    }
+
+    #[test]
+    fn rejects_column_types_that_are_not_type_names() {
+        assert!(parse_column_types(r#"{"KEY_CODE": "TEXT); DROP TABLE x; --"}"#).is_err());
+        assert!(parse_column_types(r#"["TEXT"]"#).is_err());
+    }
 }
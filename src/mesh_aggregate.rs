@@ -0,0 +1,184 @@
+use crate::{
+    mesh_diff::{NON_BAND_COLUMNS, column_index, open_mesh_csv, parse_value},
+    mesh_tile::{AggFn, map_meshcode_to_tile, subdivisions_per_axis},
+};
+use anyhow::{Context, Result, bail};
+use csv::WriterBuilder;
+use std::{collections::BTreeMap, io::Read, path::Path};
+
+const KEY_COLUMN: &str = "KEY_CODE";
+
+/// The values of one band in one parent cell. No-data cells are not collected.
+#[derive(Default)]
+pub(crate) struct BandValues {
+    values: Vec<f64>,
+    /// Every value was an integer, so the result is aggregated (and printed) as one.
+    integer: bool,
+}
+
+impl BandValues {
+    fn push(&mut self, raw: &str) {
+        let Some(value) = parse_value(raw) else {
+            return;
+        };
+        self.integer =
+            (self.values.is_empty() || self.integer) && raw.trim().parse::<i64>().is_ok();
+        self.values.push(value);
+    }
+
+    /// Blank when no fine cell had a value.
+    pub(crate) fn aggregate(&self, agg_fn: AggFn) -> String {
+        if self.integer {
+            let values = self.values.iter().map(|v| *v as i64);
+            agg_fn.aggregate(values).map(|v| v.to_string())
+        } else {
+            agg_fn
+                .aggregate_f64(self.values.iter().copied())
+                .map(|v| v.to_string())
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// Reads the remaining rows of `rdr` and collects the `indices` columns of every cell under
+/// its `target_level` parent. Returns the parents in code order and the number of cells read.
+pub(crate) fn group_cells<R: Read>(
+    rdr: &mut csv::Reader<R>,
+    path: &Path,
+    key: usize,
+    indices: &[usize],
+    input_level: u8,
+    target_level: u8,
+) -> Result<(BTreeMap<u64, Vec<BandValues>>, u64)> {
+    let rows_per_axis = subdivisions_per_axis(target_level, input_level)?;
+    let mut groups: BTreeMap<u64, Vec<BandValues>> = BTreeMap::new();
+    let mut cells = 0u64;
+    for record in rdr.records() {
+        let record = record.with_context(|| format!("when reading {}", path.display()))?;
+        let key_code = record.get(key).unwrap_or_default().trim();
+        let mesh_code: u64 = key_code
+            .parse()
+            .with_context(|| format!("invalid KEY_CODE {:?}", key_code))?;
+        let (parent, _, _) =
+            map_meshcode_to_tile(mesh_code, input_level, target_level, rows_per_axis)?;
+        let group = groups
+            .entry(parent)
+            .or_insert_with(|| (0..indices.len()).map(|_| BandValues::default()).collect());
+        for (values, &i) in group.iter_mut().zip(indices) {
+            values.push(record.get(i).unwrap_or_default());
+        }
+        cells += 1;
+    }
+    Ok((groups, cells))
+}
+
+pub fn process_mesh_aggregate(
+    input_csv: &Path,
+    input_level: u8,
+    target_level: u8,
+    bands: Option<&[String]>,
+    agg_fn: AggFn,
+    output: &Path,
+) -> Result<()> {
+    if target_level > input_level {
+        bail!(
+            "--target-level ({}) must be <= --input-level ({})",
+            target_level,
+            input_level
+        );
+    }
+    let (header, mut rdr) = open_mesh_csv(input_csv)?;
+    let key = column_index(&header, KEY_COLUMN, input_csv)?;
+    let bands: Vec<String> = match bands {
+        Some(bands) => bands.to_vec(),
+        None => header
+            .iter()
+            .filter(|h| !NON_BAND_COLUMNS.contains(&h.as_str()))
+            .cloned()
+            .collect(),
+    };
+    if bands.is_empty() {
+        bail!("{} has no statistics columns", input_csv.display());
+    }
+    let indices = bands
+        .iter()
+        .map(|band| column_index(&header, band, input_csv))
+        .collect::<Result<Vec<_>>>()?;
+
+    let (groups, cells) = group_cells(
+        &mut rdr,
+        input_csv,
+        key,
+        &indices,
+        input_level,
+        target_level,
+    )?;
+
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut writer = WriterBuilder::new().from_path(output)?;
+    writer.write_record(std::iter::once(KEY_COLUMN).chain(bands.iter().map(String::as_str)))?;
+    for (parent, group) in &groups {
+        writer.write_record(
+            std::iter::once(parent.to_string())
+                .chain(group.iter().map(|values| values.aggregate(agg_fn))),
+        )?;
+    }
+    writer.flush()?;
+
+    println!(
+        "Aggregated {} Lv{} cells into {} Lv{} cells in {}",
+        cells,
+        input_level,
+        groups.len(),
+        target_level,
+        output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_up_cells_to_their_parents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let input = dir.join("lv4.csv");
+        std::fs::write(
+            &input,
+            "KEY_CODE,HTKSYORI,人口（総数）,平均年齢\n\
+             533946111,0,10,40.5\n\
+             533946112,0,*,41\n\
+             533946114,0,5,43.5\n\
+             533946121,0,*,\n\
+             533946211,0,7,30\n",
+        )
+        .unwrap();
+        let output = dir.join("lv3.csv");
+
+        process_mesh_aggregate(&input, 4, 3, None, AggFn::Sum, &output).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "KEY_CODE,人口（総数）,平均年齢\n\
+             53394611,15,125\n\
+             53394612,,\n\
+             53394621,7,30\n"
+        );
+
+        let bands = ["平均年齢".to_string()];
+        process_mesh_aggregate(&input, 4, 2, Some(&bands), AggFn::Mean, &output).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "KEY_CODE,平均年齢\n533946,38.75\n"
+        );
+
+        assert!(process_mesh_aggregate(&input, 3, 4, None, AggFn::Sum, &output).is_err());
+        // The codes are Lv4, not Lv3.
+        assert!(process_mesh_aggregate(&input, 3, 1, None, AggFn::Sum, &output).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{mesh_code_hierarchy, mesh_geometry};
+use anyhow::Result;
+
+/// Mean Earth radius (IUGG), used for the approximate cell area.
+pub(crate) const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum MeshBboxFormat {
+    /// 緯度経度 (10進数・度分秒)・メッシュレベル・面積
+    Text,
+    /// `POLYGON((...))` 形式の WKT
+    Wkt,
+}
+
+/// `35°40'30.000"N`. Rounded to a thousandth of a second before splitting, so a value just
+/// below a whole minute does not print as 60 seconds.
+fn to_dms(degrees: f64, positive: char, negative: char) -> String {
+    let hemisphere = if degrees < 0.0 { negative } else { positive };
+    let total_ms = (degrees.abs() * 3_600_000.0).round() as u64;
+    let (deg, rest) = (total_ms / 3_600_000, total_ms % 3_600_000);
+    let (min, ms) = (rest / 60_000, rest % 60_000);
+    format!(
+        "{}°{:02}'{:02}.{:03}\"{}",
+        deg,
+        min,
+        ms / 1000,
+        ms % 1000,
+        hemisphere
+    )
+}
+
+/// Area of the cell on a sphere: `R² · Δλ · (sin φ₂ − sin φ₁)`.
+fn area_km2(bounds: mesh_geometry::CellBounds) -> f64 {
+    let d_lon = (bounds.max_lon - bounds.min_lon).to_radians();
+    let d_sin_lat = bounds.max_lat.to_radians().sin() - bounds.min_lat.to_radians().sin();
+    EARTH_RADIUS_KM * EARTH_RADIUS_KM * d_lon * d_sin_lat
+}
+
+pub fn process_mesh_bbox(code: u64, format: MeshBboxFormat) -> Result<()> {
+    let level = mesh_code_hierarchy::mesh_code_level(code)?;
+    let bounds = mesh_geometry::cell_bounds(code)?;
+    match format {
+        MeshBboxFormat::Wkt => println!("{}", bounds.to_wkt_polygon()),
+        MeshBboxFormat::Text => {
+            println!("mesh_code: {} (Lv{})", code, level);
+            for (name, value, dms) in [
+                ("min_lat", bounds.min_lat, to_dms(bounds.min_lat, 'N', 'S')),
+                ("min_lon", bounds.min_lon, to_dms(bounds.min_lon, 'E', 'W')),
+                ("max_lat", bounds.max_lat, to_dms(bounds.max_lat, 'N', 'S')),
+                ("max_lon", bounds.max_lon, to_dms(bounds.max_lon, 'E', 'W')),
+            ] {
+                println!("{}: {:.6} ({})", name, value, dms);
+            }
+            println!(
+                "area: {:.4} km² (spherical approximation)",
+                area_km2(bounds)
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_degrees_as_dms() {
+        assert_eq!(to_dms(35.675, 'N', 'S'), "35°40'30.000\"N");
+        assert_eq!(to_dms(139.7625, 'E', 'W'), "139°45'45.000\"E");
+        assert_eq!(to_dms(35.0 + 2.0 / 3.0, 'N', 'S'), "35°40'00.000\"N");
+        assert_eq!(to_dms(-0.5, 'N', 'S'), "0°30'00.000\"S");
+    }
+
+    #[test]
+    fn approximates_cell_area() {
+        // A Lv3 cell is 30" x 45", roughly 1 km² in central Japan.
+        let area = area_km2(mesh_geometry::cell_bounds(53394611).unwrap());
+        assert!((1.0..1.1).contains(&area), "{}", area);
+        let lv1 = area_km2(mesh_geometry::cell_bounds(5339).unwrap());
+        assert!((lv1 / area - 6400.0).abs() < 10.0, "{}", lv1 / area);
+    }
+}
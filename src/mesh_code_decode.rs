@@ -0,0 +1,103 @@
+use crate::mesh_code_hierarchy::mesh_code_level;
+use crate::mesh_geometry;
+use crate::mesh_tile::{
+    digits_for_level, map_meshcode_to_tile, refinement_factor, subdivisions_per_axis,
+};
+use anyhow::Result;
+
+const QUADRANTS: [&str; 4] = ["SW", "SE", "NW", "NE"];
+
+/// Ancestor of a `digits`-digit code at `level` (its leading digits).
+fn ancestor(code: u64, digits: usize, level: u8) -> Result<u64> {
+    Ok(code / 10u64.pow((digits - digits_for_level(level)?) as u32))
+}
+
+/// What the digits added at `level` say about the cell inside its parent, read back through
+/// `map_meshcode_to_tile` with the parent as a one-step tile.
+fn component(level_code: u64, level: u8) -> Result<String> {
+    if level == 1 {
+        return Ok(format!(
+            "lat code {}, lon code {}",
+            level_code / 100,
+            level_code % 100
+        ));
+    }
+    let factor = refinement_factor(level)?;
+    let (_, row_top, col) = map_meshcode_to_tile(level_code, level, level - 1, factor)?;
+    let row = factor - 1 - row_top;
+    Ok(match level {
+        2 | 3 => format!("row {}, col {}", row, col),
+        _ => {
+            let quadrant = row * 2 + col;
+            format!("quadrant {}: {}", quadrant + 1, QUADRANTS[quadrant])
+        }
+    })
+}
+
+/// Lines printed by `mesh-code-decode`: one per level from Lv1 down to the level of `code`,
+/// then where `code` lands in its Lv1 tile.
+fn decode_lines(code: u64, verbose: bool) -> Result<Vec<String>> {
+    let level = mesh_code_level(code)?;
+    let digits = digits_for_level(level)?;
+    let mut lines = vec![format!("mesh_code: {} (Lv{})", code, level)];
+    for l in 1..=level {
+        let level_code = ancestor(code, digits, l)?;
+        lines.push(format!(
+            "Lv{}: {} ({})",
+            l,
+            level_code,
+            component(level_code, l)?
+        ));
+        if verbose {
+            let bounds = mesh_geometry::cell_bounds(level_code)?;
+            lines.push(format!(
+                "  bounds: lat {:.6}..{:.6}, lon {:.6}..{:.6}",
+                bounds.min_lat, bounds.max_lat, bounds.min_lon, bounds.max_lon
+            ));
+        }
+    }
+    let rows = subdivisions_per_axis(1, level)?;
+    let (tile_code, row, col) = map_meshcode_to_tile(code, level, 1, rows)?;
+    lines.push(format!(
+        "tile: Lv1 {} row {}, col {} of {}x{} (from the north-west corner)",
+        tile_code, row, col, rows, rows
+    ));
+    Ok(lines)
+}
+
+pub fn process_mesh_code_decode(code: u64, verbose: bool) -> Result<()> {
+    for line in decode_lines(code, verbose)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_each_level() {
+        assert_eq!(
+            decode_lines(533946112, false).unwrap(),
+            [
+                "mesh_code: 533946112 (Lv4)",
+                "Lv1: 5339 (lat code 53, lon code 39)",
+                "Lv2: 533946 (row 4, col 6)",
+                "Lv3: 53394611 (row 1, col 1)",
+                "Lv4: 533946112 (quadrant 2: SE)",
+                "tile: Lv1 5339 row 77, col 123 of 160x160 (from the north-west corner)",
+            ]
+        );
+        assert_eq!(
+            decode_lines(5339, true).unwrap(),
+            [
+                "mesh_code: 5339 (Lv1)",
+                "Lv1: 5339 (lat code 53, lon code 39)",
+                "  bounds: lat 35.333333..36.000000, lon 139.000000..140.000000",
+                "tile: Lv1 5339 row 0, col 0 of 1x1 (from the north-west corner)",
+            ]
+        );
+        assert!(decode_lines(533946115, false).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::mesh_tile::{digits_for_level, map_meshcode_to_tile, subdivisions_per_axis};
+use anyhow::{Result, bail};
+
+/// Level of a mesh code from its digit count; the sub-division digits are validated too.
+pub(crate) fn mesh_code_level(code: u64) -> Result<u8> {
+    let digits = code.to_string().len();
+    let Some(level) = (1..=6).find(|level| digits_for_level(*level).ok() == Some(digits)) else {
+        bail!(
+            "mesh code {} has {} digits, not a level 1-6 code",
+            code,
+            digits
+        );
+    };
+    map_meshcode_to_tile(code, level, 1, subdivisions_per_axis(1, level)?)?;
+    Ok(level)
+}
+
+/// Ancestor of `code` at `target_level`: the leading digits of the code, as
+/// `map_meshcode_to_tile` takes them for the tile code.
+fn parent(code: u64, target_level: u8) -> Result<u64> {
+    let level = mesh_code_level(code)?;
+    if target_level > level {
+        bail!(
+            "target level ({}) must be <= the level of {} ({})",
+            target_level,
+            code,
+            level
+        );
+    }
+    let (parent, _, _) = map_meshcode_to_tile(
+        code,
+        level,
+        target_level,
+        subdivisions_per_axis(target_level, level)?,
+    )?;
+    Ok(parent)
+}
+
+/// Digits appended to a code when refining to `next_level`: two digits (row, column) for
+/// Lv2 and Lv3, one quadrant digit (1: SW, 2: SE, 3: NW, 4: NE) for Lv4–Lv6.
+fn sub_digits(next_level: u8) -> Result<Vec<u64>> {
+    Ok(match next_level {
+        2 => (0..8)
+            .flat_map(|r| (0..8).map(move |c| r * 10 + c))
+            .collect(),
+        3 => (0..100).collect(),
+        4..=6 => (1..=4).collect(),
+        _ => bail!("unsupported refinement step to level {}", next_level),
+    })
+}
+
+/// Descendants of `code` at `target_level`, in code order.
+pub(crate) fn children(code: u64, target_level: u8) -> Result<Vec<u64>> {
+    let level = mesh_code_level(code)?;
+    if !(level..=6).contains(&target_level) {
+        bail!(
+            "target level ({}) must be between the level of {} ({}) and 6",
+            target_level,
+            code,
+            level
+        );
+    }
+    let mut codes = vec![code];
+    for next_level in (level + 1)..=target_level {
+        let digits = sub_digits(next_level)?;
+        let scale = if next_level <= 3 { 100 } else { 10 };
+        codes = codes
+            .iter()
+            .flat_map(|parent| digits.iter().map(move |digit| parent * scale + digit))
+            .collect();
+    }
+    Ok(codes)
+}
+
+pub fn process_mesh_code_parent(code: u64, target_level: u8) -> Result<()> {
+    println!("{}", parent(code, target_level)?);
+    Ok(())
+}
+
+pub fn process_mesh_code_children(code: u64, target_level: u8) -> Result<()> {
+    for child in children(code, target_level)? {
+        println!("{}", child);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_parents_at_each_level() {
+        assert_eq!(parent(53394611, 1).unwrap(), 5339);
+        assert_eq!(parent(53394611, 2).unwrap(), 533946);
+        assert_eq!(parent(53394611, 3).unwrap(), 53394611);
+        assert_eq!(parent(53394611234, 4).unwrap(), 533946112);
+        assert!(parent(5339, 2).is_err());
+    }
+
+    #[test]
+    fn enumerates_children() {
+        let lv2 = children(5339, 2).unwrap();
+        assert_eq!(lv2.len(), 64);
+        assert_eq!((lv2[0], lv2[63]), (533900, 533977));
+        assert_eq!(
+            children(53394611, 4).unwrap(),
+            vec![533946111, 533946112, 533946113, 533946114]
+        );
+        let lv6 = children(533946, 6).unwrap();
+        assert_eq!(lv6.len(), 100 * 4 * 4 * 4);
+        for code in [lv6[0], lv6[lv6.len() - 1]] {
+            assert_eq!(parent(code, 2).unwrap(), 533946);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        // Lv2 row/column digits only go up to 7.
+        assert!(children(533980, 3).is_err());
+        assert!(parent(53394615, 1).is_ok());
+        assert!(parent(533946115, 1).is_err());
+        assert!(parent(12345, 1).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{mesh_geometry, mesh_tile};
+use anyhow::{Result, anyhow, bail};
+use jismesh::{MeshCode, codes::JAPAN_LV1};
+
+/// Where a point falls in the mesh and in the tile grid of `mesh-tile`.
+#[derive(Debug, Clone, PartialEq)]
+struct MeshCodeLookup {
+    mesh_code: u64,
+    bbox: [f64; 4],
+    tile_level: u8,
+    tile_code: u64,
+    /// Row from the north edge and column from the west edge inside the tile.
+    tile_row: usize,
+    tile_col: usize,
+}
+
+fn lookup(lat: f64, lon: f64, level: u8, tile_level: u8) -> Result<MeshCodeLookup> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        bail!("coordinates out of range: lat={}, lon={}", lat, lon);
+    }
+    let code = MeshCode::try_from_latlng(lat, lon, mesh_tile::mesh_level_from_u8(level)?).map_err(
+        |e| {
+            anyhow!(
+                "failed to compute the mesh code of ({}, {}): {}",
+                lat,
+                lon,
+                e
+            )
+        },
+    )?;
+    let mesh_code = u64::from(code);
+    let rows_per_axis = mesh_tile::subdivisions_per_axis(tile_level, level)?;
+    let (tile_code, tile_row, tile_col) =
+        mesh_tile::map_meshcode_to_tile(mesh_code, level, tile_level, rows_per_axis)?;
+    Ok(MeshCodeLookup {
+        mesh_code,
+        bbox: mesh_geometry::cell_bounds(mesh_code)?.to_bbox(),
+        tile_level,
+        tile_code,
+        tile_row,
+        tile_col,
+    })
+}
+
+pub fn process_mesh_code_lookup(lat: f64, lon: f64, level: u8, tile_level: u8) -> Result<()> {
+    let result = lookup(lat, lon, level, tile_level)?;
+    let [min_lon, min_lat, max_lon, max_lat] = result.bbox;
+    println!("mesh_code: {}", result.mesh_code);
+    println!(
+        "bbox: {:.6},{:.6},{:.6},{:.6} (min_lon,min_lat,max_lon,max_lat)",
+        min_lon, min_lat, max_lon, max_lat
+    );
+    println!(
+        "tile_code: {} (tile level {}, row {} / col {} from the northwest corner)",
+        result.tile_code, result.tile_level, result.tile_row, result.tile_col
+    );
+    if !JAPAN_LV1.contains(&(result.mesh_code / 10u64.pow(result.mesh_code.ilog10() - 3))) {
+        eprintln!(
+            "Warning: Lv1 mesh of {} has no e-Stat mesh statistics (outside Japan's land area)",
+            result.mesh_code
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mesh_and_tile_of_tokyo_station() {
+        let result = lookup(35.681236, 139.767125, 3, 1).unwrap();
+        assert_eq!(result.mesh_code, 53394611);
+        assert_eq!(result.tile_code, 5339);
+        // Lv3 row 4*10+1 = 41 from the south edge of an 80-row tile.
+        assert_eq!((result.tile_row, result.tile_col), (38, 61));
+        let [min_lon, min_lat, max_lon, max_lat] = result.bbox;
+        assert!(min_lon <= 139.767125 && 139.767125 < max_lon);
+        assert!(min_lat <= 35.681236 && 35.681236 < max_lat);
+    }
+
+    #[test]
+    fn rejects_tile_level_finer_than_data() {
+        assert!(lookup(35.68, 139.76, 3, 4).is_err());
+        assert!(lookup(135.0, 139.76, 3, 1).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{mesh_geometry, mesh_tile};
+use anyhow::{Result, anyhow, bail};
+use jismesh::{MeshCode, to_envelope};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BBox {
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+}
+
+impl BBox {
+    fn validate(self) -> Result<()> {
+        for lat in [self.min_lat, self.max_lat] {
+            if !(-90.0..=90.0).contains(&lat) {
+                bail!("latitude out of range: {}", lat);
+            }
+        }
+        for lon in [self.min_lon, self.max_lon] {
+            if !(-180.0..=180.0).contains(&lon) {
+                bail!("longitude out of range: {}", lon);
+            }
+        }
+        if self.min_lat > self.max_lat || self.min_lon > self.max_lon {
+            bail!(
+                "empty bounding box: lat {}..{}, lon {}..{}",
+                self.min_lat,
+                self.max_lat,
+                self.min_lon,
+                self.max_lon
+            );
+        }
+        Ok(())
+    }
+
+    /// Cells that only touch the box along an edge do not count; a zero-size box still
+    /// selects the cell(s) containing it.
+    fn intersects(self, cell: mesh_geometry::CellBounds) -> bool {
+        cell.min_lat < self.max_lat.max(self.min_lat + f64::EPSILON)
+            && cell.max_lat > self.min_lat
+            && cell.min_lon < self.max_lon.max(self.min_lon + f64::EPSILON)
+            && cell.max_lon > self.min_lon
+    }
+}
+
+fn mesh_code_at(lat: f64, lon: f64, level: u8) -> Result<MeshCode> {
+    MeshCode::try_from_latlng(lat, lon, mesh_tile::mesh_level_from_u8(level)?).map_err(|e| {
+        anyhow!(
+            "failed to compute the mesh code of ({}, {}): {}",
+            lat,
+            lon,
+            e
+        )
+    })
+}
+
+/// Mesh codes at `level` whose cell intersects `bbox`, sorted.
+fn mesh_codes_in_bbox(level: u8, bbox: BBox) -> Result<Vec<u64>> {
+    bbox.validate()?;
+    let sw = mesh_code_at(bbox.min_lat, bbox.min_lon, level)?;
+    let ne = mesh_code_at(bbox.max_lat, bbox.max_lon, level)?;
+    let envelope =
+        to_envelope(&sw, &ne).map_err(|e| anyhow!("failed to enumerate mesh codes: {}", e))?;
+    let mut codes = Vec::with_capacity(envelope.len());
+    for code in envelope {
+        let code = u64::from(code);
+        if bbox.intersects(mesh_geometry::cell_bounds(code)?) {
+            codes.push(code);
+        }
+    }
+    codes.sort_unstable();
+    codes.dedup();
+    Ok(codes)
+}
+
+pub fn process_mesh_code_range(
+    level: u8,
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+) -> Result<()> {
+    let bbox = BBox {
+        min_lat,
+        max_lat,
+        min_lon,
+        max_lon,
+    };
+    let codes = mesh_codes_in_bbox(level, bbox)?;
+    for code in &codes {
+        println!("{}", code);
+    }
+    eprintln!("{} Lv{} mesh codes", codes.len(), level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(min_lat: f64, max_lat: f64, min_lon: f64, max_lon: f64) -> BBox {
+        BBox {
+            min_lat,
+            max_lat,
+            min_lon,
+            max_lon,
+        }
+    }
+
+    #[test]
+    fn lists_lv1_codes_spanning_a_boundary() {
+        // The box crosses 35°20′N (5239 / 5339) and 140°E (5339 / 5340).
+        let codes = mesh_codes_in_bbox(1, bbox(35.2, 35.8, 139.5, 140.5)).unwrap();
+        assert_eq!(codes, vec![5239, 5240, 5339, 5340]);
+    }
+
+    #[test]
+    fn excludes_cells_that_only_touch_the_box() {
+        // The box ends exactly on the east edge of 5339 (140°E).
+        let codes = mesh_codes_in_bbox(1, bbox(35.5, 35.6, 139.5, 140.0)).unwrap();
+        assert_eq!(codes, vec![5339]);
+    }
+
+    #[test]
+    fn selects_containing_cell_of_a_point() {
+        let codes =
+            mesh_codes_in_bbox(3, bbox(35.681236, 35.681236, 139.767125, 139.767125)).unwrap();
+        assert_eq!(codes, vec![53394611]);
+    }
+
+    #[test]
+    fn rejects_inverted_box() {
+        assert!(mesh_codes_in_bbox(1, bbox(36.0, 35.0, 139.0, 140.0)).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{mesh_code_hierarchy::mesh_code_level, mesh_tile::validate_mesh_code_level};
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+
+/// Why `code` is not a level-`expected_level` mesh code, or `None` when it is.
+fn invalid_reason(code: &str, expected_level: u8) -> Option<String> {
+    if !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Some("contains non-digit characters".to_string());
+    }
+    if ![4, 6, 8, 9, 10, 11].contains(&code.len()) || code.starts_with('0') {
+        return Some(format!("{} digits, not a level 1-6 code", code.len()));
+    }
+    let parsed: u64 = code.parse().ok()?;
+    if let Err(e) = validate_mesh_code_level(parsed, expected_level) {
+        return Some(e.to_string());
+    }
+    // The subdivision digits, e.g. a Lv2 row of 8 or 9 or a quadrant of 5.
+    mesh_code_level(parsed).err().map(|e| e.to_string())
+}
+
+/// Non-blank lines as `(line number, code)`.
+fn read_codes(text: &str) -> Vec<(usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, code)| !code.is_empty())
+        .collect()
+}
+
+/// Level of the first code that is a valid mesh code of any level.
+fn infer_level(codes: &[(usize, &str)]) -> Option<u8> {
+    codes.iter().find_map(|(_, code)| {
+        if !code.bytes().all(|b| b.is_ascii_digit()) || code.starts_with('0') {
+            return None;
+        }
+        mesh_code_level(code.parse().ok()?).ok()
+    })
+}
+
+/// Validates one mesh code per line of `input_file` and fails when any is invalid.
+pub fn process_mesh_code_validate(input_file: &Path, level: Option<u8>) -> Result<()> {
+    let text = std::fs::read_to_string(input_file)
+        .with_context(|| format!("when reading {}", input_file.display()))?;
+    let codes = read_codes(&text);
+    if codes.is_empty() {
+        bail!("{} contains no mesh codes", input_file.display());
+    }
+    let Some(level) = level.or_else(|| infer_level(&codes)) else {
+        bail!(
+            "{} contains no valid mesh code to infer --level from",
+            input_file.display()
+        );
+    };
+
+    let mut invalid = 0;
+    for (line, code) in &codes {
+        if let Some(reason) = invalid_reason(code, level) {
+            invalid += 1;
+            println!("line {}: {}: {}", line, code, reason);
+        }
+    }
+    if invalid > 0 {
+        bail!(
+            "{} of {} mesh codes are not valid Lv{} codes",
+            invalid,
+            codes.len(),
+            level
+        );
+    }
+    println!("All {} mesh codes are valid Lv{} codes", codes.len(), level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_invalid_codes_with_reasons() {
+        let codes = read_codes("\n5339x611\n53394611\n5339461\n533946111\n53398011\n53394612\n");
+        assert_eq!(codes[0], (2, "5339x611"));
+        assert_eq!(infer_level(&codes), Some(3));
+
+        let reasons: Vec<Option<String>> = codes
+            .iter()
+            .map(|(_, code)| invalid_reason(code, 3))
+            .collect();
+        assert_eq!(reasons[0].as_deref(), Some("contains non-digit characters"));
+        assert_eq!(reasons[1], None);
+        assert_eq!(
+            reasons[2].as_deref(),
+            Some("7 digits, not a level 1-6 code")
+        );
+        assert_eq!(
+            reasons[3].as_deref(),
+            Some("mesh code 533946111 has level 4")
+        );
+        assert!(
+            reasons[4]
+                .as_deref()
+                .unwrap()
+                .contains("invalid Lv2 subdivision")
+        );
+        assert_eq!(reasons[5], None);
+    }
+}
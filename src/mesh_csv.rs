@@ -1,42 +1,21 @@
-use crate::download::{self, DownloadedItem};
-use anyhow::{Context, Result, anyhow};
-use csv::{ReaderBuilder, StringRecord, WriterBuilder};
-use encoding_rs::SHIFT_JIS;
-use encoding_rs_io::DecodeReaderBytesBuilder;
+use crate::{
+    MeshCsvConfig,
+    column_alias::ColumnAliases,
+    download::{self, DownloadedItem},
+    error::JpEstatError,
+    geo_filter, mesh_geometry, mesh_stats, progress,
+    shiftjis_csv::{normalize_headers, open_shiftjis_csv},
+};
+use anyhow::{Context, Result, anyhow, bail};
+use csv::WriterBuilder;
 use futures::stream;
-use indicatif::{ProgressBar, ProgressStyle};
 use jismesh::codes::JAPAN_LV1;
 use serde::Deserialize;
-use std::{fs::File, io::BufReader, path::Path};
+use std::path::Path;
 use url::Url;
 
-fn open_shiftjis_csv(path: &Path) -> Result<csv::Reader<Box<dyn std::io::Read>>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let transcoded = DecodeReaderBytesBuilder::new()
-        .encoding(Some(SHIFT_JIS))
-        .build(reader);
-
-    Ok(ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(Box::new(transcoded)))
-}
-
-fn normalize_headers(header1: &StringRecord, header2: &StringRecord) -> Vec<String> {
-    header2
-        .iter()
-        .enumerate()
-        .map(|(i, h2)| {
-            let col = if h2.trim().is_empty() {
-                header1.get(i).unwrap_or_default().to_string()
-            } else {
-                h2.to_string()
-            };
-            col.trim().replace("\u{3000}", "")
-        })
-        .collect()
-}
+/// `KEY_CODE`, `HTKSYORI`, `HTKSAKI` and `GASSAN` come before the statistics columns.
+const DATA_COLUMN_START: usize = 4;
 
 #[derive(Debug, Deserialize, Clone)]
 struct MeshStatsConfig {
@@ -64,46 +43,102 @@ lazy_static::lazy_static! {
 }
 
 fn get_matching_mesh_stats(level: u8, year: u16, survey: &str) -> Option<&'static MeshStats> {
-    for mesh in AVAILABLE.iter() {
-        if mesh.meshlevel == level && mesh.year == year && mesh.name == survey {
-            return Some(mesh);
+    AVAILABLE
+        .iter()
+        .find(|mesh| mesh.meshlevel == level && mesh.year == year && mesh.name == survey)
+}
+
+/// Reads a `--column-order` file: one column name per line, blank lines ignored.
+async fn read_column_order(path: &Path) -> Result<Vec<String>> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("when reading {}", path.display()))?;
+    let mut order: Vec<String> = Vec::new();
+    for name in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if order.iter().any(|n| n == name) {
+            bail!("column \"{}\" is listed twice in {}", name, path.display());
         }
+        order.push(name.to_string());
     }
-    None
+    Ok(order)
 }
 
-pub async fn process_mesh_csv(
-    tmp_dir: &Path,
-    level: u8,
-    year: u16,
-    survey: &str,
-    output: &Path,
-) -> Result<()> {
-    let mesh_stats = get_matching_mesh_stats(level, year, survey)
-        .ok_or(anyhow!("一致する統計データが見つかりません"))?;
-
-    let urls_with_metadata: Vec<(u64, Url)> = JAPAN_LV1
+/// Source column index for each output column: the listed columns first, then the remaining
+/// ones in their original order.
+fn column_permutation(header: &[String], order: &[String]) -> Result<Vec<usize>> {
+    let missing: Vec<&str> = order
+        .iter()
+        .filter(|name| !header.contains(name))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "--column-order lists columns not in the CSV: {}",
+            missing.join(", ")
+        );
+    }
+    let listed: Vec<usize> = order
         .iter()
+        .filter_map(|name| header.iter().position(|h| h == name))
+        .collect();
+    let rest = (0..header.len()).filter(|i| !listed.contains(i));
+    Ok(listed.iter().copied().chain(rest).collect())
+}
+
+pub async fn process_mesh_csv(http_client: &reqwest::Client, config: &MeshCsvConfig) -> Result<()> {
+    let tmp_dir = config.tmp_dir.as_path();
+    let level = config.level;
+    let year = config.year;
+    let survey = config.survey.as_str();
+    let output = config.output.as_path();
+    let geo_filter = config.geo_filter.as_ref();
+    let column_order = config.column_order.as_deref();
+    let column_aliases = config.column_alias_file.as_deref();
+    let min_nonnull_fraction = config.min_nonnull_fraction;
+    let add_geometry_wkt = config.add_geometry_wkt;
+    let cleanup = config.cleanup;
+
+    mesh_stats::check_nonnull_fraction(min_nonnull_fraction)?;
+    let column_order = match column_order {
+        Some(path) => Some(read_column_order(path).await?),
+        None => None,
+    };
+    let column_aliases = match column_aliases {
+        Some(path) => ColumnAliases::read(path).await?,
+        None => ColumnAliases::default(),
+    };
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+
+    let urls_with_metadata: Vec<(u64, Url)> = geo_filter::filter_lv1_codes(JAPAN_LV1, geo_filter)?
+        .into_iter()
         .map(|mesh| {
             let url = format!(
                 "https://www.e-stat.go.jp/gis/statmap-search/data?statsId={}&code={}&downloadType=2",
                 mesh_stats.stats_id, mesh
             );
-            (*mesh, Url::parse(&url).unwrap())
+            (mesh, Url::parse(&url).unwrap())
         })
         .collect();
 
-    let mut downloaded_items: Vec<DownloadedItem<(u64, Url)>> = download::download_and_extract_all(
-        stream::iter(urls_with_metadata),
-        |(_mesh, url)| url.clone(),
-        |(mesh, _url)| format!("{}-{}-{}.zip", mesh_stats.year, mesh_stats.stats_id, mesh),
-        "txt",
-        tmp_dir,
-        "Downloading Mesh CSVs...",
-        "Extracting Mesh CSVs...",
-        10,
-    )
-    .await?;
+    let mut downloaded_items: Vec<DownloadedItem<(u64, Url)>> = download::into_result(
+        download::download_and_extract_all(
+            http_client,
+            stream::iter(urls_with_metadata),
+            |(_mesh, url)| url.clone(),
+            |(mesh, _url)| format!("{}-{}-{}.zip", mesh_stats.year, mesh_stats.stats_id, mesh),
+            "txt",
+            tmp_dir,
+            "Downloading Mesh CSVs...",
+            "Extracting Mesh CSVs...",
+            10,
+        )
+        .await?,
+    )?;
 
     if downloaded_items.is_empty() {
         return Err(anyhow!("No files found after download/extraction"));
@@ -111,22 +146,18 @@ pub async fn process_mesh_csv(
 
     downloaded_items.sort_by_key(|item| item.metadata.0);
 
-    if let Some(parent) = output.parent() {
-        if !parent.as_os_str().is_empty() {
-            tokio::fs::create_dir_all(parent).await?;
-        }
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
     }
 
     let mut writer = WriterBuilder::new().from_path(output)?;
 
-    let pb_style = ProgressStyle::default_bar()
-        .template("{msg} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7}")?
-        .progress_chars("##-");
-    let pb = ProgressBar::new(downloaded_items.len() as u64);
-    pb.set_style(pb_style);
-    pb.set_message("Merging CSVs...");
+    let pb = progress::new_bar(downloaded_items.len() as u64, "Merging CSVs...")?;
 
     let mut expected_header: Option<Vec<String>> = None;
+    let mut permutation: Vec<usize> = Vec::new();
 
     for item in downloaded_items.iter() {
         let mut rdr = open_shiftjis_csv(&item.extracted_path)
@@ -146,25 +177,65 @@ pub async fn process_mesh_csv(
         let header = normalize_headers(&header1, &header2);
         if let Some(expected) = expected_header.as_ref() {
             if expected != &header {
-                return Err(anyhow!(
-                    "CSV header mismatch: {}",
-                    item.extracted_path.display()
-                ));
+                return Err(JpEstatError::CsvMismatch {
+                    path: item.extracted_path.clone(),
+                }
+                .into());
             }
         } else {
+            permutation = match column_order.as_deref() {
+                Some(order) => column_permutation(&header, order)?,
+                None => (0..header.len()).collect(),
+            };
+            if let Some(min_nonnull_fraction) = min_nonnull_fraction {
+                let bands: Vec<(usize, &str)> = (DATA_COLUMN_START..header.len())
+                    .map(|i| (i, header[i].as_str()))
+                    .collect();
+                let keep =
+                    mesh_stats::dense_bands(&item.extracted_path, &bands, min_nonnull_fraction)?;
+                permutation.retain(|&i| i < DATA_COLUMN_START || keep[i - DATA_COLUMN_START]);
+            }
+            let output_header: Vec<String> = permutation
+                .iter()
+                .map(|&i| header[i].clone())
+                .chain(add_geometry_wkt.then(|| "geometry_wkt".to_string()))
+                .collect();
             writer
-                .write_record(&header)
+                .write_record(column_aliases.apply(&output_header)?)
                 .with_context(|| format!("when writing {}", output.display()))?;
             expected_header = Some(header);
         }
 
         for row in rdr.records() {
             let row = row?;
+            if let Some(filter) = geo_filter {
+                let mesh_code: u64 = row.get(0).unwrap_or("").trim().parse()?;
+                if !filter.contains_mesh_centroid(mesh_code)? {
+                    continue;
+                }
+            }
+            let geometry_wkt = if add_geometry_wkt {
+                let key_code = row.get(0).unwrap_or("").trim();
+                let mesh_code: u64 = key_code
+                    .parse()
+                    .with_context(|| format!("invalid KEY_CODE {:?}", key_code))?;
+                Some(mesh_geometry::cell_bounds(mesh_code)?.to_wkt_polygon())
+            } else {
+                None
+            };
             writer
-                .write_record(&row)
+                .write_record(
+                    permutation
+                        .iter()
+                        .map(|&i| row.get(i).unwrap_or(""))
+                        .chain(geometry_wkt.as_deref()),
+                )
                 .with_context(|| format!("when writing {}", output.display()))?;
         }
 
+        if cleanup {
+            download::cleanup_extracted(item)?;
+        }
         pb.inc(1);
     }
 
@@ -173,3 +244,29 @@ pub async fn process_mesh_csv(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn listed_columns_come_first_and_the_rest_keep_their_order() {
+        let header = header(&["KEY_CODE", "HTKSYORI", "HTKSAKI", "GASSAN", "人口総数"]);
+        let order = vec!["KEY_CODE".to_string(), "人口総数".to_string()];
+        assert_eq!(
+            column_permutation(&header, &order).unwrap(),
+            vec![0, 4, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_columns_in_order() {
+        let header = header(&["KEY_CODE", "人口総数"]);
+        let err = column_permutation(&header, &["世帯総数".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("世帯総数"));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    mesh_aggregate::group_cells,
+    mesh_diff::{NON_BAND_COLUMNS, column_index, open_mesh_csv_with_codes},
+    mesh_tile::AggFn,
+};
+use anyhow::{Result, bail};
+use csv::{Terminator, WriterBuilder};
+use encoding_rs::SHIFT_JIS;
+use std::path::Path;
+
+/// Columns written before the statistics, as in the CSVs distributed by e-Stat.
+const LEADING_COLUMNS: [&str; 4] = ["KEY_CODE", "HTKSYORI", "HTKSAKI", "GASSAN"];
+
+/// Converts a mesh CSV to a coarser level, written the way e-Stat distributes them: Shift_JIS
+/// with CRLF line endings, the column codes on the first header row and the names on the
+/// second. The confidentiality columns do not carry over to the coarser cells and are blank.
+pub fn process_mesh_csv_convert(
+    input_csv: &Path,
+    input_level: u8,
+    output_level: u8,
+    agg_fn: AggFn,
+    output: &Path,
+) -> Result<()> {
+    if output_level >= input_level {
+        bail!(
+            "--output-level ({}) must be < --input-level ({})",
+            output_level,
+            input_level
+        );
+    }
+    let (codes, header, mut rdr) = open_mesh_csv_with_codes(input_csv)?;
+    let key = column_index(&header, LEADING_COLUMNS[0], input_csv)?;
+    let indices: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| !NON_BAND_COLUMNS.contains(&h.as_str()))
+        .map(|(i, _)| i)
+        .collect();
+    if indices.is_empty() {
+        bail!("{} has no statistics columns", input_csv.display());
+    }
+    let (groups, cells) = group_cells(
+        &mut rdr,
+        input_csv,
+        key,
+        &indices,
+        input_level,
+        output_level,
+    )?;
+
+    let mut writer = WriterBuilder::new()
+        .terminator(Terminator::CRLF)
+        .from_writer(Vec::new());
+    writer.write_record(
+        LEADING_COLUMNS
+            .iter()
+            .copied()
+            .chain(indices.iter().map(|&i| codes[i].as_str())),
+    )?;
+    writer.write_record(
+        std::iter::repeat_n("", LEADING_COLUMNS.len())
+            .chain(indices.iter().map(|&i| header[i].as_str())),
+    )?;
+    for (parent, group) in &groups {
+        writer.write_record(
+            [
+                parent.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ]
+            .into_iter()
+            .chain(group.iter().map(|values| values.aggregate(agg_fn))),
+        )?;
+    }
+    let csv = String::from_utf8(writer.into_inner()?)?;
+    let (bytes, _, had_errors) = SHIFT_JIS.encode(&csv);
+    if had_errors {
+        bail!(
+            "the header of {} cannot be written in Shift_JIS",
+            input_csv.display()
+        );
+    }
+
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(output, &bytes)?;
+
+    println!(
+        "Converted {} Lv{} cells into {} Lv{} cells in {}",
+        cells,
+        input_level,
+        groups.len(),
+        output_level,
+        output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_coarser_cells_with_estat_headers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let input = dir.join("tblT001142H5339.txt");
+        let (bytes, _, _) = SHIFT_JIS.encode(
+            "KEY_CODE,HTKSYORI,HTKSAKI,GASSAN,T001142001,T001142002\r\n\
+             ,,,,人口（総数）,世帯総数\r\n\
+             533946111,0,,,10,4\r\n\
+             533946112,1,533946111,,*,*\r\n\
+             533946114,0,,,5,2\r\n\
+             533946211,0,,,7,3\r\n",
+        );
+        std::fs::write(&input, &bytes).unwrap();
+        let output = dir.join("lv3.txt");
+
+        process_mesh_csv_convert(&input, 4, 3, AggFn::Max, &output).unwrap();
+        let bytes = std::fs::read(&output).unwrap();
+        let (text, _, _) = SHIFT_JIS.decode(&bytes);
+        assert_eq!(
+            text,
+            "KEY_CODE,HTKSYORI,HTKSAKI,GASSAN,T001142001,T001142002\r\n\
+             ,,,,人口（総数）,世帯総数\r\n\
+             53394611,,,,10,4\r\n\
+             53394621,,,,7,3\r\n"
+        );
+
+        // The output reads back like an e-Stat file.
+        let (codes, header, _) = open_mesh_csv_with_codes(&output).unwrap();
+        assert_eq!(codes[4], "T001142001");
+        assert_eq!(header[4], "人口（総数）");
+
+        assert!(process_mesh_csv_convert(&input, 4, 4, AggFn::Sum, &output).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    download,
+    error::JpEstatError,
+    mesh::{self, get_matching_mesh_stats},
+    shiftjis_csv::open_shiftjis_csv,
+};
+use anyhow::{Context, Result, anyhow};
+use std::path::Path;
+
+/// Row counts of the downloaded files and the ones far below the rest.
+#[derive(Debug, PartialEq)]
+struct RowCountStats {
+    mean: f64,
+    /// Population standard deviation.
+    stddev: f64,
+    /// Files with fewer rows than this are reported (`mean - 2 * stddev`).
+    threshold: f64,
+    anomalies: Vec<(u64, usize)>,
+}
+
+fn row_count_stats(counts: &[(u64, usize)]) -> RowCountStats {
+    let n = counts.len().max(1) as f64;
+    let mean = counts.iter().map(|(_, rows)| *rows as f64).sum::<f64>() / n;
+    let variance = counts
+        .iter()
+        .map(|(_, rows)| (*rows as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    let stddev = variance.sqrt();
+    let threshold = mean - 2.0 * stddev;
+    let anomalies = counts
+        .iter()
+        .filter(|(_, rows)| (*rows as f64) < threshold)
+        .copied()
+        .collect();
+    RowCountStats {
+        mean,
+        stddev,
+        threshold,
+        anomalies,
+    }
+}
+
+/// Data rows of an e-Stat mesh CSV (the two header rows are not counted).
+fn count_rows(path: &Path) -> Result<usize> {
+    let mut rdr =
+        open_shiftjis_csv(path).with_context(|| format!("when opening {}", path.display()))?;
+    let mut rows = 0;
+    for record in rdr.records().skip(2) {
+        record.with_context(|| format!("when reading {}", path.display()))?;
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+pub async fn process_mesh_csv_stats(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    level: u8,
+    year: u16,
+    survey: &str,
+    cleanup: bool,
+) -> Result<()> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+
+    let mut downloaded_items =
+        mesh::download_mesh_csvs(http_client, tmp_dir, mesh_stats, None).await?;
+    if downloaded_items.is_empty() {
+        return Err(anyhow!("No files found after download/extraction"));
+    }
+    downloaded_items.sort_by_key(|item| item.metadata.0);
+
+    let mut counts = Vec::with_capacity(downloaded_items.len());
+    for item in &downloaded_items {
+        counts.push((item.metadata.0, count_rows(&item.extracted_path)?));
+        if cleanup {
+            download::cleanup_extracted(item)?;
+        }
+    }
+
+    let stats = row_count_stats(&counts);
+    println!(
+        "{} files: mean {:.1} rows, stddev {:.1} (threshold {:.1})",
+        counts.len(),
+        stats.mean,
+        stats.stddev,
+        stats.threshold
+    );
+    if stats.anomalies.is_empty() {
+        println!("No files with unusually few rows");
+    }
+    for (mesh, rows) in &stats.anomalies {
+        println!("{}: {} rows", mesh, rows);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_files_far_below_the_mean() {
+        let mut counts: Vec<(u64, usize)> =
+            (0..20).map(|i| (5000 + i, 1000 + i as usize)).collect();
+        counts.push((5339, 3));
+        let stats = row_count_stats(&counts);
+        assert_eq!(stats.anomalies, [(5339, 3)]);
+        assert!(stats.threshold > 3.0);
+
+        let stats = row_count_stats(&[(5339, 10), (5340, 10)]);
+        assert_eq!((stats.mean, stats.stddev), (10.0, 0.0));
+        assert!(stats.anomalies.is_empty());
+    }
+
+    #[test]
+    fn counts_data_rows_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("tblT001140S5339.txt");
+        std::fs::write(
+            &path,
+            "KEY_CODE,HTKSYORI,HTKSAKI,GASSAN,T001140001\r\n,,,,total\r\n53390000,0,,,1\r\n53390001,0,,,2\r\n",
+        )
+        .unwrap();
+        assert_eq!(count_rows(&path).unwrap(), 2);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{
+    download,
+    error::JpEstatError,
+    mesh::{self, get_matching_mesh_stats, infer_column_type, parse_nullable, read_columns},
+    shiftjis_csv::open_shiftjis_csv,
+};
+use anyhow::{Context, Result, bail};
+use std::{collections::BTreeMap, path::Path};
+
+/// A cell that the `mesh` import would fail to parse.
+#[derive(Debug, PartialEq)]
+struct ParseFailure {
+    file: String,
+    /// Record number in the file, counting the two header rows from 1.
+    row: u64,
+    column: String,
+    value: String,
+}
+
+/// Whether `value` parses as the type [`infer_column_type`] gives `column`, the same way
+/// the `mesh` import reads it.
+fn parses_as_column_type(column: &str, value: &str) -> bool {
+    match infer_column_type(column) {
+        "BIGINT" => parse_nullable::<i64>(value).is_ok(),
+        "SMALLINT" => parse_nullable::<i16>(value).is_ok(),
+        "BIGINT[]" => value.is_empty() || value.split(';').all(|v| v.parse::<i64>().is_ok()),
+        _ => parse_nullable::<i32>(value).is_ok(),
+    }
+}
+
+/// Every cell of one e-Stat mesh CSV that fails to parse; also returns the data row count.
+fn validate_file(path: &Path) -> Result<(u64, Vec<ParseFailure>)> {
+    let columns = read_columns(path).with_context(|| format!("when opening {}", path.display()))?;
+    let file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    let mut rdr = open_shiftjis_csv(path)?;
+    let mut rows = 0;
+    let mut failures = Vec::new();
+    for (i, record) in rdr.records().enumerate().skip(2) {
+        let record = record.with_context(|| format!("when reading {}", path.display()))?;
+        let row = i as u64 + 1;
+        for (i, column) in columns.iter().enumerate() {
+            let value = record.get(i).unwrap_or("");
+            if !parses_as_column_type(column, value) {
+                failures.push(ParseFailure {
+                    file: file.clone(),
+                    row,
+                    column: column.clone(),
+                    value: value.to_string(),
+                });
+            }
+        }
+        rows += 1;
+    }
+    Ok((rows, failures))
+}
+
+/// Failure count per column, for the summary.
+fn failures_by_column(failures: &[ParseFailure]) -> BTreeMap<&str, usize> {
+    let mut counts = BTreeMap::new();
+    for failure in failures {
+        *counts.entry(failure.column.as_str()).or_default() += 1;
+    }
+    counts
+}
+
+pub async fn process_mesh_csv_validate(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    level: u8,
+    year: u16,
+    survey: &str,
+    cleanup: bool,
+) -> Result<()> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+
+    let mut downloaded_items =
+        mesh::download_mesh_csvs(http_client, tmp_dir, mesh_stats, None).await?;
+    if downloaded_items.is_empty() {
+        bail!("No files found after download/extraction");
+    }
+    downloaded_items.sort_by_key(|item| item.metadata.0);
+
+    let mut rows = 0;
+    let mut failures = Vec::new();
+    let mut failed_files = 0;
+    for item in &downloaded_items {
+        let (file_rows, file_failures) = validate_file(&item.extracted_path)?;
+        for failure in &file_failures {
+            println!(
+                "{}:{}: {} = {:?}",
+                failure.file, failure.row, failure.column, failure.value
+            );
+        }
+        rows += file_rows;
+        if !file_failures.is_empty() {
+            failed_files += 1;
+        }
+        failures.extend(file_failures);
+        if cleanup {
+            download::cleanup_extracted(item)?;
+        }
+    }
+
+    println!(
+        "{} files, {} rows: {} values failed to parse in {} files",
+        downloaded_items.len(),
+        rows,
+        failures.len(),
+        failed_files
+    );
+    for (column, count) in failures_by_column(&failures) {
+        println!("  {}: {} ({})", column, count, infer_column_type(column));
+    }
+    if !failures.is_empty() {
+        bail!("{} values would fail to import", failures.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_cells_that_fail_to_parse() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("tblT001140S5339.txt");
+        std::fs::write(
+            &path,
+            "KEY_CODE,HTKSYORI,HTKSAKI,GASSAN,T001140001\r\n,,,,total\r\n\
+             53390000,0,,,*\r\n53390001,0,,53390002;53390003,\"1,234\"\r\n53390002,x,,,5\r\n",
+        )
+        .unwrap();
+
+        let (rows, failures) = validate_file(&path).unwrap();
+        assert_eq!(rows, 3);
+        let summary: Vec<_> = failures
+            .iter()
+            .map(|f| (f.file.as_str(), f.row, f.column.as_str(), f.value.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("tblT001140S5339.txt", 4, "total", "1,234"),
+                ("tblT001140S5339.txt", 5, "HTKSYORI", "x"),
+            ]
+        );
+        assert_eq!(
+            failures_by_column(&failures)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [("HTKSYORI", 1), ("total", 1)]
+        );
+    }
+}
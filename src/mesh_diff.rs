@@ -0,0 +1,283 @@
+use crate::shiftjis_csv::{buffer_size, normalize_headers, shiftjis_csv_reader};
+use anyhow::{Context, Result, anyhow, bail};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::Path,
+};
+
+const KEY_COLUMN: &str = "KEY_CODE";
+
+type CsvReader = csv::Reader<Box<dyn Read>>;
+
+/// Columns of a mesh CSV that are not statistics, so they are not compared by default.
+pub(crate) const NON_BAND_COLUMNS: [&str; 5] =
+    [KEY_COLUMN, "HTKSYORI", "HTKSAKI", "GASSAN", "geometry_wkt"];
+
+/// Opens a mesh CSV and returns its column names with the reader positioned at the first
+/// data row. Both the UTF-8 output of `mesh-csv` (one header row) and the Shift_JIS CSVs
+/// extracted from e-Stat (two header rows) are accepted.
+pub(crate) fn open_mesh_csv(path: &Path) -> Result<(Vec<String>, CsvReader)> {
+    let (_, header, rdr) = open_mesh_csv_with_codes(path)?;
+    Ok((header, rdr))
+}
+
+/// Like [`open_mesh_csv`], but also returns the first header row (the column codes such as
+/// `T001140001` of an e-Stat CSV, the column names again for a one-row header).
+pub(crate) fn open_mesh_csv_with_codes(
+    path: &Path,
+) -> Result<(Vec<String>, Vec<String>, CsvReader)> {
+    let mut file = File::open(path).with_context(|| format!("when opening {}", path.display()))?;
+    let mut head = Vec::new();
+    (&mut file).take(64 * 1024).read_to_end(&mut head)?;
+    file.rewind()?;
+    // A multi-byte character cut off at the end of the sample is still valid UTF-8.
+    let is_utf8 = match std::str::from_utf8(&head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    let file = BufReader::with_capacity(buffer_size(), file);
+    let mut rdr = if is_utf8 {
+        ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .buffer_capacity(buffer_size())
+            .from_reader(Box::new(file) as Box<dyn Read>)
+    } else {
+        shiftjis_csv_reader(file)
+    };
+
+    let mut records = rdr.records();
+    let header1 = records
+        .next()
+        .transpose()?
+        .ok_or_else(|| anyhow!("{} is empty", path.display()))?;
+    let codes: Vec<String> = header1.iter().map(|h| h.trim().to_string()).collect();
+    let header = if is_utf8 {
+        codes.clone()
+    } else {
+        let header2 = records
+            .next()
+            .transpose()?
+            .ok_or_else(|| anyhow!("missing second header row in {}", path.display()))?;
+        normalize_headers(&header1, &header2)
+    };
+    drop(records);
+    Ok((codes, header, rdr))
+}
+
+pub(crate) fn column_index(header: &[String], column: &str, path: &Path) -> Result<usize> {
+    header
+        .iter()
+        .position(|h| h == column)
+        .ok_or_else(|| anyhow!("column \"{}\" not found in {}", column, path.display()))
+}
+
+/// Columns present in both files, in the order of `header_a`.
+fn default_bands(header_a: &[String], header_b: &[String]) -> Vec<String> {
+    header_a
+        .iter()
+        .filter(|h| !NON_BAND_COLUMNS.contains(&h.as_str()) && header_b.contains(h))
+        .cloned()
+        .collect()
+}
+
+/// `*` (confidential) and blank cells have no value to compare.
+pub(crate) fn parse_value(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if value.is_empty() || value == "*" {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// `b - a`, printed as an integer when both sides are integers.
+fn format_delta(a: &str, b: &str) -> Option<String> {
+    let (a, b) = (a.trim(), b.trim());
+    if let (Ok(a), Ok(b)) = (a.parse::<i64>(), b.parse::<i64>()) {
+        return Some(b.saturating_sub(a).to_string());
+    }
+    Some((parse_value(b)? - parse_value(a)?).to_string())
+}
+
+/// `Some(deltas)` when the absolute difference of at least one band exceeds `threshold`.
+fn changed_deltas(values_a: &[String], values_b: &[String], threshold: f64) -> Option<Vec<String>> {
+    let mut changed = false;
+    let deltas = values_a
+        .iter()
+        .zip(values_b)
+        .map(|(a, b)| {
+            if let (Some(va), Some(vb)) = (parse_value(a), parse_value(b))
+                && (vb - va).abs() > threshold
+            {
+                changed = true;
+            }
+            format_delta(a, b).unwrap_or_default()
+        })
+        .collect();
+    changed.then_some(deltas)
+}
+
+fn band_values(record: &StringRecord, indices: &[usize]) -> Vec<String> {
+    indices
+        .iter()
+        .map(|&i| record.get(i).unwrap_or_default().to_string())
+        .collect()
+}
+
+pub fn process_mesh_diff(
+    file_a: &Path,
+    file_b: &Path,
+    bands: Option<&[String]>,
+    threshold: f64,
+    output: &Path,
+) -> Result<()> {
+    if threshold.is_nan() || threshold < 0.0 {
+        bail!("--threshold must be zero or positive");
+    }
+    let (header_a, mut rdr_a) = open_mesh_csv(file_a)?;
+    let (header_b, mut rdr_b) = open_mesh_csv(file_b)?;
+    let bands = match bands {
+        Some(bands) => bands.to_vec(),
+        None => default_bands(&header_a, &header_b),
+    };
+    if bands.is_empty() {
+        bail!(
+            "{} and {} have no statistics columns in common",
+            file_a.display(),
+            file_b.display()
+        );
+    }
+    let key_a = column_index(&header_a, KEY_COLUMN, file_a)?;
+    let key_b = column_index(&header_b, KEY_COLUMN, file_b)?;
+    let indices_a = bands
+        .iter()
+        .map(|band| column_index(&header_a, band, file_a))
+        .collect::<Result<Vec<_>>>()?;
+    let indices_b = bands
+        .iter()
+        .map(|band| column_index(&header_b, band, file_b))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut cells_a: HashMap<String, Vec<String>> = HashMap::new();
+    for record in rdr_a.records() {
+        let record = record.with_context(|| format!("when reading {}", file_a.display()))?;
+        let key = record.get(key_a).unwrap_or_default().trim().to_string();
+        cells_a.insert(key, band_values(&record, &indices_a));
+    }
+
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut writer = WriterBuilder::new().from_path(output)?;
+    let mut header = vec![KEY_COLUMN.to_string()];
+    for band in &bands {
+        header.extend([
+            format!("{}_a", band),
+            format!("{}_b", band),
+            format!("{}_delta", band),
+        ]);
+    }
+    writer.write_record(&header)?;
+
+    let (mut compared, mut only_in_b, mut changed) = (0u64, 0u64, 0u64);
+    for record in rdr_b.records() {
+        let record = record.with_context(|| format!("when reading {}", file_b.display()))?;
+        let key = record.get(key_b).unwrap_or_default().trim();
+        let Some(values_a) = cells_a.remove(key) else {
+            only_in_b += 1;
+            continue;
+        };
+        compared += 1;
+        let values_b = band_values(&record, &indices_b);
+        if let Some(deltas) = changed_deltas(&values_a, &values_b, threshold) {
+            changed += 1;
+            let mut row = vec![key.to_string()];
+            for ((a, b), delta) in values_a.into_iter().zip(values_b).zip(deltas) {
+                row.extend([a, b, delta]);
+            }
+            writer.write_record(&row)?;
+        }
+    }
+    writer.flush()?;
+
+    println!(
+        "Wrote {} changed cells (of {} in both files) to {}",
+        changed,
+        compared,
+        output.display()
+    );
+    if !cells_a.is_empty() || only_in_b > 0 {
+        eprintln!(
+            "Warning: {} cells only in {} and {} only in {} were not compared",
+            cells_a.len(),
+            file_a.display(),
+            only_in_b,
+            file_b.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::SHIFT_JIS;
+
+    #[test]
+    fn reports_cells_over_the_threshold() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file_a = dir.join("2015.csv");
+        std::fs::write(
+            &file_a,
+            "KEY_CODE,HTKSYORI,人口（総数）,世帯総数\n\
+             53394611,0,100,40\n\
+             53394612,0,50,20\n\
+             53394613,0,*,10\n\
+             53394614,0,7,3\n",
+        )
+        .unwrap();
+        // The other year as extracted from e-Stat: Shift_JIS with two header rows.
+        let file_b = dir.join("2020.txt");
+        let (bytes, _, _) = SHIFT_JIS.encode(
+            "KEY_CODE,HTKSYORI,T000001,T000002\r\n\
+             ,,人口（総数）,世帯総数\r\n\
+             53394611,0,90,41\r\n\
+             53394612,0,52,20\r\n\
+             53394613,0,30,10\r\n\
+             53394615,0,1,1\r\n",
+        );
+        std::fs::write(&file_b, bytes).unwrap();
+        let output = dir.join("diff.csv");
+
+        process_mesh_diff(&file_a, &file_b, None, 2.0, &output).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "KEY_CODE,人口（総数）_a,人口（総数）_b,人口（総数）_delta,世帯総数_a,世帯総数_b,世帯総数_delta\n\
+             53394611,100,90,-10,40,41,1\n"
+        );
+
+        let bands = ["世帯総数".to_string()];
+        process_mesh_diff(&file_a, &file_b, Some(&bands), 0.0, &output).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "KEY_CODE,世帯総数_a,世帯総数_b,世帯総数_delta\n53394611,40,41,1\n"
+        );
+
+        let bands = ["人口".to_string()];
+        assert!(process_mesh_diff(&file_a, &file_b, Some(&bands), 0.0, &output).is_err());
+    }
+
+    #[test]
+    fn formats_integer_and_decimal_deltas() {
+        assert_eq!(format_delta("100", "90").as_deref(), Some("-10"));
+        assert_eq!(format_delta("1.5", "2").as_deref(), Some("0.5"));
+        assert_eq!(format_delta("*", "2"), None);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{
+    download,
+    error::JpEstatError,
+    mesh::{self, get_matching_mesh_stats},
+    mesh_geometry, progress,
+    shiftjis_csv::{normalize_headers, open_shiftjis_csv},
+};
+use anyhow::{Context, Result, anyhow};
+use futures::{StreamExt as _, stream};
+use geojson::{Feature, FeatureWriter, Geometry, GeometryValue, JsonObject, JsonValue, feature};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Number of level-1 files converted at the same time.
+const EXPORT_CONCURRENCY: usize = 8;
+
+/// Converts a CSV cell to a JSON value, following the same rules as the PostgreSQL import:
+/// empty and `*` become `null`, `GASSAN` is a `;`-separated list of mesh codes.
+fn parse_property(column: &str, raw: &str) -> Result<JsonValue> {
+    let v = raw.trim();
+    if v.is_empty() || v == "*" {
+        return Ok(JsonValue::Null);
+    }
+    if column == "GASSAN" {
+        let codes = v
+            .split(';')
+            .map(|s| s.trim().parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid GASSAN value '{}'", v))?;
+        return Ok(JsonValue::from(codes));
+    }
+    if let Ok(n) = v.parse::<i64>() {
+        return Ok(JsonValue::from(n));
+    }
+    let n: f64 = v
+        .parse()
+        .with_context(|| format!("invalid numeric value '{}' in column '{}'", v, column))?;
+    Ok(JsonValue::from(n))
+}
+
+fn build_feature(header: &[String], row: &csv::StringRecord) -> Result<Option<Feature>> {
+    let code_str = row.get(0).unwrap_or("").trim();
+    if code_str.is_empty() {
+        return Ok(None);
+    }
+    let mesh_code: u64 = code_str
+        .parse()
+        .with_context(|| format!("invalid mesh code '{}'", code_str))?;
+
+    let mut properties = JsonObject::new();
+    for (column, raw) in header.iter().zip(row.iter()) {
+        properties.insert(column.clone(), parse_property(column, raw)?);
+    }
+
+    let bounds = mesh_geometry::cell_bounds(mesh_code)?;
+    Ok(Some(Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(GeometryValue::new_polygon([bounds.ring()]))),
+        id: Some(feature::Id::Number(mesh_code.into())),
+        properties: Some(properties),
+        foreign_members: None,
+    }))
+}
+
+/// Writes the rows of one extracted CSV as features. Returns the feature count.
+pub(crate) fn write_csv_features<W: Write>(
+    csv_path: &Path,
+    writer: &mut FeatureWriter<W>,
+) -> Result<usize> {
+    let mut rdr = open_shiftjis_csv(csv_path)
+        .with_context(|| format!("when opening {}", csv_path.display()))?;
+    let header1 = rdr
+        .records()
+        .next()
+        .transpose()?
+        .ok_or(anyhow!("missing first header row"))?;
+    let header2 = rdr
+        .records()
+        .next()
+        .transpose()?
+        .ok_or(anyhow!("missing second header row"))?;
+    let header = normalize_headers(&header1, &header2);
+
+    let mut count = 0usize;
+    for row in rdr.records() {
+        let row = row?;
+        let Some(feature) = build_feature(&header, &row)
+            .with_context(|| format!("when reading {}", csv_path.display()))?
+        else {
+            continue;
+        };
+        writer.write_feature(&feature)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Writes `{lv1}.geojson` for one extracted CSV. Returns the feature count.
+fn export_csv_to_geojson(csv_path: &Path, output_path: &Path) -> Result<usize> {
+    let file = File::create(output_path)
+        .with_context(|| format!("when creating {}", output_path.display()))?;
+    let mut writer = FeatureWriter::from_writer(BufWriter::new(file));
+    let count = write_csv_features(csv_path, &mut writer)?;
+    writer.finish()?;
+    Ok(count)
+}
+
+pub async fn process_mesh_export(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    level: u8,
+    year: u16,
+    survey: &str,
+    output_dir: &Path,
+    cleanup: bool,
+) -> Result<()> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+
+    let downloaded_items = mesh::download_mesh_csvs(http_client, tmp_dir, mesh_stats, None).await?;
+
+    if downloaded_items.is_empty() {
+        return Err(anyhow!("No files found after download/extraction"));
+    }
+
+    tokio::fs::create_dir_all(output_dir).await?;
+
+    let pb = progress::new_bar(downloaded_items.len() as u64, "Writing GeoJSON...")?;
+
+    let results: Vec<Result<usize>> = stream::iter(downloaded_items)
+        .map(|item| {
+            let pb = pb.clone();
+            let output_path: PathBuf = output_dir.join(format!("{}.geojson", item.metadata.0));
+            async move {
+                let count = tokio::task::spawn_blocking(move || {
+                    let count = export_csv_to_geojson(&item.extracted_path, &output_path)?;
+                    if cleanup {
+                        download::cleanup_extracted(&item)?;
+                    }
+                    Ok::<_, anyhow::Error>(count)
+                })
+                .await??;
+                pb.inc(1);
+                Ok(count)
+            }
+        })
+        .buffer_unordered(EXPORT_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut total_features = 0usize;
+    let mut total_files = 0usize;
+    for result in results {
+        total_features += result?;
+        total_files += 1;
+    }
+
+    pb.finish_with_message(format!(
+        "GeoJSON export completed ({} files, {} features)",
+        total_files, total_features
+    ));
+    println!("GeoJSON directory: {}", output_dir.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_properties_like_postgres_import() {
+        assert_eq!(parse_property("T001", "").unwrap(), JsonValue::Null);
+        assert_eq!(parse_property("T001", "*").unwrap(), JsonValue::Null);
+        assert_eq!(parse_property("T001", "42").unwrap(), JsonValue::from(42));
+        assert_eq!(
+            parse_property("GASSAN", "53393599;53393600").unwrap(),
+            JsonValue::from(vec![53393599i64, 53393600])
+        );
+        assert!(parse_property("T001", "abc").is_err());
+    }
+
+    #[test]
+    fn builds_feature_with_cell_polygon() {
+        let header = vec!["KEY_CODE".to_string(), "T001".to_string()];
+        let row = csv::StringRecord::from(vec!["53393599", "12"]);
+        let feature = build_feature(&header, &row).unwrap().unwrap();
+
+        let properties = feature.properties.unwrap();
+        assert_eq!(properties["KEY_CODE"], JsonValue::from(53393599));
+        assert_eq!(properties["T001"], JsonValue::from(12));
+        let Some(GeometryValue::Polygon { coordinates }) = feature.geometry.map(|g| g.value) else {
+            panic!("expected polygon geometry");
+        };
+        assert_eq!(coordinates[0].len(), 5);
+    }
+}
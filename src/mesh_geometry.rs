@@ -0,0 +1,121 @@
+use anyhow::{Result, anyhow};
+use jismesh::MeshCode;
+use serde_json::{Value, json};
+
+/// Geographic extent of a single mesh cell, in degrees (datum of the source survey).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellBounds {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl CellBounds {
+    /// Closed exterior ring, counter-clockwise from the southwest corner.
+    pub fn ring(self) -> [[f64; 2]; 5] {
+        [
+            [self.min_lon, self.min_lat],
+            [self.max_lon, self.min_lat],
+            [self.max_lon, self.max_lat],
+            [self.min_lon, self.max_lat],
+            [self.min_lon, self.min_lat],
+        ]
+    }
+
+    /// Cell centroid as `(lon, lat)`.
+    pub fn center(self) -> (f64, f64) {
+        (
+            (self.min_lon + self.max_lon) / 2.0,
+            (self.min_lat + self.max_lat) / 2.0,
+        )
+    }
+
+    /// `[min_lon, min_lat, max_lon, max_lat]`, the GeoJSON / STAC bbox order.
+    pub fn to_bbox(self) -> [f64; 4] {
+        [self.min_lon, self.min_lat, self.max_lon, self.max_lat]
+    }
+
+    pub fn to_geojson_polygon(self) -> Value {
+        json!({
+            "type": "Polygon",
+            "coordinates": [self.ring()],
+        })
+    }
+
+    /// `POLYGON((lon lat, ...))` with the same ring as [`CellBounds::ring`].
+    pub fn to_wkt_polygon(self) -> String {
+        let points: Vec<String> = self
+            .ring()
+            .iter()
+            .map(|[lon, lat]| format!("{} {}", lon, lat))
+            .collect();
+        format!("POLYGON(({}))", points.join(", "))
+    }
+}
+
+pub fn cell_bounds(mesh_code: u64) -> Result<CellBounds> {
+    let code = MeshCode::try_from(mesh_code)
+        .map_err(|e| anyhow!("failed to parse mesh code {}: {}", mesh_code, e))?;
+    let (min_lat, min_lon) = code
+        .point(0.0, 0.0)
+        .map_err(|e| anyhow!("failed to compute bounds of {}: {}", mesh_code, e))?;
+    let (max_lat, max_lon) = code
+        .point(1.0, 1.0)
+        .map_err(|e| anyhow!("failed to compute bounds of {}: {}", mesh_code, e))?;
+    Ok(CellBounds {
+        min_lon,
+        min_lat,
+        max_lon,
+        max_lat,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn computes_lv1_bounds() {
+        let bounds = cell_bounds(5339).unwrap();
+        assert_close(bounds.min_lat, 35.0 + 1.0 / 3.0);
+        assert_close(bounds.max_lat, 36.0);
+        assert_close(bounds.min_lon, 139.0);
+        assert_close(bounds.max_lon, 140.0);
+    }
+
+    #[test]
+    fn computes_lv3_bounds() {
+        let bounds = cell_bounds(53393599).unwrap();
+        assert_close(bounds.max_lat - bounds.min_lat, 2.0 / 3.0 / 80.0);
+        assert_close(bounds.max_lon - bounds.min_lon, 1.0 / 80.0);
+        let ring = bounds.ring();
+        assert_eq!(ring.first(), ring.last());
+    }
+
+    #[test]
+    fn formats_wkt_polygon() {
+        let bounds = CellBounds {
+            min_lon: 139.0,
+            min_lat: 35.5,
+            max_lon: 140.0,
+            max_lat: 36.0,
+        };
+        assert_eq!(
+            bounds.to_wkt_polygon(),
+            "POLYGON((139 35.5, 140 35.5, 140 36, 139 36, 139 35.5))"
+        );
+        let parsed =
+            crate::geo_filter::GeoFilter::parse(&cell_bounds(5339).unwrap().to_wkt_polygon());
+        assert!(parsed.is_ok());
+    }
+}
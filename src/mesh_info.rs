@@ -1,15 +1,14 @@
-use crate::unzip;
+use crate::{
+    download,
+    shiftjis_csv::{normalize_headers, open_shiftjis_csv},
+    unzip,
+};
 use anyhow::{Context, Result, anyhow, bail};
-use csv::{ReaderBuilder, StringRecord};
-use encoding_rs::SHIFT_JIS;
-use encoding_rs_io::DecodeReaderBytesBuilder;
 use jismesh::codes::JAPAN_LV1;
 use reqwest::{Client, StatusCode};
 use serde::Deserialize;
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fs::File,
-    io::BufReader,
     path::{Path, PathBuf},
 };
 use tokio::io::AsyncWriteExt as _;
@@ -46,34 +45,6 @@ lazy_static::lazy_static! {
     };
 }
 
-fn open_shiftjis_csv(path: &Path) -> Result<csv::Reader<Box<dyn std::io::Read>>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let transcoded = DecodeReaderBytesBuilder::new()
-        .encoding(Some(SHIFT_JIS))
-        .build(reader);
-
-    Ok(ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(Box::new(transcoded)))
-}
-
-fn normalize_headers(header1: &StringRecord, header2: &StringRecord) -> Vec<String> {
-    header2
-        .iter()
-        .enumerate()
-        .map(|(i, h2)| {
-            let col = if h2.trim().is_empty() {
-                header1.get(i).unwrap_or_default().to_string()
-            } else {
-                h2.to_string()
-            };
-            col.trim().replace("\u{3000}", "")
-        })
-        .collect()
-}
-
 fn extract_bands(csv_path: &Path) -> Result<Vec<String>> {
     let mut rdr = open_shiftjis_csv(csv_path)?;
     let header1 = rdr
@@ -103,6 +74,7 @@ fn build_mesh_url(stats_id: &str, mesh_code: u64) -> String {
 }
 
 async fn download_zip(client: &Client, zip_path: &Path, url: &str) -> Result<StatusCode> {
+    download::RATE_LIMITER.acquire().await;
     let response = client.get(url).send().await?;
     let status = response.status();
     if !status.is_success() {
@@ -118,11 +90,14 @@ async fn download_zip(client: &Client, zip_path: &Path, url: &str) -> Result<Sta
 
 async fn try_extract_txt(zip_path: &Path) -> Option<PathBuf> {
     let extracted = unzip::unzip_archive(zip_path).await.ok()?;
-    unzip::find_file_with_ext(&extracted, "txt").await.ok()
+    unzip::find_file_with_ext_or_gz(&extracted, "txt")
+        .await
+        .ok()
+        .map(|(path, _)| path)
 }
 
 async fn ensure_sample_csv(tmp_dir: &Path, client: &Client, stats: &MeshStats) -> Result<PathBuf> {
-    for mesh in JAPAN_LV1.iter().copied() {
+    for mesh in JAPAN_LV1.iter() {
         let zip_filename = format!("{}-{}-{}.zip", stats.year, stats.stats_id, mesh);
         let zip_path = tmp_dir.join(zip_filename);
         if !zip_path.exists() {
@@ -231,7 +206,11 @@ fn print_report(datasets: &[DatasetInfo]) {
     }
 }
 
-pub async fn process_mesh_info(tmp_dir: &Path, year_filter: Option<&[u16]>) -> Result<()> {
+pub async fn process_mesh_info(
+    client: &Client,
+    tmp_dir: &Path,
+    year_filter: Option<&[u16]>,
+) -> Result<()> {
     let mut available = AVAILABLE.clone();
     if let Some(years) = year_filter {
         let years_set: BTreeSet<u16> = years.iter().copied().collect();
@@ -261,10 +240,9 @@ pub async fn process_mesh_info(tmp_dir: &Path, year_filter: Option<&[u16]>) -> R
         return Ok(());
     }
 
-    let client = Client::new();
     let mut datasets = Vec::with_capacity(available.len());
     for stats in available {
-        let (bands, bands_error) = match ensure_sample_csv(tmp_dir, &client, &stats)
+        let (bands, bands_error) = match ensure_sample_csv(tmp_dir, client, &stats)
             .await
             .with_context(|| {
                 format!(
@@ -0,0 +1,159 @@
+use crate::{
+    mesh_bbox::EARTH_RADIUS_KM,
+    mesh_math::{cell_area_km2, digits_for_level, subdivisions_per_axis},
+};
+use anyhow::Result;
+use jismesh::codes::JAPAN_LV1;
+
+/// Latitude at which the east-west size is given: the middle of the Lv1 rows covering the
+/// main islands, where the cells are closest to square.
+const REFERENCE_LAT: f64 = 36.0;
+
+/// A Lv1 cell spans 40′ of latitude and 1° of longitude.
+const LV1_LAT_SECONDS: f64 = 2400.0;
+const LV1_LON_SECONDS: f64 = 3600.0;
+
+#[derive(Debug, PartialEq)]
+struct LevelInfo {
+    level: u8,
+    digits: usize,
+    /// Cells per axis of one Lv1 cell.
+    per_axis: usize,
+    lat_seconds: f64,
+    lon_seconds: f64,
+    /// North-south size, the same at every latitude on a sphere.
+    height_km: f64,
+    /// East-west size at `REFERENCE_LAT`.
+    width_km: f64,
+    /// Cells in the Lv1 meshes that cover Japan (`JAPAN_LV1`), sea included.
+    cells_in_japan: u64,
+    use_case: &'static str,
+}
+
+impl LevelInfo {
+    fn nominal_size(&self) -> String {
+        let side_km = cell_area_km2(self.level).unwrap_or_default().sqrt();
+        if side_km >= 1.0 {
+            format!("{} km", side_km)
+        } else {
+            format!("{} m", side_km * 1000.0)
+        }
+    }
+}
+
+fn use_case(level: u8) -> &'static str {
+    match level {
+        1 => "regional (prefectures, wide-area maps)",
+        2 => "municipal (cities, towns and villages)",
+        3 => "district (town blocks, catchment areas)",
+        4 | 5 => "block (neighbourhoods, facility siting)",
+        _ => "building (individual sites, dense urban areas)",
+    }
+}
+
+/// Sizes of a level, derived from the Lv1 cell and the refinement factors of the levels
+/// below it.
+fn level_info(level: u8) -> Result<LevelInfo> {
+    let per_axis = subdivisions_per_axis(1, level)?;
+    let lat_seconds = LV1_LAT_SECONDS / per_axis as f64;
+    let lon_seconds = LV1_LON_SECONDS / per_axis as f64;
+    let seconds_to_km = |seconds: f64| EARTH_RADIUS_KM * (seconds / 3600.0).to_radians();
+    Ok(LevelInfo {
+        level,
+        digits: digits_for_level(level)?,
+        per_axis,
+        lat_seconds,
+        lon_seconds,
+        height_km: seconds_to_km(lat_seconds),
+        width_km: seconds_to_km(lon_seconds) * REFERENCE_LAT.to_radians().cos(),
+        cells_in_japan: JAPAN_LV1.len() as u64 * (per_axis * per_axis) as u64,
+        use_case: use_case(level),
+    })
+}
+
+fn table_lines() -> Result<Vec<String>> {
+    let mut lines = vec![format!(
+        "{:<5} {:>7} {:>10} {:>10} {:>10} {:>10} {:>14}  {}",
+        "level", "size", "lat_deg", "lon_deg", "height_km", "width_km", "cells_japan", "use_case"
+    )];
+    for level in 1..=6 {
+        let info = level_info(level)?;
+        lines.push(format!(
+            "{:<5} {:>7} {:>10.6} {:>10.6} {:>10.3} {:>10.3} {:>14}  {}",
+            format!("Lv{}", info.level),
+            info.nominal_size(),
+            info.lat_seconds / 3600.0,
+            info.lon_seconds / 3600.0,
+            info.height_km,
+            info.width_km,
+            info.cells_in_japan,
+            info.use_case
+        ));
+    }
+    Ok(lines)
+}
+
+fn detail_lines(level: u8) -> Result<Vec<String>> {
+    let info = level_info(level)?;
+    Ok(vec![
+        format!("level: Lv{} ({}-digit codes)", info.level, info.digits),
+        format!("nominal size: {}", info.nominal_size()),
+        format!(
+            "resolution: {}\" lat x {}\" lon ({:.6}° x {:.6}°)",
+            info.lat_seconds,
+            info.lon_seconds,
+            info.lat_seconds / 3600.0,
+            info.lon_seconds / 3600.0
+        ),
+        format!(
+            "cell size: {:.3} km (N-S) x {:.3} km (E-W at {}°N)",
+            info.height_km, info.width_km, REFERENCE_LAT
+        ),
+        format!(
+            "cells per Lv1 mesh: {}x{} = {}",
+            info.per_axis,
+            info.per_axis,
+            info.per_axis * info.per_axis
+        ),
+        format!(
+            "cells covering Japan: {} ({} Lv1 meshes)",
+            info.cells_in_japan,
+            JAPAN_LV1.len()
+        ),
+        format!("use case: {}", info.use_case),
+    ])
+}
+
+pub fn process_mesh_level_info(level: Option<u8>) -> Result<()> {
+    let lines = match level {
+        Some(level) => detail_lines(level)?,
+        None => table_lines()?,
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_sizes_from_the_mesh_definition() {
+        let lv3 = level_info(3).unwrap();
+        assert_eq!((lv3.lat_seconds, lv3.lon_seconds), (30.0, 45.0));
+        assert_eq!(lv3.nominal_size(), "1 km");
+        assert!((lv3.height_km - 0.927).abs() < 0.001, "{}", lv3.height_km);
+        assert!((lv3.width_km - 1.125).abs() < 0.001, "{}", lv3.width_km);
+        assert_eq!(lv3.cells_in_japan, JAPAN_LV1.len() as u64 * 6400);
+
+        let lv6 = level_info(6).unwrap();
+        assert_eq!((lv6.lat_seconds, lv6.lon_seconds), (3.75, 5.625));
+        assert_eq!(lv6.nominal_size(), "125 m");
+        assert_eq!(level_info(1).unwrap().nominal_size(), "80 km");
+
+        assert_eq!(table_lines().unwrap().len(), 7);
+        assert!(level_info(7).is_err());
+    }
+}
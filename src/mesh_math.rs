@@ -0,0 +1,256 @@
+//! Mesh-code arithmetic behind the `mesh-tile` grid: digit counts per level, how many cells
+//! a level splits into, and where a mesh code falls inside its parent tile.
+//!
+//! Only `core` is used (no allocation, no I/O), so the same file also builds as the
+//! `#![no_std]` crate in `mesh-math/` for firmware and WebAssembly.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshMathError {
+    UnsupportedLevel(u8),
+    UnsupportedRefinement(u8),
+    TileLevelAboveDataLevel {
+        tile_level: u8,
+        data_level: u8,
+    },
+    Overflow,
+    DigitCount {
+        mesh_code: u64,
+        digits: usize,
+        expected: usize,
+        level: u8,
+    },
+    InvalidSubdivision {
+        mesh_code: u64,
+        level: u8,
+    },
+    InvalidQuadrant(u8),
+    OutOfRange {
+        mesh_code: u64,
+        row_south: usize,
+        col: usize,
+        rows: usize,
+    },
+}
+
+impl fmt::Display for MeshMathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MeshMathError::UnsupportedLevel(level) => {
+                write!(f, "unsupported mesh level: {}", level)
+            }
+            MeshMathError::UnsupportedRefinement(level) => {
+                write!(f, "unsupported refinement step to level {}", level)
+            }
+            MeshMathError::TileLevelAboveDataLevel {
+                tile_level,
+                data_level,
+            } => write!(
+                f,
+                "tile-level ({}) must be <= data level ({})",
+                tile_level, data_level
+            ),
+            MeshMathError::Overflow => write!(f, "tile resolution overflow"),
+            MeshMathError::DigitCount {
+                mesh_code,
+                digits,
+                expected,
+                level,
+            } => write!(
+                f,
+                "mesh code {} has {} digits, expected {} for level {}",
+                mesh_code, digits, expected, level
+            ),
+            MeshMathError::InvalidSubdivision { mesh_code, level } => {
+                write!(
+                    f,
+                    "invalid Lv{} subdivision in mesh code {}",
+                    level, mesh_code
+                )
+            }
+            MeshMathError::InvalidQuadrant(q) => write!(f, "invalid split mesh quadrant: {}", q),
+            MeshMathError::OutOfRange {
+                mesh_code,
+                row_south,
+                col,
+                rows,
+            } => write!(
+                f,
+                "computed tile coordinates out of range for mesh code {} (row_south={}, col={}, rows={})",
+                mesh_code, row_south, col, rows
+            ),
+        }
+    }
+}
+
+impl core::error::Error for MeshMathError {}
+
+pub const fn digits_for_level(level: u8) -> Result<usize, MeshMathError> {
+    match level {
+        1 => Ok(4),
+        2 => Ok(6),
+        3 => Ok(8),
+        4 => Ok(9),
+        5 => Ok(10),
+        6 => Ok(11),
+        _ => Err(MeshMathError::UnsupportedLevel(level)),
+    }
+}
+
+/// Nominal area of one cell in km²: Lv1 ≈ 80 km square, Lv2 ≈ 10 km, Lv3 ≈ 1 km, and each
+/// split level a quarter of the one above. The real area shrinks towards the north (a Lv3
+/// cell is about 0.91 km² in northern Hokkaido and 1.16 km² in Okinawa), so this is the JIS
+/// figure, not a measurement.
+pub const fn cell_area_km2(level: u8) -> Result<f64, MeshMathError> {
+    match level {
+        1 => Ok(6400.0),
+        2 => Ok(100.0),
+        3 => Ok(1.0),
+        4 => Ok(0.25),
+        5 => Ok(0.0625),
+        6 => Ok(0.015625),
+        _ => Err(MeshMathError::UnsupportedLevel(level)),
+    }
+}
+
+/// Cells per axis a level-`next_level - 1` cell is split into.
+pub const fn refinement_factor(next_level: u8) -> Result<usize, MeshMathError> {
+    match next_level {
+        2 => Ok(8),
+        3 => Ok(10),
+        4..=6 => Ok(2),
+        _ => Err(MeshMathError::UnsupportedRefinement(next_level)),
+    }
+}
+
+/// Data-level cells per axis of one tile-level cell.
+pub fn subdivisions_per_axis(tile_level: u8, data_level: u8) -> Result<usize, MeshMathError> {
+    if tile_level > data_level {
+        return Err(MeshMathError::TileLevelAboveDataLevel {
+            tile_level,
+            data_level,
+        });
+    }
+
+    let mut size = 1usize;
+    for next_level in (tile_level + 1)..=data_level {
+        size = size
+            .checked_mul(refinement_factor(next_level)?)
+            .ok_or(MeshMathError::Overflow)?;
+    }
+
+    Ok(size)
+}
+
+fn digit_count(value: u64) -> usize {
+    value.checked_ilog10().map_or(1, |log| log as usize + 1)
+}
+
+/// Digit `idx` (0-based from the left) of a `digits`-digit code.
+fn digit_at(code: u64, digits: usize, idx: usize) -> u8 {
+    ((code / 10u64.pow((digits - 1 - idx) as u32)) % 10) as u8
+}
+
+/// `(row, col)` from the south-west corner of a Lv4–Lv6 quadrant digit.
+pub fn decode_quadrant(q: u8) -> Result<(usize, usize), MeshMathError> {
+    match q {
+        1 => Ok((0, 0)), // southwest
+        2 => Ok((0, 1)), // southeast
+        3 => Ok((1, 0)), // northwest
+        4 => Ok((1, 1)), // northeast
+        _ => Err(MeshMathError::InvalidQuadrant(q)),
+    }
+}
+
+/// Maps a data-level mesh code to its tile-level parent code and its `(row, col)` in that
+/// tile, counted from the north-west corner (the payload order of `mesh-data-tile`).
+pub fn map_meshcode_to_tile(
+    mesh_code: u64,
+    data_level: u8,
+    tile_level: u8,
+    rows_per_axis: usize,
+) -> Result<(u64, usize, usize), MeshMathError> {
+    let digits = digit_count(mesh_code);
+    let expected = digits_for_level(data_level)?;
+    if digits != expected {
+        return Err(MeshMathError::DigitCount {
+            mesh_code,
+            digits,
+            expected,
+            level: data_level,
+        });
+    }
+
+    let tile_digits = digits_for_level(tile_level)?;
+    let tile_code = mesh_code / 10u64.pow((digits.saturating_sub(tile_digits)) as u32);
+
+    let mut row_south = 0usize;
+    let mut col = 0usize;
+
+    for next_level in (tile_level + 1)..=data_level {
+        let factor = refinement_factor(next_level)?;
+        let (sub_row, sub_col) = match next_level {
+            2 | 3 => {
+                let idx = if next_level == 2 { 4 } else { 6 };
+                let r = usize::from(digit_at(mesh_code, digits, idx));
+                let c = usize::from(digit_at(mesh_code, digits, idx + 1));
+                if r >= factor || c >= factor {
+                    return Err(MeshMathError::InvalidSubdivision {
+                        mesh_code,
+                        level: next_level,
+                    });
+                }
+                (r, c)
+            }
+            _ => decode_quadrant(digit_at(mesh_code, digits, usize::from(next_level) + 4))?,
+        };
+
+        row_south = row_south * factor + sub_row;
+        col = col * factor + sub_col;
+    }
+
+    if row_south >= rows_per_axis || col >= rows_per_axis {
+        return Err(MeshMathError::OutOfRange {
+            mesh_code,
+            row_south,
+            col,
+            rows: rows_per_axis,
+        });
+    }
+
+    let row_top = rows_per_axis - 1 - row_south;
+    Ok((tile_code, row_top, col))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_digits_from_the_left() {
+        assert_eq!(digit_count(5339), 4);
+        assert_eq!(digit_count(0), 1);
+        assert_eq!(digit_at(53394611, 8, 4), 4);
+        assert_eq!(digit_at(53394611, 8, 7), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_subdivisions() {
+        assert_eq!(
+            map_meshcode_to_tile(53398011, 3, 1, 80),
+            Err(MeshMathError::InvalidSubdivision {
+                mesh_code: 53398011,
+                level: 2
+            })
+        );
+        assert_eq!(
+            map_meshcode_to_tile(533946115, 4, 3, 2),
+            Err(MeshMathError::InvalidQuadrant(5))
+        );
+        assert!(matches!(
+            map_meshcode_to_tile(5339461, 3, 1, 80),
+            Err(MeshMathError::DigitCount { digits: 7, .. })
+        ));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{
+    MeshTileConfig, mesh_geometry,
+    mesh_tile::{self, map_meshcode_to_tile, subdivisions_per_axis},
+};
+use anyhow::{Context, Result, anyhow, bail};
+use rusqlite::{Connection, params};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// `format` written to the MBTiles metadata. The tiles are mesh-data-tile blobs, not one of
+/// the image or vector formats listed in the spec.
+const TILE_FORMAT: &str = "application/vnd.mesh-data-tile";
+
+/// TMS `(tile_column, tile_row)` of a tile: columns count east from 100°E and rows north
+/// from the equator, in units of the tile level's cell size.
+fn tile_coordinates(tile_code: u64, tile_level: u8) -> Result<(u32, u32)> {
+    let per_lv1 = subdivisions_per_axis(1, tile_level)?;
+    let (lv1_code, row_top, col) = map_meshcode_to_tile(tile_code, tile_level, 1, per_lv1)?;
+    let lat_index = (lv1_code / 100) as usize;
+    let lon_index = (lv1_code % 100) as usize;
+    let row_south = per_lv1 - 1 - row_top;
+    let column = u32::try_from(lon_index * per_lv1 + col).context("tile column exceeds u32")?;
+    let row = u32::try_from(lat_index * per_lv1 + row_south).context("tile row exceeds u32")?;
+    Ok((column, row))
+}
+
+/// Tile codes and paths of the `{meshcode}.tile` files in a flat tile directory.
+fn list_tiles(tile_dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut tiles = Vec::new();
+    for entry in std::fs::read_dir(tile_dir)
+        .with_context(|| format!("when reading {}", tile_dir.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "tile")
+            && let Some(code) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+        {
+            tiles.push((code, path));
+        }
+    }
+    tiles.sort();
+    Ok(tiles)
+}
+
+fn metadata_str<'a>(metadata: &'a Value, key: &str) -> Result<&'a str> {
+    metadata[key]
+        .as_str()
+        .ok_or_else(|| anyhow!("metadata.json is missing '{}'", key))
+}
+
+fn metadata_u8(metadata: &Value, key: &str) -> Result<u8> {
+    metadata[key]
+        .as_u64()
+        .and_then(|v| u8::try_from(v).ok())
+        .ok_or_else(|| anyhow!("metadata.json is missing '{}'", key))
+}
+
+/// Packs a directory written by `mesh-tile` (`metadata.json` and `{meshcode}.tile`) into an
+/// MBTiles database at `output`, replacing any existing file. Returns the tile count.
+fn write_mbtiles(tile_dir: &Path, output: &Path) -> Result<usize> {
+    let metadata_path = tile_dir.join("metadata.json");
+    let metadata: Value = serde_json::from_slice(
+        &std::fs::read(&metadata_path)
+            .with_context(|| format!("when reading {}", metadata_path.display()))?,
+    )?;
+    let tile_level = metadata_u8(&metadata, "tile_mesh_level")?;
+    let data_level = metadata_u8(&metadata, "data_mesh_level")?;
+    let survey = metadata_str(&metadata, "survey")?;
+    let stats_id = metadata_str(&metadata, "stats_id")?;
+    let year = metadata["year"].as_u64().unwrap_or_default();
+
+    let tiles = list_tiles(tile_dir)?;
+    if tiles.is_empty() {
+        bail!("no tiles found in {}", tile_dir.display());
+    }
+
+    if output.exists() {
+        std::fs::remove_file(output)
+            .with_context(|| format!("when replacing {}", output.display()))?;
+    }
+    let mut conn =
+        Connection::open(output).with_context(|| format!("when creating {}", output.display()))?;
+    conn.execute_batch(
+        "CREATE TABLE metadata (name TEXT, value TEXT);
+         CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+         CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);",
+    )?;
+
+    let tx = conn.transaction()?;
+    let mut bounds: Option<[f64; 4]> = None;
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (tile_code, path) in &tiles {
+            let (column, row) = tile_coordinates(*tile_code, tile_level)
+                .with_context(|| format!("when placing tile {}", path.display()))?;
+            let data =
+                std::fs::read(path).with_context(|| format!("when reading {}", path.display()))?;
+            insert.execute(params![tile_level, column, row, data])?;
+
+            let [min_lon, min_lat, max_lon, max_lat] =
+                mesh_geometry::cell_bounds(*tile_code)?.to_bbox();
+            bounds = Some(match bounds {
+                None => [min_lon, min_lat, max_lon, max_lat],
+                Some([a, b, c, d]) => [
+                    a.min(min_lon),
+                    b.min(min_lat),
+                    c.max(max_lon),
+                    d.max(max_lat),
+                ],
+            });
+        }
+    }
+
+    let [min_lon, min_lat, max_lon, max_lat] = bounds.unwrap_or_default();
+    let entries = [
+        ("name", format!("{} {}年 Lv{}", survey, year, data_level)),
+        ("format", TILE_FORMAT.to_string()),
+        (
+            "bounds",
+            format!("{},{},{},{}", min_lon, min_lat, max_lon, max_lat),
+        ),
+        ("minzoom", tile_level.to_string()),
+        ("maxzoom", tile_level.to_string()),
+        (
+            "center",
+            format!(
+                "{},{},{}",
+                (min_lon + max_lon) / 2.0,
+                (min_lat + max_lat) / 2.0,
+                tile_level
+            ),
+        ),
+        (
+            "description",
+            format!(
+                "e-Stat {} ({}) の{}年 Lv{}メッシュデータ。Lv{}メッシュ単位の mesh-data-tile (zoom_level はメッシュレベル)",
+                survey, stats_id, year, data_level, tile_level
+            ),
+        ),
+        ("type", "overlay".to_string()),
+        ("json", metadata.to_string()),
+    ];
+    for (name, value) in &entries {
+        tx.execute(
+            "INSERT INTO metadata (name, value) VALUES (?1, ?2)",
+            params![name, value],
+        )?;
+    }
+    tx.commit()?;
+
+    Ok(tiles.len())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn process_mesh_mbtiles(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    level: u8,
+    year: u16,
+    survey: &str,
+    tile_level: Option<u8>,
+    output: &Path,
+    cleanup: bool,
+) -> Result<()> {
+    let stem = output
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| anyhow!("invalid output path: {}", output.display()))?;
+    // Tiles are encoded by the mesh-tile pipeline into a staging directory, then packed.
+    let staging_dir = tmp_dir.join("mbtiles").join(stem);
+    if staging_dir.exists() {
+        tokio::fs::remove_dir_all(&staging_dir).await?;
+    }
+
+    let config = MeshTileConfig::new(level, year, survey, &staging_dir)
+        .with_tmp_dir(tmp_dir)
+        .with_tile_level(tile_level)
+        .with_cleanup(cleanup);
+    mesh_tile::process_mesh_tile(http_client, &config, None).await?;
+
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tiles = {
+        let staging_dir = staging_dir.clone();
+        let output = output.to_path_buf();
+        tokio::task::spawn_blocking(move || write_mbtiles(&staging_dir, &output)).await??
+    };
+    tokio::fs::remove_dir_all(&staging_dir).await?;
+
+    println!("Wrote {} tiles to {}", tiles, output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh_data_tile::CompressionMode;
+
+    #[test]
+    fn computes_tms_coordinates() {
+        assert_eq!(tile_coordinates(5339, 1).unwrap(), (39, 53));
+        // Lv2 533900 is the southwest corner of 5339; 533977 the northeast one.
+        assert_eq!(tile_coordinates(533900, 2).unwrap(), (39 * 8, 53 * 8));
+        assert_eq!(
+            tile_coordinates(533977, 2).unwrap(),
+            (39 * 8 + 7, 53 * 8 + 7)
+        );
+    }
+
+    #[test]
+    fn packs_tile_directory_into_mbtiles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let metadata = serde_json::json!({
+            "data_mesh_level": 3,
+            "tile_mesh_level": 1,
+            "year": 2020,
+            "survey": "人口及び世帯",
+            "stats_id": "T001140",
+        });
+        std::fs::write(dir.join("metadata.json"), metadata.to_string()).unwrap();
+        let mut encoded = Vec::new();
+        for code in [5339u64, 5340] {
+            let bytes = mesh_tile::encode_tile_bytes(
+                code,
+                1,
+                1,
+                &[120],
+                mesh_tile::TileDType::Int32,
+                CompressionMode::None,
+            )
+            .unwrap();
+            std::fs::write(dir.join(format!("{}.tile", code)), &bytes).unwrap();
+            encoded.push(bytes);
+        }
+        let output = dir.join("out.mbtiles");
+
+        assert_eq!(write_mbtiles(dir, &output).unwrap(), 2);
+
+        let conn = Connection::open(&output).unwrap();
+        let data: Vec<u8> = conn
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level = 1 AND tile_column = 40 AND tile_row = 53",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(data, encoded[1]);
+        let value = |name: &str| -> String {
+            conn.query_row(
+                "SELECT value FROM metadata WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(value("format"), TILE_FORMAT);
+        assert_eq!(value("minzoom"), "1");
+        let bounds: Vec<f64> = value("bounds")
+            .split(',')
+            .map(|v| v.parse().unwrap())
+            .collect();
+        for (actual, expected) in bounds.iter().zip([139.0, 35.0 + 1.0 / 3.0, 141.0, 36.0]) {
+            assert!((actual - expected).abs() < 1e-9, "{:?}", bounds);
+        }
+        assert_eq!(value("name"), "人口及び世帯 2020年 Lv3");
+        drop(conn);
+    }
+}
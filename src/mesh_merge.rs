@@ -0,0 +1,224 @@
+use crate::postgres;
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+
+/// A column as reported by `pg_attribute`, with its type already formatted for DDL.
+#[derive(Debug, Clone, PartialEq)]
+struct Column {
+    name: String,
+    data_type: String,
+}
+
+/// Table names are resolved with `to_regclass`, so the same (unquoted) spelling accepted by
+/// `mesh` works here in any case.
+async fn table_columns(client: &tokio_postgres::Client, table: &str) -> Result<Vec<Column>> {
+    let rows = client
+        .query(
+            r#"SELECT a.attname::text, format_type(a.atttypid, a.atttypmod)
+FROM pg_attribute a
+WHERE a.attrelid = to_regclass($1) AND a.attnum > 0 AND NOT a.attisdropped
+ORDER BY a.attnum"#,
+            &[&table],
+        )
+        .await
+        .with_context(|| format!("when reading columns of {}", table))?;
+    if rows.is_empty() {
+        bail!("table {} does not exist or has no columns", table);
+    }
+    Ok(rows
+        .iter()
+        .map(|row| Column {
+            name: row.get(0),
+            data_type: row.get(1),
+        })
+        .collect())
+}
+
+/// The `_`-separated part of each table name that differs from the other one, e.g.
+/// `T001141` / `T001142` for `jp_estat_mesh_2020_T001141_4` and `jp_estat_mesh_2020_T001142_4`.
+fn distinguishing_suffixes(table_a: &str, table_b: &str) -> (String, String) {
+    let a: Vec<&str> = table_a.split('_').collect();
+    let b: Vec<&str> = table_b.split('_').collect();
+    let common_prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let max_suffix = a.len().min(b.len()) - common_prefix;
+    let common_suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take(max_suffix)
+        .take_while(|(x, y)| x == y)
+        .count();
+    let middle = |parts: &[&str]| {
+        let s = parts[common_prefix..parts.len() - common_suffix].join("_");
+        if s.is_empty() { parts.join("_") } else { s }
+    };
+    (middle(&a), middle(&b))
+}
+
+/// Builds the select list. Every column of `a` is kept, `b` contributes everything except the
+/// join column, and names present in both tables are prefixed with each table's suffix.
+fn build_select_list(
+    columns_a: &[Column],
+    columns_b: &[Column],
+    join_col: &str,
+    suffix_a: &str,
+    suffix_b: &str,
+) -> Vec<String> {
+    let names_a: HashSet<&str> = columns_a.iter().map(|c| c.name.as_str()).collect();
+    let names_b: HashSet<&str> = columns_b
+        .iter()
+        .map(|c| c.name.as_str())
+        .filter(|n| *n != join_col)
+        .collect();
+
+    let mut select = Vec::with_capacity(columns_a.len() + columns_b.len());
+    for column in columns_a {
+        if column.name != join_col && names_b.contains(column.name.as_str()) {
+            select.push(format!(
+                "a.{} AS {}",
+                postgres::pg_ident(&column.name),
+                postgres::pg_ident(&format!("{}_{}", suffix_a, column.name))
+            ));
+        } else {
+            select.push(format!("a.{}", postgres::pg_ident(&column.name)));
+        }
+    }
+    for column in columns_b.iter().filter(|c| c.name != join_col) {
+        if names_a.contains(column.name.as_str()) {
+            select.push(format!(
+                "b.{} AS {}",
+                postgres::pg_ident(&column.name),
+                postgres::pg_ident(&format!("{}_{}", suffix_b, column.name))
+            ));
+        } else {
+            select.push(format!("b.{}", postgres::pg_ident(&column.name)));
+        }
+    }
+    select
+}
+
+fn build_merge_sql(
+    table_a: &str,
+    table_b: &str,
+    output_table: &str,
+    join_col: &str,
+    select_list: &[String],
+) -> String {
+    let join_col = postgres::pg_ident(join_col);
+    format!(
+        "CREATE TABLE {} AS\nSELECT\n    {}\nFROM {} a\nLEFT JOIN {} b ON a.{join_col} = b.{join_col}",
+        postgres::folded_ident(output_table),
+        select_list.join(",\n    "),
+        postgres::folded_ident(table_a),
+        postgres::folded_ident(table_b),
+    )
+}
+
+fn format_ddl(table: &str, columns: &[Column]) -> String {
+    let defs: Vec<String> = columns
+        .iter()
+        .map(|c| format!("    {} {}", postgres::pg_ident(&c.name), c.data_type))
+        .collect();
+    format!(
+        "CREATE TABLE {} (\n{}\n);",
+        postgres::folded_ident(table),
+        defs.join(",\n")
+    )
+}
+
+pub async fn process_mesh_merge(
+    postgres_url: &str,
+    table_a: &str,
+    table_b: &str,
+    output_table: &str,
+    join_col: &str,
+) -> Result<()> {
+    let mut client = postgres::connect(postgres_url).await?;
+    let columns_a = table_columns(&client, table_a).await?;
+    let columns_b = table_columns(&client, table_b).await?;
+    for (table, columns) in [(table_a, &columns_a), (table_b, &columns_b)] {
+        if !columns.iter().any(|c| c.name == join_col) {
+            bail!("join column \"{}\" not found in {}", join_col, table);
+        }
+    }
+
+    let (suffix_a, suffix_b) = distinguishing_suffixes(table_a, table_b);
+    let select_list = build_select_list(&columns_a, &columns_b, join_col, &suffix_a, &suffix_b);
+    let sql = build_merge_sql(table_a, table_b, output_table, join_col, &select_list);
+
+    let transaction = client.transaction().await?;
+    transaction
+        .batch_execute(&format!(
+            "DROP TABLE IF EXISTS {}",
+            postgres::folded_ident(output_table)
+        ))
+        .await?;
+    transaction
+        .batch_execute(&sql)
+        .await
+        .with_context(|| format!("when creating {}", output_table))?;
+    transaction.commit().await?;
+
+    let columns = table_columns(&client, output_table).await?;
+    println!("{}", format_ddl(output_table, &columns));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(names: &[&str]) -> Vec<Column> {
+        names
+            .iter()
+            .map(|name| Column {
+                name: name.to_string(),
+                data_type: "integer".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_distinguishing_suffixes() {
+        assert_eq!(
+            distinguishing_suffixes(
+                "jp_estat_mesh_2020_t001141_4",
+                "jp_estat_mesh_2020_t001142_4"
+            ),
+            ("t001141".to_string(), "t001142".to_string())
+        );
+        assert_eq!(
+            distinguishing_suffixes("population", "population_2"),
+            ("population".to_string(), "2".to_string())
+        );
+    }
+
+    #[test]
+    fn prefixes_conflicting_columns_from_both_tables() {
+        let a = columns(&["KEY_CODE", "GASSAN", "人口（\"総数\"）"]);
+        let b = columns(&["KEY_CODE", "GASSAN", "就業者数"]);
+        let select = build_select_list(&a, &b, "KEY_CODE", "t001141", "t001142");
+        assert_eq!(
+            select,
+            vec![
+                "a.\"KEY_CODE\"",
+                "a.\"GASSAN\" AS \"t001141_GASSAN\"",
+                "a.\"人口（\"\"総数\"\"）\"",
+                "b.\"GASSAN\" AS \"t001142_GASSAN\"",
+                "b.\"就業者数\"",
+            ]
+        );
+        let sql = build_merge_sql(
+            "jp_estat_mesh_2020_T001141_4",
+            "jp_estat_mesh_2020_T001142_4",
+            "Merged",
+            "KEY_CODE",
+            &select,
+        );
+        assert!(sql.starts_with("CREATE TABLE \"merged\" AS\n"));
+        assert!(sql.contains("FROM \"jp_estat_mesh_2020_t001141_4\" a\n"));
+        assert!(sql.ends_with(
+            "LEFT JOIN \"jp_estat_mesh_2020_t001142_4\" b ON a.\"KEY_CODE\" = b.\"KEY_CODE\""
+        ));
+    }
+}
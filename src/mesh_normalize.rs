@@ -0,0 +1,176 @@
+use crate::{
+    download,
+    error::JpEstatError,
+    mesh::{self, get_matching_mesh_stats},
+    mesh_diff::{NON_BAND_COLUMNS, parse_value},
+    mesh_math::cell_area_km2,
+    progress,
+    shiftjis_csv::{normalize_headers, open_shiftjis_csv},
+};
+use anyhow::{Context, Result, anyhow, bail};
+use csv::WriterBuilder;
+use std::path::Path;
+
+/// Indices of the columns to normalize: the listed bands, or every statistics column.
+fn band_indices(header: &[String], bands: Option<&[String]>) -> Result<Vec<usize>> {
+    let Some(bands) = bands else {
+        return Ok((0..header.len())
+            .filter(|&i| !NON_BAND_COLUMNS.contains(&header[i].as_str()))
+            .collect());
+    };
+    bands
+        .iter()
+        .map(|band| {
+            header
+                .iter()
+                .position(|h| h == band)
+                .ok_or_else(|| anyhow!("column \"{}\" not found in the mesh CSV", band))
+        })
+        .collect()
+}
+
+/// `value / area_km2`. Confidential (`*`), blank and other non-numeric cells have no rate,
+/// so they are copied as they are.
+fn per_km2(value: &str, area_km2: f64) -> String {
+    match parse_value(value) {
+        Some(v) => (v / area_km2).to_string(),
+        None => value.trim().to_string(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn process_mesh_normalize(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    level: u8,
+    year: u16,
+    survey: &str,
+    bands: Option<&[String]>,
+    output: &Path,
+    cleanup: bool,
+) -> Result<()> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+    let area_km2 = cell_area_km2(level)?;
+
+    let mut downloaded_items =
+        mesh::download_mesh_csvs(http_client, tmp_dir, mesh_stats, None).await?;
+    if downloaded_items.is_empty() {
+        return Err(anyhow!("No files found after download/extraction"));
+    }
+    downloaded_items.sort_by_key(|item| item.metadata.0);
+
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut writer = WriterBuilder::new().from_path(output)?;
+
+    let pb = progress::new_bar(downloaded_items.len() as u64, "Normalizing CSVs...")?;
+
+    let mut expected_header: Option<Vec<String>> = None;
+    let mut indices: Vec<usize> = Vec::new();
+    let mut rows = 0u64;
+    for item in downloaded_items.iter() {
+        let mut rdr = open_shiftjis_csv(&item.extracted_path)
+            .with_context(|| format!("when opening {}", item.extracted_path.display()))?;
+        let header1 = rdr
+            .records()
+            .next()
+            .transpose()?
+            .ok_or(anyhow!("missing first header row"))?;
+        let header2 = rdr
+            .records()
+            .next()
+            .transpose()?
+            .ok_or(anyhow!("missing second header row"))?;
+
+        let header = normalize_headers(&header1, &header2);
+        if let Some(expected) = expected_header.as_ref() {
+            if expected != &header {
+                return Err(JpEstatError::CsvMismatch {
+                    path: item.extracted_path.clone(),
+                }
+                .into());
+            }
+        } else {
+            indices = band_indices(&header, bands)?;
+            if indices.is_empty() {
+                bail!(
+                    "{} has no statistics columns",
+                    item.extracted_path.display()
+                );
+            }
+            let normalized = indices.iter().map(|&i| format!("{}_per_km2", header[i]));
+            writer
+                .write_record(header.iter().cloned().chain(normalized))
+                .with_context(|| format!("when writing {}", output.display()))?;
+            expected_header = Some(header);
+        }
+
+        for row in rdr.records() {
+            let row = row?;
+            let normalized = indices
+                .iter()
+                .map(|&i| per_km2(row.get(i).unwrap_or(""), area_km2));
+            writer
+                .write_record(row.iter().map(str::to_string).chain(normalized))
+                .with_context(|| format!("when writing {}", output.display()))?;
+            rows += 1;
+        }
+
+        if cleanup {
+            download::cleanup_extracted(item)?;
+        }
+        pb.inc(1);
+    }
+
+    writer.flush()?;
+    pb.finish_with_message(format!(
+        "Normalized {} cells ({} km² per cell) to {}",
+        rows,
+        area_km2,
+        output.display()
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divides_by_the_cell_area_and_keeps_no_data() {
+        let area = cell_area_km2(4).unwrap();
+        assert_eq!(per_km2("100", area), "400");
+        assert_eq!(per_km2(" 3 ", cell_area_km2(3).unwrap()), "3");
+        assert_eq!(per_km2("1", cell_area_km2(2).unwrap()), "0.01");
+        assert_eq!(per_km2("*", area), "*");
+        assert_eq!(per_km2("", area), "");
+    }
+
+    #[test]
+    fn selects_statistics_columns() {
+        let header: Vec<String> = [
+            "KEY_CODE",
+            "HTKSYORI",
+            "HTKSAKI",
+            "GASSAN",
+            "人口（総数）",
+            "世帯総数",
+        ]
+        .iter()
+        .map(|h| h.to_string())
+        .collect();
+        assert_eq!(band_indices(&header, None).unwrap(), vec![4, 5]);
+        let bands = ["世帯総数".to_string()];
+        assert_eq!(band_indices(&header, Some(&bands)).unwrap(), vec![5]);
+        let bands = ["人口".to_string()];
+        assert!(band_indices(&header, Some(&bands)).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::shiftjis_csv::open_shiftjis_csv;
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+
+/// Returns the first standalone run of exactly four digits in `text` that reads as a survey year
+/// (1900–2099). Full-width digits are accepted, and longer runs such as the `T001101001`
+/// column codes are skipped.
+fn find_year(text: &str) -> Option<u16> {
+    let digits: Vec<Option<u32>> = text
+        .chars()
+        .map(|c| match c {
+            '0'..='9' => c.to_digit(10),
+            '０'..='９' => Some(c as u32 - '０' as u32),
+            _ => None,
+        })
+        .collect();
+
+    let mut i = 0;
+    while i < digits.len() {
+        if digits[i].is_none() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < digits.len() && digits[i].is_some() {
+            i += 1;
+        }
+        if i - start == 4 {
+            let year = digits[start..i]
+                .iter()
+                .fold(0u32, |acc, d| acc * 10 + d.unwrap_or(0));
+            if (1900..=2099).contains(&year) {
+                return Some(year as u16);
+            }
+        }
+    }
+    None
+}
+
+/// Looks for a survey year in the two e-Stat header rows of a mesh CSV. Data rows are not
+/// scanned because populations and household counts can look like years too.
+pub fn detect_year_from_csv(path: &Path) -> Result<Option<u16>> {
+    let mut rdr =
+        open_shiftjis_csv(path).with_context(|| format!("when opening {}", path.display()))?;
+    for record in rdr.records().take(2) {
+        let record = record.with_context(|| format!("when reading {}", path.display()))?;
+        if let Some(year) = record.iter().find_map(find_year) {
+            return Ok(Some(year));
+        }
+    }
+    Ok(None)
+}
+
+/// Prints a warning when the year found in `path` differs from the `--year` the user asked for.
+/// Detection is best-effort, so read errors are left to the import itself.
+pub fn warn_on_year_mismatch(path: &Path, expected_year: u16) {
+    if let Ok(Some(detected)) = detect_year_from_csv(path)
+        && detected != expected_year
+    {
+        eprintln!(
+            "Warning: {} looks like {} data, but --year {} was given. Check the --year option.",
+            path.display(),
+            detected,
+            expected_year
+        );
+    }
+}
+
+/// Fraction of cells in each of `band_indices` that hold a value (neither `*` nor blank),
+/// over the data rows of one e-Stat mesh CSV.
+pub fn compute_band_sparsity(path: &Path, band_indices: &[usize]) -> Result<Vec<f64>> {
+    let mut rdr =
+        open_shiftjis_csv(path).with_context(|| format!("when opening {}", path.display()))?;
+    let mut present = vec![0u64; band_indices.len()];
+    let mut rows = 0u64;
+    for record in rdr.records().skip(2) {
+        let record = record.with_context(|| format!("when reading {}", path.display()))?;
+        rows += 1;
+        for (count, &i) in present.iter_mut().zip(band_indices) {
+            let value = record.get(i).unwrap_or("").trim();
+            if !value.is_empty() && value != "*" {
+                *count += 1;
+            }
+        }
+    }
+    Ok(present
+        .into_iter()
+        .map(|count| {
+            if rows == 0 {
+                0.0
+            } else {
+                count as f64 / rows as f64
+            }
+        })
+        .collect())
+}
+
+pub(crate) fn check_nonnull_fraction(min_nonnull_fraction: Option<f64>) -> Result<()> {
+    if let Some(fraction) = min_nonnull_fraction
+        && !(0.0..=1.0).contains(&fraction)
+    {
+        bail!("--min-nonnull-fraction must be between 0.0 and 1.0");
+    }
+    Ok(())
+}
+
+/// Whether to keep each of `bands` (source column index, name) under
+/// `--min-nonnull-fraction`, judged from the first downloaded file `path`.
+pub(crate) fn dense_bands(
+    path: &Path,
+    bands: &[(usize, &str)],
+    min_nonnull_fraction: f64,
+) -> Result<Vec<bool>> {
+    let indices: Vec<usize> = bands.iter().map(|(i, _)| *i).collect();
+    let fractions = compute_band_sparsity(path, &indices)?;
+    let mut excluded = Vec::new();
+    let keep = bands
+        .iter()
+        .zip(&fractions)
+        .map(|((_, name), &fraction)| {
+            let keep = fraction >= min_nonnull_fraction;
+            if !keep {
+                tracing::info!(band = name, fraction, "excluding sparse band");
+                excluded.push(format!("{} ({:.1}%)", name, fraction * 100.0));
+            }
+            keep
+        })
+        .collect();
+    if !excluded.is_empty() {
+        println!(
+            "Excluded {} bands with fewer than {:.1}% non-null cells in {}: {}",
+            excluded.len(),
+            min_nonnull_fraction * 100.0,
+            path.display(),
+            excluded.join(", ")
+        );
+    }
+    Ok(keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_non_null_fraction_per_band() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("tblT001140S5339.txt");
+        let (body, _, _) = encoding_rs::SHIFT_JIS.encode(
+            "KEY_CODE,HTKSYORI,T001140001,T001140002\n,,人口,世帯\n\
+             53390000,0,10,*\n53390001,0,*,\n53390002,0,5,*\n53390003,0,0,1\n",
+        );
+        std::fs::write(&path, body).unwrap();
+        assert_eq!(
+            compute_band_sparsity(&path, &[2, 3]).unwrap(),
+            vec![0.75, 0.25]
+        );
+        assert_eq!(
+            dense_bands(&path, &[(2, "人口"), (3, "世帯")], 0.5).unwrap(),
+            vec![true, false]
+        );
+        assert!(check_nonnull_fraction(Some(1.5)).is_err());
+    }
+
+    #[test]
+    fn finds_only_four_digit_years() {
+        assert_eq!(find_year("2020年国勢調査"), Some(2020));
+        assert_eq!(find_year("令和２年（２０２０年）"), Some(2020));
+        assert_eq!(find_year("T001101001"), None);
+        assert_eq!(find_year("人口（総数）"), None);
+        assert_eq!(find_year("1234"), None);
+    }
+
+    #[test]
+    fn detects_year_in_header_rows_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("tblT001140S5339.txt");
+        let (body, _, _) =
+            encoding_rs::SHIFT_JIS.encode("KEY_CODE,T001140001\n,2015年人口\n53390000,2020\n");
+        std::fs::write(&path, body).unwrap();
+        assert_eq!(detect_year_from_csv(&path).unwrap(), Some(2015));
+
+        let (body, _, _) =
+            encoding_rs::SHIFT_JIS.encode("KEY_CODE,T001140001\n,人口\n53390000,2020\n");
+        std::fs::write(&path, body).unwrap();
+        assert_eq!(detect_year_from_csv(&path).unwrap(), None);
+    }
+}
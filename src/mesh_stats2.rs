@@ -0,0 +1,172 @@
+use crate::{
+    mesh_diff::{column_index, open_mesh_csv, parse_value},
+    stats::{self, LinearRegression},
+};
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::{collections::HashMap, path::Path};
+
+const KEY_COLUMN: &str = "KEY_CODE";
+
+#[derive(Debug, Serialize)]
+struct Regression {
+    slope: f64,
+    intercept: f64,
+    r_squared: f64,
+}
+
+impl From<LinearRegression> for Regression {
+    fn from(fit: LinearRegression) -> Self {
+        Self {
+            slope: fit.slope,
+            intercept: fit.intercept,
+            r_squared: fit.r_squared,
+        }
+    }
+}
+
+/// Written to `--output`. The statistics are `null` when they are undefined (fewer than two
+/// cells, or a band that is constant over the joined cells).
+#[derive(Debug, Serialize)]
+struct CorrelationReport {
+    csv_a: String,
+    csv_b: String,
+    band_a: String,
+    band_b: String,
+    /// Cells in both files with a value in both bands.
+    cells: usize,
+    /// Cells in both files left out because one of the values is confidential or blank.
+    excluded_no_data: usize,
+    pearson: Option<f64>,
+    spearman: Option<f64>,
+    /// `band_b = slope · band_a + intercept`.
+    regression: Option<Regression>,
+}
+
+/// Values of `band` by `KEY_CODE`; `None` for no-data cells.
+fn read_band(path: &Path, band: &str) -> Result<HashMap<String, Option<f64>>> {
+    let (header, mut rdr) = open_mesh_csv(path)?;
+    let key = column_index(&header, KEY_COLUMN, path)?;
+    let value = column_index(&header, band, path)?;
+    let mut values = HashMap::new();
+    for record in rdr.records() {
+        let record = record.with_context(|| format!("when reading {}", path.display()))?;
+        values.insert(
+            record.get(key).unwrap_or_default().trim().to_string(),
+            parse_value(record.get(value).unwrap_or_default()),
+        );
+    }
+    Ok(values)
+}
+
+/// Inner join on `KEY_CODE`, in key order so the output does not depend on hash order.
+/// Returns the paired values and the number of joined cells with no data on either side.
+fn join_bands(
+    a: &HashMap<String, Option<f64>>,
+    b: &HashMap<String, Option<f64>>,
+) -> (Vec<f64>, Vec<f64>, usize) {
+    let mut keys: Vec<&String> = a.keys().filter(|key| b.contains_key(*key)).collect();
+    keys.sort_unstable();
+    let (mut xs, mut ys, mut excluded) = (Vec::new(), Vec::new(), 0);
+    for key in keys {
+        match (a[key], b[key]) {
+            (Some(x), Some(y)) => {
+                xs.push(x);
+                ys.push(y);
+            }
+            _ => excluded += 1,
+        }
+    }
+    (xs, ys, excluded)
+}
+
+pub fn process_mesh_stats2(
+    csv_a: &Path,
+    csv_b: &Path,
+    band_a: &str,
+    band_b: &str,
+    output: &Path,
+) -> Result<()> {
+    let values_a = read_band(csv_a, band_a)?;
+    let values_b = read_band(csv_b, band_b)?;
+    let (xs, ys, excluded_no_data) = join_bands(&values_a, &values_b);
+    if xs.is_empty() {
+        bail!(
+            "{} and {} have no cells with values in both \"{}\" and \"{}\"",
+            csv_a.display(),
+            csv_b.display(),
+            band_a,
+            band_b
+        );
+    }
+
+    let report = CorrelationReport {
+        csv_a: csv_a.display().to_string(),
+        csv_b: csv_b.display().to_string(),
+        band_a: band_a.to_string(),
+        band_b: band_b.to_string(),
+        cells: xs.len(),
+        excluded_no_data,
+        pearson: stats::pearson(&xs, &ys),
+        spearman: stats::spearman(&xs, &ys),
+        regression: stats::linear_regression(&xs, &ys).map(Regression::from),
+    };
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(output, serde_json::to_vec_pretty(&report)?)
+        .with_context(|| format!("when writing {}", output.display()))?;
+
+    println!(
+        "Correlated {} cells ({} excluded for missing values): Pearson {}, Spearman {}",
+        report.cells,
+        report.excluded_no_data,
+        report
+            .pearson
+            .map_or("n/a".to_string(), |r| format!("{:.4}", r)),
+        report
+            .spearman
+            .map_or("n/a".to_string(), |r| format!("{:.4}", r)),
+    );
+    println!("Results written to {}", output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correlates_joined_cells_with_values() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let csv_a = dir.join("population.csv");
+        std::fs::write(
+            &csv_a,
+            "KEY_CODE,人口（総数）\n53394611,100\n53394612,200\n53394613,300\n53394614,*\n53394615,50\n",
+        )
+        .unwrap();
+        let csv_b = dir.join("households.csv");
+        std::fs::write(
+            &csv_b,
+            "KEY_CODE,世帯総数\n53394611,40\n53394612,80\n53394613,120\n53394614,10\n53394616,5\n",
+        )
+        .unwrap();
+        let output = dir.join("stats.json");
+
+        process_mesh_stats2(&csv_a, &csv_b, "人口（総数）", "世帯総数", &output).unwrap();
+        let report: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&output).unwrap()).unwrap();
+        assert_eq!(report["cells"], 3);
+        assert_eq!(report["excluded_no_data"], 1);
+        assert_eq!(report["spearman"], 1.0);
+        let r = report["pearson"].as_f64().unwrap();
+        assert!((r - 1.0).abs() < 1e-12, "{}", r);
+        let slope = report["regression"]["slope"].as_f64().unwrap();
+        assert!((slope - 0.4).abs() < 1e-12, "{}", slope);
+
+        assert!(process_mesh_stats2(&csv_a, &csv_b, "人口", "世帯総数", &output).is_err());
+    }
+}
@@ -1,54 +1,42 @@
-use crate::download::{self, DownloadedItem};
+use crate::{
+    MeshTileConfig,
+    column_alias::ColumnAliases,
+    download::{self, DownloadedItem},
+    error::JpEstatError,
+    geo_filter, memory_report, mesh_geometry, mesh_stats,
+    mesh_values::{NO_DATA_I32, NO_DATA_U16, parse_stat_value, parse_stat_value_u16},
+    ogc_features::OgcFeaturesWriter,
+    progress,
+    shiftjis_csv::{normalize_headers, open_shiftjis_csv},
+    stac,
+    telemetry::SpanTimer,
+};
 use anyhow::{Context, Result, anyhow, bail};
-use csv::{ReaderBuilder, StringRecord};
-use encoding_rs::SHIFT_JIS;
-use encoding_rs_io::DecodeReaderBytesBuilder;
 use futures::stream;
-use indicatif::{ProgressBar, ProgressStyle};
 use jismesh::{MeshLevel, codes::JAPAN_LV1, to_meshlevel};
 use mesh_data_tile::{
     CompressionMode, DType, Endianness, MeshKind, TileDimensions, TileEncodeInput, encode_tile,
 };
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, HashSet},
-    fs::File,
-    io::BufReader,
-    path::Path,
+    collections::{BTreeMap, HashSet, VecDeque},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
+use tracing::Span;
 use url::Url;
 
-const DATA_COLUMN_START: usize = 4;
-const NO_DATA_I32: i32 = i32::MIN;
-
-fn open_shiftjis_csv(path: &Path) -> Result<csv::Reader<Box<dyn std::io::Read>>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let transcoded = DecodeReaderBytesBuilder::new()
-        .encoding(Some(SHIFT_JIS))
-        .build(reader);
-
-    Ok(ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(Box::new(transcoded)))
-}
+pub(crate) use crate::mesh_math::{
+    digits_for_level, map_meshcode_to_tile, refinement_factor, subdivisions_per_axis,
+};
 
-fn normalize_headers(header1: &StringRecord, header2: &StringRecord) -> Vec<String> {
-    header2
-        .iter()
-        .enumerate()
-        .map(|(i, h2)| {
-            let col = if h2.trim().is_empty() {
-                header1.get(i).unwrap_or_default().to_string()
-            } else {
-                h2.to_string()
-            };
-            col.trim().replace("\u{3000}", "")
-        })
-        .collect()
-}
+const DATA_COLUMN_START: usize = 4;
 
+/// Default of `--max-tile-count`. Tiling by level-1 code needs a few hundred tiles for all of
+/// Japan; a `--tile-level` close to the data level needs up to 6400 per level-1 code.
+pub const DEFAULT_MAX_TILE_COUNT: usize = 10_000;
 #[derive(Debug, Deserialize, Clone)]
 struct MeshStatsConfig {
     mesh_stats: Vec<MeshStats>,
@@ -60,11 +48,197 @@ struct MeshStats {
     year: u16,
     meshlevel: u8,
     stats_id: String,
-
-    #[allow(dead_code)]
     datum: u16,
 }
 
+/// Tile payload compression selected with `--compression`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TileCompression {
+    DeflateRaw,
+    None,
+}
+
+impl TileCompression {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            TileCompression::DeflateRaw => "deflate-raw",
+            TileCompression::None => "none",
+        }
+    }
+
+    pub(crate) fn to_mode(self) -> CompressionMode {
+        match self {
+            TileCompression::DeflateRaw => CompressionMode::DeflateRaw,
+            TileCompression::None => CompressionMode::None,
+        }
+    }
+}
+
+/// Pixel type selected with `--dtype`. Values are collected as `i32` either way and narrowed
+/// when the payload is built, so `Uint16` keeps its own no-data sentinel in that buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TileDType {
+    Int32,
+    Uint16,
+}
+
+impl TileDType {
+    fn as_str(self) -> &'static str {
+        match self {
+            TileDType::Int32 => "int32",
+            TileDType::Uint16 => "uint16",
+        }
+    }
+
+    fn to_dtype(self) -> DType {
+        match self {
+            TileDType::Int32 => DType::Int32,
+            TileDType::Uint16 => DType::Uint16,
+        }
+    }
+
+    pub(crate) fn no_data(self) -> i32 {
+        match self {
+            TileDType::Int32 => NO_DATA_I32,
+            TileDType::Uint16 => i32::from(NO_DATA_U16),
+        }
+    }
+
+    fn parse_value(self, raw: &str) -> Result<i32> {
+        match self {
+            TileDType::Int32 => parse_stat_value(raw),
+            TileDType::Uint16 => parse_stat_value_u16(raw).map(i32::from),
+        }
+    }
+
+    /// Largest storable value (the no-data sentinel excluded).
+    fn max_value(self) -> i32 {
+        match self {
+            TileDType::Int32 => i32::MAX,
+            TileDType::Uint16 => i32::from(NO_DATA_U16) - 1,
+        }
+    }
+}
+
+/// How fine cells are combined into one coarse cell when building `--pyramid` layers
+/// (`--aggregate-fn`) and by `mesh-aggregate`. No-data cells are skipped by every function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum AggFn {
+    /// 合計 (人口などの件数向け)
+    Sum,
+    /// 平均 (率・比率向け、整数の値は整数に四捨五入)
+    Mean,
+    /// 最大値
+    Max,
+    /// 最小値
+    Min,
+    /// タイル内の行順で最初の欠損でない値
+    FirstValid,
+}
+
+impl AggFn {
+    pub(crate) fn aggregate(self, mut values: impl Iterator<Item = i64>) -> Option<i64> {
+        match self {
+            AggFn::Sum => {
+                let first = values.next()?;
+                Some(values.fold(first, |sum, value| sum + value))
+            }
+            AggFn::Mean => {
+                let (sum, count) =
+                    values.fold((0i64, 0i64), |(sum, count), value| (sum + value, count + 1));
+                (count > 0).then(|| (sum as f64 / count as f64).round() as i64)
+            }
+            AggFn::Max => values.max(),
+            AggFn::Min => values.min(),
+            AggFn::FirstValid => values.next(),
+        }
+    }
+
+    /// [`AggFn::aggregate`] for values with a fractional part; the mean is not rounded.
+    pub(crate) fn aggregate_f64(self, mut values: impl Iterator<Item = f64>) -> Option<f64> {
+        match self {
+            AggFn::Sum => {
+                let first = values.next()?;
+                Some(values.fold(first, |sum, value| sum + value))
+            }
+            AggFn::Mean => {
+                let (sum, count) =
+                    values.fold((0.0, 0u64), |(sum, count), value| (sum + value, count + 1));
+                (count > 0).then(|| sum / count as f64)
+            }
+            AggFn::Max => values.reduce(f64::max),
+            AggFn::Min => values.reduce(f64::min),
+            AggFn::FirstValid => values.next(),
+        }
+    }
+}
+
+/// Aggregates a `fine_rows`×`fine_rows` tile into `coarse`, each coarse cell covering
+/// `factor`×`factor` fine cells. Both buffers are row-major with bands innermost, like the
+/// tile payload. No-data cells are ignored; a coarse cell with no data stays no-data.
+fn aggregate_tiles(
+    fine: &[i32],
+    coarse: &mut [i32],
+    fine_rows: usize,
+    bands: usize,
+    factor: usize,
+    agg_fn: AggFn,
+    dtype: TileDType,
+) -> Result<()> {
+    let coarse_rows = fine_rows / factor;
+    if factor == 0 || coarse_rows * factor != fine_rows {
+        bail!("{} rows cannot be aggregated by {}", fine_rows, factor);
+    }
+    if fine.len() != fine_rows * fine_rows * bands
+        || coarse.len() != coarse_rows * coarse_rows * bands
+    {
+        bail!(
+            "tile buffer sizes do not match {} rows x {} bands",
+            fine_rows,
+            bands
+        );
+    }
+    let no_data = dtype.no_data();
+    for row in 0..coarse_rows {
+        for col in 0..coarse_rows {
+            for band in 0..bands {
+                let block = (row * factor..(row + 1) * factor)
+                    .flat_map(|fine_row| {
+                        (col * factor..(col + 1) * factor).map(move |fine_col| {
+                            fine[(fine_row * fine_rows + fine_col) * bands + band]
+                        })
+                    })
+                    .filter(|value| *value != no_data)
+                    .map(i64::from);
+                let Some(value) = agg_fn.aggregate(block) else {
+                    continue;
+                };
+                let value = i32::try_from(value)
+                    .ok()
+                    .filter(|v| *v <= dtype.max_value())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "aggregated value {} does not fit in {} (try --dtype int32)",
+                            value,
+                            dtype.as_str()
+                        )
+                    })?;
+                coarse[(row * coarse_rows + col) * bands + band] = value;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One output tile set of `--pyramid`: cells at `level`, written to `dir`.
+struct PyramidLayer {
+    level: u8,
+    dir: PathBuf,
+    rows_per_axis: usize,
+    /// Data-level cells per layer cell along each axis (1 for the data level itself).
+    factor: usize,
+}
+
 #[derive(Debug, Serialize)]
 struct TileSetMetadata {
     format: &'static str,
@@ -77,6 +251,8 @@ struct TileSetMetadata {
     year: u16,
     survey: String,
     stats_id: String,
+    /// EPSG code of the geodetic datum the mesh codes are defined on.
+    srid: u16,
     rows: u32,
     cols: u32,
     bands: u8,
@@ -87,6 +263,27 @@ struct TileSetMetadata {
     band_columns: Vec<BandColumnMetadata>,
 }
 
+/// Top-level `manifest.json` of `--split-by-l1`.
+#[derive(Debug, Serialize)]
+struct L1Manifest {
+    format: &'static str,
+    data_mesh_level: u8,
+    tile_mesh_level: u8,
+    year: u16,
+    stats_id: String,
+    l1: Vec<L1ManifestEntry>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct L1ManifestEntry {
+    code: u64,
+    /// Subdirectory holding this code's tiles and `metadata.json`, relative to the manifest.
+    dir: String,
+    tile_count: usize,
+    /// `[min_lon, min_lat, max_lon, max_lat]` of the tiles in this directory.
+    bbox: [f64; 4],
+}
+
 #[derive(Debug, Serialize)]
 struct BandColumnMetadata {
     band: u16,
@@ -109,12 +306,9 @@ lazy_static::lazy_static! {
 }
 
 fn get_matching_mesh_stats(level: u8, year: u16, survey: &str) -> Option<&'static MeshStats> {
-    for mesh in AVAILABLE.iter() {
-        if mesh.meshlevel == level && mesh.year == year && mesh.name == survey {
-            return Some(mesh);
-        }
-    }
-    None
+    AVAILABLE
+        .iter()
+        .find(|mesh| mesh.meshlevel == level && mesh.year == year && mesh.name == survey)
 }
 
 fn build_available_bands(
@@ -128,13 +322,15 @@ fn build_available_bands(
         bail!("no stat columns found");
     }
 
-    let mut bands = Vec::with_capacity(header_codes.len() - DATA_COLUMN_START);
-    for source_idx in DATA_COLUMN_START..header_codes.len() {
-        bands.push(SelectedBand {
+    let bands = normalized_header
+        .iter()
+        .enumerate()
+        .skip(DATA_COLUMN_START)
+        .map(|(source_idx, name)| SelectedBand {
             source_idx,
-            name: normalized_header[source_idx].clone(),
-        });
-    }
+            name: name.clone(),
+        })
+        .collect();
     Ok(bands)
 }
 
@@ -176,66 +372,6 @@ fn resolve_selected_bands(
     Ok(selected)
 }
 
-fn digits_for_level(level: u8) -> Result<usize> {
-    match level {
-        1 => Ok(4),
-        2 => Ok(6),
-        3 => Ok(8),
-        4 => Ok(9),
-        5 => Ok(10),
-        6 => Ok(11),
-        _ => bail!("unsupported mesh level: {}", level),
-    }
-}
-
-fn refinement_factor(next_level: u8) -> Result<usize> {
-    match next_level {
-        2 => Ok(8),
-        3 => Ok(10),
-        4..=6 => Ok(2),
-        _ => bail!("unsupported refinement step to level {}", next_level),
-    }
-}
-
-fn subdivisions_per_axis(tile_level: u8, data_level: u8) -> Result<usize> {
-    if tile_level > data_level {
-        bail!(
-            "tile-level ({}) must be <= data level ({})",
-            tile_level,
-            data_level
-        );
-    }
-
-    let mut size = 1usize;
-    for next_level in (tile_level + 1)..=data_level {
-        size = size
-            .checked_mul(refinement_factor(next_level)?)
-            .ok_or(anyhow!("tile resolution overflow"))?;
-    }
-
-    Ok(size)
-}
-
-fn parse_digit(bytes: &[u8], idx: usize) -> Result<u8> {
-    let b = bytes
-        .get(idx)
-        .ok_or(anyhow!("mesh code is shorter than expected"))?;
-    if !b.is_ascii_digit() {
-        bail!("mesh code contains non-digit character at position {}", idx);
-    }
-    Ok(*b - b'0')
-}
-
-fn decode_quadrant(q: u8) -> Result<(usize, usize)> {
-    match q {
-        1 => Ok((0, 0)), // southwest
-        2 => Ok((0, 1)), // southeast
-        3 => Ok((1, 0)), // northwest
-        4 => Ok((1, 1)), // northeast
-        _ => bail!("invalid split mesh quadrant: {}", q),
-    }
-}
-
 fn mesh_level_to_u8(level: MeshLevel) -> Option<u8> {
     match level {
         MeshLevel::Lv1 => Some(1),
@@ -248,7 +384,7 @@ fn mesh_level_to_u8(level: MeshLevel) -> Option<u8> {
     }
 }
 
-fn mesh_level_from_u8(level: u8) -> Result<MeshLevel> {
+pub(crate) fn mesh_level_from_u8(level: u8) -> Result<MeshLevel> {
     match level {
         1 => Ok(MeshLevel::Lv1),
         2 => Ok(MeshLevel::Lv2),
@@ -260,7 +396,7 @@ fn mesh_level_from_u8(level: u8) -> Result<MeshLevel> {
     }
 }
 
-fn validate_mesh_code_level(mesh_code: u64, expected_level: u8) -> Result<()> {
+pub(crate) fn validate_mesh_code_level(mesh_code: u64, expected_level: u8) -> Result<()> {
     let levels = to_meshlevel(&[mesh_code])
         .map_err(|e| anyhow!("failed to parse mesh code {}: {}", mesh_code, e))?;
     let actual_level = levels
@@ -274,119 +410,51 @@ fn validate_mesh_code_level(mesh_code: u64, expected_level: u8) -> Result<()> {
     ))?;
 
     if actual_level_u8 != expected_level {
-        bail!(
-            "mesh code {} has level {}, expected {}",
-            mesh_code,
-            actual_level_u8,
-            expected_level
-        );
+        return Err(JpEstatError::MeshCodeInvalid {
+            code: mesh_code,
+            expected_level,
+        })
+        .with_context(|| format!("mesh code {} has level {}", mesh_code, actual_level_u8));
     }
 
     Ok(())
 }
 
-fn map_meshcode_to_tile(
-    mesh_code: u64,
-    data_level: u8,
-    tile_level: u8,
-    rows_per_axis: usize,
-) -> Result<(u64, usize, usize)> {
-    let code_str = mesh_code.to_string();
-    let expected_digits = digits_for_level(data_level)?;
-    if code_str.len() != expected_digits {
-        bail!(
-            "mesh code {} has {} digits, expected {} for level {}",
-            mesh_code,
-            code_str.len(),
-            expected_digits,
-            data_level
-        );
-    }
-
-    let tile_digits = digits_for_level(tile_level)?;
-    let tile_code: u64 = code_str[..tile_digits]
-        .parse()
-        .with_context(|| format!("failed to parse parent tile code from {}", mesh_code))?;
-
-    let bytes = code_str.as_bytes();
-    let mut row_south = 0usize;
-    let mut col = 0usize;
-
-    for next_level in (tile_level + 1)..=data_level {
-        let factor = refinement_factor(next_level)?;
-        let (sub_row, sub_col) = match next_level {
-            2 => {
-                let r = parse_digit(bytes, 4)?;
-                let c = parse_digit(bytes, 5)?;
-                if r > 7 || c > 7 {
-                    bail!("invalid Lv2 subdivision in mesh code {}", mesh_code);
-                }
-                (usize::from(r), usize::from(c))
-            }
-            3 => {
-                let r = parse_digit(bytes, 6)?;
-                let c = parse_digit(bytes, 7)?;
-                if r > 9 || c > 9 {
-                    bail!("invalid Lv3 subdivision in mesh code {}", mesh_code);
-                }
-                (usize::from(r), usize::from(c))
-            }
-            4 => decode_quadrant(parse_digit(bytes, 8)?)?,
-            5 => decode_quadrant(parse_digit(bytes, 9)?)?,
-            6 => decode_quadrant(parse_digit(bytes, 10)?)?,
-            _ => bail!("unsupported mesh level {}", next_level),
-        };
-
-        row_south = row_south * factor + sub_row;
-        col = col * factor + sub_col;
-    }
-
-    if row_south >= rows_per_axis || col >= rows_per_axis {
-        bail!(
-            "computed tile coordinates out of range for mesh code {} (row_south={}, col={}, rows={})",
-            mesh_code,
-            row_south,
-            col,
-            rows_per_axis
-        );
-    }
-
-    let row_top = rows_per_axis - 1 - row_south;
-    Ok((tile_code, row_top, col))
-}
-
-fn parse_stat_value(value: &str) -> Result<i32> {
-    let v = value.trim();
-    if v.is_empty() || v == "*" {
-        return Ok(NO_DATA_I32);
-    }
-
-    let parsed = v
-        .parse::<i64>()
-        .with_context(|| format!("invalid integer value: {}", v))?;
-    if parsed < i64::from(i32::MIN) || parsed > i64::from(i32::MAX) {
-        bail!("value out of i32 range: {}", parsed);
+pub(crate) fn build_payload_i32(values: &[i32]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(std::mem::size_of_val(values));
+    for value in values {
+        payload.extend_from_slice(&value.to_le_bytes());
     }
-
-    Ok(parsed as i32)
+    payload
 }
 
-fn build_payload_i32(values: &[i32]) -> Vec<u8> {
-    let mut payload = Vec::with_capacity(values.len() * std::mem::size_of::<i32>());
+/// Values were range-checked by `parse_stat_value_u16`, so the narrowing cast is lossless.
+fn build_payload_u16(values: &[i32]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(values.len() * size_of::<u16>());
     for value in values {
-        payload.extend_from_slice(&value.to_le_bytes());
+        payload.extend_from_slice(&(*value as u16).to_le_bytes());
     }
     payload
 }
 
-async fn write_tile(
-    output_dir: &Path,
+#[tracing::instrument(
+    name = "encode_tile",
+    skip_all,
+    fields(tile.code = tile_code, bands = band_count, bytes, duration_ms)
+)]
+pub(crate) fn encode_tile_bytes(
     tile_code: u64,
     rows_per_axis: usize,
     band_count: usize,
     values: &[i32],
-) -> Result<()> {
-    let payload = build_payload_i32(values);
+    dtype: TileDType,
+    compression: CompressionMode,
+) -> Result<Vec<u8>> {
+    let _timer = SpanTimer::new(&Span::current());
+    let payload = match dtype {
+        TileDType::Int32 => build_payload_i32(values),
+        TileDType::Uint16 => build_payload_u16(values),
+    };
 
     let rows = u32::try_from(rows_per_axis).context("tile rows exceed u32")?;
     let cols = u32::try_from(rows_per_axis).context("tile cols exceed u32")?;
@@ -395,23 +463,120 @@ async fn write_tile(
     let encoded = encode_tile(TileEncodeInput {
         tile_id: tile_code,
         mesh_kind: MeshKind::JisX0410,
-        dtype: DType::Int32,
+        dtype: dtype.to_dtype(),
         endianness: Endianness::Little,
-        compression: CompressionMode::DeflateRaw,
+        compression,
         dimensions: TileDimensions { rows, cols, bands },
-        no_data: Some(NO_DATA_I32 as f64),
+        no_data: Some(f64::from(dtype.no_data())),
         payload: &payload,
     })
     .map_err(|e| anyhow!("failed to encode tile {}: {}", tile_code, e))?;
 
-    let output_path = output_dir.join(format!("{}.tile", tile_code));
-    tokio::fs::write(&output_path, encoded.bytes)
+    Span::current().record("bytes", encoded.bytes.len());
+    Ok(encoded.bytes)
+}
+
+/// Path of a tile relative to the output directory. With `shard_dir`, tiles are grouped
+/// under their level-1 parent code (the first 4 digits) to keep directories small.
+fn tile_relative_path(tile_code: u64, shard_dir: bool) -> PathBuf {
+    let filename = format!("{}.tile", tile_code);
+    if shard_dir {
+        let code = tile_code.to_string();
+        Path::new(&code[..code.len().min(4)]).join(filename)
+    } else {
+        PathBuf::from(filename)
+    }
+}
+
+/// Fails once more than `max_tile_count` tiles (0: no limit) would be generated.
+fn check_tile_count(tile_count: usize, max_tile_count: usize) -> Result<()> {
+    if max_tile_count > 0 && tile_count > max_tile_count {
+        bail!(
+            "more than {} tiles would be generated; check --tile-level, or raise --max-tile-count",
+            max_tile_count
+        );
+    }
+    Ok(())
+}
+
+/// Percentage of no-data values across every band of `tiles`.
+fn null_rate(tiles: &BTreeMap<u64, Vec<i32>>, no_data: i32) -> f64 {
+    let (mut total, mut missing) = (0usize, 0usize);
+    for values in tiles.values() {
+        total += values.len();
+        missing += values.iter().filter(|v| **v == no_data).count();
+    }
+    if total == 0 {
+        return 0.0;
+    }
+    missing as f64 / total as f64 * 100.0
+}
+
+fn progress_message(file_count: usize, total: usize, tile_count: usize, null_rate: f64) -> String {
+    format!(
+        "{}/{} | tiles: {} | null_rate: {:.1}%",
+        file_count, total, tile_count, null_rate
+    )
+}
+
+fn tile_file_pattern(shard_dir: bool) -> &'static str {
+    if shard_dir {
+        "{parent}/{meshcode}.tile"
+    } else {
+        "{meshcode}.tile"
+    }
+}
+
+async fn write_tile(output_path: &Path, encoded: &[u8]) -> Result<()> {
+    if let Some(parent) = output_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(output_path, encoded)
         .await
         .with_context(|| format!("failed to write {}", output_path.display()))?;
 
     Ok(())
 }
 
+/// One tile waiting to be encoded: output path, tile code and the collected values.
+type TileJob = (PathBuf, u64, Vec<i32>);
+
+/// Encodes one level-1 file's tiles on `pool` and writes them. Deflate is CPU-bound, so it
+/// runs off the async executor; only the file writes stay on tokio. Returns the tile count.
+async fn encode_and_write_tiles(
+    pool: Arc<rayon::ThreadPool>,
+    jobs: Vec<TileJob>,
+    rows_per_axis: usize,
+    band_count: usize,
+    dtype: TileDType,
+    compression: CompressionMode,
+) -> Result<usize> {
+    let encoded: Vec<(PathBuf, Vec<u8>)> = tokio::task::spawn_blocking(move || {
+        pool.install(|| {
+            jobs.into_par_iter()
+                .map(|(path, tile_code, values)| {
+                    let bytes = encode_tile_bytes(
+                        tile_code,
+                        rows_per_axis,
+                        band_count,
+                        &values,
+                        dtype,
+                        compression,
+                    )?;
+                    Ok((path, bytes))
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+    })
+    .await??;
+
+    for (path, bytes) in &encoded {
+        write_tile(path, bytes).await?;
+    }
+    Ok(encoded.len())
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn write_metadata(
     output_dir: &Path,
     mesh_stats: &MeshStats,
@@ -420,6 +585,9 @@ async fn write_metadata(
     tile_level: u8,
     rows_per_axis: usize,
     band_names: &[String],
+    dtype: TileDType,
+    compression: TileCompression,
+    shard_dir: bool,
 ) -> Result<()> {
     let rows = u32::try_from(rows_per_axis).context("tile rows exceed u32")?;
     let cols = u32::try_from(rows_per_axis).context("tile cols exceed u32")?;
@@ -438,7 +606,7 @@ async fn write_metadata(
 
     let metadata = TileSetMetadata {
         format: "MTI1",
-        tile_file_pattern: "{meshcode}.tile",
+        tile_file_pattern: tile_file_pattern(shard_dir),
         mesh_kind: "jis-x0410",
         data_mesh_level: data_level,
         tile_mesh_level: tile_level,
@@ -447,13 +615,14 @@ async fn write_metadata(
         year: mesh_stats.year,
         survey: survey.to_string(),
         stats_id: mesh_stats.stats_id.clone(),
+        srid: mesh_stats.datum,
         rows,
         cols,
         bands,
-        dtype: "int32",
+        dtype: dtype.as_str(),
         endianness: "little",
-        compression: "deflate-raw",
-        no_data: NO_DATA_I32,
+        compression: compression.as_str(),
+        no_data: dtype.no_data(),
         band_columns,
     };
 
@@ -466,16 +635,126 @@ async fn write_metadata(
     Ok(())
 }
 
-pub async fn process_mesh_tile(
-    tmp_dir: &Path,
-    level: u8,
-    year: u16,
+/// Groups the written tiles by level-1 code, with the extent of each group's tiles.
+fn l1_manifest_entries(tiles: &[(u64, PathBuf)]) -> Result<Vec<L1ManifestEntry>> {
+    let mut groups: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for &(tile_code, _) in tiles {
+        let code = tile_code.to_string();
+        let l1: u64 = code[..code.len().min(4)].parse()?;
+        groups.entry(l1).or_default().push(tile_code);
+    }
+    groups
+        .into_iter()
+        .map(|(l1, tile_codes)| {
+            let mut bbox = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+            for &tile_code in &tile_codes {
+                let bounds = mesh_geometry::cell_bounds(tile_code)?;
+                bbox[0] = bbox[0].min(bounds.min_lon);
+                bbox[1] = bbox[1].min(bounds.min_lat);
+                bbox[2] = bbox[2].max(bounds.max_lon);
+                bbox[3] = bbox[3].max(bounds.max_lat);
+            }
+            Ok(L1ManifestEntry {
+                code: l1,
+                dir: l1.to_string(),
+                tile_count: tile_codes.len(),
+                bbox,
+            })
+        })
+        .collect()
+}
+
+/// `--split-by-l1`: a `metadata.json` in every level-1 subdirectory (tile paths relative to
+/// that directory) and a `manifest.json` listing the subdirectories.
+#[allow(clippy::too_many_arguments)]
+async fn write_l1_split(
+    layer: &PyramidLayer,
+    mesh_stats: &MeshStats,
     survey: &str,
-    tile_level: Option<u8>,
-    bands: Option<&[String]>,
-    output_dir: &Path,
+    tile_level: u8,
+    band_names: &[String],
+    dtype: TileDType,
+    compression: TileCompression,
+    tiles: &[(u64, PathBuf)],
 ) -> Result<()> {
-    let tile_level = tile_level.unwrap_or(level);
+    let entries = l1_manifest_entries(tiles)?;
+    for entry in &entries {
+        write_metadata(
+            &layer.dir.join(&entry.dir),
+            mesh_stats,
+            survey,
+            layer.level,
+            tile_level,
+            layer.rows_per_axis,
+            band_names,
+            dtype,
+            compression,
+            false,
+        )
+        .await?;
+    }
+    let manifest = L1Manifest {
+        format: "MTI1",
+        data_mesh_level: layer.level,
+        tile_mesh_level: tile_level,
+        year: mesh_stats.year,
+        stats_id: mesh_stats.stats_id.clone(),
+        l1: entries,
+    };
+    let manifest_path = layer.dir.join("manifest.json");
+    tokio::fs::write(&manifest_path, serde_json::to_vec_pretty(&manifest)?)
+        .await
+        .with_context(|| format!("failed to write {}", manifest_path.display()))?;
+    Ok(())
+}
+
+/// Outcome of [`process_mesh_tile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileEncodeReport {
+    pub output_dir: PathBuf,
+    /// Tiles encoded and written in this run.
+    pub tiles_written: usize,
+    /// Existing tiles left untouched because `overwrite` was off.
+    pub tiles_skipped: usize,
+    pub tile_level: u8,
+    pub band_names: Vec<String>,
+}
+
+/// Encodes a mesh survey as tiles as configured by `config`. `prefetched` are CSVs already
+/// downloaded (by a pipeline `download` step); otherwise they are downloaded here. The S3
+/// upload, proxy and `User-Agent` of `config` are left to the caller.
+pub async fn process_mesh_tile(
+    http_client: &reqwest::Client,
+    config: &MeshTileConfig,
+    prefetched: Option<Vec<DownloadedItem<(u64, Url)>>>,
+) -> Result<TileEncodeReport> {
+    let tmp_dir = config.tmp_dir.as_path();
+    let (level, year, survey) = (config.level, config.year, config.survey.as_str());
+    let tile_level = config.tile_level;
+    let bands = config.bands.as_deref();
+    let column_aliases = config.column_alias_file.as_deref();
+    let min_nonnull_fraction = config.min_nonnull_fraction;
+    let output_dir = config.output_dir.as_path();
+    let ogc_features_output = config.ogc_features_output.as_deref();
+    let (compression, dtype) = (config.compression, config.dtype);
+    let geo_filter = config.geo_filter.as_ref();
+    let (shard_dir, overwrite) = (config.shard_dir, config.overwrite);
+    let encode_threads = config.encode_threads;
+    let (pyramid, aggregate_fn) = (config.pyramid, config.aggregate_fn);
+    let split_by_l1 = config.split_by_l1;
+    let max_tile_count = config.max_tile_count;
+    let cleanup = config.cleanup;
+    let column_aliases = match column_aliases {
+        Some(path) => ColumnAliases::read(path).await?,
+        None => ColumnAliases::default(),
+    };
+    mesh_stats::check_nonnull_fraction(min_nonnull_fraction)?;
+    let compression_mode = compression.to_mode();
+    // The split layout is the sharded one, plus per-directory metadata and a manifest.
+    let shard_dir = shard_dir || split_by_l1;
+    let no_data = dtype.no_data();
+    // Every pyramid layer shares the tile grid, so it defaults to the coarsest one.
+    let tile_level = tile_level.unwrap_or(if pyramid { 1 } else { level });
     if tile_level > level {
         bail!(
             "tile-level ({}) must be <= data level ({})",
@@ -489,51 +768,107 @@ pub async fn process_mesh_tile(
     let _ = mesh_level_from_u8(tile_level)?;
 
     let rows_per_axis = subdivisions_per_axis(tile_level, level)?;
-    let mesh_stats = get_matching_mesh_stats(level, year, survey)
-        .ok_or(anyhow!("一致する統計データが見つかりません"))?;
-
-    let urls_with_metadata: Vec<(u64, Url)> = JAPAN_LV1
-        .iter()
-        .map(|mesh| {
-            let url = format!(
-                "https://www.e-stat.go.jp/gis/statmap-search/data?statsId={}&code={}&downloadType=2",
-                mesh_stats.stats_id, mesh
-            );
-            (*mesh, Url::parse(&url).unwrap())
-        })
-        .collect();
-
-    let mut downloaded_items: Vec<DownloadedItem<(u64, Url)>> = download::download_and_extract_all(
-        stream::iter(urls_with_metadata),
-        |(_mesh, url)| url.clone(),
-        |(mesh, _url)| format!("{}-{}-{}.zip", mesh_stats.year, mesh_stats.stats_id, mesh),
-        "txt",
-        tmp_dir,
-        "Downloading Mesh CSVs...",
-        "Extracting Mesh CSVs...",
-        10,
-    )
-    .await?;
+    let layers: Vec<PyramidLayer> = if pyramid {
+        (tile_level..=level)
+            .rev()
+            .map(|layer_level| {
+                Ok(PyramidLayer {
+                    level: layer_level,
+                    dir: output_dir.join(format!("lv{}", layer_level)),
+                    rows_per_axis: subdivisions_per_axis(tile_level, layer_level)?,
+                    factor: subdivisions_per_axis(layer_level, level)?,
+                })
+            })
+            .collect::<Result<_>>()?
+    } else {
+        vec![PyramidLayer {
+            level,
+            dir: output_dir.to_path_buf(),
+            rows_per_axis,
+            factor: 1,
+        }]
+    };
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+
+    let mut downloaded_items: Vec<DownloadedItem<(u64, Url)>> = match prefetched {
+        Some(items) => items,
+        None => {
+            let urls_with_metadata: Vec<(u64, Url)> =
+                geo_filter::filter_lv1_codes(JAPAN_LV1, geo_filter)?
+                    .into_iter()
+                    .map(|mesh| {
+                        let url = format!(
+                            "https://www.e-stat.go.jp/gis/statmap-search/data?statsId={}&code={}&downloadType=2",
+                            mesh_stats.stats_id, mesh
+                        );
+                        (mesh, Url::parse(&url).unwrap())
+                    })
+                    .collect();
+            download::into_result(
+                download::download_and_extract_all(
+                    http_client,
+                    stream::iter(urls_with_metadata),
+                    |(_mesh, url)| url.clone(),
+                    |(mesh, _url)| {
+                        format!("{}-{}-{}.zip", mesh_stats.year, mesh_stats.stats_id, mesh)
+                    },
+                    "txt",
+                    tmp_dir,
+                    "Downloading Mesh CSVs...",
+                    "Extracting Mesh CSVs...",
+                    10,
+                )
+                .await?,
+            )?
+        }
+    };
 
     if downloaded_items.is_empty() {
         return Err(anyhow!("No files found after download/extraction"));
     }
 
-    tokio::fs::create_dir_all(output_dir).await?;
+    for layer in &layers {
+        tokio::fs::create_dir_all(&layer.dir).await?;
+    }
     downloaded_items.sort_by_key(|item| item.metadata.0);
+    mesh_stats::warn_on_year_mismatch(&downloaded_items[0].extracted_path, mesh_stats.year);
 
-    let pb_style = ProgressStyle::default_bar()
-        .template("{msg} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7}")?
-        .progress_chars("##-");
-    let pb = ProgressBar::new(downloaded_items.len() as u64);
-    pb.set_style(pb_style);
-    pb.set_message("Encoding mesh tiles...");
+    let pb = progress::new_bar(downloaded_items.len() as u64, "Encoding mesh tiles...")?;
 
     let mut expected_header: Option<Vec<String>> = None;
     let mut selected_bands: Vec<SelectedBand> = Vec::new();
     let mut total_tiles = 0usize;
+    let mut skipped_tiles = 0usize;
+    let mut band_names: Vec<String> = Vec::new();
+    let mut stac_tiles: Vec<(u64, PathBuf)> = Vec::new();
+    let encode_threads = encode_threads
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(encode_threads)
+            .build()
+            .context("failed to build the tile encoding thread pool")?,
+    );
+    let mut pending_encodes: VecDeque<tokio::task::JoinHandle<Result<usize>>> = VecDeque::new();
+    let mut ogc_writer = match ogc_features_output {
+        Some(dir) => Some(
+            OgcFeaturesWriter::create(
+                dir,
+                &format!("{}_{}_{}", mesh_stats.year, mesh_stats.stats_id, level),
+                &format!("{} {}年 Lv{}", survey, mesh_stats.year, level),
+            )
+            .await?,
+        ),
+        None => None,
+    };
 
-    for item in downloaded_items.iter() {
+    for (file_idx, item) in downloaded_items.iter().enumerate() {
         let mut rdr = open_shiftjis_csv(&item.extracted_path)
             .with_context(|| format!("when opening {}", item.extracted_path.display()))?;
 
@@ -555,7 +890,10 @@ pub async fn process_mesh_tile(
 
         if let Some(expected) = expected_header.as_ref() {
             if expected != &normalized_header {
-                bail!("CSV header mismatch: {}", item.extracted_path.display());
+                return Err(JpEstatError::CsvMismatch {
+                    path: item.extracted_path.clone(),
+                }
+                .into());
             }
         } else {
             let header_codes: Vec<String> = header1.iter().map(|s| s.trim().to_string()).collect();
@@ -567,6 +905,24 @@ pub async fn process_mesh_tile(
                     )
                 })?;
             selected_bands = resolve_selected_bands(&available_bands, bands)?;
+            if let Some(min_nonnull_fraction) = min_nonnull_fraction {
+                let keep = mesh_stats::dense_bands(
+                    &item.extracted_path,
+                    &selected_bands
+                        .iter()
+                        .map(|b| (b.source_idx, b.name.as_str()))
+                        .collect::<Vec<_>>(),
+                    min_nonnull_fraction,
+                )?;
+                let mut keep = keep.into_iter();
+                selected_bands.retain(|_| keep.next().unwrap_or(true));
+                if selected_bands.is_empty() {
+                    bail!(
+                        "every band has fewer than {:.1}% non-null cells; lower --min-nonnull-fraction",
+                        min_nonnull_fraction * 100.0
+                    );
+                }
+            }
             if selected_bands.len() > usize::from(u8::MAX) {
                 bail!(
                     "too many columns for tile bands ({} > {})",
@@ -575,19 +931,29 @@ pub async fn process_mesh_tile(
                 );
             }
 
-            let metadata_band_names: Vec<String> =
-                selected_bands.iter().map(|b| b.name.clone()).collect();
-
-            write_metadata(
-                output_dir,
-                mesh_stats,
-                survey,
-                level,
-                tile_level,
-                rows_per_axis,
-                &metadata_band_names,
-            )
-            .await?;
+            // `--bands` selects by the original names; the aliases only rename the output.
+            band_names = column_aliases.apply(
+                &selected_bands
+                    .iter()
+                    .map(|b| b.name.clone())
+                    .collect::<Vec<_>>(),
+            )?;
+
+            for layer in &layers {
+                write_metadata(
+                    &layer.dir,
+                    mesh_stats,
+                    survey,
+                    layer.level,
+                    tile_level,
+                    layer.rows_per_axis,
+                    &band_names,
+                    dtype,
+                    compression,
+                    shard_dir,
+                )
+                .await?;
+            }
 
             expected_header = Some(normalized_header);
         }
@@ -629,6 +995,12 @@ pub async fn process_mesh_tile(
                 validated_this_file = true;
             }
 
+            if let Some(filter) = geo_filter
+                && !filter.contains_mesh_centroid(mesh_code)?
+            {
+                continue;
+            }
+
             let (tile_code, row_idx, col_idx) =
                 map_meshcode_to_tile(mesh_code, level, tile_level, rows_per_axis).with_context(
                     || {
@@ -640,14 +1012,17 @@ pub async fn process_mesh_tile(
                     },
                 )?;
 
+            if !tiles.contains_key(&tile_code) {
+                check_tile_count(stac_tiles.len() + tiles.len() + 1, max_tile_count)?;
+            }
             let tile = tiles
                 .entry(tile_code)
-                .or_insert_with(|| vec![NO_DATA_I32; tile_value_count]);
+                .or_insert_with(|| vec![no_data; tile_value_count]);
             let base_idx = ((row_idx * rows_per_axis) + col_idx) * band_count;
 
             for (band_idx, band) in selected_bands.iter().enumerate() {
                 let raw = row.get(band.source_idx).unwrap_or("");
-                let value = parse_stat_value(raw).with_context(|| {
+                let value = dtype.parse_value(raw).with_context(|| {
                     format!(
                         "invalid value in column '{}' for mesh code {}",
                         band.name, mesh_code
@@ -655,34 +1030,253 @@ pub async fn process_mesh_tile(
                 })?;
                 tile[base_idx + band_idx] = value;
             }
+
+            if let Some(writer) = ogc_writer.as_mut() {
+                let cell_values = &tile[base_idx..base_idx + band_count];
+                if cell_values.iter().any(|v| *v != no_data) {
+                    let properties = band_names
+                        .iter()
+                        .zip(cell_values)
+                        .map(|(name, value)| {
+                            let value = if *value == no_data {
+                                serde_json::Value::Null
+                            } else {
+                                serde_json::Value::from(*value)
+                            };
+                            (name.clone(), value)
+                        })
+                        .collect();
+                    let geometry = mesh_geometry::cell_bounds(mesh_code)?.to_geojson_polygon();
+                    writer.push(mesh_code, geometry, properties).await?;
+                }
+            }
+        }
+
+        let file_null_rate = null_rate(&tiles, no_data);
+        stac_tiles.extend(
+            tiles
+                .keys()
+                .map(|&tile_code| (tile_code, tile_relative_path(tile_code, shard_dir))),
+        );
+        // Coarse layers are aggregated from the data-level tiles before those are handed off.
+        let mut layer_jobs: Vec<(usize, Vec<TileJob>)> = Vec::with_capacity(layers.len());
+        for layer in layers.iter().rev() {
+            let mut jobs: Vec<TileJob> = Vec::with_capacity(tiles.len());
+            let existing = |tile_code: u64| {
+                let tile_path = layer.dir.join(tile_relative_path(tile_code, shard_dir));
+                (!overwrite && tile_path.exists(), tile_path)
+            };
+            if layer.factor == 1 {
+                for (tile_code, values) in std::mem::take(&mut tiles) {
+                    match existing(tile_code) {
+                        (true, _) => skipped_tiles += 1,
+                        (false, tile_path) => jobs.push((tile_path, tile_code, values)),
+                    }
+                }
+            } else {
+                for (&tile_code, values) in &tiles {
+                    let (skip, tile_path) = existing(tile_code);
+                    if skip {
+                        skipped_tiles += 1;
+                        continue;
+                    }
+                    let mut coarse =
+                        vec![no_data; layer.rows_per_axis * layer.rows_per_axis * band_count];
+                    aggregate_tiles(
+                        values,
+                        &mut coarse,
+                        rows_per_axis,
+                        band_count,
+                        layer.factor,
+                        aggregate_fn,
+                        dtype,
+                    )
+                    .with_context(|| {
+                        format!("when building the Lv{} tile {}", layer.level, tile_code)
+                    })?;
+                    jobs.push((tile_path, tile_code, coarse));
+                }
+            }
+            layer_jobs.push((layer.rows_per_axis, jobs));
         }
 
-        for (tile_code, values) in tiles.into_iter() {
-            write_tile(output_dir, tile_code, rows_per_axis, band_count, &values).await?;
-            total_tiles += 1;
+        for (layer_rows, jobs) in layer_jobs {
+            // Encoding overlaps with reading the next CSV; the queue is bounded so at most
+            // `encode_threads` batches of tiles are held in memory at once.
+            if pending_encodes.len() >= encode_threads
+                && let Some(handle) = pending_encodes.pop_front()
+            {
+                total_tiles += handle.await??;
+            }
+            pending_encodes.push_back(tokio::spawn(encode_and_write_tiles(
+                pool.clone(),
+                jobs,
+                layer_rows,
+                band_count,
+                dtype,
+                compression_mode,
+            )));
         }
 
+        if cleanup {
+            download::cleanup_extracted(item)?;
+        }
+        pb.set_message(format!(
+            "Encoding mesh tiles... {}",
+            progress_message(
+                file_idx + 1,
+                downloaded_items.len(),
+                stac_tiles.len(),
+                file_null_rate
+            )
+        ));
         pb.inc(1);
     }
 
+    while let Some(handle) = pending_encodes.pop_front() {
+        total_tiles += handle.await??;
+    }
+    memory_report::record("encode");
+
     pb.finish_with_message(format!(
         "Mesh tile encoding completed ({} tiles)",
         total_tiles
     ));
 
-    println!("Tile directory: {}", output_dir.display());
+    for layer in &layers {
+        stac::write_stac(
+            &layer.dir,
+            &stac::StacContext {
+                catalog_id: format!(
+                    "{}_{}_{}",
+                    mesh_stats.year, mesh_stats.stats_id, layer.level
+                ),
+                title: format!("{} {}年 Lv{}", survey, mesh_stats.year, layer.level),
+                year: mesh_stats.year,
+                survey,
+                data_level: layer.level,
+                tile_level,
+                bands: &band_names,
+            },
+            &stac_tiles,
+        )
+        .await?;
+        if split_by_l1 {
+            write_l1_split(
+                layer,
+                mesh_stats,
+                survey,
+                tile_level,
+                &band_names,
+                dtype,
+                compression,
+                &stac_tiles,
+            )
+            .await?;
+            println!("L1 manifest: {}", layer.dir.join("manifest.json").display());
+        }
+
+        println!("Tile directory: {}", layer.dir.display());
+        println!("STAC catalog: {}", layer.dir.join("catalog.json").display());
+    }
+    if skipped_tiles > 0 {
+        println!("Skipped {} existing tiles (--no-overwrite)", skipped_tiles);
+    }
+    if let (Some(writer), Some(dir)) = (ogc_writer, ogc_features_output) {
+        let feature_count = writer.finish().await?;
+        println!(
+            "OGC API Features output: {} ({} features)",
+            dir.display(),
+            feature_count
+        );
+    }
     println!(
         "Tile mesh level: Lv{} (data level: Lv{}, rows/cols: {})",
         tile_level, level, rows_per_axis
     );
 
-    Ok(())
+    Ok(TileEncodeReport {
+        output_dir: output_dir.to_path_buf(),
+        tiles_written: total_tiles,
+        tiles_skipped: skipped_tiles,
+        tile_level,
+        band_names,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_tile_relative_path() {
+        assert_eq!(
+            tile_relative_path(53393599, false),
+            PathBuf::from("53393599.tile")
+        );
+        assert_eq!(
+            tile_relative_path(53393599, true),
+            Path::new("5339").join("53393599.tile")
+        );
+        assert_eq!(
+            tile_relative_path(5339, true),
+            Path::new("5339").join("5339.tile")
+        );
+        assert_eq!(tile_file_pattern(true), "{parent}/{meshcode}.tile");
+    }
+
+    #[test]
+    fn test_check_tile_count() {
+        assert!(check_tile_count(10_000, DEFAULT_MAX_TILE_COUNT).is_ok());
+        assert!(check_tile_count(10_001, DEFAULT_MAX_TILE_COUNT).is_err());
+        assert!(check_tile_count(1_000_000, 0).is_ok());
+    }
+
+    #[test]
+    fn test_null_rate_progress() {
+        let no_data = -1;
+        let tiles = BTreeMap::from([(5339, vec![1, -1, 2, -1]), (5340, vec![-1, -1, -1, 3])]);
+        let rate = null_rate(&tiles, no_data);
+        assert_eq!(rate, 62.5);
+        assert_eq!(
+            progress_message(3, 12, 40, rate),
+            "3/12 | tiles: 40 | null_rate: 62.5%"
+        );
+        assert_eq!(null_rate(&BTreeMap::new(), no_data), 0.0);
+    }
+
+    #[test]
+    fn test_l1_manifest_entries() {
+        let tiles: Vec<(u64, PathBuf)> = [53394611, 53394612, 53404600]
+            .into_iter()
+            .map(|code| (code, tile_relative_path(code, true)))
+            .collect();
+        let entries = l1_manifest_entries(&tiles).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            (
+                entries[0].code,
+                entries[0].dir.as_str(),
+                entries[0].tile_count
+            ),
+            (5339, "5339", 2)
+        );
+        let cell = mesh_geometry::cell_bounds(53394611).unwrap();
+        assert_eq!(entries[0].bbox[0], cell.min_lon);
+        assert_eq!(entries[0].bbox[1], cell.min_lat);
+        assert_eq!(entries[0].bbox[3], cell.max_lat);
+        let next = mesh_geometry::cell_bounds(53394612).unwrap();
+        assert_eq!(entries[0].bbox[2], next.max_lon);
+        assert_eq!(entries[1].tile_count, 1);
+    }
+
+    #[test]
+    fn test_digits_for_level() {
+        assert_eq!(digits_for_level(3).unwrap(), 8);
+        assert_eq!(digits_for_level(6).unwrap(), 11);
+        assert!(digits_for_level(7).is_err());
+    }
+
     #[test]
     fn test_subdivisions_per_axis() {
         assert_eq!(subdivisions_per_axis(1, 3).unwrap(), 80);
@@ -691,6 +1285,192 @@ mod tests {
         assert_eq!(subdivisions_per_axis(6, 6).unwrap(), 1);
     }
 
+    #[test]
+    fn test_aggregate_tiles() {
+        let nd = TileDType::Int32.no_data();
+        // 4x4 cells, 2 bands, aggregated 2x2 -> 2x2.
+        #[rustfmt::skip]
+        let fine = vec![
+            1, 10,  2, 20,    5, nd,  nd, nd,
+            3, 30,  4, 40,    nd, nd, nd, nd,
+            nd, nd, nd, nd,   7, 1,   8, 2,
+            nd, nd, nd, nd,   9, 3,   10, 5,
+        ];
+        let mut sum = vec![nd; 8];
+        aggregate_tiles(&fine, &mut sum, 4, 2, 2, AggFn::Sum, TileDType::Int32).unwrap();
+        assert_eq!(sum, vec![10, 100, 5, nd, nd, nd, 34, 11]);
+
+        let mut mean = vec![nd; 8];
+        aggregate_tiles(&fine, &mut mean, 4, 2, 2, AggFn::Mean, TileDType::Int32).unwrap();
+        assert_eq!(mean, vec![3, 25, 5, nd, nd, nd, 9, 3]);
+
+        let mut max = vec![nd; 8];
+        aggregate_tiles(&fine, &mut max, 4, 2, 2, AggFn::Max, TileDType::Int32).unwrap();
+        assert_eq!(max, vec![4, 40, 5, nd, nd, nd, 10, 5]);
+
+        let mut min = vec![nd; 8];
+        aggregate_tiles(&fine, &mut min, 4, 2, 2, AggFn::Min, TileDType::Int32).unwrap();
+        assert_eq!(min, vec![1, 10, 5, nd, nd, nd, 7, 1]);
+
+        let mut first = vec![nd; 8];
+        aggregate_tiles(
+            &fine,
+            &mut first,
+            4,
+            2,
+            2,
+            AggFn::FirstValid,
+            TileDType::Int32,
+        )
+        .unwrap();
+        assert_eq!(first, vec![1, 10, 5, nd, nd, nd, 7, 1]);
+
+        // The first valid value is taken in row order, skipping no-data cells.
+        let sparse = vec![nd, -3, 8, nd];
+        let mut first = vec![nd];
+        aggregate_tiles(
+            &sparse,
+            &mut first,
+            2,
+            1,
+            2,
+            AggFn::FirstValid,
+            TileDType::Int32,
+        )
+        .unwrap();
+        assert_eq!(first, vec![-3]);
+        let mut min = vec![nd];
+        aggregate_tiles(&sparse, &mut min, 2, 1, 2, AggFn::Min, TileDType::Int32).unwrap();
+        assert_eq!(min, vec![-3]);
+
+        let mut too_small = vec![nd; 2];
+        assert!(
+            aggregate_tiles(&fine, &mut too_small, 4, 2, 2, AggFn::Sum, TileDType::Int32).is_err()
+        );
+    }
+
+    #[test]
+    fn test_aggregate_tiles_rejects_uint16_overflow() {
+        let fine = vec![40000, 30000, 1, 1];
+        let mut coarse = vec![TileDType::Uint16.no_data()];
+        let err = aggregate_tiles(&fine, &mut coarse, 2, 1, 2, AggFn::Sum, TileDType::Uint16)
+            .unwrap_err();
+        assert!(err.to_string().contains("--dtype int32"));
+        aggregate_tiles(&fine, &mut coarse, 2, 1, 2, AggFn::Mean, TileDType::Uint16).unwrap();
+        assert_eq!(coarse, vec![17501]);
+    }
+
+    #[test]
+    fn test_compression_modes_change_tile_size() {
+        let mut values = vec![NO_DATA_I32; 80 * 80];
+        values[0] = 120;
+        values[81] = 35;
+        let deflate = encode_tile_bytes(
+            5339,
+            80,
+            1,
+            &values,
+            TileDType::Int32,
+            TileCompression::DeflateRaw.to_mode(),
+        )
+        .unwrap();
+        let none = encode_tile_bytes(
+            5339,
+            80,
+            1,
+            &values,
+            TileDType::Int32,
+            TileCompression::None.to_mode(),
+        )
+        .unwrap();
+        assert!(none.len() >= values.len() * size_of::<i32>());
+        assert!(deflate.len() < none.len());
+    }
+
+    #[test]
+    fn test_uint16_tiles_halve_payload() {
+        let mut values = vec![TileDType::Uint16.no_data(); 80 * 80];
+        values[0] = TileDType::Uint16.parse_value("120").unwrap();
+        let int32 = encode_tile_bytes(
+            5339,
+            80,
+            1,
+            &values,
+            TileDType::Int32,
+            CompressionMode::None,
+        )
+        .unwrap();
+        let uint16 = encode_tile_bytes(
+            5339,
+            80,
+            1,
+            &values,
+            TileDType::Uint16,
+            CompressionMode::None,
+        )
+        .unwrap();
+        assert_eq!(int32.len() - uint16.len(), 80 * 80 * 2);
+
+        let decoded = mesh_data_tile::decode_tile_minimal(&uint16).unwrap();
+        assert_eq!(decoded.header.dtype, DType::Uint16);
+        assert_eq!(decoded.header.no_data, Some(65535.0));
+        assert_eq!(&decoded.payload[..4], &[120, 0, 0xff, 0xff]);
+        assert!(TileDType::Uint16.parse_value("65535").is_err());
+        assert!(TileDType::Uint16.parse_value("-1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_encodes_tiles_in_parallel_and_writes_them() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let pool = Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(2)
+                .build()
+                .unwrap(),
+        );
+        let jobs: Vec<TileJob> = [5339u64, 5340]
+            .iter()
+            .map(|code| (dir.join(format!("{}.tile", code)), *code, vec![7; 4]))
+            .collect();
+        let written =
+            encode_and_write_tiles(pool, jobs, 2, 1, TileDType::Int32, CompressionMode::None)
+                .await
+                .unwrap();
+        assert_eq!(written, 2);
+        let bytes = std::fs::read(dir.join("5340.tile")).unwrap();
+        let decoded = mesh_data_tile::decode_tile_minimal(&bytes).unwrap();
+        assert_eq!(decoded.header.tile_id, 5340);
+    }
+
+    /// Builds a Lv3 code from a Lv1 code and south-origin row/col within it (0..80).
+    fn lv3_code(lv1: u64, row_south: usize, col: usize) -> u64 {
+        let (r2, r3) = (row_south / 10, row_south % 10);
+        let (c2, c3) = (col / 10, col % 10);
+        lv1 * 10_000 + (r2 * 1000 + c2 * 100 + r3 * 10 + c3) as u64
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_map_lv3_to_lv1_round_trips(
+            lv1_idx in 0..JAPAN_LV1.len(),
+            row_south in 0usize..80,
+            col in 0usize..80,
+        ) {
+            let rows_per_axis = subdivisions_per_axis(1, 3).unwrap();
+            let mesh_code = lv3_code(JAPAN_LV1[lv1_idx], row_south, col);
+
+            let (tile_code, row_top, mapped_col) =
+                map_meshcode_to_tile(mesh_code, 3, 1, rows_per_axis).unwrap();
+
+            proptest::prop_assert!(JAPAN_LV1.contains(&tile_code));
+            proptest::prop_assert!(row_top < rows_per_axis);
+            proptest::prop_assert!(mapped_col < rows_per_axis);
+            let rebuilt = lv3_code(tile_code, rows_per_axis - 1 - row_top, mapped_col);
+            proptest::prop_assert_eq!(rebuilt, mesh_code);
+        }
+    }
+
     #[test]
     fn test_map_lv3_to_lv1() {
         let (tile_code, row, col) = map_meshcode_to_tile(53393599, 3, 1, 80).unwrap();
@@ -0,0 +1,202 @@
+use anyhow::{Context, Result, bail};
+use axum::{
+    Router,
+    extract::{Path as UrlPath, State},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Tiles are immutable once written, so browsers may keep them for a day.
+const CACHE_CONTROL: &str = "max-age=86400";
+
+fn respond(status: StatusCode, content_type: &'static str, body: Vec<u8>) -> Response {
+    let mut response = (status, body).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(CACHE_CONTROL),
+    );
+    response
+}
+
+fn not_found() -> Response {
+    respond(
+        StatusCode::NOT_FOUND,
+        "text/plain; charset=utf-8",
+        b"not found".to_vec(),
+    )
+}
+
+/// `{code}.tile` in the flat layout of `mesh-tile`, or below its level-1 parent directory
+/// with `--shard-dir` / `--split-by-l1`.
+pub(crate) fn tile_candidates(tiles_dir: &Path, tile_code: u64) -> [PathBuf; 2] {
+    let filename = format!("{}.tile", tile_code);
+    let code = tile_code.to_string();
+    [
+        tiles_dir.join(&filename),
+        tiles_dir.join(&code[..code.len().min(4)]).join(&filename),
+    ]
+}
+
+/// Codes of every `.tile` file below `tiles_dir`, in order.
+pub(crate) fn tile_codes(tiles_dir: &Path) -> Result<Vec<u64>> {
+    let mut codes = Vec::new();
+    let mut pending = vec![tiles_dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "tile")
+                && let Some(code) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok())
+            {
+                codes.push(code);
+            }
+        }
+    }
+    codes.sort_unstable();
+    Ok(codes)
+}
+
+/// A `tile_index.json` in the directory is served as is; otherwise the tile codes found on
+/// disk are listed.
+async fn tile_index(tiles_dir: &Path) -> Result<Vec<u8>> {
+    let path = tiles_dir.join("tile_index.json");
+    if path.exists() {
+        return Ok(tokio::fs::read(&path).await?);
+    }
+    let tiles_dir = tiles_dir.to_path_buf();
+    let codes = tokio::task::spawn_blocking(move || tile_codes(&tiles_dir)).await??;
+    Ok(serde_json::to_vec(&serde_json::json!({ "tiles": codes }))?)
+}
+
+async fn serve_file(
+    State(tiles_dir): State<Arc<PathBuf>>,
+    UrlPath(file): UrlPath<String>,
+) -> Response {
+    let result = match file.as_str() {
+        "metadata.json" => tokio::fs::read(tiles_dir.join("metadata.json"))
+            .await
+            .ok()
+            .map(|body| ("application/json", body)),
+        "tile_index.json" => match tile_index(&tiles_dir).await {
+            Ok(body) => Some(("application/json", body)),
+            Err(err) => {
+                eprintln!("Warning: failed to build the tile index: {:#}", err);
+                return respond(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "text/plain; charset=utf-8",
+                    b"failed to build the tile index".to_vec(),
+                );
+            }
+        },
+        _ => {
+            // Only digits reach the file system, so the path cannot leave `tiles_dir`.
+            let code = file
+                .strip_suffix(".tile")
+                .filter(|code| !code.is_empty() && code.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|code| code.parse::<u64>().ok());
+            match code {
+                Some(code) => {
+                    let mut found = None;
+                    for path in tile_candidates(&tiles_dir, code) {
+                        if let Ok(body) = tokio::fs::read(&path).await {
+                            found = Some(("application/octet-stream", body));
+                            break;
+                        }
+                    }
+                    found
+                }
+                None => None,
+            }
+        }
+    };
+    match result {
+        Some((content_type, body)) => respond(StatusCode::OK, content_type, body),
+        None => not_found(),
+    }
+}
+
+fn router(tiles_dir: PathBuf) -> Router {
+    Router::new()
+        .route("/tiles/{file}", get(serve_file))
+        .fallback(|| async { not_found() })
+        .with_state(Arc::new(tiles_dir))
+}
+
+/// Serves a `mesh-tile` output directory under `/tiles/` until Ctrl+C.
+pub async fn process_mesh_tile_server(tiles_dir: &Path, bind: IpAddr, port: u16) -> Result<()> {
+    if !tiles_dir.join("metadata.json").exists() {
+        bail!(
+            "{} has no metadata.json; point --tiles-dir at a mesh-tile output directory",
+            tiles_dir.display()
+        );
+    }
+    let addr = SocketAddr::new(bind, port);
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("when binding {}", addr))?;
+    println!(
+        "Serving {} at http://{}/tiles/ (Ctrl+C to stop)",
+        tiles_dir.display(),
+        listener.local_addr()?
+    );
+    axum::serve(listener, router(tiles_dir.to_path_buf()))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_tiles_metadata_and_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("5339")).unwrap();
+        std::fs::write(dir.join("metadata.json"), br#"{"format":"MTI1"}"#).unwrap();
+        std::fs::write(dir.join("5340.tile"), b"flat").unwrap();
+        std::fs::write(dir.join("5339").join("53393599.tile"), b"sharded").unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/tiles", listener.local_addr().unwrap());
+        tokio::spawn(axum::serve(listener, router(dir.to_path_buf())).into_future());
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(format!("{}/{}", base, path)).send();
+
+        let response = get("5340.tile").await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["access-control-allow-origin"], "*");
+        assert_eq!(response.headers()["cache-control"], "max-age=86400");
+        assert_eq!(response.bytes().await.unwrap(), &b"flat"[..]);
+        let response = get("53393599.tile").await.unwrap();
+        assert_eq!(response.bytes().await.unwrap(), &b"sharded"[..]);
+
+        let response = get("metadata.json").await.unwrap();
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(response.text().await.unwrap(), r#"{"format":"MTI1"}"#);
+        let index = get("tile_index.json").await.unwrap().text().await.unwrap();
+        assert_eq!(index, r#"{"tiles":[5340,53393599]}"#);
+
+        assert_eq!(get("..%2Fmetadata.json.tile").await.unwrap().status(), 404);
+        assert_eq!(get("9999.tile").await.unwrap().status(), 404);
+    }
+}
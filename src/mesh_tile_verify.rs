@@ -0,0 +1,197 @@
+use crate::mesh_tile_server::{tile_candidates, tile_codes};
+use anyhow::{Context, Result, anyhow, bail};
+use mesh_data_tile::{decode_payload_values, decode_tile_minimal};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// The parts of `metadata.json` every tile of the set must agree with.
+#[derive(Debug, Deserialize)]
+struct ExpectedTile {
+    rows: u32,
+    cols: u32,
+    bands: u8,
+    dtype: String,
+    no_data: Option<f64>,
+}
+
+/// Tile codes from `tile_index.json` when the directory has one, otherwise the `.tile` files
+/// found on disk.
+fn listed_codes(tiles_dir: &Path) -> Result<Vec<u64>> {
+    let path = tiles_dir.join("tile_index.json");
+    if !path.exists() {
+        return tile_codes(tiles_dir);
+    }
+    #[derive(Deserialize)]
+    struct TileIndex {
+        tiles: Vec<u64>,
+    }
+    let index: TileIndex = serde_json::from_slice(&std::fs::read(&path)?)
+        .with_context(|| format!("when parsing {}", path.display()))?;
+    Ok(index.tiles)
+}
+
+/// Decodes one tile and checks it against the metadata. A tile whose every sample is the
+/// no-data value decodes fine but usually means the values were lost while encoding.
+fn verify_tile(bytes: &[u8], tile_code: u64, expected: &ExpectedTile) -> Result<()> {
+    let tile = decode_tile_minimal(bytes).map_err(|e| anyhow!("failed to decode: {}", e))?;
+    let header = &tile.header;
+    if header.tile_id != tile_code {
+        bail!("tile_id {} does not match the file name", header.tile_id);
+    }
+    let dims = header.dimensions;
+    if (dims.rows, dims.cols, dims.bands) != (expected.rows, expected.cols, expected.bands) {
+        bail!(
+            "{}x{}x{} (rows x cols x bands), metadata.json has {}x{}x{}",
+            dims.rows,
+            dims.cols,
+            dims.bands,
+            expected.rows,
+            expected.cols,
+            expected.bands
+        );
+    }
+    let dtype = format!("{:?}", header.dtype).to_lowercase();
+    if dtype != expected.dtype {
+        bail!("dtype {}, metadata.json has {}", dtype, expected.dtype);
+    }
+
+    let no_data = header.no_data.or(expected.no_data);
+    let values = decode_payload_values(header.dtype, header.endianness, &tile.payload, no_data)
+        .map_err(|e| anyhow!("failed to decode the payload: {}", e))?;
+    let samples = dims.rows as usize * dims.cols as usize * usize::from(dims.bands);
+    if values.len() != samples {
+        bail!("{} samples, expected {}", values.len(), samples);
+    }
+    if values.iter().all(Option::is_none) {
+        bail!("every sample is no-data");
+    }
+    Ok(())
+}
+
+/// `PASS` / `FAIL` line for one listed tile code; the second value is whether it passed.
+fn report_line(tiles_dir: &Path, tile_code: u64, expected: &ExpectedTile) -> (String, bool) {
+    let Some(path) = tile_candidates(tiles_dir, tile_code)
+        .into_iter()
+        .find(|path| path.exists())
+    else {
+        return (format!("FAIL {}.tile: file not found", tile_code), false);
+    };
+    let result = std::fs::read(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| verify_tile(&bytes, tile_code, expected));
+    match result {
+        Ok(()) => (format!("PASS {}", display_path(tiles_dir, &path)), true),
+        Err(err) => (
+            format!("FAIL {}: {:#}", display_path(tiles_dir, &path), err),
+            false,
+        ),
+    }
+}
+
+fn display_path(tiles_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(tiles_dir)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+pub fn process_mesh_tile_verify(tiles_dir: &Path) -> Result<()> {
+    let metadata_path = tiles_dir.join("metadata.json");
+    if !metadata_path.exists() {
+        bail!(
+            "{} has no metadata.json; point --tiles-dir at a mesh-tile output directory",
+            tiles_dir.display()
+        );
+    }
+    let expected: ExpectedTile = serde_json::from_slice(&std::fs::read(&metadata_path)?)
+        .with_context(|| format!("when parsing {}", metadata_path.display()))?;
+
+    let codes = listed_codes(tiles_dir)?;
+    if codes.is_empty() {
+        bail!("no tiles found in {}", tiles_dir.display());
+    }
+    let mut failed = 0;
+    for &tile_code in &codes {
+        let (line, passed) = report_line(tiles_dir, tile_code, &expected);
+        println!("{}", line);
+        if !passed {
+            failed += 1;
+        }
+    }
+    println!(
+        "{} tiles: {} passed, {} failed",
+        codes.len(),
+        codes.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        bail!("{} of {} tiles failed verification", failed, codes.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh_tile::{TileDType, encode_tile_bytes};
+    use mesh_data_tile::CompressionMode;
+
+    #[test]
+    fn reports_each_listed_tile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("5340")).unwrap();
+        let expected: ExpectedTile = serde_json::from_str(
+            r#"{"rows":2,"cols":2,"bands":1,"dtype":"int32","no_data":-2147483648}"#,
+        )
+        .unwrap();
+        let no_data = TileDType::Int32.no_data();
+        let tile = |code, values: &[i32], rows| {
+            encode_tile_bytes(
+                code,
+                rows,
+                1,
+                values,
+                TileDType::Int32,
+                CompressionMode::None,
+            )
+            .unwrap()
+        };
+        std::fs::write(dir.join("5339.tile"), tile(5339, &[1, no_data, 3, 4], 2)).unwrap();
+        std::fs::write(
+            dir.join("5340").join("5340.tile"),
+            tile(5340, &[no_data; 4], 2),
+        )
+        .unwrap();
+        std::fs::write(dir.join("5341.tile"), tile(5341, &[1; 9], 3)).unwrap();
+        std::fs::write(dir.join("5342.tile"), b"not a tile").unwrap();
+
+        assert_eq!(listed_codes(dir).unwrap(), [5339, 5340, 5341, 5342]);
+        let lines: Vec<_> = [5339, 5340, 5341, 5342, 5343]
+            .into_iter()
+            .map(|code| report_line(dir, code, &expected))
+            .collect();
+        assert_eq!(lines[0], ("PASS 5339.tile".to_string(), true));
+        assert_eq!(
+            lines[1],
+            (
+                "FAIL 5340/5340.tile: every sample is no-data".to_string(),
+                false
+            )
+        );
+        assert!(
+            lines[2].0.starts_with("FAIL 5341.tile: 3x3x1"),
+            "{}",
+            lines[2].0
+        );
+        assert!(lines[3].0.starts_with("FAIL 5342.tile: failed to decode"));
+        assert_eq!(
+            lines[4],
+            ("FAIL 5343.tile: file not found".to_string(), false)
+        );
+
+        std::fs::write(dir.join("tile_index.json"), br#"{"tiles":[5339]}"#).unwrap();
+        assert_eq!(listed_codes(dir).unwrap(), [5339]);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{
+    download,
+    error::JpEstatError,
+    list_cached::human_size,
+    mesh::{self, get_matching_mesh_stats, infer_column_type, read_columns},
+    shiftjis_csv::open_shiftjis_csv,
+};
+use anyhow::{Context, Result, anyhow, bail};
+use arrow::{
+    array::{ArrayRef, Int16Builder, Int32Builder, Int64Builder, ListBuilder},
+    datatypes::{DataType, Field, Schema},
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Rows per record batch written to the stream.
+const BATCH_ROWS: usize = 10_000;
+
+/// Arrow type for the PostgreSQL type `mesh` would create. `GASSAN` (`BIGINT[]`) is a list
+/// of the `;`-separated mesh codes.
+fn arrow_type(postgres_type: &str) -> DataType {
+    match postgres_type {
+        "BIGINT" => DataType::Int64,
+        "SMALLINT" => DataType::Int16,
+        "BIGINT[]" => DataType::List(Arc::new(Field::new_list_field(DataType::Int64, true))),
+        _ => DataType::Int32,
+    }
+}
+
+fn schema(columns: &[String]) -> Schema {
+    Schema::new(
+        columns
+            .iter()
+            .map(|col| Field::new(col, arrow_type(infer_column_type(col)), true))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// One column of the batch being built.
+enum ColumnBuilder {
+    Int16(Int16Builder),
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    List(ListBuilder<Int64Builder>),
+}
+
+impl ColumnBuilder {
+    fn new(data_type: &DataType) -> Self {
+        match data_type {
+            DataType::Int16 => Self::Int16(Int16Builder::with_capacity(BATCH_ROWS)),
+            DataType::Int64 => Self::Int64(Int64Builder::with_capacity(BATCH_ROWS)),
+            DataType::List(_) => Self::List(ListBuilder::new(Int64Builder::new())),
+            _ => Self::Int32(Int32Builder::with_capacity(BATCH_ROWS)),
+        }
+    }
+
+    /// `*` (confidential) and blank cells become null, as in the PostgreSQL import.
+    fn append(&mut self, column: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        let value = (!value.is_empty() && value != "*").then_some(value);
+        let invalid = || format!("invalid {} value {:?}", column, value.unwrap_or_default());
+        match self {
+            Self::Int16(builder) => {
+                builder.append_option(value.map(str::parse).transpose().with_context(invalid)?)
+            }
+            Self::Int32(builder) => {
+                builder.append_option(value.map(str::parse).transpose().with_context(invalid)?)
+            }
+            Self::Int64(builder) => {
+                builder.append_option(value.map(str::parse).transpose().with_context(invalid)?)
+            }
+            Self::List(builder) => match value {
+                Some(value) => {
+                    for code in value.split(';') {
+                        builder
+                            .values()
+                            .append_value(code.trim().parse().with_context(invalid)?);
+                    }
+                    builder.append(true);
+                }
+                None => builder.append_null(),
+            },
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Int16(builder) => Arc::new(builder.finish()),
+            Self::Int32(builder) => Arc::new(builder.finish()),
+            Self::Int64(builder) => Arc::new(builder.finish()),
+            Self::List(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// Writes every row of `files` to an Arrow IPC stream at `output`, one record batch per
+/// `BATCH_ROWS` rows, so only one batch is held in memory. The schema comes from the header
+/// of the first file. Returns the number of rows written.
+fn write_arrow(output: &Path, files: &[PathBuf]) -> Result<u64> {
+    let first = files
+        .first()
+        .ok_or_else(|| anyhow!("No files found after download/extraction"))?;
+    let columns = read_columns(first)?;
+    let schema = Arc::new(schema(&columns));
+    let file =
+        File::create(output).with_context(|| format!("when creating {}", output.display()))?;
+    let mut writer = StreamWriter::try_new(BufWriter::new(file), &schema)?;
+    let mut builders: Vec<ColumnBuilder> = schema
+        .fields()
+        .iter()
+        .map(|field| ColumnBuilder::new(field.data_type()))
+        .collect();
+
+    let mut flush = |builders: &mut Vec<ColumnBuilder>| -> Result<()> {
+        let arrays = builders.iter_mut().map(ColumnBuilder::finish).collect();
+        writer.write(&RecordBatch::try_new(schema.clone(), arrays)?)?;
+        Ok(())
+    };
+    let mut rows = 0u64;
+    let mut pending = 0usize;
+    for file in files {
+        if read_columns(file)? != columns {
+            return Err(JpEstatError::CsvMismatch { path: file.clone() }.into());
+        }
+        let mut rdr = open_shiftjis_csv(file)?;
+        for record in rdr.records().skip(2) {
+            let record = record.with_context(|| format!("when reading {}", file.display()))?;
+            for (i, (builder, col)) in builders.iter_mut().zip(&columns).enumerate() {
+                builder
+                    .append(col, record.get(i).unwrap_or(""))
+                    .with_context(|| format!("in {}", file.display()))?;
+            }
+            rows += 1;
+            pending += 1;
+            if pending == BATCH_ROWS {
+                flush(&mut builders)?;
+                pending = 0;
+            }
+        }
+    }
+    if pending > 0 {
+        flush(&mut builders)?;
+    }
+    writer.finish()?;
+    Ok(rows)
+}
+
+/// Writes a mesh survey to an Arrow IPC stream file straight from the downloaded CSVs,
+/// without the merged CSV of `mesh-csv`, for loading into DuckDB, Polars or a Parquet
+/// conversion.
+pub async fn process_mesh_to_arrow(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    level: u8,
+    year: u16,
+    survey: &str,
+    output: &Path,
+    cleanup: bool,
+) -> Result<()> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+    let mut downloaded_items =
+        mesh::download_mesh_csvs(http_client, tmp_dir, mesh_stats, None).await?;
+    if downloaded_items.is_empty() {
+        bail!("No files found after download/extraction");
+    }
+    downloaded_items.sort_by_key(|item| item.metadata.0);
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let files: Vec<PathBuf> = downloaded_items
+        .iter()
+        .map(|item| item.extracted_path.clone())
+        .collect();
+    let rows = {
+        let output = output.to_path_buf();
+        tokio::task::spawn_blocking(move || write_arrow(&output, &files)).await??
+    };
+    if cleanup {
+        for item in &downloaded_items {
+            download::cleanup_extracted(item)?;
+        }
+    }
+
+    let size = tokio::fs::metadata(output).await?.len();
+    println!(
+        "Wrote {} rows to {} ({})",
+        rows,
+        output.display(),
+        human_size(size)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::{
+        array::{Array, AsArray},
+        datatypes::{Int32Type, Int64Type},
+        ipc::reader::StreamReader,
+    };
+    use encoding_rs::SHIFT_JIS;
+
+    #[test]
+    fn streams_csvs_as_record_batches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let mut files = Vec::new();
+        for (i, rows) in [
+            "53394611,0,,,120\r\n53394612,2,53394611,53394611;53394612,*\r\n",
+            "53404611,0,,,7\r\n",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let path = dir.join(format!("tblT001140S{}.txt", 5339 + i));
+            let csv = format!(
+                "KEY_CODE,HTKSYORI,HTKSAKI,GASSAN,T001140001\r\n,,,,人口（総数）\r\n{}",
+                rows
+            );
+            let (bytes, _, _) = SHIFT_JIS.encode(&csv);
+            std::fs::write(&path, bytes).unwrap();
+            files.push(path);
+        }
+        let output = dir.join("mesh.arrows");
+
+        assert_eq!(write_arrow(&output, &files).unwrap(), 3);
+
+        let reader = StreamReader::try_new(File::open(&output).unwrap(), None).unwrap();
+        let schema = reader.schema();
+        let types: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|f| (f.name().as_str(), f.data_type().clone()))
+            .collect();
+        assert_eq!(types[0], ("KEY_CODE", DataType::Int64));
+        assert_eq!(types[1], ("HTKSYORI", DataType::Int16));
+        assert!(matches!(types[3], ("GASSAN", DataType::List(_))));
+        assert_eq!(types[4], ("人口（総数）", DataType::Int32));
+
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        // Each file is written as it is read, but rows only go out in full batches.
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 3);
+        let key_codes = batch.column(0).as_primitive::<Int64Type>();
+        assert_eq!(key_codes.values(), &[53394611, 53394612, 53404611]);
+        let population = batch.column(4).as_primitive::<Int32Type>();
+        assert!(population.is_null(1));
+        assert_eq!(population.value(2), 7);
+        let gassan = batch.column(3).as_list::<i32>();
+        assert!(gassan.is_null(0));
+        assert_eq!(
+            gassan.value(1).as_primitive::<Int64Type>().values(),
+            &[53394611, 53394612]
+        );
+
+        std::fs::write(
+            &files[1],
+            SHIFT_JIS
+                .encode("KEY_CODE,HTKSYORI\r\n,\r\n53404611,0\r\n")
+                .0,
+        )
+        .unwrap();
+        assert!(write_arrow(&output, &files).is_err());
+    }
+}
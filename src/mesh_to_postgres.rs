@@ -0,0 +1,348 @@
+use crate::{mesh_geometry, postgres, progress};
+use anyhow::{Context, Result, bail};
+use mesh_data_tile::{DType, DecodedTile, Endianness, decode_tile_minimal};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Fallback for `metadata.json` files written before the `srid` field existed (JGD2011).
+const DEFAULT_TILE_SRID: u16 = 6668;
+
+/// The subset of the `metadata.json` written by `mesh-tile` that the import needs.
+#[derive(Debug, Deserialize)]
+struct TileSetInfo {
+    year: u16,
+    survey: String,
+    data_mesh_level: u8,
+    #[serde(default)]
+    srid: Option<u16>,
+    band_columns: Vec<BandColumn>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BandColumn {
+    name: String,
+}
+
+async fn read_tile_set_info(tiles_dir: &Path) -> Result<TileSetInfo> {
+    let path = tiles_dir.join("metadata.json");
+    let body = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("when reading {}", path.display()))?;
+    serde_json::from_slice(&body).with_context(|| format!("when parsing {}", path.display()))
+}
+
+/// Finds `.tile` files directly under `tiles_dir` and in `--shard-dir` subdirectories.
+fn find_tile_files(tiles_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![tiles_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)
+            .with_context(|| format!("when reading directory {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "tile") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn tiff_sample_format(dtype: DType) -> u16 {
+    match dtype {
+        DType::Uint8 | DType::Uint16 | DType::Uint32 => 1,
+        DType::Int8 | DType::Int16 | DType::Int32 => 2,
+        DType::Float32 | DType::Float64 => 3,
+    }
+}
+
+const TIFF_SHORT: u16 = 3;
+const TIFF_LONG: u16 = 4;
+const TIFF_DOUBLE: u16 = 12;
+
+struct TiffEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    data: Vec<u8>,
+}
+
+impl TiffEntry {
+    fn shorts(tag: u16, values: &[u16]) -> Self {
+        Self {
+            tag,
+            field_type: TIFF_SHORT,
+            count: values.len() as u32,
+            data: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+
+    fn long(tag: u16, value: u32) -> Self {
+        Self {
+            tag,
+            field_type: TIFF_LONG,
+            count: 1,
+            data: value.to_le_bytes().to_vec(),
+        }
+    }
+
+    fn doubles(tag: u16, values: &[f64]) -> Self {
+        Self {
+            tag,
+            field_type: TIFF_DOUBLE,
+            count: values.len() as u32,
+            data: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+}
+
+/// Wraps a decoded tile in a single-strip, pixel-interleaved little-endian GeoTIFF so
+/// `ST_FromGDALRaster` can read it. The tile payload is already laid out row-major from the
+/// north edge with bands innermost, which is exactly TIFF's `PlanarConfiguration=1`.
+fn tile_to_geotiff(tile: &DecodedTile, srid: u16) -> Result<Vec<u8>> {
+    let header = &tile.header;
+    if header.endianness != Endianness::Little {
+        bail!("big-endian tiles are not supported");
+    }
+    let dims = header.dimensions;
+    let bounds = mesh_geometry::cell_bounds(header.tile_id)?;
+    let bands = u16::from(dims.bands);
+    let bits = (header.dtype.byte_size() * 8) as u16;
+    let image_len = u32::try_from(tile.payload.len()).context("tile payload exceeds 4 GiB")?;
+
+    let mut entries = vec![
+        TiffEntry::long(256, dims.cols),
+        TiffEntry::long(257, dims.rows),
+        TiffEntry::shorts(258, &vec![bits; usize::from(bands)]),
+        TiffEntry::shorts(259, &[1]),
+        TiffEntry::shorts(262, &[1]),
+        // StripOffsets, patched below once the layout is known.
+        TiffEntry::long(273, 0),
+        TiffEntry::shorts(277, &[bands]),
+        TiffEntry::long(278, dims.rows),
+        TiffEntry::long(279, image_len),
+        TiffEntry::shorts(284, &[1]),
+    ];
+    if bands > 1 {
+        entries.push(TiffEntry::shorts(338, &vec![0; usize::from(bands - 1)]));
+    }
+    entries.push(TiffEntry::shorts(
+        339,
+        &vec![tiff_sample_format(header.dtype); usize::from(bands)],
+    ));
+    entries.push(TiffEntry::doubles(
+        33550,
+        &[
+            (bounds.max_lon - bounds.min_lon) / f64::from(dims.cols),
+            (bounds.max_lat - bounds.min_lat) / f64::from(dims.rows),
+            0.0,
+        ],
+    ));
+    entries.push(TiffEntry::doubles(
+        33922,
+        &[0.0, 0.0, 0.0, bounds.min_lon, bounds.max_lat, 0.0],
+    ));
+    // GeoKeyDirectory: geographic model, pixel-is-area, EPSG geographic CRS.
+    entries.push(TiffEntry::shorts(
+        34735,
+        &[1, 1, 0, 3, 1024, 0, 1, 2, 1025, 0, 1, 1, 2048, 0, 1, srid],
+    ));
+
+    let ifd_len = 2 + entries.len() * 12 + 4;
+    let mut extra_offset = 8 + ifd_len;
+    let mut offsets = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        if entry.data.len() > 4 {
+            offsets.push(Some(extra_offset));
+            extra_offset += entry.data.len() + entry.data.len() % 2;
+        } else {
+            offsets.push(None);
+        }
+    }
+    let strip_offset = u32::try_from(extra_offset).context("TIFF header too large")?;
+    if let Some(entry) = entries.iter_mut().find(|e| e.tag == 273) {
+        entry.data = strip_offset.to_le_bytes().to_vec();
+    }
+
+    let mut out = Vec::with_capacity(extra_offset + tile.payload.len());
+    out.extend_from_slice(b"II");
+    out.extend_from_slice(&42u16.to_le_bytes());
+    out.extend_from_slice(&8u32.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (entry, offset) in entries.iter().zip(&offsets) {
+        out.extend_from_slice(&entry.tag.to_le_bytes());
+        out.extend_from_slice(&entry.field_type.to_le_bytes());
+        out.extend_from_slice(&entry.count.to_le_bytes());
+        match offset {
+            Some(offset) => out.extend_from_slice(&(*offset as u32).to_le_bytes()),
+            None => {
+                let mut inline = [0u8; 4];
+                inline[..entry.data.len()].copy_from_slice(&entry.data);
+                out.extend_from_slice(&inline);
+            }
+        }
+    }
+    out.extend_from_slice(&0u32.to_le_bytes());
+    for entry in entries.iter().filter(|e| e.data.len() > 4) {
+        out.extend_from_slice(&entry.data);
+        if entry.data.len() % 2 == 1 {
+            out.push(0);
+        }
+    }
+    out.extend_from_slice(&tile.payload);
+    Ok(out)
+}
+
+/// Builds the raster expression for one tile: `$2` is the GeoTIFF bytes and `$3` the no-data
+/// value, applied to every band (the same thing `raster2pgsql -N` does).
+fn build_raster_expr(srid: u16, band_count: u8, has_no_data: bool) -> String {
+    let mut expr = format!("ST_FromGDALRaster($2, {})", srid);
+    if has_no_data {
+        for band in 1..=band_count {
+            expr = format!("ST_SetBandNoDataValue({}, {}, $3)", expr, band);
+        }
+    }
+    expr
+}
+
+fn build_insert_sql(table_name: &str, raster_expr: &str) -> String {
+    format!(
+        r#"INSERT INTO {table} (tile_code, rast) VALUES ($1, {raster_expr})
+ON CONFLICT (tile_code) DO UPDATE SET rast = EXCLUDED.rast"#,
+        table = postgres::pg_ident(table_name)
+    )
+}
+
+pub async fn process_mesh_to_postgres(
+    postgres_url: &str,
+    tiles_dir: &Path,
+    table_name: &str,
+) -> Result<()> {
+    let info = read_tile_set_info(tiles_dir).await?;
+    let srid = info.srid.unwrap_or(DEFAULT_TILE_SRID);
+    let tile_files = find_tile_files(tiles_dir)?;
+    if tile_files.is_empty() {
+        bail!("no .tile files found in {}", tiles_dir.display());
+    }
+
+    let mut client = postgres::connect(postgres_url).await?;
+    let band_names: Vec<&str> = info.band_columns.iter().map(|b| b.name.as_str()).collect();
+    client
+        .batch_execute(&format!(
+            r#"CREATE TABLE IF NOT EXISTS {table} (tile_code bigint PRIMARY KEY, rast raster NOT NULL);
+COMMENT ON TABLE {table} IS '{survey} {year}年 Lv{level} (mesh-data-tile)';
+COMMENT ON COLUMN {table}.rast IS 'bands: {bands}';"#,
+            table = postgres::pg_ident(table_name),
+            survey = info.survey.replace('\'', "''"),
+            year = info.year,
+            level = info.data_mesh_level,
+            bands = band_names.join(", ").replace('\'', "''"),
+        ))
+        .await
+        .with_context(|| {
+            format!(
+                "when creating {} (is the postgis_raster extension installed?)",
+                table_name
+            )
+        })?;
+
+    let pb = progress::new_bar(tile_files.len() as u64, "Importing tiles...")?;
+
+    let transaction = client.transaction().await?;
+    for path in &tile_files {
+        let bytes = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("when reading {}", path.display()))?;
+        let tile = decode_tile_minimal(&bytes)
+            .map_err(|e| anyhow::anyhow!("failed to decode {}: {}", path.display(), e))?;
+        let geotiff = tile_to_geotiff(&tile, srid)
+            .with_context(|| format!("when converting {}", path.display()))?;
+        let tile_code = i64::try_from(tile.header.tile_id).context("tile code exceeds i64")?;
+
+        let sql = build_insert_sql(
+            table_name,
+            &build_raster_expr(
+                srid,
+                tile.header.dimensions.bands,
+                tile.header.no_data.is_some(),
+            ),
+        );
+        match tile.header.no_data {
+            Some(no_data) => {
+                transaction
+                    .execute(&sql, &[&tile_code, &geotiff, &no_data])
+                    .await
+            }
+            None => transaction.execute(&sql, &[&tile_code, &geotiff]).await,
+        }
+        .with_context(|| format!("when importing {}", path.display()))?;
+        pb.inc(1);
+    }
+    transaction.commit().await?;
+
+    pb.finish_with_message(format!(
+        "Raster import completed ({} tiles)",
+        tile_files.len()
+    ));
+    println!("Raster table: {} (SRID {})", table_name, srid);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh_data_tile::{CompressionMode, MeshKind, TileDimensions, TileEncodeInput, encode_tile};
+
+    #[test]
+    fn wraps_tile_payload_in_geotiff() {
+        let values: Vec<u8> = [1i32, 2, 3, 4, 5, 6, 7, 8]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let encoded = encode_tile(TileEncodeInput {
+            tile_id: 5339,
+            mesh_kind: MeshKind::JisX0410,
+            dtype: DType::Int32,
+            endianness: Endianness::Little,
+            compression: CompressionMode::None,
+            dimensions: TileDimensions {
+                rows: 2,
+                cols: 2,
+                bands: 2,
+            },
+            no_data: Some(f64::from(i32::MIN)),
+            payload: &values,
+        })
+        .unwrap();
+        let tile = decode_tile_minimal(&encoded.bytes).unwrap();
+        let tiff = tile_to_geotiff(&tile, 6668).unwrap();
+
+        assert_eq!(&tiff[..4], b"II\x2a\x00");
+        assert!(tiff.ends_with(&values));
+        let entry_count = u16::from_le_bytes([tiff[8], tiff[9]]);
+        let tags: Vec<u16> = (0..usize::from(entry_count))
+            .map(|i| u16::from_le_bytes([tiff[10 + i * 12], tiff[11 + i * 12]]))
+            .collect();
+        assert!(tags.windows(2).all(|w| w[0] < w[1]), "tags must be sorted");
+        let strip_entry = 10 + tags.iter().position(|t| *t == 273).unwrap() * 12;
+        let strip_offset =
+            u32::from_le_bytes(tiff[strip_entry + 8..strip_entry + 12].try_into().unwrap());
+        assert_eq!(strip_offset as usize, tiff.len() - values.len());
+    }
+
+    #[test]
+    fn sets_no_data_on_every_band() {
+        let expr = build_raster_expr(6668, 2, true);
+        assert_eq!(
+            expr,
+            "ST_SetBandNoDataValue(ST_SetBandNoDataValue(ST_FromGDALRaster($2, 6668), 1, $3), 2, $3)"
+        );
+        assert_eq!(
+            build_raster_expr(6668, 2, false),
+            "ST_FromGDALRaster($2, 6668)"
+        );
+    }
+}
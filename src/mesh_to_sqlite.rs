@@ -0,0 +1,210 @@
+use crate::{
+    download,
+    error::JpEstatError,
+    list_cached::human_size,
+    mesh::{self, get_matching_mesh_stats, infer_column_type, read_columns},
+    shiftjis_csv::open_shiftjis_csv,
+};
+use anyhow::{Context, Result, anyhow, bail};
+use rusqlite::{Connection, params_from_iter, types::Value};
+use std::path::{Path, PathBuf};
+
+/// SQLite storage class for the PostgreSQL type `mesh` would create. `GASSAN` (`BIGINT[]`)
+/// is kept as the `;`-separated text of the CSV.
+fn sqlite_type(postgres_type: &str) -> &'static str {
+    match postgres_type {
+        "BIGINT[]" => "TEXT",
+        _ => "INTEGER",
+    }
+}
+
+fn create_table_sql(table_name: &str, columns: &[String]) -> String {
+    let defs: Vec<String> = columns
+        .iter()
+        .map(|col| format!("\"{}\" {}", col, sqlite_type(infer_column_type(col))))
+        .collect();
+    format!(
+        "CREATE TABLE IF NOT EXISTS \"{table}\" ({defs});\n\
+         CREATE UNIQUE INDEX IF NOT EXISTS \"{table}_key_code_idx\" ON \"{table}\" (\"KEY_CODE\");",
+        table = table_name,
+        defs = defs.join(", ")
+    )
+}
+
+fn insert_sql(table_name: &str, columns: &[String]) -> String {
+    format!(
+        "INSERT OR REPLACE INTO \"{}\" ({}) VALUES ({})",
+        table_name,
+        columns
+            .iter()
+            .map(|c| format!("\"{}\"", c))
+            .collect::<Vec<_>>()
+            .join(", "),
+        (1..=columns.len())
+            .map(|i| format!("?{}", i))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// `*` (confidential) and blank cells become NULL, as in the PostgreSQL import.
+fn sqlite_value(column: &str, value: &str) -> Result<Value> {
+    let value = value.trim();
+    if value.is_empty() || value == "*" {
+        return Ok(Value::Null);
+    }
+    if column == "GASSAN" {
+        return Ok(Value::Text(value.to_string()));
+    }
+    let parsed: i64 = value
+        .parse()
+        .with_context(|| format!("invalid {} value {:?}", column, value))?;
+    Ok(Value::Integer(parsed))
+}
+
+/// Creates `table_name` in the SQLite database at `output` and inserts every row of `files`
+/// in one transaction. Returns the number of rows inserted.
+fn write_sqlite(output: &Path, table_name: &str, files: &[PathBuf]) -> Result<u64> {
+    let first = files
+        .first()
+        .ok_or_else(|| anyhow!("No files found after download/extraction"))?;
+    let columns = read_columns(first)?;
+    let mut conn =
+        Connection::open(output).with_context(|| format!("when opening {}", output.display()))?;
+    conn.execute_batch(&create_table_sql(table_name, &columns))?;
+
+    let tx = conn.transaction()?;
+    let mut rows = 0u64;
+    {
+        let mut insert = tx.prepare(&insert_sql(table_name, &columns))?;
+        for file in files {
+            if read_columns(file)? != columns {
+                return Err(JpEstatError::CsvMismatch { path: file.clone() }.into());
+            }
+            let mut rdr = open_shiftjis_csv(file)?;
+            for record in rdr.records().skip(2) {
+                let record = record.with_context(|| format!("when reading {}", file.display()))?;
+                let values = columns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, col)| sqlite_value(col, record.get(i).unwrap_or("")))
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("in {}", file.display()))?;
+                insert.execute(params_from_iter(values))?;
+                rows += 1;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(rows)
+}
+
+/// Imports a mesh survey into a table of a local SQLite database, for users without
+/// PostgreSQL. The table has the name `mesh` would give it; rows of a mesh code that is
+/// already there are replaced.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_mesh_to_sqlite(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    table_prefix: &str,
+    level: u8,
+    year: u16,
+    survey: &str,
+    output: &Path,
+    cleanup: bool,
+) -> Result<()> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+    let mut downloaded_items =
+        mesh::download_mesh_csvs(http_client, tmp_dir, mesh_stats, None).await?;
+    if downloaded_items.is_empty() {
+        bail!("No files found after download/extraction");
+    }
+    downloaded_items.sort_by_key(|item| item.metadata.0);
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let table_name = mesh::table_name(table_prefix, mesh_stats);
+    let files: Vec<PathBuf> = downloaded_items
+        .iter()
+        .map(|item| item.extracted_path.clone())
+        .collect();
+    let rows = {
+        let output = output.to_path_buf();
+        let table_name = table_name.clone();
+        tokio::task::spawn_blocking(move || write_sqlite(&output, &table_name, &files)).await??
+    };
+    if cleanup {
+        for item in &downloaded_items {
+            download::cleanup_extracted(item)?;
+        }
+    }
+
+    let size = tokio::fs::metadata(output).await?.len();
+    println!(
+        "Imported {} rows into {} in {} ({})",
+        rows,
+        table_name,
+        output.display(),
+        human_size(size)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::SHIFT_JIS;
+
+    #[test]
+    fn imports_csvs_and_replaces_rows_by_key_code() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let csv = dir.join("tblT001140S5339.txt");
+        let (bytes, _, _) = SHIFT_JIS.encode(
+            "KEY_CODE,HTKSYORI,HTKSAKI,GASSAN,T001140001\r\n\
+             ,,,,人口（総数）\r\n\
+             53394611,0,,,120\r\n\
+             53394612,2,53394611,53394611;53394612,*\r\n",
+        );
+        std::fs::write(&csv, bytes).unwrap();
+        let output = dir.join("mesh.sqlite");
+        let files = vec![csv];
+
+        assert_eq!(write_sqlite(&output, "m", &files).unwrap(), 2);
+        // A second run replaces the rows instead of duplicating them.
+        assert_eq!(write_sqlite(&output, "m", &files).unwrap(), 2);
+
+        let conn = Connection::open(&output).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM m", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        let (gassan, population): (Option<String>, Option<i64>) = conn
+            .query_row(
+                "SELECT GASSAN, \"人口（総数）\" FROM m WHERE KEY_CODE = 53394612",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(gassan.as_deref(), Some("53394611;53394612"));
+        assert_eq!(population, None);
+        let total: i64 = conn
+            .query_row(
+                "SELECT \"人口（総数）\" FROM m WHERE KEY_CODE = 53394611",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(total, 120);
+
+        drop(conn);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{
+    download,
+    error::JpEstatError,
+    gdal,
+    mesh::{self, get_matching_mesh_stats},
+    mesh_export::write_csv_features,
+};
+use anyhow::{Context, Result, anyhow};
+use geojson::FeatureWriter;
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// Writes every cell of a mesh survey as a polygon feature of one GeoPackage layer.
+///
+/// The CSVs are first combined into a GeoJSON file in `tmp_dir` (numbers stay numbers, `*`
+/// becomes null, as in `mesh-export`), which `ogr2ogr -f GPKG` then copies into `output`
+/// with the survey datum as the layer's SRS.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_mesh_to_vector(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    table_prefix: &str,
+    level: u8,
+    year: u16,
+    survey: &str,
+    output: &Path,
+    cleanup: bool,
+) -> Result<()> {
+    let mesh_stats =
+        get_matching_mesh_stats(level, year, survey).ok_or_else(|| JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        })?;
+    // Fail before downloading anything when GDAL is missing.
+    let gdal_version = gdal::check_ogr2ogr_available().await?;
+    println!("Using {}", gdal_version);
+
+    let mut downloaded_items =
+        mesh::download_mesh_csvs(http_client, tmp_dir, mesh_stats, None).await?;
+    if downloaded_items.is_empty() {
+        return Err(anyhow!("No files found after download/extraction"));
+    }
+    downloaded_items.sort_by_key(|item| item.metadata.0);
+
+    let layer_name = mesh::table_name(table_prefix, mesh_stats);
+    let geojson_path = tmp_dir.join(format!("{}.geojson", layer_name));
+    let (features, downloaded_items) = {
+        let geojson_path = geojson_path.clone();
+        let items = downloaded_items;
+        tokio::task::spawn_blocking(move || {
+            let file = File::create(&geojson_path)
+                .with_context(|| format!("when creating {}", geojson_path.display()))?;
+            let mut writer = FeatureWriter::from_writer(BufWriter::new(file));
+            let mut features = 0usize;
+            for item in &items {
+                features += write_csv_features(&item.extracted_path, &mut writer)
+                    .with_context(|| format!("when reading {}", item.extracted_path.display()))?;
+            }
+            writer.finish()?;
+            Ok::<_, anyhow::Error>((features, items))
+        })
+        .await??
+    };
+
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let result = gdal::write_gpkg(&geojson_path, output, &layer_name, mesh_stats.datum)
+        .await
+        .with_context(|| format!("when writing {}", output.display()));
+    tokio::fs::remove_file(&geojson_path).await?;
+    result?;
+
+    if cleanup {
+        for item in &downloaded_items {
+            download::cleanup_extracted(item)?;
+        }
+    }
+    println!(
+        "Wrote {} features to layer {} of {} (EPSG:{})",
+        features,
+        layer_name,
+        output.display(),
+        mesh_stats.datum
+    );
+    Ok(())
+}
@@ -0,0 +1,46 @@
+//! Parsers for untrusted mesh CSV content. Kept free of crate dependencies so the
+//! fuzz targets in `fuzz/` can include this file directly.
+
+use anyhow::{Context, Result, bail};
+
+/// Sentinel written to tiles for missing values (`*` or blank cells).
+pub(crate) const NO_DATA_I32: i32 = i32::MIN;
+
+/// Sentinel for missing values in `uint16` tiles. Real values must stay below it.
+pub(crate) const NO_DATA_U16: u16 = u16::MAX;
+
+pub(crate) fn parse_stat_value(value: &str) -> Result<i32> {
+    let v = value.trim();
+    if v.is_empty() || v == "*" {
+        return Ok(NO_DATA_I32);
+    }
+
+    let parsed = v
+        .parse::<i64>()
+        .with_context(|| format!("invalid integer value: {}", v))?;
+    if parsed < i64::from(i32::MIN) || parsed > i64::from(i32::MAX) {
+        bail!("value out of i32 range: {}", parsed);
+    }
+
+    Ok(parsed as i32)
+}
+
+pub(crate) fn parse_stat_value_u16(value: &str) -> Result<u16> {
+    let v = value.trim();
+    if v.is_empty() || v == "*" {
+        return Ok(NO_DATA_U16);
+    }
+
+    let parsed = v
+        .parse::<i64>()
+        .with_context(|| format!("invalid integer value: {}", v))?;
+    if parsed < 0 || parsed >= i64::from(NO_DATA_U16) {
+        bail!(
+            "value out of uint16 range (0..={}): {}; use --dtype int32",
+            NO_DATA_U16 - 1,
+            parsed
+        );
+    }
+
+    Ok(parsed as u16)
+}
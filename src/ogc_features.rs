@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value, json};
+use std::path::{Path, PathBuf};
+
+/// Maximum number of features written to a single items page.
+pub const PAGE_SIZE: usize = 1000;
+
+/// Writes a static, paged OGC API Features collection.
+///
+/// The layout mirrors `/collections/{id}` and `/collections/{id}/items` so the directory
+/// can be served as-is by a static file host:
+///
+/// * `collection.json` - collection description
+/// * `items.json`, `items-2.json`, ... - `FeatureCollection` pages linked with `next`/`prev`
+pub struct OgcFeaturesWriter {
+    output_dir: PathBuf,
+    collection_id: String,
+    title: String,
+    page_size: usize,
+    buffer: Vec<Value>,
+    pages_written: usize,
+    features_written: usize,
+}
+
+fn page_filename(page: usize) -> String {
+    if page == 1 {
+        "items.json".to_string()
+    } else {
+        format!("items-{}.json", page)
+    }
+}
+
+impl OgcFeaturesWriter {
+    pub async fn create(output_dir: &Path, collection_id: &str, title: &str) -> Result<Self> {
+        tokio::fs::create_dir_all(output_dir)
+            .await
+            .with_context(|| format!("failed to create {}", output_dir.display()))?;
+        Ok(Self {
+            output_dir: output_dir.to_path_buf(),
+            collection_id: collection_id.to_string(),
+            title: title.to_string(),
+            page_size: PAGE_SIZE,
+            buffer: Vec::with_capacity(PAGE_SIZE),
+            pages_written: 0,
+            features_written: 0,
+        })
+    }
+
+    /// Adds a feature. A full page is only flushed once the next feature arrives,
+    /// so the final page never carries a dangling `next` link.
+    pub async fn push(
+        &mut self,
+        id: u64,
+        geometry: Value,
+        properties: Map<String, Value>,
+    ) -> Result<()> {
+        if self.buffer.len() == self.page_size {
+            self.flush_page(true).await?;
+        }
+        self.buffer.push(json!({
+            "type": "Feature",
+            "id": id,
+            "geometry": geometry,
+            "properties": properties,
+        }));
+        Ok(())
+    }
+
+    /// Flushes the last page and writes `collection.json`. Returns the number of features written.
+    pub async fn finish(mut self) -> Result<usize> {
+        if !self.buffer.is_empty() || self.pages_written == 0 {
+            self.flush_page(false).await?;
+        }
+
+        let collection = json!({
+            "id": self.collection_id,
+            "title": self.title,
+            "itemType": "feature",
+            "crs": ["http://www.opengis.net/def/crs/OGC/1.3/CRS84"],
+            "links": [
+                {
+                    "href": "collection.json",
+                    "rel": "self",
+                    "type": "application/json",
+                    "title": "This collection",
+                },
+                {
+                    "href": page_filename(1),
+                    "rel": "items",
+                    "type": "application/geo+json",
+                    "title": "Items",
+                },
+            ],
+        });
+        self.write_json("collection.json", &collection).await?;
+        Ok(self.features_written)
+    }
+
+    async fn flush_page(&mut self, has_next: bool) -> Result<()> {
+        let page = self.pages_written + 1;
+        let mut links = vec![json!({
+            "href": page_filename(page),
+            "rel": "self",
+            "type": "application/geo+json",
+        })];
+        if page > 1 {
+            links.push(json!({
+                "href": page_filename(page - 1),
+                "rel": "prev",
+                "type": "application/geo+json",
+            }));
+        }
+        if has_next {
+            links.push(json!({
+                "href": page_filename(page + 1),
+                "rel": "next",
+                "type": "application/geo+json",
+            }));
+        }
+        links.push(json!({
+            "href": "collection.json",
+            "rel": "collection",
+            "type": "application/json",
+        }));
+
+        let features = std::mem::take(&mut self.buffer);
+        let number_returned = features.len();
+        let body = json!({
+            "type": "FeatureCollection",
+            "numberReturned": number_returned,
+            "links": links,
+            "features": features,
+        });
+        self.write_json(&page_filename(page), &body).await?;
+
+        self.pages_written = page;
+        self.features_written += number_returned;
+        Ok(())
+    }
+
+    async fn write_json(&self, filename: &str, value: &Value) -> Result<()> {
+        let path = self.output_dir.join(filename);
+        let body = serde_json::to_vec(value)?;
+        tokio::fs::write(&path, body)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_json(path: PathBuf) -> Value {
+        serde_json::from_slice(&tokio::fs::read(path).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pages_features_with_next_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let mut writer = OgcFeaturesWriter::create(dir, "test", "Test")
+            .await
+            .unwrap();
+        writer.page_size = 2;
+        for id in 0..5 {
+            writer
+                .push(
+                    id,
+                    json!({"type": "Point", "coordinates": [0, 0]}),
+                    Map::new(),
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(writer.finish().await.unwrap(), 5);
+
+        let first = read_json(dir.join("items.json")).await;
+        assert_eq!(first["numberReturned"], 2);
+        assert!(
+            first["links"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|link| link["rel"] == "next" && link["href"] == "items-2.json")
+        );
+
+        let last = read_json(dir.join("items-3.json")).await;
+        assert_eq!(last["numberReturned"], 1);
+        assert!(
+            !last["links"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|link| link["rel"] == "next")
+        );
+        assert!(dir.join("collection.json").exists());
+    }
+}
@@ -0,0 +1,559 @@
+use crate::{
+    MeshImportConfig, MeshTileConfig,
+    download::{self, DownloadedItem},
+    error::JpEstatError,
+    geo_filter::GeoFilter,
+    mesh,
+    mesh_tile::{self, AggFn, TileCompression, TileDType},
+};
+use anyhow::{Context, Result, bail};
+use futures::future;
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, num::NonZeroUsize, path::Path, path::PathBuf};
+use url::Url;
+
+#[derive(Debug, Deserialize)]
+struct PipelineFile {
+    #[serde(default, rename = "step")]
+    steps: Vec<PipelineStep>,
+}
+
+/// One `[[step]]` of a `--pipeline` file. A step runs once every step in `depends_on` has
+/// finished; steps whose dependencies are done run concurrently, except `download` steps
+/// (see [`batches`]).
+#[derive(Debug, Deserialize)]
+struct PipelineStep {
+    id: String,
+    #[serde(default)]
+    depends_on: Vec<String>,
+    #[serde(flatten)]
+    action: StepAction,
+}
+
+/// `mesh` and `mesh-tile` steps take the survey, the geo filter and the extracted CSVs
+/// from the `download` step they depend on, so each survey is downloaded once.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum StepAction {
+    Download {
+        level: u8,
+        year: u16,
+        survey: String,
+        geo_filter: Option<String>,
+    },
+    Mesh {
+        postgres_url: String,
+        #[serde(default)]
+        row_mismatch_threshold: u64,
+        #[serde(default)]
+        allow_row_mismatch: bool,
+        before_import_sql: Option<PathBuf>,
+        after_import_sql: Option<PathBuf>,
+        column_types: Option<PathBuf>,
+        #[serde(default)]
+        add_centroid_column: bool,
+        srid_override: Option<u32>,
+        #[serde(default)]
+        comment_table: bool,
+        #[serde(default = "default_insert_batch_size")]
+        insert_batch_size: usize,
+        temp_postgres_url: Option<String>,
+        #[serde(default)]
+        drop_old_table: bool,
+        lockfile: Option<PathBuf>,
+    },
+    MeshTile {
+        output_dir: PathBuf,
+        tile_level: Option<u8>,
+        bands: Option<Vec<String>>,
+        column_alias_file: Option<PathBuf>,
+        min_nonnull_fraction: Option<f64>,
+        ogc_features_output: Option<PathBuf>,
+        #[serde(default = "default_compression", deserialize_with = "value_enum")]
+        compression: TileCompression,
+        #[serde(default = "default_dtype", deserialize_with = "value_enum")]
+        dtype: TileDType,
+        #[serde(default)]
+        shard_dir: bool,
+        #[serde(default)]
+        split_by_l1: bool,
+        encode_threads: Option<NonZeroUsize>,
+        #[serde(default)]
+        pyramid: bool,
+        #[serde(default = "default_aggregate_fn", deserialize_with = "value_enum")]
+        aggregate_fn: AggFn,
+        #[serde(default = "default_overwrite")]
+        overwrite: bool,
+        #[serde(default = "default_max_tile_count")]
+        max_tile_count: usize,
+    },
+}
+
+impl StepAction {
+    fn command(&self) -> &'static str {
+        match self {
+            StepAction::Download { .. } => "download",
+            StepAction::Mesh { .. } => "mesh",
+            StepAction::MeshTile { .. } => "mesh-tile",
+        }
+    }
+}
+
+fn default_insert_batch_size() -> usize {
+    mesh::DEFAULT_INSERT_BATCH_SIZE
+}
+
+fn default_compression() -> TileCompression {
+    TileCompression::DeflateRaw
+}
+
+fn default_dtype() -> TileDType {
+    TileDType::Int32
+}
+
+fn default_aggregate_fn() -> AggFn {
+    AggFn::Sum
+}
+
+fn default_overwrite() -> bool {
+    true
+}
+
+fn default_max_tile_count() -> usize {
+    mesh_tile::DEFAULT_MAX_TILE_COUNT
+}
+
+/// Reads a value with the same spelling as the CLI flag (`deflate-raw`, `uint16`, ...).
+fn value_enum<'de, D, E>(deserializer: D) -> Result<E, D::Error>
+where
+    D: Deserializer<'de>,
+    E: clap::ValueEnum,
+{
+    let value = String::deserialize(deserializer)?;
+    E::from_str(&value, false).map_err(serde::de::Error::custom)
+}
+
+fn parse_pipeline(text: &str) -> Result<Vec<PipelineStep>> {
+    let file: PipelineFile = toml::from_str(text)?;
+    if file.steps.is_empty() {
+        bail!("the pipeline has no [[step]] entries");
+    }
+    Ok(file.steps)
+}
+
+/// Groups steps into stages: every step's dependencies are in an earlier stage.
+fn schedule(steps: &[PipelineStep]) -> Result<Vec<Vec<usize>>> {
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (i, step) in steps.iter().enumerate() {
+        if index.insert(step.id.as_str(), i).is_some() {
+            bail!("step id \"{}\" is used more than once", step.id);
+        }
+    }
+    let deps: Vec<Vec<usize>> = steps
+        .iter()
+        .map(|step| {
+            step.depends_on
+                .iter()
+                .map(|dep| {
+                    index.get(dep.as_str()).copied().ok_or_else(|| {
+                        anyhow::anyhow!("step \"{}\" depends on unknown step \"{}\"", step.id, dep)
+                    })
+                })
+                .collect()
+        })
+        .collect::<Result<_>>()?;
+
+    let mut done = vec![false; steps.len()];
+    let mut stages = Vec::new();
+    while done.iter().any(|d| !d) {
+        let stage: Vec<usize> = (0..steps.len())
+            .filter(|&i| !done[i] && deps[i].iter().all(|&dep| done[dep]))
+            .collect();
+        if stage.is_empty() {
+            let remaining: Vec<&str> = (0..steps.len())
+                .filter(|&i| !done[i])
+                .map(|i| steps[i].id.as_str())
+                .collect();
+            bail!(
+                "the dependencies of these steps form a cycle: {}",
+                remaining.join(", ")
+            );
+        }
+        for &i in &stage {
+            done[i] = true;
+        }
+        stages.push(stage);
+    }
+    Ok(stages)
+}
+
+/// Splits each stage into batches run one after another, the steps of a batch concurrently.
+/// Every `download` step is a batch of its own: downloads read `downloads_manifest.json` in
+/// `tmp_dir` at the start and write it back at the end, so two at once would drop each
+/// other's entries.
+fn batches(steps: &[PipelineStep], stages: Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+    let mut batches = Vec::new();
+    for stage in stages {
+        let (downloads, others): (Vec<usize>, Vec<usize>) = stage
+            .into_iter()
+            .partition(|&i| matches!(steps[i].action, StepAction::Download { .. }));
+        batches.extend(downloads.into_iter().map(|i| vec![i]));
+        if !others.is_empty() {
+            batches.push(others);
+        }
+    }
+    batches
+}
+
+/// The `download` step each `mesh` / `mesh-tile` step reads from, found through its
+/// dependencies (direct or not). There must be exactly one. Call after [`schedule`], which
+/// rejects unknown step ids.
+fn download_sources(steps: &[PipelineStep]) -> Result<HashMap<usize, usize>> {
+    let index: HashMap<&str, usize> = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| (step.id.as_str(), i))
+        .collect();
+    let mut sources = HashMap::new();
+    for (i, step) in steps.iter().enumerate() {
+        if matches!(step.action, StepAction::Download { .. }) {
+            continue;
+        }
+        let mut seen = vec![false; steps.len()];
+        let mut stack: Vec<usize> = step
+            .depends_on
+            .iter()
+            .map(|dep| index[dep.as_str()])
+            .collect();
+        let mut downloads = Vec::new();
+        while let Some(j) = stack.pop() {
+            if std::mem::replace(&mut seen[j], true) {
+                continue;
+            }
+            if matches!(steps[j].action, StepAction::Download { .. }) {
+                downloads.push(j);
+            } else {
+                stack.extend(steps[j].depends_on.iter().map(|dep| index[dep.as_str()]));
+            }
+        }
+        match downloads.as_slice() {
+            [source] => {
+                sources.insert(i, *source);
+            }
+            _ => bail!(
+                "step \"{}\" ({}) must depend on exactly one download step, found {}",
+                step.id,
+                step.action.command(),
+                downloads.len()
+            ),
+        }
+    }
+    Ok(sources)
+}
+
+/// The extracted CSVs of a `download` step, shared by the steps that depend on it.
+struct Dataset {
+    level: u8,
+    year: u16,
+    survey: String,
+    geo_filter: Option<GeoFilter>,
+    items: Vec<DownloadedItem<(u64, Url)>>,
+}
+
+async fn run_step(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    table_prefix: &str,
+    step: &PipelineStep,
+    source: Option<&Dataset>,
+) -> Result<Option<Dataset>> {
+    println!("Pipeline: starting {} ({})", step.id, step.action.command());
+    let dataset = match (&step.action, source) {
+        (
+            StepAction::Download {
+                level,
+                year,
+                survey,
+                geo_filter,
+            },
+            _,
+        ) => {
+            let mesh_stats =
+                mesh::get_matching_mesh_stats(*level, *year, survey).ok_or_else(|| {
+                    JpEstatError::NotFound {
+                        survey: survey.clone(),
+                        level: *level,
+                        year: *year,
+                    }
+                })?;
+            let geo_filter = geo_filter.as_deref().map(GeoFilter::parse).transpose()?;
+            let items =
+                mesh::download_mesh_csvs(http_client, tmp_dir, mesh_stats, geo_filter.as_ref())
+                    .await?;
+            if items.is_empty() {
+                bail!("No files found after download/extraction");
+            }
+            Some(Dataset {
+                level: *level,
+                year: *year,
+                survey: survey.clone(),
+                geo_filter,
+                items,
+            })
+        }
+        (
+            StepAction::Mesh {
+                postgres_url,
+                row_mismatch_threshold,
+                allow_row_mismatch,
+                before_import_sql,
+                after_import_sql,
+                column_types,
+                add_centroid_column,
+                srid_override,
+                comment_table,
+                insert_batch_size,
+                temp_postgres_url,
+                drop_old_table,
+                lockfile,
+            },
+            Some(source),
+        ) => {
+            let config = MeshImportConfig::new(
+                postgres_url.as_str(),
+                source.level,
+                source.year,
+                source.survey.as_str(),
+            )
+            .with_tmp_dir(tmp_dir)
+            .with_table_prefix(table_prefix)
+            .with_row_mismatch_threshold(*row_mismatch_threshold)
+            .with_allow_row_mismatch(*allow_row_mismatch)
+            .with_before_import_sql(before_import_sql.clone())
+            .with_after_import_sql(after_import_sql.clone())
+            .with_geo_filter(source.geo_filter.clone())
+            .with_column_types(column_types.clone())
+            .with_centroid_column(*add_centroid_column)
+            .with_srid_override(*srid_override)
+            .with_comment_table(*comment_table)
+            .with_insert_batch_size(*insert_batch_size)
+            .with_temp_postgres_url(temp_postgres_url.clone())
+            .with_drop_old_table(*drop_old_table)
+            .with_lockfile(lockfile.clone());
+            let report =
+                mesh::process_mesh(http_client, &config, Some(source.items.clone())).await?;
+            println!(
+                "Pipeline: {} imported {} rows into {}",
+                step.id, report.imported_rows, report.table_name
+            );
+            None
+        }
+        (
+            StepAction::MeshTile {
+                output_dir,
+                tile_level,
+                bands,
+                column_alias_file,
+                min_nonnull_fraction,
+                ogc_features_output,
+                compression,
+                dtype,
+                shard_dir,
+                split_by_l1,
+                encode_threads,
+                pyramid,
+                aggregate_fn,
+                overwrite,
+                max_tile_count,
+            },
+            Some(source),
+        ) => {
+            let config = MeshTileConfig::new(
+                source.level,
+                source.year,
+                source.survey.as_str(),
+                output_dir,
+            )
+            .with_tmp_dir(tmp_dir)
+            .with_tile_level(*tile_level)
+            .with_bands(bands.clone())
+            .with_column_alias_file(column_alias_file.clone())
+            .with_min_nonnull_fraction(*min_nonnull_fraction)
+            .with_ogc_features_output(ogc_features_output.clone())
+            .with_compression(*compression)
+            .with_dtype(*dtype)
+            .with_geo_filter(source.geo_filter.clone())
+            .with_shard_dir(*shard_dir)
+            .with_overwrite(*overwrite)
+            .with_encode_threads(*encode_threads)
+            .with_pyramid(*pyramid)
+            .with_aggregate_fn(*aggregate_fn)
+            .with_split_by_l1(*split_by_l1)
+            .with_max_tile_count(*max_tile_count);
+            let report =
+                mesh_tile::process_mesh_tile(http_client, &config, Some(source.items.clone()))
+                    .await?;
+            println!(
+                "Pipeline: {} wrote {} tiles to {}",
+                step.id,
+                report.tiles_written,
+                report.output_dir.display()
+            );
+            None
+        }
+        (_, None) => unreachable!("download_sources covers every non-download step"),
+    };
+    Ok(dataset)
+}
+
+/// Runs the steps of a `--pipeline` TOML file in dependency order. Each `download` step
+/// fetches its survey once; the `mesh` and `mesh-tile` steps after it read the same
+/// extracted CSVs, concurrently when they do not depend on each other.
+pub async fn process_pipeline(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    table_prefix: &str,
+    pipeline: &Path,
+    cleanup: bool,
+) -> Result<()> {
+    let text = tokio::fs::read_to_string(pipeline)
+        .await
+        .with_context(|| format!("when reading {}", pipeline.display()))?;
+    let steps =
+        parse_pipeline(&text).with_context(|| format!("when parsing {}", pipeline.display()))?;
+    let stages = schedule(&steps)?;
+    let sources = download_sources(&steps)?;
+    println!("Pipeline: {} steps in {} stages", steps.len(), stages.len());
+
+    let mut datasets: HashMap<usize, Dataset> = HashMap::new();
+    for batch in batches(&steps, stages) {
+        let results = future::try_join_all(batch.iter().map(|&i| {
+            let step = &steps[i];
+            let source = sources.get(&i).and_then(|source| datasets.get(source));
+            async move {
+                run_step(http_client, tmp_dir, table_prefix, step, source)
+                    .await
+                    .with_context(|| format!("in pipeline step \"{}\"", step.id))
+            }
+        }))
+        .await?;
+        for (i, dataset) in batch.into_iter().zip(results) {
+            if let Some(dataset) = dataset {
+                datasets.insert(i, dataset);
+            }
+        }
+    }
+
+    // The steps share the extracted files, so they are only removed at the end.
+    if cleanup {
+        for dataset in datasets.values() {
+            for item in &dataset.items {
+                download::cleanup_extracted(item)?;
+            }
+        }
+    }
+    println!("Pipeline: all {} steps finished", steps.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIPELINE: &str = r#"
+[[step]]
+id = "download"
+command = "download"
+level = 3
+year = 2020
+survey = "人口及び世帯"
+
+[[step]]
+id = "import"
+command = "mesh"
+depends_on = ["download"]
+postgres_url = "host=127.0.0.1 dbname=jp_estat"
+
+[[step]]
+id = "tiles"
+command = "mesh-tile"
+depends_on = ["download"]
+output_dir = "./tiles"
+dtype = "uint16"
+
+[[step]]
+id = "pyramid"
+command = "mesh-tile"
+depends_on = ["tiles"]
+output_dir = "./pyramid"
+pyramid = true
+aggregate_fn = "mean"
+"#;
+
+    #[test]
+    fn parses_steps_and_schedules_them_by_dependency() {
+        let steps = parse_pipeline(PIPELINE).unwrap();
+        assert_eq!(steps.len(), 4);
+        let StepAction::MeshTile {
+            dtype, compression, ..
+        } = &steps[2].action
+        else {
+            panic!("{:?}", steps[2].action);
+        };
+        assert_eq!(
+            (*dtype, *compression),
+            (TileDType::Uint16, TileCompression::DeflateRaw)
+        );
+        assert_eq!(
+            schedule(&steps).unwrap(),
+            vec![vec![0], vec![1, 2], vec![3]]
+        );
+        // The pyramid step reads the download through the tiles step.
+        let sources = download_sources(&steps).unwrap();
+        assert_eq!(
+            (sources[&1], sources[&2], sources[&3], sources.get(&0)),
+            (0, 0, 0, None)
+        );
+    }
+
+    #[test]
+    fn runs_downloads_of_one_stage_one_at_a_time() {
+        let two_surveys = PIPELINE.replacen(
+            "[[step]]\nid = \"import\"",
+            "[[step]]\nid = \"download-2015\"\ncommand = \"download\"\nlevel = 3\nyear = 2015\nsurvey = \"人口及び世帯\"\n\n[[step]]\nid = \"import\"",
+            1,
+        );
+        let steps = parse_pipeline(&two_surveys).unwrap();
+        let stages = schedule(&steps).unwrap();
+        assert_eq!(stages, vec![vec![0, 1], vec![2, 3], vec![4]]);
+        assert_eq!(
+            batches(&steps, stages),
+            vec![vec![0], vec![1], vec![2, 3], vec![4]]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_graphs() {
+        let cycle = PIPELINE.replacen(
+            "command = \"download\"\n",
+            "command = \"download\"\ndepends_on = [\"pyramid\"]\n",
+            1,
+        );
+        let err = schedule(&parse_pipeline(&cycle).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("cycle"), "{}", err);
+
+        let unknown = PIPELINE.replace(r#"depends_on = ["tiles"]"#, r#"depends_on = ["tile"]"#);
+        let err = schedule(&parse_pipeline(&unknown).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("unknown step \"tile\""), "{}", err);
+
+        let no_source = PIPELINE.replace(r#"depends_on = ["download"]"#, "depends_on = []");
+        let err = download_sources(&parse_pipeline(&no_source).unwrap()).unwrap_err();
+        assert!(
+            err.to_string().contains("exactly one download step"),
+            "{}",
+            err
+        );
+
+        assert!(parse_pipeline(&PIPELINE.replace("uint16", "uint8")).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::error::JpEstatError;
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use tokio_postgres::NoTls;
+
+/// Connects to PostgreSQL and drives the connection on a background task.
+pub async fn connect(postgres_url: &str) -> Result<tokio_postgres::Client> {
+    let (client, connection) = tokio_postgres::connect(postgres_url, NoTls)
+        .await
+        .map_err(JpEstatError::from)
+        .with_context(|| "when connecting to PostgreSQL")?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("DB error: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+/// `name` as a quoted PostgreSQL identifier, with embedded `"` doubled.
+pub fn pg_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// [`pg_ident`] of `name` folded to lower case. Mesh tables have always been created with
+/// unquoted names such as `jp_estat_mesh_2020_T001140_3`, which PostgreSQL stores in lower
+/// case; quoting the folded name keeps addressing the same tables.
+pub fn folded_ident(name: &str) -> String {
+    pg_ident(&name.to_lowercase())
+}
+
+/// Table `mesh --temp-postgres-url` imports into before it replaces `table_name`.
+pub fn temp_table_name(table_name: &str) -> String {
+    format!("{}_tmp", table_name)
+}
+
+/// Where `swap_in_table` keeps the replaced table unless it is dropped.
+pub fn old_table_name(table_name: &str) -> String {
+    format!("{}_old", table_name)
+}
+
+fn swap_statements(table_name: &str, drop_old_table: bool) -> String {
+    let tmp = folded_ident(&temp_table_name(table_name));
+    let old = folded_ident(&old_table_name(table_name));
+    let table_name = folded_ident(table_name);
+    let retire = if drop_old_table {
+        format!("DROP TABLE IF EXISTS {}", table_name)
+    } else {
+        format!("DROP TABLE IF EXISTS {old};\nALTER TABLE IF EXISTS {table_name} RENAME TO {old}")
+    };
+    format!("BEGIN;\n{retire};\nALTER TABLE {tmp} RENAME TO {table_name};\nCOMMIT;")
+}
+
+/// Replaces `table_name` with its fully imported `{table_name}_tmp` in one transaction, so
+/// queries see either the old or the new rows. The old table is kept as `{table_name}_old`
+/// (replacing an earlier one) unless `drop_old_table` is set.
+pub async fn swap_in_table(
+    client: &tokio_postgres::Client,
+    table_name: &str,
+    drop_old_table: bool,
+) -> Result<()> {
+    let tmp = temp_table_name(table_name);
+    let exists: bool = client
+        .query_one("SELECT to_regclass($1) IS NOT NULL", &[&tmp])
+        .await?
+        .get(0);
+    if !exists {
+        bail!("{} does not exist", tmp);
+    }
+    client
+        .batch_execute(&swap_statements(table_name, drop_old_table))
+        .await
+        .with_context(|| format!("when renaming {} to {}", tmp, table_name))?;
+    if drop_old_table {
+        println!("Replaced {} with {}", table_name, tmp);
+    } else {
+        println!(
+            "Replaced {} with {} (previous rows kept in {})",
+            table_name,
+            tmp,
+            old_table_name(table_name)
+        );
+    }
+    Ok(())
+}
+
+/// Reads a user-supplied SQL file. Called before any downloads so a bad path fails early.
+pub async fn read_sql_file(path: &Path) -> Result<String> {
+    tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("when reading SQL file {}", path.display()))
+}
+
+/// Executes a (possibly multi-statement) SQL hook.
+pub async fn run_sql_hook(client: &tokio_postgres::Client, label: &str, sql: &str) -> Result<()> {
+    client
+        .batch_execute(sql)
+        .await
+        .with_context(|| format!("when executing {} SQL", label))?;
+    println!("Executed {} SQL.", label);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swaps_the_temp_table_in_one_transaction() {
+        assert_eq!(
+            swap_statements("jp_estat_mesh_2020_T001140_3", true),
+            "BEGIN;\n\
+             DROP TABLE IF EXISTS \"jp_estat_mesh_2020_t001140_3\";\n\
+             ALTER TABLE \"jp_estat_mesh_2020_t001140_3_tmp\" RENAME TO \"jp_estat_mesh_2020_t001140_3\";\n\
+             COMMIT;"
+        );
+        assert_eq!(
+            swap_statements("t", false),
+            "BEGIN;\n\
+             DROP TABLE IF EXISTS \"t_old\";\n\
+             ALTER TABLE IF EXISTS \"t\" RENAME TO \"t_old\";\n\
+             ALTER TABLE \"t_tmp\" RENAME TO \"t\";\n\
+             COMMIT;"
+        );
+    }
+
+    #[test]
+    fn quotes_identifiers() {
+        assert_eq!(
+            pg_ident("jp_estat_areamap_2020"),
+            "\"jp_estat_areamap_2020\""
+        );
+        assert_eq!(
+            pg_ident("a\"; DROP TABLE b; --"),
+            "\"a\"\"; DROP TABLE b; --\""
+        );
+        assert_eq!(
+            folded_ident("jp_estat_mesh_2020_T001140_3"),
+            "\"jp_estat_mesh_2020_t001140_3\""
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Progress bars shared by every subcommand, drawn in the style selected with
+//! `--progress-style`.
+
+use anyhow::Result;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    borrow::Cow,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Appearance of the progress bars selected with `--progress-style`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum BarStyle {
+    /// Message, elapsed time, bar and item count.
+    Default,
+    /// Message, percentage and ETA.
+    Minimal,
+    /// Like `default`, with emoji for the bar fill.
+    Emoji,
+    /// No progress bars.
+    None,
+}
+
+impl BarStyle {
+    const ALL: [BarStyle; 4] = [
+        BarStyle::Default,
+        BarStyle::Minimal,
+        BarStyle::Emoji,
+        BarStyle::None,
+    ];
+
+    fn style(self) -> Result<ProgressStyle> {
+        Ok(match self {
+            BarStyle::Default | BarStyle::None => ProgressStyle::default_bar()
+                .template("{msg} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7}")?
+                .progress_chars("##-"),
+            BarStyle::Minimal => {
+                ProgressStyle::default_bar().template("{msg} {percent:>3}% (ETA {eta})")?
+            }
+            BarStyle::Emoji => ProgressStyle::default_bar()
+                .template("{msg} [{elapsed_precise}] {bar:20} {pos:>7}/{len:7}")?
+                .progress_chars("🟩🟨⬜"),
+        })
+    }
+}
+
+static BAR_STYLE: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the style of the bars created afterwards; set from `--progress-style`.
+pub fn set_bar_style(style: BarStyle) {
+    let index = BarStyle::ALL.iter().position(|s| *s == style).unwrap_or(0);
+    BAR_STYLE.store(index as u8, Ordering::Relaxed);
+}
+
+fn bar_style() -> BarStyle {
+    BarStyle::ALL[BAR_STYLE.load(Ordering::Relaxed) as usize]
+}
+
+fn styled_bar(
+    style: BarStyle,
+    len: u64,
+    message: impl Into<Cow<'static, str>>,
+) -> Result<ProgressBar> {
+    let pb = match style {
+        BarStyle::None => ProgressBar::hidden(),
+        _ => ProgressBar::new(len),
+    };
+    pb.set_length(len);
+    pb.set_style(style.style()?);
+    pb.set_message(message);
+    Ok(pb)
+}
+
+/// A progress bar over `len` items, labelled with `message`.
+pub(crate) fn new_bar(len: u64, message: impl Into<Cow<'static, str>>) -> Result<ProgressBar> {
+    styled_bar(bar_style(), len, message)
+}
+
+/// Container for bars drawn together, e.g. the download and extraction bars.
+pub(crate) fn new_multi() -> MultiProgress {
+    match bar_style() {
+        BarStyle::None => MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        _ => MultiProgress::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_every_style() {
+        for style in BarStyle::ALL {
+            let pb = styled_bar(style, 10, "Merging CSVs...").unwrap();
+            assert_eq!(pb.length(), Some(10));
+            assert_eq!(pb.message(), "Merging CSVs...");
+            if style == BarStyle::None {
+                assert!(pb.is_hidden());
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Optional upload of `mesh-tile` output to S3 (`--upload-s3`, cargo feature `s3`).
+//!
+//! The tiles are uploaded first and every `metadata.json` / `manifest.json` only after all of
+//! them succeeded, so a reader that finds the metadata can rely on its tiles being there.
+
+use anyhow::{Result, bail};
+use std::path::{Path, PathBuf};
+
+/// Default of `--s3-prefix`.
+pub const DEFAULT_S3_PREFIX: &str = "mesh-tiles/";
+
+/// Concurrent `PutObject` requests.
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+const UPLOAD_CONCURRENCY: usize = 20;
+
+/// Files that describe the tiles and are uploaded after them.
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+const METADATA_FILES: [&str; 2] = ["metadata.json", "manifest.json"];
+
+/// Where `--upload-s3` puts the tiles.
+#[derive(Debug, Clone)]
+pub struct S3Upload {
+    pub bucket: String,
+    /// Key prefix; a `/` is added when missing.
+    pub prefix: String,
+    /// Endpoint of an S3-compatible service such as MinIO (path-style addressing is used).
+    pub endpoint: Option<String>,
+}
+
+impl S3Upload {
+    pub fn new(bucket: impl Into<String>) -> Self {
+        Self {
+            bucket: bucket.into(),
+            prefix: DEFAULT_S3_PREFIX.to_string(),
+            endpoint: None,
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_endpoint(mut self, endpoint: Option<String>) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+}
+
+/// Fails before any work is done when this build cannot upload.
+pub fn ensure_available() -> Result<()> {
+    if cfg!(feature = "s3") {
+        Ok(())
+    } else {
+        bail!("--upload-s3 requires a build with `--features s3`")
+    }
+}
+
+/// Object key of `relative` (a path below the output directory) under `prefix`.
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+fn object_key(prefix: &str, relative: &Path) -> String {
+    let path = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    if prefix.is_empty() || prefix.ends_with('/') {
+        format!("{}{}", prefix, path)
+    } else {
+        format!("{}/{}", prefix, path)
+    }
+}
+
+/// Every file below `dir`, relative to it, split into (tiles and other data, metadata).
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+fn collect_files(dir: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path.strip_prefix(dir)?.to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    Ok(files.into_iter().partition(|path| {
+        !path
+            .file_name()
+            .is_some_and(|name| METADATA_FILES.iter().any(|m| name == *m))
+    }))
+}
+
+#[cfg(feature = "s3")]
+async fn put_all(
+    client: &aws_sdk_s3::Client,
+    upload: &S3Upload,
+    dir: &Path,
+    files: &[PathBuf],
+) -> Result<()> {
+    use anyhow::Context as _;
+    use aws_sdk_s3::primitives::ByteStream;
+    use futures::{StreamExt as _, stream};
+
+    let results = stream::iter(files)
+        .map(|relative| async move {
+            let key = object_key(&upload.prefix, relative);
+            let content_type = if relative.extension().is_some_and(|e| e == "json") {
+                "application/json"
+            } else {
+                "application/octet-stream"
+            };
+            let body = ByteStream::from_path(dir.join(relative))
+                .await
+                .with_context(|| format!("when reading {}", relative.display()))?;
+            client
+                .put_object()
+                .bucket(&upload.bucket)
+                .key(&key)
+                .content_type(content_type)
+                .body(body)
+                .send()
+                .await
+                .with_context(|| format!("when uploading s3://{}/{}", upload.bucket, key))?;
+            Ok(()) as Result<()>
+        })
+        .buffer_unordered(UPLOAD_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+    match results.into_iter().find_map(Result::err) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Uploads everything below `dir`. Credentials and region come from the usual AWS
+/// environment variables and profiles.
+#[cfg(feature = "s3")]
+pub async fn upload_dir(dir: &Path, upload: &S3Upload) -> Result<()> {
+    let (data, metadata) = collect_files(dir)?;
+    let config = aws_config::load_from_env().await;
+    let mut builder = aws_sdk_s3::config::Builder::from(&config);
+    if let Some(endpoint) = &upload.endpoint {
+        builder = builder.endpoint_url(endpoint).force_path_style(true);
+    }
+    let client = aws_sdk_s3::Client::from_conf(builder.build());
+
+    put_all(&client, upload, dir, &data).await?;
+    put_all(&client, upload, dir, &metadata).await?;
+    println!(
+        "Uploaded {} files to s3://{}/{}",
+        data.len() + metadata.len(),
+        upload.bucket,
+        object_key(&upload.prefix, Path::new(""))
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "s3"))]
+pub async fn upload_dir(_dir: &Path, _upload: &S3Upload) -> Result<()> {
+    ensure_available()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_keys_under_the_prefix() {
+        let tile = Path::new("5339").join("53393599.tile");
+        assert_eq!(
+            object_key("mesh-tiles/", &tile),
+            "mesh-tiles/5339/53393599.tile"
+        );
+        assert_eq!(object_key("tiles", &tile), "tiles/5339/53393599.tile");
+        assert_eq!(object_key("", Path::new("metadata.json")), "metadata.json");
+    }
+
+    #[test]
+    fn uploads_metadata_after_the_tiles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("5339")).unwrap();
+        for file in [
+            "manifest.json",
+            "5339/metadata.json",
+            "5339/5339.tile",
+            "5340.tile",
+        ] {
+            std::fs::write(dir.join(file), b"x").unwrap();
+        }
+
+        let (data, metadata) = collect_files(dir).unwrap();
+        assert_eq!(
+            data,
+            [
+                Path::new("5339").join("5339.tile"),
+                PathBuf::from("5340.tile")
+            ]
+        );
+        assert_eq!(
+            metadata,
+            [
+                Path::new("5339").join("metadata.json"),
+                PathBuf::from("manifest.json")
+            ]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    error::JpEstatError,
+    mesh::{self, ColumnTypes},
+    postgres,
+};
+use anyhow::{Result, anyhow, bail};
+use std::{collections::HashMap, fmt, path::Path};
+
+/// Lv1 file fetched when no archive of the survey is cached: central Tokyo is in every survey.
+const SAMPLE_LV1: u64 = 5339;
+
+/// Added by `mesh --add-centroid-column`, so it is not reported when present.
+const CENTROID_COLUMN: &str = "centroid";
+
+#[derive(Debug, PartialEq, Eq)]
+enum SchemaMismatch {
+    Missing {
+        column: String,
+        expected: String,
+    },
+    Unexpected {
+        column: String,
+        actual: String,
+    },
+    WrongType {
+        column: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaMismatch::Missing { column, expected } => {
+                write!(f, "missing column \"{}\" ({})", column, expected)
+            }
+            SchemaMismatch::Unexpected { column, actual } => {
+                write!(f, "unexpected column \"{}\" ({})", column, actual)
+            }
+            SchemaMismatch::WrongType {
+                column,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "column \"{}\" has type {}, expected {}",
+                column, actual, expected
+            ),
+        }
+    }
+}
+
+/// Normalises a type as written in DDL to the `udt_name` shown by `information_schema`
+/// (`BIGINT[]` -> `_int8`), so both sides compare as plain strings.
+fn udt_name(sql_type: &str) -> String {
+    let sql_type = sql_type.trim().to_ascii_lowercase();
+    let (base, is_array) = match sql_type.strip_suffix("[]") {
+        Some(base) => (base.trim(), true),
+        None => (sql_type.as_str(), false),
+    };
+    let base = match base {
+        "bigint" | "int8" => "int8",
+        "integer" | "int" | "int4" => "int4",
+        "smallint" | "int2" => "int2",
+        "double precision" | "float8" => "float8",
+        "real" | "float4" => "float4",
+        "boolean" | "bool" => "bool",
+        "character varying" | "varchar" => "varchar",
+        other => other.split('(').next().unwrap_or(other).trim(),
+    };
+    if is_array {
+        format!("_{}", base)
+    } else {
+        base.to_string()
+    }
+}
+
+/// Compares the columns `create_schema` would create against the table's actual columns
+/// (`(column_name, udt_name)`). Mismatches are listed in the expected column order, then
+/// the unexpected columns in table order.
+fn compare_columns(expected: &[(&str, &str)], actual: &[(String, String)]) -> Vec<SchemaMismatch> {
+    let actual_types: HashMap<&str, &str> = actual
+        .iter()
+        .map(|(column, udt)| (column.as_str(), udt.as_str()))
+        .collect();
+    let mut mismatches = Vec::new();
+    for (column, expected_type) in expected {
+        match actual_types.get(column) {
+            None => mismatches.push(SchemaMismatch::Missing {
+                column: column.to_string(),
+                expected: expected_type.to_string(),
+            }),
+            Some(actual_type) if *actual_type != udt_name(expected_type) => {
+                mismatches.push(SchemaMismatch::WrongType {
+                    column: column.to_string(),
+                    expected: udt_name(expected_type),
+                    actual: actual_type.to_string(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for (column, actual_type) in actual {
+        let known = expected.iter().any(|(expected, _)| expected == column)
+            || (column == CENTROID_COLUMN && actual_type == "geometry");
+        if !known {
+            mismatches.push(SchemaMismatch::Unexpected {
+                column: column.clone(),
+                actual: actual_type.clone(),
+            });
+        }
+    }
+    mismatches
+}
+
+/// Lv1 code of the first cached archive of this survey in `tmp_dir`, if any.
+async fn cached_lv1(tmp_dir: &Path, prefix: &str) -> Result<Option<u64>> {
+    let mut codes = Vec::new();
+    let mut entries = match tokio::fs::read_dir(tmp_dir).await {
+        Ok(entries) => entries,
+        Err(_) => return Ok(None),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        if let Some(code) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|rest| rest.strip_suffix(".zip"))
+            .and_then(|code| code.parse().ok())
+        {
+            codes.push(code);
+        }
+    }
+    Ok(codes.into_iter().min())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn process_check(
+    http_client: &reqwest::Client,
+    table_prefix: &str,
+    postgres_url: &str,
+    tmp_dir: &Path,
+    level: u8,
+    year: u16,
+    survey: &str,
+    column_types: Option<&Path>,
+) -> Result<()> {
+    let mesh_stats = mesh::get_matching_mesh_stats(level, year, survey).ok_or_else(|| {
+        JpEstatError::NotFound {
+            survey: survey.to_string(),
+            level,
+            year,
+        }
+    })?;
+    let column_types = match column_types {
+        Some(path) => mesh::load_column_types(path).await?,
+        None => ColumnTypes::new(),
+    };
+
+    // The expected columns come from a CSV header, like `mesh` itself; a cached archive
+    // is used when there is one.
+    let prefix = format!("{}-{}-", mesh_stats.year, mesh_stats.stats_id);
+    let lv1 = cached_lv1(tmp_dir, &prefix).await?.unwrap_or(SAMPLE_LV1);
+    let items = mesh::download_lv1_csvs(
+        http_client,
+        tmp_dir,
+        mesh_stats,
+        vec![(lv1, mesh::lv1_download_url(mesh_stats, lv1))],
+    )
+    .await?;
+    let item = items
+        .first()
+        .ok_or_else(|| anyhow!("no CSV found for Lv1 mesh {}", lv1))?;
+    let columns = mesh::read_columns(&item.extracted_path)?;
+    let expected: Vec<(&str, &str)> = mesh::column_schema(&columns, &column_types).collect();
+
+    let table_name = mesh::table_name(table_prefix, mesh_stats);
+    let client = postgres::connect(postgres_url).await?;
+    // Unquoted identifiers are folded to lower case by PostgreSQL.
+    let rows = client
+        .query(
+            "SELECT column_name::text, udt_name::text FROM information_schema.columns \
+             WHERE table_schema = current_schema() AND table_name = $1 \
+             ORDER BY ordinal_position",
+            &[&table_name.to_lowercase()],
+        )
+        .await?;
+    if rows.is_empty() {
+        bail!("table {} does not exist", table_name);
+    }
+    let actual: Vec<(String, String)> = rows.iter().map(|row| (row.get(0), row.get(1))).collect();
+
+    let mismatches = compare_columns(&expected, &actual);
+    if mismatches.is_empty() {
+        println!(
+            "{}: {} columns match the expected schema",
+            table_name,
+            actual.len()
+        );
+        return Ok(());
+    }
+    for mismatch in &mismatches {
+        println!("{}: {}", table_name, mismatch);
+    }
+    bail!(
+        "{} schema mismatch(es) found in {}",
+        mismatches.len(),
+        table_name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_ddl_types_to_udt_names() {
+        assert_eq!(udt_name("BIGINT"), "int8");
+        assert_eq!(udt_name("BIGINT[]"), "_int8");
+        assert_eq!(udt_name("SMALLINT"), "int2");
+        assert_eq!(udt_name("NUMERIC(10,2)"), "numeric");
+        assert_eq!(udt_name("text"), "text");
+    }
+
+    #[test]
+    fn reports_missing_unexpected_and_retyped_columns() {
+        let expected = [
+            ("KEY_CODE", "BIGINT"),
+            ("GASSAN", "BIGINT[]"),
+            ("人口総数", "INTEGER"),
+            ("世帯総数", "INTEGER"),
+        ];
+        let actual = vec![
+            ("KEY_CODE".to_string(), "int8".to_string()),
+            ("GASSAN".to_string(), "_int8".to_string()),
+            ("人口総数".to_string(), "int8".to_string()),
+            ("旧列".to_string(), "int4".to_string()),
+            ("centroid".to_string(), "geometry".to_string()),
+        ];
+        assert_eq!(
+            compare_columns(&expected, &actual),
+            vec![
+                SchemaMismatch::WrongType {
+                    column: "人口総数".to_string(),
+                    expected: "int4".to_string(),
+                    actual: "int8".to_string(),
+                },
+                SchemaMismatch::Missing {
+                    column: "世帯総数".to_string(),
+                    expected: "INTEGER".to_string(),
+                },
+                SchemaMismatch::Unexpected {
+                    column: "旧列".to_string(),
+                    actual: "int4".to_string(),
+                },
+            ]
+        );
+        assert!(compare_columns(&expected[..2], &actual[..2]).is_empty());
+    }
+}
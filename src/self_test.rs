@@ -0,0 +1,135 @@
+use crate::{gdal, postgres};
+use anyhow::{Result, bail};
+use tokio::process::Command;
+
+const ESTAT_URL: &str = "https://www.e-stat.go.jp";
+
+struct CheckResult {
+    name: &'static str,
+    /// A failed non-critical check is reported but does not fail the command; `ogr2ogr`
+    /// is only needed by `areamap` and the GeoPackage export.
+    critical: bool,
+    /// Detail shown after PASS (a version or status) or FAIL (the error chain).
+    outcome: Result<String>,
+}
+
+async fn check_unzip() -> Result<String> {
+    let output = Command::new("unzip")
+        .arg("-v")
+        .output()
+        .await
+        .map_err(|err| anyhow::anyhow!("`unzip` could not be run ({})", err))?;
+    if !output.status.success() {
+        bail!("`unzip -v` exited with status {}", output.status);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+async fn check_ogr2ogr() -> Result<String> {
+    let version = gdal::check_ogr2ogr_available().await?;
+    gdal::check_postgresql_driver().await?;
+    Ok(format!("{}, PostgreSQL driver available", version))
+}
+
+async fn check_estat(http_client: &reqwest::Client) -> Result<String> {
+    let response = http_client.get(ESTAT_URL).send().await?;
+    let status = response.status();
+    if status != reqwest::StatusCode::OK {
+        bail!("{} returned HTTP {}", ESTAT_URL, status);
+    }
+    Ok(format!("{} returned HTTP {}", ESTAT_URL, status))
+}
+
+async fn check_postgres(postgres_url: &str) -> Result<String> {
+    let client = postgres::connect(postgres_url).await?;
+    let value: i32 = client.query_one("SELECT 1", &[]).await?.get(0);
+    if value != 1 {
+        bail!("SELECT 1 returned {}", value);
+    }
+    let version: String = client.query_one("SHOW server_version", &[]).await?.get(0);
+    Ok(format!("SELECT 1 succeeded (PostgreSQL {})", version))
+}
+
+/// Prints one line per check. Returns the number of failed critical checks.
+fn report(results: &[CheckResult]) -> usize {
+    let mut failed = 0;
+    for result in results {
+        match &result.outcome {
+            Ok(detail) => println!("PASS  {}: {}", result.name, detail),
+            Err(err) => {
+                let label = if result.critical {
+                    failed += 1;
+                    "FAIL"
+                } else {
+                    "FAIL (optional)"
+                };
+                println!("{}  {}: {:#}", label, result.name, err);
+            }
+        }
+    }
+    failed
+}
+
+pub async fn process_self_test(
+    http_client: &reqwest::Client,
+    postgres_url: Option<&str>,
+) -> Result<()> {
+    let mut results = vec![
+        CheckResult {
+            name: "unzip",
+            critical: true,
+            outcome: check_unzip().await,
+        },
+        CheckResult {
+            name: "ogr2ogr",
+            critical: false,
+            outcome: check_ogr2ogr().await,
+        },
+        CheckResult {
+            name: "e-Stat",
+            critical: true,
+            outcome: check_estat(http_client).await,
+        },
+    ];
+    if let Some(postgres_url) = postgres_url {
+        results.push(CheckResult {
+            name: "PostgreSQL",
+            critical: true,
+            outcome: check_postgres(postgres_url).await,
+        });
+    }
+    let failed = report(&results);
+    if failed > 0 {
+        let critical = results.iter().filter(|r| r.critical).count();
+        bail!("{} of {} critical checks failed", failed, critical);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_only_critical_failures() {
+        let results = [
+            CheckResult {
+                name: "unzip",
+                critical: true,
+                outcome: Ok("UnZip 6.00".to_string()),
+            },
+            CheckResult {
+                name: "ogr2ogr",
+                critical: false,
+                outcome: Err(anyhow::anyhow!("not found")),
+            },
+            CheckResult {
+                name: "e-Stat",
+                critical: true,
+                outcome: Err(anyhow::anyhow!("timed out")),
+            },
+        ];
+        assert_eq!(report(&results), 1);
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use csv::{ReaderBuilder, StringRecord};
+use encoding_rs::SHIFT_JIS;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::GzDecoder;
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Default of `--buffer-size-kb`.
+pub const DEFAULT_BUFFER_SIZE_KB: usize = 256;
+
+/// Smallest accepted `--buffer-size-kb`; the default `BufReader` capacity.
+pub const MIN_BUFFER_SIZE_KB: usize = 8;
+
+static BUFFER_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_BUFFER_SIZE_KB * 1024);
+
+/// Sets the read buffer used for the file and the CSV parser; set from `--buffer-size-kb`.
+pub fn set_buffer_size_kb(kb: usize) {
+    BUFFER_SIZE.store(kb.max(MIN_BUFFER_SIZE_KB) * 1024, Ordering::Relaxed);
+}
+
+pub(crate) fn buffer_size() -> usize {
+    BUFFER_SIZE.load(Ordering::Relaxed)
+}
+
+/// Wraps `reader` so Shift_JIS bytes are decoded to UTF-8 before CSV parsing.
+/// e-Stat CSVs carry two header rows, so headers are left to the caller.
+pub(crate) fn shiftjis_csv_reader<R: std::io::Read + 'static>(
+    reader: R,
+) -> csv::Reader<Box<dyn std::io::Read>> {
+    let transcoded = DecodeReaderBytesBuilder::new()
+        .encoding(Some(SHIFT_JIS))
+        .build(reader);
+
+    ReaderBuilder::new()
+        .has_headers(false)
+        .buffer_capacity(buffer_size())
+        .from_reader(Box::new(transcoded))
+}
+
+/// Opens an extracted e-Stat CSV. Files ending in `.gz` are decompressed transparently.
+pub(crate) fn open_shiftjis_csv(path: &Path) -> Result<csv::Reader<Box<dyn std::io::Read>>> {
+    let file = BufReader::with_capacity(buffer_size(), File::open(path)?);
+    if path.extension().is_some_and(|e| e == "gz") {
+        Ok(shiftjis_csv_reader(GzDecoder::new(file)))
+    } else {
+        Ok(shiftjis_csv_reader(file))
+    }
+}
+
+/// Merges the two e-Stat header rows: the second (Japanese) row wins unless it is blank.
+pub(crate) fn normalize_headers(header1: &StringRecord, header2: &StringRecord) -> Vec<String> {
+    header2
+        .iter()
+        .enumerate()
+        .map(|(i, h2)| {
+            let col = if h2.trim().is_empty() {
+                header1.get(i).unwrap_or_default().to_string()
+            } else {
+                h2.to_string()
+            };
+            col.trim().replace("\u{3000}", "")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_shiftjis_headers() {
+        // "KEY_CODE,人口" followed by a second header row with a blank first column.
+        let (bytes, _, _) = SHIFT_JIS.encode("KEY_CODE,T001\r\n,人口　総数\r\n5339,12\r\n");
+        let mut rdr = shiftjis_csv_reader(std::io::Cursor::new(bytes.into_owned()));
+        let mut records = rdr.records();
+        let header1 = records.next().unwrap().unwrap();
+        let header2 = records.next().unwrap().unwrap();
+        assert_eq!(
+            normalize_headers(&header1, &header2),
+            vec!["KEY_CODE", "人口総数"]
+        );
+    }
+
+    #[test]
+    fn clamps_buffer_size_to_the_minimum() {
+        set_buffer_size_kb(1);
+        assert_eq!(buffer_size(), MIN_BUFFER_SIZE_KB * 1024);
+        set_buffer_size_kb(DEFAULT_BUFFER_SIZE_KB);
+        assert_eq!(buffer_size(), 256 * 1024);
+    }
+
+    #[test]
+    fn opens_gzip_compressed_csv() {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write as _;
+
+        let (bytes, _, _) = SHIFT_JIS.encode("KEY_CODE,T001\r\n,人口\r\n5339,12\r\n");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("shiftjis_csv.txt.gz");
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let mut rdr = open_shiftjis_csv(&path).unwrap();
+        let rows: Vec<_> = rdr.records().map(|r| r.unwrap()).collect();
+        assert_eq!(&rows[1][1], "人口");
+        assert_eq!(&rows[2][0], "5339");
+    }
+}
@@ -0,0 +1,173 @@
+use crate::mesh_geometry;
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::path::{Component, Path, PathBuf};
+
+const STAC_VERSION: &str = "1.0.0";
+
+/// Survey-level fields shared by every item in the catalog.
+pub struct StacContext<'a> {
+    pub catalog_id: String,
+    pub title: String,
+    pub year: u16,
+    pub survey: &'a str,
+    pub data_level: u8,
+    pub tile_level: u8,
+    pub bands: &'a [String],
+}
+
+/// `./` or `../` prefix that leads from the directory of `relative_path` back to the root.
+fn root_href_prefix(relative_path: &Path) -> String {
+    let depth = relative_path
+        .parent()
+        .map(|parent| {
+            parent
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count()
+        })
+        .unwrap_or(0);
+    if depth == 0 {
+        "./".to_string()
+    } else {
+        "../".repeat(depth)
+    }
+}
+
+fn href(path: &Path) -> String {
+    let parts: Vec<String> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    format!("./{}", parts.join("/"))
+}
+
+fn build_item(ctx: &StacContext<'_>, tile_code: u64, tile_path: &Path) -> Result<Value> {
+    let bounds = mesh_geometry::cell_bounds(tile_code)?;
+    let root = root_href_prefix(tile_path);
+    let tile_filename = tile_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(json!({
+        "type": "Feature",
+        "stac_version": STAC_VERSION,
+        "id": tile_code.to_string(),
+        "bbox": bounds.to_bbox(),
+        "geometry": bounds.to_geojson_polygon(),
+        "properties": {
+            "datetime": format!("{:04}-01-01T00:00:00Z", ctx.year),
+            "survey": ctx.survey,
+            "level": ctx.data_level,
+            "tile_level": ctx.tile_level,
+            "bands": ctx.bands,
+        },
+        "links": [
+            { "rel": "root", "href": format!("{}catalog.json", root), "type": "application/json" },
+            { "rel": "parent", "href": format!("{}catalog.json", root), "type": "application/json" },
+        ],
+        "assets": {
+            "data": {
+                "href": format!("./{}", tile_filename),
+                "type": "application/vnd.mesh-data-tile",
+                "roles": ["data"],
+            },
+            "metadata": {
+                "href": format!("{}metadata.json", root),
+                "type": "application/json",
+                "roles": ["metadata"],
+            },
+        },
+    }))
+}
+
+/// Writes `{tile_code}.json` next to each tile and a `catalog.json` linking them at `output_dir`.
+/// `tiles` holds each tile code with its path relative to `output_dir`.
+pub async fn write_stac(
+    output_dir: &Path,
+    ctx: &StacContext<'_>,
+    tiles: &[(u64, PathBuf)],
+) -> Result<()> {
+    let mut links = vec![json!({
+        "rel": "root",
+        "href": "./catalog.json",
+        "type": "application/json",
+    })];
+
+    for (tile_code, tile_path) in tiles {
+        let item_path = tile_path.with_extension("json");
+        let item = build_item(ctx, *tile_code, tile_path)?;
+        let full_path = output_dir.join(&item_path);
+        tokio::fs::write(&full_path, serde_json::to_vec_pretty(&item)?)
+            .await
+            .with_context(|| format!("failed to write {}", full_path.display()))?;
+        links.push(json!({
+            "rel": "item",
+            "href": href(&item_path),
+            "type": "application/geo+json",
+        }));
+    }
+
+    let catalog = json!({
+        "type": "Catalog",
+        "stac_version": STAC_VERSION,
+        "id": ctx.catalog_id,
+        "title": ctx.title,
+        "description": format!(
+            "{} {}年 Lv{} mesh-data-tile (tile level {})",
+            ctx.survey, ctx.year, ctx.data_level, ctx.tile_level
+        ),
+        "links": links,
+    });
+    let catalog_path = output_dir.join("catalog.json");
+    tokio::fs::write(&catalog_path, serde_json::to_vec_pretty(&catalog)?)
+        .await
+        .with_context(|| format!("failed to write {}", catalog_path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(bands: &[String]) -> StacContext<'_> {
+        StacContext {
+            catalog_id: "2020_T001140_3".to_string(),
+            title: "人口及び世帯 2020年 Lv3".to_string(),
+            year: 2020,
+            survey: "人口及び世帯",
+            data_level: 3,
+            tile_level: 1,
+            bands,
+        }
+    }
+
+    #[test]
+    fn builds_item_for_sharded_tile() {
+        let bands = vec!["人口（総数）".to_string()];
+        let ctx = context(&bands);
+        let item = build_item(&ctx, 5339, &Path::new("5339").join("5339.tile")).unwrap();
+
+        assert_eq!(item["id"], "5339");
+        assert_eq!(item["properties"]["datetime"], "2020-01-01T00:00:00Z");
+        assert_eq!(item["properties"]["bands"][0], "人口（総数）");
+        assert_eq!(item["assets"]["data"]["href"], "./5339.tile");
+        assert_eq!(item["links"][0]["href"], "../catalog.json");
+        let bbox = item["bbox"].as_array().unwrap();
+        assert_eq!(bbox[0], 139.0);
+        assert!((bbox[3].as_f64().unwrap() - 36.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn root_prefix_follows_depth() {
+        assert_eq!(root_href_prefix(Path::new("5339.tile")), "./");
+        assert_eq!(
+            root_href_prefix(&Path::new("5339").join("53393599.tile")),
+            "../"
+        );
+        assert_eq!(
+            href(&Path::new("5339").join("53393599.json")),
+            "./5339/53393599.json"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Correlation and regression of paired samples, for `mesh-stats2`. Only `std` is used.
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Sums of squares and cross products around the means: `(Sxx, Syy, Sxy)`.
+fn sums_of_squares(xs: &[f64], ys: &[f64]) -> (f64, f64, f64) {
+    let (mean_x, mean_y) = (mean(xs), mean(ys));
+    xs.iter()
+        .zip(ys)
+        .fold((0.0, 0.0, 0.0), |(sxx, syy, sxy), (x, y)| {
+            let (dx, dy) = (x - mean_x, y - mean_y);
+            (sxx + dx * dx, syy + dy * dy, sxy + dx * dy)
+        })
+}
+
+/// Pearson's r. `None` for fewer than two pairs or when either sample is constant.
+pub fn pearson(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    assert_eq!(xs.len(), ys.len(), "samples must be paired");
+    if xs.len() < 2 {
+        return None;
+    }
+    let (sxx, syy, sxy) = sums_of_squares(xs, ys);
+    if sxx == 0.0 || syy == 0.0 {
+        return None;
+    }
+    Some(sxy / (sxx * syy).sqrt())
+}
+
+/// 1-based ranks; tied values share the mean of the ranks they span.
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        // Ranks start..end (0-based) are tied; their mean as 1-based ranks.
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &i in &order[start..end] {
+            ranks[i] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+/// Spearman's ρ: Pearson's r of the ranks, which handles ties.
+pub fn spearman(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    pearson(&ranks(xs), &ranks(ys))
+}
+
+/// Least-squares fit of `y = slope · x + intercept`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearRegression {
+    pub slope: f64,
+    pub intercept: f64,
+    pub r_squared: f64,
+}
+
+/// `None` for fewer than two pairs or when `xs` is constant. A constant `ys` fits exactly.
+pub fn linear_regression(xs: &[f64], ys: &[f64]) -> Option<LinearRegression> {
+    assert_eq!(xs.len(), ys.len(), "samples must be paired");
+    if xs.len() < 2 {
+        return None;
+    }
+    let (sxx, syy, sxy) = sums_of_squares(xs, ys);
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    let r_squared = if syy == 0.0 {
+        1.0
+    } else {
+        sxy * sxy / (sxx * syy)
+    };
+    Some(LinearRegression {
+        slope,
+        intercept: mean(ys) - slope * mean(xs),
+        r_squared,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn computes_pearson_and_regression() {
+        let xs = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let ys = [2.0, 4.1, 5.9, 8.2, 9.8];
+        let r = pearson(&xs, &ys).unwrap();
+        assert!(close(r, 0.998_829_649_329_885_9), "{}", r);
+        let fit = linear_regression(&xs, &ys).unwrap();
+        assert!(close(fit.slope, 1.97), "{:?}", fit);
+        assert!(close(fit.intercept, 0.09), "{:?}", fit);
+        assert!(close(fit.r_squared, r * r), "{:?}", fit);
+
+        assert_eq!(pearson(&[1.0], &[2.0]), None);
+        assert_eq!(pearson(&[1.0, 1.0], &[2.0, 3.0]), None);
+        assert_eq!(linear_regression(&[1.0, 1.0], &[2.0, 3.0]), None);
+    }
+
+    #[test]
+    fn ranks_ties_by_their_mean() {
+        assert_eq!(ranks(&[10.0, 30.0, 20.0, 30.0]), vec![1.0, 3.5, 2.0, 3.5]);
+        // Monotonic but not linear: ρ is 1 while r is below 1.
+        let xs = [1.0, 2.0, 3.0, 4.0];
+        let ys = [1.0, 8.0, 27.0, 64.0];
+        assert!(close(spearman(&xs, &ys).unwrap(), 1.0));
+        assert!(pearson(&xs, &ys).unwrap() < 0.99);
+        assert!(close(spearman(&xs, &[4.0, 3.0, 2.0, 1.0]).unwrap(), -1.0));
+    }
+}
@@ -0,0 +1,110 @@
+//! Optional OTLP trace export (`--otlp-endpoint`, cargo feature `otlp`).
+//!
+//! The key operations are always instrumented with `tracing` spans; without a subscriber they
+//! cost next to nothing, and with `--otlp-endpoint` they are exported to the collector.
+
+use anyhow::Result;
+use std::time::Instant;
+use tracing::Span;
+
+/// Records `duration_ms` on a span when dropped, so early returns are timed too.
+/// The span must declare an empty `duration_ms` field.
+pub(crate) struct SpanTimer {
+    span: Span,
+    started: Instant,
+}
+
+impl SpanTimer {
+    pub(crate) fn new(span: &Span) -> Self {
+        Self {
+            span: span.clone(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for SpanTimer {
+    fn drop(&mut self) {
+        let millis = u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.span.record("duration_ms", millis);
+    }
+}
+
+/// The OTLP/HTTP trace path is appended when only the collector base URL is given
+/// (e.g. `http://localhost:4318`).
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+fn traces_endpoint(endpoint: &str) -> String {
+    let trimmed = endpoint.trim_end_matches('/');
+    if trimmed.ends_with("/v1/traces") {
+        trimmed.to_string()
+    } else {
+        format!("{}/v1/traces", trimmed)
+    }
+}
+
+/// Keeps the tracer provider alive; call [`Telemetry::shutdown`] before exiting so buffered
+/// spans are flushed.
+pub struct Telemetry {
+    #[cfg(feature = "otlp")]
+    provider: opentelemetry_sdk::trace::SdkTracerProvider,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        #[cfg(feature = "otlp")]
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Warning: failed to flush traces: {}", e);
+        }
+    }
+}
+
+#[cfg(feature = "otlp")]
+pub fn init(endpoint: &str) -> Result<Telemetry> {
+    use anyhow::Context;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_endpoint(endpoint))
+        .build()
+        .with_context(|| format!("when creating the OTLP exporter for {}", endpoint))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)
+        .context("a tracing subscriber is already installed")?;
+    Ok(Telemetry { provider })
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn init(_endpoint: &str) -> Result<Telemetry> {
+    anyhow::bail!("--otlp-endpoint requires a build with `--features otlp`")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_trace_path_to_collector_url() {
+        assert_eq!(
+            traces_endpoint("http://localhost:4318/"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_endpoint("http://tempo:4318/v1/traces"),
+            "http://tempo:4318/v1/traces"
+        );
+    }
+}
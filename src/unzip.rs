@@ -1,8 +1,16 @@
+use crate::{error::JpEstatError, telemetry::SpanTimer};
 use anyhow::{Result, anyhow};
 use std::path::{Path, PathBuf};
 use tokio::process::Command;
+use tracing::Span;
 
+#[tracing::instrument(
+    name = "extract_zip",
+    skip_all,
+    fields(file.name = %zip_path.display(), duration_ms)
+)]
 pub async fn unzip_archive(zip_path: &Path) -> Result<PathBuf> {
+    let _timer = SpanTimer::new(&Span::current());
     let out_dir = zip_path.with_extension("");
     // Remove the output directory if it exists
     if out_dir.exists() {
@@ -20,7 +28,10 @@ pub async fn unzip_archive(zip_path: &Path) -> Result<PathBuf> {
             "Failed to unzip: {}",
             String::from_utf8_lossy(&output.stderr)
         );
-        return Err(anyhow!("Failed to unzip"));
+        return Err(JpEstatError::ExtractFailed {
+            path: zip_path.to_path_buf(),
+        }
+        .into());
     }
 
     Ok(out_dir)
@@ -32,13 +43,38 @@ pub async fn find_file_with_ext(dir: &Path, ext: &str) -> Result<PathBuf> {
     let mut entries = tokio::fs::read_dir(dir).await?;
     while let Some(entry) = entries.next_entry().await? {
         let entry = entry.path();
-        if entry.extension().map_or(false, |e| e == ext) {
+        if entry.extension().is_some_and(|e| e == ext) {
             return Ok(entry);
         }
     }
     Err(anyhow!("No .{} file found in the directory", ext))
 }
 
+/// Like [`find_file_with_ext`], but falls back to a gzip-compressed `.<ext>.gz` file.
+/// Returns the path and whether the file is gzip-compressed.
+pub async fn find_file_with_ext_or_gz(dir: &Path, ext: &str) -> Result<(PathBuf, bool)> {
+    if let Ok(path) = find_file_with_ext(dir, ext).await {
+        return Ok((path, false));
+    }
+    let gz_suffix = format!(".{}.gz", ext);
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let entry = entry.path();
+        if entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(&gz_suffix))
+        {
+            return Ok((entry, true));
+        }
+    }
+    Err(anyhow!(
+        "No .{} or .{}.gz file found in the directory",
+        ext,
+        ext
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,4 +90,22 @@ mod tests {
 
         tokio::fs::remove_dir_all(out_dir).await.unwrap();
     }
+
+    #[tokio::test]
+    async fn falls_back_to_gzip_variant() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        tokio::fs::write(dir.join("mesh.txt.gz"), b"")
+            .await
+            .unwrap();
+
+        let (path, gzipped) = find_file_with_ext_or_gz(dir, "txt").await.unwrap();
+        assert!(gzipped);
+        assert_eq!(path.file_name().unwrap(), "mesh.txt.gz");
+
+        tokio::fs::write(dir.join("mesh.txt"), b"").await.unwrap();
+        let (path, gzipped) = find_file_with_ext_or_gz(dir, "txt").await.unwrap();
+        assert!(!gzipped);
+        assert_eq!(path.file_name().unwrap(), "mesh.txt");
+    }
 }
@@ -0,0 +1,257 @@
+//! Rebuilds `mesh_stats.json` from the e-Stat statistics list (`getStatsList` with
+//! `searchKind=2`, the small-area and grid-square tables).
+
+use crate::{
+    estat_api::EStatApiClient,
+    mesh::{MeshStats, MeshStatsConfig},
+};
+use anyhow::{Context, Result, bail};
+use reqwest::Client;
+use serde_json::Value;
+use std::{collections::HashSet, path::Path};
+
+/// Mesh level named in a table title, e.g. `3次メッシュ` or `（1kmメッシュ）`. Longer sizes
+/// are checked first so `10km` is not read as `1km`.
+fn mesh_level_from_title(title: &str) -> Option<u8> {
+    const PATTERNS: [(&str, u8); 12] = [
+        ("6次メッシュ", 6),
+        ("5次メッシュ", 5),
+        ("4次メッシュ", 4),
+        ("3次メッシュ", 3),
+        ("2次メッシュ", 2),
+        ("1次メッシュ", 1),
+        ("125m", 6),
+        ("250m", 5),
+        ("500m", 4),
+        ("80km", 1),
+        ("10km", 2),
+        ("1km", 3),
+    ];
+    let title = to_half_width(title);
+    PATTERNS
+        .iter()
+        .find(|(pattern, _)| title.contains(pattern))
+        .map(|(_, level)| *level)
+}
+
+/// Full-width ASCII (`１ｋｍ`) as half-width, the way titles are matched.
+fn to_half_width(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '！'..='～' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// Survey name from a table title: parenthesised parts that describe the mesh are dropped,
+/// e.g. `人口及び世帯（1kmメッシュ）` becomes `人口及び世帯`.
+fn survey_name_from_title(title: &str) -> String {
+    let mut name = String::new();
+    let mut group: Option<String> = None;
+    for c in title.chars() {
+        match (c, group.as_mut()) {
+            ('(' | '（', None) => group = Some(c.to_string()),
+            (')' | '）', Some(text)) => {
+                text.push(c);
+                if !text.contains("メッシュ") {
+                    name.push_str(text);
+                }
+                group = None;
+            }
+            (_, Some(text)) => text.push(c),
+            (_, None) => name.push(c),
+        }
+    }
+    if let Some(text) = group {
+        name.push_str(&text);
+    }
+    name.trim().to_string()
+}
+
+/// JGD2011 from the 2015 round on, JGD2000 for 2002–2014 and the Tokyo Datum before that.
+fn datum_for_year(year: u16) -> u16 {
+    match year {
+        2015.. => 6668,
+        2002.. => 4612,
+        _ => 4301,
+    }
+}
+
+/// `"..."` or `{"$": "..."}`, both of which e-Stat uses for text fields.
+fn text(value: Option<&Value>) -> Option<&str> {
+    match value? {
+        Value::String(text) => Some(text),
+        Value::Object(object) => object.get("$").and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+/// `TABLE_INF` is an object instead of an array when a page has a single table.
+fn table_infos(page: &Value) -> Vec<&Value> {
+    match page
+        .get("GET_STATS_LIST")
+        .and_then(|root| root.get("DATALIST_INF"))
+        .and_then(|list| list.get("TABLE_INF"))
+    {
+        Some(Value::Array(tables)) => tables.iter().collect(),
+        Some(table) => vec![table],
+        None => Vec::new(),
+    }
+}
+
+/// A `TABLE_INF` entry as a mesh statistic, or `None` when it is not a grid-square table
+/// downloadable by statistic ID (`T` and six digits).
+fn parse_table_info(table: &Value) -> Option<MeshStats> {
+    let stats_id = text(table.get("@id"))?;
+    if !(stats_id.len() == 7
+        && stats_id.starts_with('T')
+        && stats_id[1..].bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    let title = text(table.get("TITLE"))?;
+    let year = text(table.get("SURVEY_DATE"))
+        .and_then(|date| date.get(..4))
+        .and_then(|year| year.parse().ok())?;
+    let meshlevel = mesh_level_from_title(title)
+        .or_else(|| text(table.get("STATISTICS_NAME")).and_then(mesh_level_from_title))?;
+    Some(MeshStats {
+        name: survey_name_from_title(title),
+        year,
+        meshlevel,
+        stats_id: stats_id.to_string(),
+        datum: datum_for_year(year),
+    })
+}
+
+/// The compiled-in list with the newly found statistics appended (ordered by year, level and
+/// ID). Entries already in the list are kept as they are.
+fn merge(known: Vec<MeshStats>, found: Vec<MeshStats>) -> (Vec<MeshStats>, usize) {
+    let known_ids: HashSet<String> = known.iter().map(|m| m.stats_id.clone()).collect();
+    let mut added: Vec<MeshStats> = found
+        .into_iter()
+        .filter(|m| !known_ids.contains(&m.stats_id))
+        .collect();
+    added.sort_by(|a, b| {
+        (a.year, a.meshlevel, &a.stats_id).cmp(&(b.year, b.meshlevel, &b.stats_id))
+    });
+    added.dedup_by(|a, b| a.stats_id == b.stats_id);
+    let added_count = added.len();
+    let mut merged = known;
+    merged.extend(added);
+    (merged, added_count)
+}
+
+pub async fn process_update_survey_list(
+    http_client: &Client,
+    app_id: &str,
+    output_path: &Path,
+) -> Result<()> {
+    let api = EStatApiClient::with_client(http_client.clone());
+    let pages = api
+        .get_stats_list_pages(app_id, &[("searchKind", "2".to_string())])
+        .await
+        .context("failed to fetch getStatsList")?;
+    let found: Vec<MeshStats> = pages
+        .iter()
+        .flat_map(table_infos)
+        .filter_map(parse_table_info)
+        .collect();
+    if found.is_empty() {
+        bail!("getStatsList returned no grid-square statistics");
+    }
+    let found_count = found.len();
+
+    let known: MeshStatsConfig = serde_json::from_str(include_str!("mesh_stats.json"))
+        .expect("Failed to parse mesh_stats.json");
+    let (mesh_stats, added) = merge(known.mesh_stats, found);
+
+    if let Some(parent) = output_path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut json = serde_json::to_string_pretty(&MeshStatsConfig { mesh_stats })?;
+    json.push('\n');
+    std::fs::write(output_path, json)
+        .with_context(|| format!("when writing {}", output_path.display()))?;
+    println!(
+        "Found {} grid-square statistics ({} not in this build); wrote {}",
+        found_count,
+        added,
+        output_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_mesh_tables_from_the_stats_list() {
+        let page = json!({
+            "GET_STATS_LIST": {
+                "DATALIST_INF": {
+                    "TABLE_INF": [
+                        {
+                            "@id": "T001140",
+                            "STATISTICS_NAME": "国勢調査",
+                            "TITLE": {"@no": "1", "$": "人口及び世帯（1ｋｍメッシュ）"},
+                            "SURVEY_DATE": "202010"
+                        },
+                        {
+                            "@id": "T009001",
+                            "STATISTICS_NAME": "国勢調査 4次メッシュ",
+                            "TITLE": "人口及び世帯 (男女別)",
+                            "SURVEY_DATE": "202510"
+                        },
+                        {
+                            "@id": "T009002",
+                            "TITLE": "人口及び世帯（10kmメッシュ）",
+                            "SURVEY_DATE": "201010"
+                        },
+                        {
+                            "@id": "A000001",
+                            "TITLE": "小地域（町丁・字）",
+                            "SURVEY_DATE": "202010"
+                        }
+                    ]
+                }
+            }
+        });
+        let found: Vec<MeshStats> = table_infos(&page)
+            .into_iter()
+            .filter_map(parse_table_info)
+            .collect();
+        let summary: Vec<_> = found
+            .iter()
+            .map(|m| {
+                (
+                    m.stats_id.as_str(),
+                    m.name.as_str(),
+                    m.year,
+                    m.meshlevel,
+                    m.datum,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("T001140", "人口及び世帯", 2020, 3, 6668),
+                ("T009001", "人口及び世帯 (男女別)", 2025, 4, 6668),
+                ("T009002", "人口及び世帯", 2010, 2, 4612),
+            ]
+        );
+
+        let known: MeshStatsConfig = serde_json::from_str(include_str!("mesh_stats.json")).unwrap();
+        let known_count = known.mesh_stats.len();
+        let (merged, added) = merge(known.mesh_stats, found);
+        assert_eq!(added, 2);
+        assert_eq!(merged.len(), known_count + 2);
+        assert_eq!(merged[known_count].stats_id, "T009002");
+    }
+}
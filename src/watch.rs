@@ -0,0 +1,207 @@
+use crate::{cancel, download, download_manifest::DownloadManifest};
+use anyhow::{Context, Result};
+use futures::{StreamExt, stream};
+use reqwest::{StatusCode, header};
+use std::{
+    future::Future,
+    num::NonZeroU64,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use url::Url;
+
+/// Default of `--poll-interval-minutes`: once a day.
+pub const DEFAULT_POLL_INTERVAL_MINUTES: NonZeroU64 = NonZeroU64::new(24 * 60).unwrap();
+
+enum RemoteState {
+    Unchanged,
+    Updated,
+    /// The server sent no usable `Last-Modified`, so there is nothing to compare.
+    Unknown,
+}
+
+/// Interprets the reply to a `HEAD` with `If-Modified-Since: reference`. Servers that ignore
+/// the condition answer 200, so the `Last-Modified` they send is compared as well.
+fn remote_state(
+    status: StatusCode,
+    last_modified: Option<&str>,
+    reference: Option<SystemTime>,
+) -> RemoteState {
+    if status == StatusCode::NOT_MODIFIED {
+        return RemoteState::Unchanged;
+    }
+    if !status.is_success() {
+        return RemoteState::Unknown;
+    }
+    match (
+        last_modified.and_then(|v| httpdate::parse_http_date(v).ok()),
+        reference,
+    ) {
+        (Some(remote), Some(reference)) if remote > reference => RemoteState::Updated,
+        (Some(_), Some(_)) => RemoteState::Unchanged,
+        // Nothing cached to compare against: the file has not been downloaded yet.
+        (_, None) => RemoteState::Updated,
+        (None, Some(_)) => RemoteState::Unknown,
+    }
+}
+
+/// Archives (file names in `tmp_dir`) whose content changed on e-Stat since they were
+/// downloaded. The reference time is the `Last-Modified` recorded in the download manifest,
+/// or the archive's modification time when none was recorded.
+async fn updated_archives(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    archives: &[(String, Url)],
+) -> Result<Vec<String>> {
+    let manifest = DownloadManifest::load(tmp_dir).await;
+    let checks = archives.iter().map(|(filename, url)| {
+        let archive = tmp_dir.join(filename);
+        let reference = manifest
+            .last_modified(filename)
+            .and_then(|v| httpdate::parse_http_date(v).ok())
+            .or_else(|| std::fs::metadata(&archive).ok()?.modified().ok());
+        async move {
+            let mut request = http_client.head(url.clone());
+            if let Some(reference) = reference {
+                request = request.header(
+                    header::IF_MODIFIED_SINCE,
+                    httpdate::fmt_http_date(reference),
+                );
+            }
+            download::RATE_LIMITER.acquire().await;
+            let response = request
+                .send()
+                .await
+                .with_context(|| format!("when checking {}", url))?;
+            if response.status() == StatusCode::NOT_FOUND {
+                // Not published for this code, like the 404s skipped when downloading.
+                return Ok((filename, RemoteState::Unchanged));
+            }
+            let last_modified = response
+                .headers()
+                .get(header::LAST_MODIFIED)
+                .and_then(|value| value.to_str().ok());
+            Ok((
+                filename,
+                remote_state(response.status(), last_modified, reference),
+            )) as Result<_>
+        }
+    });
+    let states: Vec<_> = stream::iter(checks)
+        .buffer_unordered(10)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<_>>()?;
+
+    let mut updated = Vec::new();
+    let mut unknown = 0usize;
+    for (filename, state) in states {
+        match state {
+            RemoteState::Updated => updated.push(filename.clone()),
+            RemoteState::Unknown => unknown += 1,
+            RemoteState::Unchanged => {}
+        }
+    }
+    if unknown > 0 {
+        eprintln!(
+            "Warning: {} archives could not be checked (no Last-Modified from e-Stat)",
+            unknown
+        );
+    }
+    updated.sort();
+    Ok(updated)
+}
+
+/// Runs `import`, then every `poll_interval_minutes` asks e-Stat whether any of `archives`
+/// changed; changed archives are removed from `tmp_dir` and `import` runs again to fetch
+/// them. Returns after Ctrl+C. A failed check is retried at the next interval, while a
+/// failed import ends the watch.
+pub async fn watch<F, Fut>(
+    http_client: &reqwest::Client,
+    tmp_dir: &Path,
+    archives: &[(String, Url)],
+    poll_interval_minutes: NonZeroU64,
+    mut import: F,
+) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let period = Duration::from_secs(poll_interval_minutes.get().saturating_mul(60));
+    import().await?;
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick completes immediately.
+    interval.tick().await;
+    while !cancel::is_cancelled() {
+        println!(
+            "Watching for updates every {} minutes (Ctrl+C to stop)",
+            poll_interval_minutes
+        );
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = cancel::cancelled() => break,
+        }
+        let updated = match updated_archives(http_client, tmp_dir, archives).await {
+            Ok(updated) => updated,
+            Err(err) => {
+                eprintln!("Warning: update check failed: {:#}", err);
+                continue;
+            }
+        };
+        if updated.is_empty() {
+            println!("No updates on e-Stat");
+            continue;
+        }
+        println!(
+            "{} archives were updated on e-Stat, importing again: {}",
+            updated.len(),
+            updated.join(", ")
+        );
+        for filename in &updated {
+            let archive = tmp_dir.join(filename);
+            if archive.exists() {
+                tokio::fs::remove_file(&archive)
+                    .await
+                    .with_context(|| format!("when removing {}", archive.display()))?;
+            }
+        }
+        download::DOWNLOAD_BUDGET.reset();
+        import().await?;
+    }
+    println!("Stopped watching");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_last_modified_with_the_cached_copy() {
+        let cached = httpdate::parse_http_date("Wed, 01 Apr 2026 00:00:00 GMT").ok();
+        let state = |status, last_modified| remote_state(status, last_modified, cached);
+        assert!(matches!(
+            state(StatusCode::NOT_MODIFIED, None),
+            RemoteState::Unchanged
+        ));
+        assert!(matches!(
+            state(StatusCode::OK, Some("Wed, 01 Apr 2026 00:00:00 GMT")),
+            RemoteState::Unchanged
+        ));
+        assert!(matches!(
+            state(StatusCode::OK, Some("Thu, 02 Apr 2026 09:30:00 GMT")),
+            RemoteState::Updated
+        ));
+        assert!(matches!(state(StatusCode::OK, None), RemoteState::Unknown));
+        assert!(matches!(
+            state(StatusCode::INTERNAL_SERVER_ERROR, None),
+            RemoteState::Unknown
+        ));
+        assert!(matches!(
+            remote_state(StatusCode::OK, None, None),
+            RemoteState::Updated
+        ));
+    }
+}
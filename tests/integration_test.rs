@@ -0,0 +1,148 @@
+//! Download → extract pipeline against a local mock of the e-Stat download endpoint.
+
+use futures::stream;
+use jp_estat_util::{
+    download::{self, DownloadResults},
+    error::JpEstatError,
+};
+use url::Url;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path, query_param},
+};
+
+/// A minimal e-Stat mesh ZIP: one Shift_JIS CSV with the usual two header rows.
+const MESH_ZIP: &[u8] = include_bytes!("fixtures/mesh/tblT001140S5339.zip");
+
+/// 50 Lv3 codes from `gen-test-fixtures --level 3 --num-codes 50`.
+const SYNTHETIC_ZIP: &[u8] = include_bytes!("fixtures/synthetic/tblT001140S5339.zip");
+
+async fn mock_mesh_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/gis/statmap-search/data"))
+        .and(query_param("code", "5339"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(MESH_ZIP))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/gis/statmap-search/data"))
+        .and(query_param("code", "3036"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/gis/statmap-search/data"))
+        .and(query_param("code", "6848"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn download(
+    server: &MockServer,
+    codes: &[u64],
+    tmp_dir: &std::path::Path,
+) -> anyhow::Result<DownloadResults<(u64, Url)>> {
+    let items: Vec<(u64, Url)> = codes
+        .iter()
+        .map(|code| {
+            let url = format!(
+                "{}/gis/statmap-search/data?statsId=T001140&code={}&downloadType=2",
+                server.uri(),
+                code
+            );
+            (*code, Url::parse(&url).unwrap())
+        })
+        .collect();
+    download::download_and_extract_all(
+        &reqwest::Client::new(),
+        stream::iter(items),
+        |(_, url)| url.clone(),
+        |(code, _)| format!("2020-T001140-{}.zip", code),
+        "txt",
+        tmp_dir,
+        "Downloading...",
+        "Extracting...",
+        2,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn downloads_and_extracts_csv_and_skips_missing_codes() {
+    let server = mock_mesh_server().await;
+    let tmp = tempfile::tempdir().unwrap();
+    let tmp_dir = tmp.path();
+
+    let (items, errors) = download(&server, &[5339, 3036], tmp_dir).await.unwrap();
+
+    assert!(errors.is_empty());
+
+    assert_eq!(items.len(), 1, "the 404 code is skipped without an error");
+    let item = &items[0];
+    assert_eq!(item.metadata.0, 5339);
+    assert!(item.extracted_path.exists());
+    assert_eq!(item.extracted_path.extension().unwrap(), "txt");
+    assert!(item.extracted_path.starts_with(&item.extracted_dir));
+
+    let bytes = std::fs::read(&item.extracted_path).unwrap();
+    let (text, _, had_errors) = encoding_rs::SHIFT_JIS.decode(&bytes);
+    assert!(!had_errors);
+    assert!(text.starts_with("KEY_CODE,HTKSYORI,HTKSAKI,GASSAN,T001140001\r\n"));
+    assert!(text.contains("人口（総数）"));
+    assert!(text.contains("53394526,0,,,120"));
+}
+
+#[tokio::test]
+async fn reports_server_errors_per_item() {
+    let server = mock_mesh_server().await;
+    let tmp = tempfile::tempdir().unwrap();
+    let tmp_dir = tmp.path();
+
+    let (items, errors) = download(&server, &[5339, 6848], tmp_dir).await.unwrap();
+
+    assert_eq!(items.len(), 1, "the other download is not aborted");
+    assert_eq!(items[0].metadata.0, 5339);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].metadata.0, 6848);
+    assert_eq!(errors[0].filename, "2020-T001140-6848.zip");
+
+    // `--fail-on-any-error` (the default) turns the failure back into the error.
+    let err = download::into_result((items, errors)).unwrap_err();
+
+    match err.downcast_ref::<JpEstatError>() {
+        Some(JpEstatError::DownloadFailed { url, status }) => {
+            assert_eq!(*status, 500);
+            assert!(url.contains("code=6848"));
+        }
+        other => panic!("expected DownloadFailed, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn extracts_generated_fixtures() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/gis/statmap-search/data"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(SYNTHETIC_ZIP))
+        .mount(&server)
+        .await;
+    let tmp = tempfile::tempdir().unwrap();
+    let tmp_dir = tmp.path();
+
+    let (items, errors) = download(&server, &[5339], tmp_dir).await.unwrap();
+
+    assert!(errors.is_empty());
+    let bytes = std::fs::read(&items[0].extracted_path).unwrap();
+    let (text, _, had_errors) = encoding_rs::SHIFT_JIS.decode(&bytes);
+    assert!(!had_errors);
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2 + 50);
+    assert_eq!(
+        lines[1],
+        ",,,,人口（総数）,人口（総数）　男,人口（総数）　女,世帯総数"
+    );
+    assert!(lines.iter().any(|line| line.ends_with(",*,*,*,*")));
+}